@@ -0,0 +1,61 @@
+//! Compares the latency of requests made on new connections, with and without the DNS cache of
+//! [`ReqwestTransportBuilder::with_dns_cache`].
+//!
+//! Run with `cargo bench -p ic-agent --features dns-cache --bench dns_cache`. The requests go to
+//! a local server at `localhost`, which closes every connection, so that each request resolves
+//! the host again.
+use ic_agent::agent::http_transport::{ReqwestTransport, ReqwestTransportBuilder};
+use ic_agent::agent::Transport;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+const REQUESTS: usize = 1000;
+
+/// Start a server answering every request with an empty response, and return its port.
+async fn serve() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = [0; 4096];
+                let _ = stream.read(&mut buf).await;
+                let _ = stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                    .await;
+            });
+        }
+    });
+    port
+}
+
+async fn run(name: &str, builder: ReqwestTransportBuilder) {
+    let transport = builder.build().unwrap();
+    let mut latencies = Vec::with_capacity(REQUESTS);
+    for _ in 0..REQUESTS {
+        let start = Instant::now();
+        transport.status().await.unwrap();
+        latencies.push(start.elapsed());
+    }
+    latencies.sort();
+    let percentile = |p: usize| latencies[(latencies.len() * p / 100).min(latencies.len() - 1)];
+    println!(
+        "{name}: p50 {:?}, p99 {:?}, max {:?}",
+        percentile(50),
+        percentile(99),
+        latencies.last().copied().unwrap_or(Duration::ZERO),
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    let url = format!("http://localhost:{}", serve().await);
+    run("system resolver", ReqwestTransport::builder(url.clone())).await;
+    run(
+        "dns cache",
+        ReqwestTransport::builder(url).with_dns_cache(Duration::from_secs(60), 16),
+    )
+    .await;
+}
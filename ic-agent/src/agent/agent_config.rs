@@ -1,5 +1,8 @@
 use crate::{
-    agent::{NonceFactory, NonceGenerator, Transport},
+    agent::{
+        CborSerializer, ExponentialBackoffConfig, NonceFactory, NonceGenerator, RequestLog,
+        SignedQueryPolicy, Transport,
+    },
     identity::{anonymous::AnonymousIdentity, Identity},
 };
 use std::{sync::Arc, time::Duration};
@@ -14,6 +17,15 @@ pub struct AgentConfig {
     pub ingress_expiry: Option<Duration>,
     /// The [`with_transport`](super::AgentBuilder::with_transport).
     pub transport: Option<Arc<dyn Transport>>,
+    /// See [`with_request_logger`](super::AgentBuilder::with_request_logger).
+    pub request_logger: Option<Arc<dyn Fn(RequestLog) + Send + Sync>>,
+    /// See [`with_cbor_serializer`](super::AgentBuilder::with_cbor_serializer). Defaults to
+    /// [`SerdeCborSerializer`](super::cbor::SerdeCborSerializer) with the `serde-cbor` feature.
+    pub cbor_serializer: Option<Arc<dyn CborSerializer>>,
+    /// See [`with_exponential_backoff`](super::AgentBuilder::with_exponential_backoff).
+    pub backoff: Option<ExponentialBackoffConfig>,
+    /// See [`with_signed_query_verification`](super::AgentBuilder::with_signed_query_verification).
+    pub signed_query_policy: SignedQueryPolicy,
 }
 
 impl Default for AgentConfig {
@@ -23,6 +35,10 @@ impl Default for AgentConfig {
             identity: Arc::new(AnonymousIdentity {}),
             ingress_expiry: None,
             transport: None,
+            request_logger: None,
+            cbor_serializer: None,
+            backoff: None,
+            signed_query_policy: SignedQueryPolicy::Disabled,
         }
     }
 }
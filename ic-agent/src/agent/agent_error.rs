@@ -1,7 +1,7 @@
 //! Errors that can occur when using the replica agent.
 
 use crate::{
-    agent::{replica_api::RejectResponse, status::Status},
+    agent::{cbor::SerializeError, replica_api::RejectResponse, status::Status},
     RequestIdError,
 };
 use ic_certification::Label;
@@ -31,6 +31,10 @@ pub enum AgentError {
     #[error("Invalid CBOR data, could not deserialize: {0}")]
     InvalidCborData(#[from] serde_cbor::Error),
 
+    /// The request could not be encoded as CBOR.
+    #[error("Could not serialize the request as CBOR: {0}")]
+    CborSerializationError(#[from] SerializeError),
+
     /// There was an error calculating a request ID.
     #[error("Cannot calculate a RequestID: {0}")]
     CannotCalculateRequestId(#[from] RequestIdError),
@@ -99,6 +103,20 @@ pub enum AgentError {
     #[error("Certificate verification failed.")]
     CertificateVerificationFailed(),
 
+    /// A query response was not signed, while the agent requires it with
+    /// [`SignedQueryPolicy::VerifySignatureOrFail`](crate::agent::SignedQueryPolicy::VerifySignatureOrFail).
+    #[error("The query response is not signed.")]
+    MissingQuerySignature,
+
+    /// The signature of a query response by a node could not be verified, e.g. because the node
+    /// is not part of the subnet of the canister.
+    #[error("The signature of the query response by node {0} is invalid.")]
+    InvalidQuerySignature(crate::export::Principal),
+
+    /// A certificate or one of its delegations could not be parsed.
+    #[error("Invalid certificate chain: {0}")]
+    InvalidCertificateChain(#[from] ic_certification::CertificateError),
+
     /// The certificate contained a delegation that does not include the effective_canister_id in the canister_ranges field.
     #[error("Certificate is not authorized to respond to queries for this canister. While developing: Did you forget to set effective_canister_id?")]
     CertificateNotAuthorized(),
@@ -143,7 +161,15 @@ pub enum AgentError {
     #[error("Missing replica transport in the Agent Builder.")]
     MissingReplicaTransport(),
 
-    /// The response size exceeded the provided limit.
+    /// The CBOR serializer was not specified in the [`AgentBuilder`](super::AgentBuilder), and
+    /// there is no default one without the `serde-cbor` feature.
+    #[error("Missing CBOR serializer in the Agent Builder.")]
+    MissingCborSerializer(),
+
+    /// The response size exceeded the limit set with `with_max_response_body_size` on the
+    /// transport, or the default one of 50 MiB of
+    /// [`ReqwestTransport`](crate::agent::http_transport::ReqwestTransport). The body is not
+    /// read further once it exceeds the limit.
     #[error("Response size exceeded limit.")]
     ResponseSizeExceededLimit(),
 
@@ -161,6 +187,47 @@ pub enum AgentError {
         /// The value that was actually in the CBOR.
         value_cbor: String,
     },
+
+    /// The caller is not permitted to access the requested information.
+    #[error("Not permitted: {0}")]
+    NotPermitted(String),
+
+    /// The deadline of the context the call was made in had already passed.
+    #[error("The context deadline was exceeded before the call was sent.")]
+    ContextDeadlineExceeded(),
+
+    /// The canister was not found in the state tree.
+    #[error("Canister {0} was not found in the state tree.")]
+    CanisterNotFound(crate::export::Principal),
+
+    /// The canister has not set any certified data.
+    #[error("Canister {0} has not set any certified data.")]
+    NoCertifiedData(crate::export::Principal),
+
+    /// The update call was estimated to cost more cycles than the budget it was made with, and
+    /// was not sent.
+    #[error(
+        "The call is estimated to cost {estimated} cycles, more than the budget of {limit} cycles."
+    )]
+    CycleBudgetExceeded {
+        /// The estimated cost of the call, in cycles.
+        estimated: u128,
+        /// The cycle budget, in cycles.
+        limit: u128,
+    },
+}
+
+impl AgentError {
+    /// Whether the error may not happen again if the request is retried, e.g. because the
+    /// connection failed or the replica was overloaded.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            AgentError::TransportError(_) => true,
+            AgentError::ReplicaError(response) => response.reject_code.is_transient(),
+            AgentError::HttpError(payload) => payload.status >= 500 || payload.status == 429,
+            _ => false,
+        }
+    }
 }
 
 impl PartialEq for AgentError {
@@ -224,8 +291,23 @@ impl Display for HttpErrorPayload {
 #[cfg(test)]
 mod tests {
     use super::HttpErrorPayload;
+    use crate::agent::{RejectCode, RejectResponse};
     use crate::AgentError;
 
+    #[test]
+    fn reject_code_is_transient() {
+        let error = |reject_code| {
+            AgentError::ReplicaError(RejectResponse {
+                reject_code,
+                reject_message: String::new(),
+                error_code: None,
+            })
+        };
+        assert!(error(RejectCode::SysTransient).is_transient());
+        assert!(!error(RejectCode::CanisterReject).is_transient());
+        assert_eq!(RejectCode::SysTransient.to_string(), "SYS_TRANSIENT (2)");
+    }
+
     #[test]
     fn content_type_none_valid_utf8() {
         let payload = HttpErrorPayload {
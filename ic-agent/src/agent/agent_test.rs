@@ -5,14 +5,18 @@ use self::mock::{assert_mock, mock};
 use crate::{
     agent::{
         http_transport::ReqwestTransport,
-        replica_api::{CallReply, QueryResponse, RejectCode, RejectResponse},
-        Status,
+        replica_api::{CallReply, QueryResponse, ReadStateResponse, RejectCode, RejectResponse},
+        response_authentication::lookup_value,
+        PreSignHook, Status,
     },
     export::Principal,
     Agent, AgentError,
 };
-use ic_certification::Label;
-use std::collections::BTreeMap;
+use ic_certification::{Certificate, Label};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
 #[cfg(all(target_family = "wasm", feature = "wasm-bindgen"))]
 use wasm_bindgen_test::wasm_bindgen_test;
 
@@ -46,6 +50,7 @@ async fn query() -> Result<(), AgentError> {
             "main",
             &[],
             None,
+            None,
         )
         .await;
 
@@ -72,12 +77,51 @@ async fn query_error() -> Result<(), AgentError> {
             "greet",
             &[],
             None,
+            None,
+        )
+        .await;
+
+    assert_mock(query_mock).await;
+
+    assert!(result.is_err());
+
+    Ok(())
+}
+
+#[cfg_attr(not(target_family = "wasm"), tokio::test)]
+#[cfg_attr(target_family = "wasm", wasm_bindgen_test)]
+async fn request_logger() -> Result<(), AgentError> {
+    let (query_mock, url) =
+        mock("POST", "/api/v2/canister/aaaaa-aa/query", 500, vec![], None).await;
+    let logs = Arc::new(Mutex::new(Vec::new()));
+    let agent = {
+        let logs = logs.clone();
+        Agent::builder()
+            .with_transport(ReqwestTransport::create(url)?)
+            .with_request_logger(move |log| logs.lock().unwrap().push(log))
+            .build()?
+    };
+
+    let result = agent
+        .query_raw(
+            &Principal::management_canister(),
+            Principal::management_canister(),
+            "greet",
+            &[],
+            None,
+            None,
         )
         .await;
 
     assert_mock(query_mock).await;
 
     assert!(result.is_err());
+    let logs = logs.lock().unwrap();
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].method, "POST");
+    assert_eq!(logs[0].url, "canister/aaaaa-aa/query");
+    assert_eq!(logs[0].response_status, Some(500));
+    assert_eq!(logs[0].response_body_len, 0);
 
     Ok(())
 }
@@ -111,6 +155,7 @@ async fn query_rejected() -> Result<(), AgentError> {
             "greet",
             &[],
             None,
+            None,
         )
         .await;
 
@@ -286,6 +331,42 @@ async fn status_okay() -> Result<(), AgentError> {
     Ok(())
 }
 
+#[cfg_attr(not(target_family = "wasm"), tokio::test)]
+#[cfg_attr(target_family = "wasm", wasm_bindgen_test)]
+async fn replica_time_is_cached() -> Result<(), AgentError> {
+    let mut map = BTreeMap::new();
+    map.insert(
+        serde_cbor::Value::Text("ic_api_version".to_owned()),
+        serde_cbor::Value::Text("1.2.3".to_owned()),
+    );
+    map.insert(
+        serde_cbor::Value::Text("impl_time".to_owned()),
+        serde_cbor::Value::Integer(1_685_570_400_000_000_000),
+    );
+    let response = serde_cbor::Value::Map(map);
+    let (read_mock, url) = mock(
+        "GET",
+        "/api/v2/status",
+        200,
+        serde_cbor::to_vec(&response)?,
+        Some("application/cbor"),
+    )
+    .await;
+
+    let agent = Agent::builder()
+        .with_transport(ReqwestTransport::create(&url)?)
+        .build()?;
+    let first = agent.replica_time().await?;
+    let second = agent.replica_time().await?;
+
+    // The second call is served from the cache, so the status endpoint is only hit once.
+    assert_mock(read_mock).await;
+    assert_eq!(first, 1_685_570_400_000_000_000);
+    assert!(second >= first);
+
+    Ok(())
+}
+
 #[cfg_attr(not(target_family = "wasm"), tokio::test)]
 #[cfg_attr(target_family = "wasm", wasm_bindgen_test)]
 // test that the agent (re)tries to reach the server.
@@ -315,6 +396,10 @@ const REQ_WITH_DELEGATED_CERT_PATH: [&str; 2] = [
     "92F03ABDDC774EE97882320CF15F2029A868FFCFE3BE48FEF84FC97B5A13E04A",
 ];
 const REQ_WITH_DELEGATED_CERT_CANISTER: &str = "ivg37-qiaaa-aaaab-aaaga-cai";
+// the ID of the request whose status is certified in the response, which is not the one in the
+// path above
+const REQ_WITH_DELEGATED_CERT_REQUEST_ID: &str =
+    "EDAD510EAAA08ED2ACD4781324E6446269DA6753EC17760F206BBE81C465FF52";
 const REQ_WITH_DELEGATED_CERT_RESPONSE: [u8; 1074] = [
     217, 217, 247, 161, 107, 99, 101, 114, 116, 105, 102, 105, 99, 97, 116, 101, 89, 4, 31, 217,
     217, 247, 163, 100, 116, 114, 101, 101, 131, 1, 131, 1, 130, 4, 88, 32, 37, 15, 94, 38, 134,
@@ -518,6 +603,210 @@ async fn check_subnet_range_with_pruned_range() {
     assert!(result.is_err());
 }
 
+#[cfg_attr(not(target_family = "wasm"), tokio::test)]
+#[cfg_attr(target_family = "wasm", wasm_bindgen_test)]
+// asserts that a delegated certificate is only accepted if the delegation carries the public key
+// registered for its subnet with `set_root_keys`
+async fn check_delegation_with_registered_subnet_key() {
+    let canister = Principal::from_text(REQ_WITH_DELEGATED_CERT_CANISTER).unwrap();
+    let response: ReadStateResponse =
+        serde_cbor::from_slice(&REQ_WITH_DELEGATED_CERT_RESPONSE).unwrap();
+    let cert: Certificate = serde_cbor::from_slice(&response.certificate).unwrap();
+    let delegation = cert.delegation.unwrap();
+    let subnet_id = Principal::try_from_slice(&delegation.subnet_id).unwrap();
+    let delegation_cert: Certificate = serde_cbor::from_slice(&delegation.certificate).unwrap();
+    let subnet_key = lookup_value(
+        &delegation_cert,
+        [
+            "subnet".as_bytes(),
+            subnet_id.as_slice(),
+            "public_key".as_bytes(),
+        ],
+    )
+    .unwrap()
+    .to_vec();
+
+    for (registered_key, ok) in [(subnet_key, true), (vec![0; 133], false)] {
+        let (_read_mock, url) = mock(
+            "POST",
+            "/api/v2/canister/ivg37-qiaaa-aaaab-aaaga-cai/read_state",
+            200,
+            REQ_WITH_DELEGATED_CERT_RESPONSE.into(),
+            Some("application/cbor"),
+        )
+        .await;
+        let agent = Agent::builder()
+            .with_transport(ReqwestTransport::create(&url).unwrap())
+            .build()
+            .unwrap();
+        agent.set_root_keys([(subnet_id, registered_key)].into_iter().collect());
+        let result = agent
+            .read_state_raw(
+                vec![REQ_WITH_DELEGATED_CERT_PATH
+                    .into_iter()
+                    .map(Label::from)
+                    .collect()],
+                canister,
+            )
+            .await;
+        // Certificates verified offline are pinned the same way.
+        let verification = agent.verify_response_certificate(&response.certificate, canister);
+        if ok {
+            result.expect("read state failed");
+            verification.expect("verification failed");
+        } else {
+            assert_eq!(result, Err(AgentError::CertificateVerificationFailed()));
+            assert_eq!(
+                verification.err(),
+                Some(AgentError::CertificateVerificationFailed())
+            );
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+#[tokio::test]
+async fn fetch_subnet_public_keys_routing() {
+    use crate::agent::mock::{MockTransport, Response};
+
+    // The request goes to the subnet of the given canister, whose certificate is verified. The
+    // captured certificate prunes the subnet keys.
+    let canister = Principal::from_text(REQ_WITH_DELEGATED_CERT_CANISTER).unwrap();
+    let subnet_id = Principal::from_slice(&[9; 29]);
+    let agent = Agent::builder()
+        .with_transport(MockTransport::new().expect_read_state(
+            canister,
+            Response::Bytes(REQ_WITH_DELEGATED_CERT_RESPONSE.to_vec()),
+        ))
+        .build()
+        .unwrap();
+    assert!(matches!(
+        agent.fetch_subnet_public_keys(&[subnet_id], canister).await,
+        Err(AgentError::LookupPathUnknown(_))
+    ));
+}
+
+#[test]
+fn update_nonce_len() {
+    let agent = Agent::builder()
+        .with_url("http://localhost:1")
+        .build()
+        .unwrap();
+    let canister_id = Principal::management_canister();
+
+    let signed = agent
+        .update(&canister_id, "greet")
+        .with_nonce_len(0)
+        .sign()
+        .unwrap();
+    assert_eq!(signed.nonce, None);
+
+    let signed = agent
+        .update(&canister_id, "greet")
+        .with_nonce_len(8)
+        .sign()
+        .unwrap();
+    assert_eq!(signed.nonce.map(|nonce| nonce.len()), Some(8));
+
+    // Nonces longer than the replica allows are refused instead of being sent.
+    assert!(matches!(
+        agent
+            .update(&canister_id, "greet")
+            .with_nonce_len(33)
+            .sign(),
+        Err(AgentError::MessageError(_))
+    ));
+}
+
+#[test]
+fn query_sender_override() {
+    let agent = Agent::builder()
+        .with_url("http://localhost:1")
+        .build()
+        .unwrap();
+    let canister_id = Principal::management_canister();
+    let sender = Principal::from_slice(&[1; 29]);
+
+    let signed = agent.query(&canister_id, "greet").sign().unwrap();
+    assert_eq!(signed.sender, Principal::anonymous());
+
+    let signed = agent
+        .query(&canister_id, "greet")
+        .with_sender_override(sender)
+        .sign()
+        .unwrap();
+    assert_eq!(signed.sender, sender);
+}
+
+#[cfg_attr(not(target_family = "wasm"), tokio::test)]
+#[cfg_attr(target_family = "wasm", wasm_bindgen_test)]
+async fn pre_sign_hook() {
+    let agent = Agent::builder()
+        .with_url("http://localhost:1")
+        .build()
+        .unwrap();
+    let canister_id = Principal::management_canister();
+    let hashed = Arc::new(Mutex::new(None));
+
+    let mut builder = agent.update(&canister_id, "greet");
+    let hashed_by_hook = hashed.clone();
+    builder.with_pre_sign_hook(PreSignHook::new(move |hash| {
+        *hashed_by_hook.lock().unwrap() = Some(hash.to_vec());
+        Ok(())
+    }));
+    let signed = builder.sign().unwrap();
+    assert_eq!(*hashed.lock().unwrap(), Some(signed.request_id.to_vec()));
+
+    // The call fails before reaching the transport, which would fail to connect.
+    builder.with_pre_sign_hook(PreSignHook::new(|_| {
+        Err(AgentError::MessageError("denied".to_string()))
+    }));
+    assert!(matches!(
+        builder.call().await,
+        Err(AgentError::MessageError(message)) if message == "denied"
+    ));
+}
+
+#[test]
+// asserts that a stored delegated certificate can be verified without any network call, and that
+// tampering with its signature is detected
+fn verify_response_certificate() {
+    let agent = Agent::builder()
+        .with_url("http://localhost:1")
+        .build()
+        .unwrap();
+    let response: ReadStateResponse =
+        serde_cbor::from_slice(&REQ_WITH_DELEGATED_CERT_RESPONSE).unwrap();
+    let mut certificate = response.certificate;
+
+    let canister = Principal::from_text(REQ_WITH_DELEGATED_CERT_CANISTER).unwrap();
+    let verification = agent
+        .verify_response_certificate(&certificate, canister)
+        .unwrap();
+    assert!(verification.subnet_id.is_some());
+    assert!(verification.certified_at_ns > 0);
+
+    // the signature of the certificate itself comes before the one of the delegation
+    let signature_key = b"isignature\x58\x30";
+    let signature_start = certificate
+        .windows(signature_key.len())
+        .position(|window| window == signature_key)
+        .unwrap()
+        + signature_key.len();
+    // the subnet is not authorized for other canisters
+    let wrong_canister = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    assert_eq!(
+        agent.verify_response_certificate(&certificate, wrong_canister),
+        Err(AgentError::CertificateNotAuthorized())
+    );
+
+    certificate[signature_start] ^= 1;
+    assert_eq!(
+        agent.verify_response_certificate(&certificate, canister),
+        Err(AgentError::CertificateVerificationFailed())
+    );
+}
+
 #[cfg(not(target_family = "wasm"))]
 mod mock {
 
@@ -629,3 +918,165 @@ mod mock {
         assert!(hits.parse::<i32>().unwrap() >= 1);
     }
 }
+
+#[cfg(not(target_family = "wasm"))]
+#[tokio::test]
+// asserts that the certificate of a request status is verified before its status is looked up,
+// using the certificate of a rejected call captured from mainnet
+async fn wait_certified_verifies_certificate() {
+    use crate::{
+        agent::mock::{MockTransport, Response},
+        RequestId,
+    };
+    use std::str::FromStr;
+
+    let canister = Principal::from_text(REQ_WITH_DELEGATED_CERT_CANISTER).unwrap();
+    let request_id = RequestId::from_str(REQ_WITH_DELEGATED_CERT_REQUEST_ID).unwrap();
+    let agent = Agent::builder()
+        .with_transport(MockTransport::new().expect_read_state(
+            canister,
+            Response::Bytes(REQ_WITH_DELEGATED_CERT_RESPONSE.to_vec()),
+        ))
+        .build()
+        .unwrap();
+    let result = agent.wait_certified(request_id, canister).await;
+    assert!(matches!(
+        result,
+        Err(AgentError::ReplicaError(RejectResponse { reject_message, .. }))
+            if reject_message.contains("has no update method 'register'")
+    ));
+
+    let wrong_canister = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let agent = Agent::builder()
+        .with_transport(MockTransport::new().expect_read_state(
+            wrong_canister,
+            Response::Bytes(REQ_WITH_DELEGATED_CERT_RESPONSE.to_vec()),
+        ))
+        .build()
+        .unwrap();
+    assert_eq!(
+        agent.wait_certified(request_id, wrong_canister).await,
+        Err(AgentError::CertificateNotAuthorized())
+    );
+}
+
+#[cfg(not(target_family = "wasm"))]
+#[tokio::test]
+// asserts that a call still unknown after its ingress expiry times out instead of being polled
+async fn wait_many_unknown_after_expiry() {
+    use crate::{
+        agent::{
+            mock::{MockTransport, Response},
+            WaitLimits,
+        },
+        RequestId,
+    };
+    use std::{
+        str::FromStr,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    let canister = Principal::from_text(REQ_WITH_DELEGATED_CERT_CANISTER).unwrap();
+    let rejected = RequestId::from_str(REQ_WITH_DELEGATED_CERT_REQUEST_ID).unwrap();
+    // The certificate prunes the status of any other call.
+    let unknown = RequestId::new(&[1; 32]);
+    let transport = Arc::new(MockTransport::new().expect_read_state(
+        canister,
+        Response::Bytes(REQ_WITH_DELEGATED_CERT_RESPONSE.to_vec()),
+    ));
+    let agent = Agent::builder()
+        .with_arc_transport(transport.clone())
+        .build()
+        .unwrap();
+    let limits = WaitLimits {
+        ingress_expiry: Some(UNIX_EPOCH + Duration::from_secs(1)),
+        ..Default::default()
+    };
+    let results = agent
+        .wait_many(canister, vec![rejected, unknown], limits)
+        .await
+        .unwrap();
+    assert!(matches!(&results[0], Err(AgentError::ReplicaError(_))));
+    assert_eq!(results[1], Err(AgentError::TimeoutWaitingForResponse()));
+    // Both results come from the first read.
+    transport.verify_all_called();
+}
+
+#[cfg(not(target_family = "wasm"))]
+#[tokio::test]
+// asserts that the paths read together are looked up in the same certificate, absent paths
+// being reported as such and pruned ones failing the read
+async fn read_state_multi() {
+    use crate::agent::mock::{MockTransport, Response};
+
+    let canister = Principal::from_text(REQ_WITH_DELEGATED_CERT_CANISTER).unwrap();
+    let request_id = hex::decode(REQ_WITH_DELEGATED_CERT_REQUEST_ID).unwrap();
+    let request_status = |label: &str| {
+        vec![
+            b"request_status".to_vec(),
+            request_id.clone(),
+            label.as_bytes().to_vec(),
+        ]
+    };
+    let agent = Agent::builder()
+        .with_transport(MockTransport::new().expect_read_state(
+            canister,
+            Response::Bytes(REQ_WITH_DELEGATED_CERT_RESPONSE.to_vec()),
+        ))
+        .build()
+        .unwrap();
+
+    let values = agent
+        .read_state_multi(
+            canister,
+            vec![request_status("status"), request_status("reply")],
+        )
+        .await
+        .unwrap();
+    assert_eq!(values, vec![Some(b"rejected".to_vec()), None]);
+
+    let module_hash = vec![
+        b"canister".to_vec(),
+        canister.as_slice().to_vec(),
+        b"module_hash".to_vec(),
+    ];
+    assert!(matches!(
+        agent
+            .read_state_multi(canister, vec![request_status("status"), module_hash])
+            .await,
+        Err(AgentError::LookupPathUnknown(_))
+    ));
+}
+
+#[cfg(not(target_family = "wasm"))]
+#[tokio::test]
+// asserts that the subnet of a canister is only read from a certificate the subnet may issue
+// for it, and only if the certificate shows whether the path exists
+async fn read_canister_subnet_id() {
+    use crate::agent::mock::{MockTransport, Response};
+
+    let agent_for = |canister| {
+        Agent::builder()
+            .with_transport(MockTransport::new().expect_read_state(
+                canister,
+                Response::Bytes(REQ_WITH_DELEGATED_CERT_RESPONSE.to_vec()),
+            ))
+            .build()
+            .unwrap()
+    };
+
+    // The captured certificate prunes the canister paths.
+    let canister = Principal::from_text(REQ_WITH_DELEGATED_CERT_CANISTER).unwrap();
+    assert!(matches!(
+        agent_for(canister).read_canister_subnet_id(canister).await,
+        Err(AgentError::LookupPathUnknown(_))
+    ));
+
+    let wrong_canister = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    assert_eq!(
+        agent_for(wrong_canister)
+            .read_canister_subnet_id(wrong_canister)
+            .await,
+        Err(AgentError::CertificateNotAuthorized())
+    );
+}
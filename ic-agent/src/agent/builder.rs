@@ -1,27 +1,126 @@
+#[cfg(feature = "reqwest")]
+use crate::agent::http_transport::RoutingPolicy;
 use crate::{
-    agent::{agent_config::AgentConfig, Agent, Transport},
+    agent::{
+        agent_config::AgentConfig, Agent, CborSerializer, ExponentialBackoffConfig, RequestLog,
+        SignedQueryPolicy, Transport,
+    },
     AgentError, Identity, NonceFactory, NonceGenerator,
 };
 use std::sync::Arc;
 
+/// A [`ReqwestTransport`](crate::agent::http_transport::ReqwestTransport) for `url`, with a pool of
+/// `max_connections_per_host` idle connections if it is set.
+#[cfg(feature = "reqwest")]
+fn reqwest_transport(
+    url: String,
+    max_connections_per_host: Option<u64>,
+) -> Result<crate::agent::http_transport::ReqwestTransport, AgentError> {
+    use crate::agent::http_transport::ReqwestTransport;
+
+    match max_connections_per_host {
+        Some(n) => ReqwestTransport::create_with_max_connections_per_host(url, n),
+        None => ReqwestTransport::create(url),
+    }
+}
+
 /// A builder for an [`Agent`].
 #[derive(Default)]
 pub struct AgentBuilder {
     config: AgentConfig,
+    #[cfg(feature = "reqwest")]
+    url: Option<String>,
+    #[cfg(feature = "reqwest")]
+    urls: Vec<String>,
+    #[cfg(feature = "reqwest")]
+    max_connections_per_host: Option<u64>,
+    #[cfg(feature = "reqwest")]
+    routing_policy: RoutingPolicy,
 }
 
 impl AgentBuilder {
     /// Create an instance of [Agent] with the information from this builder.
     pub fn build(self) -> Result<Agent, AgentError> {
-        Agent::new(self.config)
+        #[cfg(feature = "reqwest")]
+        let config = self.into_reqwest_config()?;
+        #[cfg(not(feature = "reqwest"))]
+        let config = self.config;
+        Agent::new(config)
+    }
+
+    /// Creates the transport for the URLs passed to [Self::with_urls], if any.
+    #[cfg(feature = "reqwest")]
+    fn into_reqwest_config(self) -> Result<AgentConfig, AgentError> {
+        use crate::agent::http_transport::RoundRobinTransport;
+
+        let max_connections_per_host = self.max_connections_per_host;
+        let create = |url: String| -> Result<Arc<dyn Transport>, AgentError> {
+            Ok(Arc::new(reqwest_transport(url, max_connections_per_host)?))
+        };
+
+        let mut config = self.config;
+        let mut urls = self.urls;
+        if urls.len() == 1 {
+            config.transport = Some(create(urls.remove(0))?);
+        } else if !urls.is_empty() {
+            let transports = urls
+                .into_iter()
+                .map(|url| {
+                    let parsed = url::Url::parse(&url)
+                        .map_err(|_| AgentError::InvalidReplicaUrl(url.clone()))?;
+                    Ok((parsed, create(url)?))
+                })
+                .collect::<Result<Vec<_>, AgentError>>()?;
+            config.transport = Some(Arc::new(
+                RoundRobinTransport::new(transports).with_routing_policy(self.routing_policy),
+            ));
+        }
+        Ok(config)
     }
 
     /// Set the URL of the [Agent].
     #[cfg(feature = "reqwest")]
     pub fn with_url<S: Into<String>>(self, url: S) -> Self {
-        use crate::agent::http_transport::ReqwestTransport;
+        let url = url.into();
+        let transport = reqwest_transport(url.clone(), self.max_connections_per_host).unwrap();
+        let mut builder = self.with_transport(transport);
+        builder.url = Some(url);
+        builder
+    }
+
+    /// Set several URLs for the [Agent], e.g. of different boundary nodes. Requests are spread
+    /// over them by a [`RoundRobinTransport`](crate::agent::http_transport::RoundRobinTransport),
+    /// according to the policy set with [Self::with_routing_policy].
+    #[cfg(feature = "reqwest")]
+    pub fn with_urls<S: Into<String>>(mut self, urls: Vec<S>) -> Self {
+        self.config.transport = None;
+        self.url = None;
+        self.urls = urls.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set how requests are spread over the URLs passed to [Self::with_urls]. Defaults to
+    /// [`RoutingPolicy::RoundRobin`].
+    #[cfg(feature = "reqwest")]
+    pub fn with_routing_policy(mut self, policy: RoutingPolicy) -> Self {
+        self.routing_policy = policy;
+        self
+    }
 
-        self.with_transport(ReqwestTransport::create(url).unwrap())
+    /// Set how many idle connections per host the transports created by [Self::with_url] and
+    /// [Self::with_urls] keep open, whether they are called before or after this.
+    /// Has no effect on a transport passed to [Self::with_transport].
+    ///
+    /// See [`ReqwestTransport::create_with_max_connections_per_host`](crate::agent::http_transport::ReqwestTransport::create_with_max_connections_per_host)
+    /// for the trade-offs involved.
+    #[cfg(feature = "reqwest")]
+    pub fn with_max_connections_per_host(mut self, max_connections_per_host: u64) -> Self {
+        self.max_connections_per_host = Some(max_connections_per_host);
+        match self.url.take() {
+            // The URL was already accepted by `with_url`, and is used again for the new pool.
+            Some(url) => self.with_url(url),
+            None => self,
+        }
     }
 
     /// Set a Replica transport to talk to serve as the replica interface.
@@ -32,11 +131,23 @@ impl AgentBuilder {
     /// Same as [Self::with_transport], but provides a `Arc` boxed implementation instead
     /// of a direct type.
     pub fn with_arc_transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        #[cfg(feature = "reqwest")]
+        {
+            self.url = None;
+            self.urls.clear();
+        }
         self.config.transport = Some(transport);
         self
     }
 
-    /// Add a NonceFactory to this Agent. By default, no nonce is produced.
+    /// Add a NonceFactory to this Agent, e.g. [`NonceFactory::from_fn`] for a custom strategy.
+    /// It is called once per update call, unless the call overrides its nonce length. By
+    /// default, random 16-byte nonces are produced.
+    ///
+    /// The replica only executes an update call once, and two calls with the same content and
+    /// nonce are the same call, so nonces must be unique for each identity: a factory repeating
+    /// nonces makes such calls fail to execute, or lets a captured call be replayed in their
+    /// place.
     pub fn with_nonce_factory(self, nonce_factory: NonceFactory) -> AgentBuilder {
         self.with_nonce_generator(nonce_factory)
     }
@@ -87,4 +198,57 @@ impl AgentBuilder {
         self.config.ingress_expiry = ingress_expiry;
         self
     }
+
+    /// Use `serializer` to encode request envelopes, instead of the default
+    /// [`SerdeCborSerializer`](crate::agent::cbor::SerdeCborSerializer). Without the `serde-cbor`
+    /// feature, there is no default, and a serializer must be set.
+    pub fn with_cbor_serializer<S: 'static + CborSerializer>(self, serializer: S) -> Self {
+        self.with_arc_cbor_serializer(Arc::new(serializer))
+    }
+
+    /// Same as [Self::with_cbor_serializer], but provides a `Arc` boxed implementation instead
+    /// of a direct type.
+    pub fn with_arc_cbor_serializer(mut self, serializer: Arc<dyn CborSerializer>) -> Self {
+        self.config.cbor_serializer = Some(serializer);
+        self
+    }
+
+    /// Add a logger that is called with a [`RequestLog`] after every exchange with the replica.
+    /// This is independent of the transport, and is meant for audit logs that must record every request.
+    pub fn with_request_logger<F>(self, logger: F) -> Self
+    where
+        F: 'static + Fn(RequestLog) + Send + Sync,
+    {
+        self.with_arc_request_logger(Arc::new(logger))
+    }
+
+    /// Same as [Self::with_request_logger], but provides a `Arc` boxed implementation instead
+    /// of a direct type.
+    pub fn with_arc_request_logger(
+        mut self,
+        logger: Arc<dyn Fn(RequestLog) + Send + Sync>,
+    ) -> Self {
+        self.config.request_logger = Some(logger);
+        self
+    }
+
+    /// Retry every call to the transport that fails with a [transient](AgentError::is_transient)
+    /// error, such as a connection failure or an HTTP 5xx or 429 status, waiting longer after
+    /// each attempt as described by `config`.
+    ///
+    /// Unlike the polling done by [`Agent::wait`], this applies to every exchange with the
+    /// replica. Retrying a submitted update call sends the same signed request again, which the
+    /// replica only executes once.
+    pub fn with_exponential_backoff(mut self, config: ExponentialBackoffConfig) -> Self {
+        self.config.backoff = Some(config);
+        self
+    }
+
+    /// Verify the node signatures of query responses according to `policy`, so that a boundary
+    /// node cannot forge them. The node keys of each subnet are read from the state tree once,
+    /// and cached by the agent.
+    pub fn with_signed_query_verification(mut self, policy: SignedQueryPolicy) -> Self {
+        self.config.signed_query_policy = policy;
+        self
+    }
 }
@@ -0,0 +1,42 @@
+//! Pluggable CBOR encoding for request envelopes.
+
+use thiserror::Error;
+
+/// An error produced by a [`CborSerializer`].
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct SerializeError(pub Box<dyn std::error::Error + Send + Sync>);
+
+/// Encodes the CBOR request envelopes sent to the replica. Set with
+/// [`with_cbor_serializer`](super::AgentBuilder::with_cbor_serializer).
+///
+/// The value is passed as an [`erased_serde::Serialize`], which implements [`serde::Serialize`],
+/// so any serde-based CBOR encoder (e.g. `ciborium`) can be used. The encoder must not be
+/// human-readable, so that blobs and principals are encoded as byte strings.
+pub trait CborSerializer: Send + Sync {
+    /// Encode `value` as CBOR.
+    fn serialize(&self, value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, SerializeError>;
+}
+
+/// The default [`CborSerializer`], using [`serde_cbor`]. The output starts with the
+/// self-describing CBOR tag, as the interface specification recommends.
+///
+/// `serde_cbor` is always used to decode the responses from the replica, regardless of which
+/// serializer encodes the requests.
+#[cfg(feature = "serde-cbor")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SerdeCborSerializer;
+
+#[cfg(feature = "serde-cbor")]
+impl CborSerializer for SerdeCborSerializer {
+    fn serialize(&self, value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, SerializeError> {
+        let mut serialized_bytes = Vec::new();
+        let mut serializer = serde_cbor::Serializer::new(&mut serialized_bytes);
+        serializer
+            .self_describe()
+            .map_err(|e| SerializeError(Box::new(e)))?;
+        serde::Serialize::serialize(value, &mut serializer)
+            .map_err(|e| SerializeError(Box::new(e)))?;
+        Ok(serialized_bytes)
+    }
+}
@@ -7,6 +7,9 @@ pub mod reqwest_transport;
 #[doc(inline)]
 pub use reqwest_transport::ReqwestTransport;
 #[cfg(feature = "reqwest")]
+#[doc(inline)]
+pub use reqwest_transport::ReqwestTransportBuilder;
+#[cfg(feature = "reqwest")]
 #[doc(hidden)]
 pub use reqwest_transport::*; // deprecate after 0.24
 
@@ -20,7 +23,49 @@ pub use hyper_transport::HyperTransport;
 #[doc(hidden)]
 pub use hyper_transport::*; // deprecate after 0.24
 
+pub mod round_robin_transport;
+
+#[doc(inline)]
+pub use round_robin_transport::{RoundRobinTransport, RoutingPolicy};
+
+use crate::{
+    agent::{agent_error::HttpErrorPayload, replica_api::RejectResponse},
+    AgentError,
+};
+
+#[allow(dead_code)]
+pub(crate) const IC0_DOMAIN: &str = "ic0.app";
 #[allow(dead_code)]
-const IC0_DOMAIN: &str = "ic0.app";
+pub(crate) const IC0_SUB_DOMAIN: &str = ".ic0.app";
+
+/// The body of the response with the HTTP `status` to a request to `endpoint`, or the error
+/// it holds.
 #[allow(dead_code)]
-const IC0_SUB_DOMAIN: &str = ".ic0.app";
+pub(crate) fn response_body(
+    endpoint: &str,
+    status: u16,
+    content_type: Option<String>,
+    body: Vec<u8>,
+) -> Result<Vec<u8>, AgentError> {
+    // status == OK means we have an error message for call requests
+    // see https://internetcomputer.org/docs/current/references/ic-interface-spec#http-call
+    if status == 200 && endpoint.ends_with("call") {
+        let cbor_decoded_body: Result<RejectResponse, serde_cbor::Error> =
+            serde_cbor::from_slice(&body);
+
+        let agent_error = match cbor_decoded_body {
+            Ok(replica_error) => AgentError::ReplicaError(replica_error),
+            Err(cbor_error) => AgentError::InvalidCborData(cbor_error),
+        };
+
+        Err(agent_error)
+    } else if (400..600).contains(&status) {
+        Err(AgentError::HttpError(HttpErrorPayload {
+            status,
+            content_type,
+            content: body,
+        }))
+    } else {
+        Ok(body)
+    }
+}
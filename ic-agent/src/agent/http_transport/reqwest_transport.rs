@@ -10,46 +10,60 @@ use reqwest::{
     header::{HeaderMap, CONTENT_TYPE},
     Body, Client, Method, Request, StatusCode, Url,
 };
+use std::time::Duration;
 
 use crate::{
     agent::{
-        agent_error::HttpErrorPayload,
-        http_transport::{IC0_DOMAIN, IC0_SUB_DOMAIN},
-        replica_api::RejectResponse,
+        http_transport::{response_body, IC0_DOMAIN, IC0_SUB_DOMAIN},
         AgentFuture, Transport,
     },
     export::Principal,
     AgentError, RequestId,
 };
 
+// The default limit of the size of a response body, 50 MiB.
+const DEFAULT_MAX_RESPONSE_SIZE: usize = 50 * 1024 * 1024;
+
 /// A [`Transport`] using [`reqwest`] to make HTTP calls to the Internet Computer.
 #[derive(Debug)]
 pub struct ReqwestTransport {
     url: Url,
     client: Client,
     max_response_body_size: Option<usize>,
+    query_timeout: Option<Duration>,
+    update_timeout: Option<Duration>,
+    read_state_timeout: Option<Duration>,
 }
 
 #[doc(hidden)]
 pub use ReqwestTransport as ReqwestHttpReplicaV2Transport; // deprecate after 0.24
 
 impl ReqwestTransport {
+    /// Creates a [`ReqwestTransportBuilder`] for a transport to the replica at the HTTP URL.
+    pub fn builder<U: Into<String>>(url: U) -> ReqwestTransportBuilder {
+        ReqwestTransportBuilder {
+            url: url.into(),
+            client: None,
+            #[cfg(all(feature = "tls-client-auth", not(target_family = "wasm")))]
+            client_cert: None,
+            #[cfg(all(feature = "tls-client-auth", not(target_family = "wasm")))]
+            extra_root_cas: vec![],
+            #[cfg(all(feature = "dns-cache", not(target_family = "wasm")))]
+            dns_cache: None,
+            max_response_body_size: None,
+            query_timeout: None,
+            update_timeout: None,
+            read_state_timeout: None,
+        }
+    }
+
     /// Creates a replica transport from a HTTP URL.
     pub fn create<U: Into<String>>(url: U) -> Result<Self, AgentError> {
         #[cfg(not(target_family = "wasm"))]
         {
-            let mut tls_config = rustls::ClientConfig::builder()
-                .with_safe_defaults()
-                .with_webpki_roots()
-                .with_no_client_auth();
-
-            // Advertise support for HTTP/2
-            tls_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
-
             Self::create_with_client(
                 url,
-                Client::builder()
-                    .use_preconfigured_tls(tls_config)
+                Self::client_builder()
                     .build()
                     .expect("Could not create HTTP client."),
             )
@@ -60,6 +74,52 @@ impl ReqwestTransport {
         }
     }
 
+    /// Creates a replica transport from a HTTP URL, keeping up to `max_connections_per_host`
+    /// idle connections open to the replica instead of `reqwest`'s default. `TCP_NODELAY` is
+    /// also set on the connections.
+    ///
+    /// A larger pool lets more concurrent calls reuse open connections, but boundary nodes rate
+    /// limit requests per client, so raising it past what the boundary node allows only trades
+    /// connection setup for rate limit errors. On WASM targets, the browser manages connections
+    /// and `max_connections_per_host` is ignored.
+    pub fn create_with_max_connections_per_host<U: Into<String>>(
+        url: U,
+        max_connections_per_host: u64,
+    ) -> Result<Self, AgentError> {
+        #[cfg(not(target_family = "wasm"))]
+        {
+            Self::create_with_client(
+                url,
+                Self::client_builder()
+                    .pool_max_idle_per_host(
+                        usize::try_from(max_connections_per_host).unwrap_or(usize::MAX),
+                    )
+                    .tcp_nodelay(true)
+                    .connection_verbose(false)
+                    .build()
+                    .expect("Could not create HTTP client."),
+            )
+        }
+        #[cfg(all(target_family = "wasm", feature = "wasm-bindgen"))]
+        {
+            let _ = max_connections_per_host;
+            Self::create(url)
+        }
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    fn client_builder() -> reqwest::ClientBuilder {
+        let mut tls_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_webpki_roots()
+            .with_no_client_auth();
+
+        // Advertise support for HTTP/2
+        tls_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Client::builder().use_preconfigured_tls(tls_config)
+    }
+
     /// Creates a replica transport from a HTTP URL and a [`reqwest::Client`].
     pub fn create_with_client<U: Into<String>>(url: U, client: Client) -> Result<Self, AgentError> {
         let url = url.into();
@@ -76,11 +136,14 @@ impl ReqwestTransport {
                 })
                 .map_err(|_| AgentError::InvalidReplicaUrl(url.clone()))?,
             client,
-            max_response_body_size: None,
+            max_response_body_size: Some(DEFAULT_MAX_RESPONSE_SIZE),
+            query_timeout: None,
+            update_timeout: None,
+            read_state_timeout: None,
         })
     }
 
-    /// Sets a max response body size limit
+    /// Sets a max response body size limit, instead of the default one of 50 MiB.
     pub fn with_max_response_body_size(self, max_response_body_size: usize) -> Self {
         ReqwestTransport {
             max_response_body_size: Some(max_response_body_size),
@@ -88,6 +151,21 @@ impl ReqwestTransport {
         }
     }
 
+    /// Sets the time after which query and update requests fail with a timeout error, e.g. to
+    /// give up on queries sooner than on updates. `read_state` requests, which poll for the
+    /// result of updates, share the update timeout.
+    ///
+    /// The timeouts apply to each HTTP request, on top of any timeout of the client. A timed
+    /// out update may still be executed by the replica.
+    pub fn with_request_timeout(self, query: Duration, update: Duration) -> Self {
+        ReqwestTransport {
+            query_timeout: Some(query),
+            update_timeout: Some(update),
+            read_state_timeout: Some(update),
+            ..self
+        }
+    }
+
     async fn request(
         &self,
         http_request: Request,
@@ -102,11 +180,12 @@ impl ReqwestTransport {
         let response_headers = response.headers().clone();
 
         // Size Check (Content-Length)
-        if matches!(self
-            .max_response_body_size
-            .zip(response.content_length()), Some((size_limit, content_length)) if content_length as usize > size_limit)
+        if let Some((limit, content_length)) =
+            self.max_response_body_size.zip(response.content_length())
         {
-            return Err(AgentError::ResponseSizeExceededLimit());
+            if content_length as usize > limit {
+                return Err(AgentError::ResponseSizeExceededLimit());
+            }
         }
 
         let mut body: Vec<u8> = response
@@ -119,10 +198,10 @@ impl ReqwestTransport {
             let chunk = chunk.map_err(|x| AgentError::TransportError(Box::new(x)))?;
 
             // Size Check (Body Size)
-            if matches!(self
-                .max_response_body_size, Some(size_limit) if body.len() + chunk.len() > size_limit)
-            {
-                return Err(AgentError::ResponseSizeExceededLimit());
+            if let Some(limit) = self.max_response_body_size {
+                if body.len() + chunk.len() > limit {
+                    return Err(AgentError::ResponseSizeExceededLimit());
+                }
             }
 
             body.extend_from_slice(chunk.as_ref());
@@ -136,12 +215,19 @@ impl ReqwestTransport {
         method: Method,
         endpoint: &str,
         body: Option<Vec<u8>>,
+        timeout: Option<Duration>,
     ) -> Result<Vec<u8>, AgentError> {
         let url = self.url.join(endpoint)?;
         let mut http_request = Request::new(method, url);
         http_request
             .headers_mut()
             .insert(CONTENT_TYPE, "application/cbor".parse().unwrap());
+        #[cfg(not(target_family = "wasm"))]
+        {
+            *http_request.timeout_mut() = timeout;
+        }
+        #[cfg(target_family = "wasm")]
+        let _ = timeout;
 
         *http_request.body_mut() = body.map(Body::from);
 
@@ -150,30 +236,11 @@ impl ReqwestTransport {
         let headers = request_result.1;
         let body = request_result.2;
 
-        // status == OK means we have an error message for call requests
-        // see https://internetcomputer.org/docs/current/references/ic-interface-spec#http-call
-        if status == StatusCode::OK && endpoint.ends_with("call") {
-            let cbor_decoded_body: Result<RejectResponse, serde_cbor::Error> =
-                serde_cbor::from_slice(&body);
-
-            let agent_error = match cbor_decoded_body {
-                Ok(replica_error) => AgentError::ReplicaError(replica_error),
-                Err(cbor_error) => AgentError::InvalidCborData(cbor_error),
-            };
-
-            Err(agent_error)
-        } else if status.is_client_error() || status.is_server_error() {
-            Err(AgentError::HttpError(HttpErrorPayload {
-                status: status.into(),
-                content_type: headers
-                    .get(CONTENT_TYPE)
-                    .and_then(|value| value.to_str().ok())
-                    .map(|x| x.to_string()),
-                content: body,
-            }))
-        } else {
-            Ok(body)
-        }
+        let content_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|x| x.to_string());
+        response_body(endpoint, status.into(), content_type, body)
     }
 }
 
@@ -186,7 +253,7 @@ impl Transport for ReqwestTransport {
     ) -> AgentFuture<()> {
         Box::pin(async move {
             let endpoint = format!("canister/{}/call", effective_canister_id.to_text());
-            self.execute(Method::POST, &endpoint, Some(envelope))
+            self.execute(Method::POST, &endpoint, Some(envelope), self.update_timeout)
                 .await?;
             Ok(())
         })
@@ -199,19 +266,336 @@ impl Transport for ReqwestTransport {
     ) -> AgentFuture<Vec<u8>> {
         Box::pin(async move {
             let endpoint = format!("canister/{effective_canister_id}/read_state");
-            self.execute(Method::POST, &endpoint, Some(envelope)).await
+            self.execute(
+                Method::POST,
+                &endpoint,
+                Some(envelope),
+                self.read_state_timeout,
+            )
+            .await
         })
     }
 
     fn query(&self, effective_canister_id: Principal, envelope: Vec<u8>) -> AgentFuture<Vec<u8>> {
         Box::pin(async move {
             let endpoint = format!("canister/{effective_canister_id}/query");
-            self.execute(Method::POST, &endpoint, Some(envelope)).await
+            self.execute(Method::POST, &endpoint, Some(envelope), self.query_timeout)
+                .await
         })
     }
 
     fn status(&self) -> AgentFuture<Vec<u8>> {
-        Box::pin(async move { self.execute(Method::GET, "status", None).await })
+        Box::pin(async move { self.execute(Method::GET, "status", None, None).await })
+    }
+}
+
+/// A builder for a [`ReqwestTransport`], created with [`ReqwestTransport::builder`].
+#[derive(Debug)]
+pub struct ReqwestTransportBuilder {
+    url: String,
+    client: Option<Client>,
+    #[cfg(all(feature = "tls-client-auth", not(target_family = "wasm")))]
+    client_cert: Option<(Certificate, PrivateKey)>,
+    #[cfg(all(feature = "tls-client-auth", not(target_family = "wasm")))]
+    extra_root_cas: Vec<Certificate>,
+    #[cfg(all(feature = "dns-cache", not(target_family = "wasm")))]
+    dns_cache: Option<(Duration, usize)>,
+    max_response_body_size: Option<usize>,
+    query_timeout: Option<Duration>,
+    update_timeout: Option<Duration>,
+    read_state_timeout: Option<Duration>,
+}
+
+impl ReqwestTransportBuilder {
+    /// Makes the requests with `client` instead of the default one of
+    /// [`ReqwestTransport::create`].
+    pub fn with_client(self, client: Client) -> Self {
+        ReqwestTransportBuilder {
+            client: Some(client),
+            ..self
+        }
+    }
+
+    /// Authenticates to the replica with the client certificate `cert`, whose private key is
+    /// `key`, for deployments requiring mutual TLS. Ignored if a client is set with
+    /// [`with_client`](Self::with_client).
+    #[cfg(all(feature = "tls-client-auth", not(target_family = "wasm")))]
+    pub fn with_client_cert(self, cert: Certificate, key: PrivateKey) -> Self {
+        ReqwestTransportBuilder {
+            client_cert: Some((cert, key)),
+            ..self
+        }
+    }
+
+    /// Trusts the certificates signed by the certificate authority `cert`, e.g. the private CA
+    /// of a deployment, along with the usual web roots. Ignored if a client is set with
+    /// [`with_client`](Self::with_client).
+    #[cfg(all(feature = "tls-client-auth", not(target_family = "wasm")))]
+    pub fn with_extra_root_ca(mut self, cert: Certificate) -> Self {
+        self.extra_root_cas.push(cert);
+        self
+    }
+
+    /// Caches up to `max_entries` DNS resolutions for at most `ttl` each, so that new connections
+    /// to the same host, e.g. after idle connections were closed, do not each pay for a lookup.
+    /// Ignored if a client is set with [`with_client`](Self::with_client).
+    ///
+    /// Lookups are made with [`hickory_resolver`], configured like the system resolver. If a
+    /// lookup fails, it is made again with the system resolver, without caching its result.
+    #[cfg(all(feature = "dns-cache", not(target_family = "wasm")))]
+    pub fn with_dns_cache(self, ttl: Duration, max_entries: usize) -> Self {
+        ReqwestTransportBuilder {
+            dns_cache: Some((ttl, max_entries)),
+            ..self
+        }
+    }
+
+    /// Fails requests whose response body is larger than `max_response_body_size` with
+    /// [`AgentError::ResponseSizeExceededLimit`], without reading more of it. The default limit
+    /// is 50 MiB.
+    pub fn with_max_response_body_size(self, max_response_body_size: usize) -> Self {
+        ReqwestTransportBuilder {
+            max_response_body_size: Some(max_response_body_size),
+            ..self
+        }
+    }
+
+    /// Sets the time after which query requests fail with a timeout error.
+    pub fn with_query_timeout(self, timeout: Duration) -> Self {
+        ReqwestTransportBuilder {
+            query_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Sets the time after which update requests fail with a timeout error. A timed out update
+    /// may still be executed by the replica.
+    pub fn with_update_timeout(self, timeout: Duration) -> Self {
+        ReqwestTransportBuilder {
+            update_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Sets the time after which `read_state` requests, e.g. those polling for the result of an
+    /// update, fail with a timeout error.
+    pub fn with_read_state_timeout(self, timeout: Duration) -> Self {
+        ReqwestTransportBuilder {
+            read_state_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Creates the transport.
+    pub fn build(self) -> Result<ReqwestTransport, AgentError> {
+        let transport = match self.client {
+            Some(client) => ReqwestTransport::create_with_client(self.url, client),
+            #[cfg(not(target_family = "wasm"))]
+            None => {
+                #[cfg(feature = "tls-client-auth")]
+                let builder = if self.client_cert.is_some() || !self.extra_root_cas.is_empty() {
+                    tls::client_builder(self.client_cert, self.extra_root_cas)?
+                } else {
+                    ReqwestTransport::client_builder()
+                };
+                #[cfg(not(feature = "tls-client-auth"))]
+                let builder = ReqwestTransport::client_builder();
+                #[cfg(feature = "dns-cache")]
+                let builder = match self.dns_cache {
+                    Some((ttl, max_entries)) => builder.dns_resolver(std::sync::Arc::new(
+                        dns_cache::CachingResolver::new(ttl, max_entries),
+                    )),
+                    None => builder,
+                };
+                let client = builder
+                    .build()
+                    .map_err(|e| AgentError::TransportError(Box::new(e)))?;
+                ReqwestTransport::create_with_client(self.url, client)
+            }
+            #[cfg(target_family = "wasm")]
+            None => ReqwestTransport::create(self.url),
+        }?;
+        Ok(ReqwestTransport {
+            max_response_body_size: self
+                .max_response_body_size
+                .or(transport.max_response_body_size),
+            query_timeout: self.query_timeout,
+            update_timeout: self.update_timeout,
+            read_state_timeout: self.read_state_timeout,
+            ..transport
+        })
+    }
+}
+
+/// A DER-encoded X.509 certificate, for [`ReqwestTransportBuilder::with_client_cert`] and
+/// [`ReqwestTransportBuilder::with_extra_root_ca`].
+#[cfg(all(feature = "tls-client-auth", not(target_family = "wasm")))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Certificate(pub Vec<u8>);
+
+/// A DER-encoded private key, in the PKCS#8, PKCS#1 or SEC1 format, for
+/// [`ReqwestTransportBuilder::with_client_cert`].
+#[cfg(all(feature = "tls-client-auth", not(target_family = "wasm")))]
+#[derive(Clone, PartialEq, Eq)]
+pub struct PrivateKey(pub Vec<u8>);
+
+#[cfg(all(feature = "tls-client-auth", not(target_family = "wasm")))]
+impl std::fmt::Debug for PrivateKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PrivateKey").finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(feature = "tls-client-auth", not(target_family = "wasm")))]
+mod tls {
+    use super::{Certificate, PrivateKey};
+    use crate::AgentError;
+    use reqwest::{Client, ClientBuilder};
+    use rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore};
+
+    fn tls_error(e: rustls::Error) -> AgentError {
+        AgentError::TransportError(Box::new(e))
+    }
+
+    /// A client builder trusting the web roots and `extra_root_cas`, and authenticating with
+    /// `client_cert` if set.
+    pub(super) fn client_builder(
+        client_cert: Option<(Certificate, PrivateKey)>,
+        extra_root_cas: Vec<Certificate>,
+    ) -> Result<ClientBuilder, AgentError> {
+        let mut roots = RootCertStore::empty();
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        }));
+        for cert in extra_root_cas {
+            roots.add(&rustls::Certificate(cert.0)).map_err(tls_error)?;
+        }
+
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+        let mut tls_config = match client_cert {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(vec![rustls::Certificate(cert.0)], rustls::PrivateKey(key.0))
+                .map_err(tls_error)?,
+            None => builder.with_no_client_auth(),
+        };
+
+        // Advertise support for HTTP/2
+        tls_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(Client::builder().use_preconfigured_tls(tls_config))
+    }
+}
+
+#[cfg(all(feature = "dns-cache", not(target_family = "wasm")))]
+mod dns_cache {
+    use hickory_resolver::{
+        config::{ResolverConfig, ResolverOpts},
+        TokioAsyncResolver,
+    };
+    use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+    use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+    /// A [`Resolve`] caching the addresses returned by a [`TokioAsyncResolver`], and falling back
+    /// to the system resolver if it fails.
+    pub(super) struct CachingResolver {
+        resolver: Arc<TokioAsyncResolver>,
+    }
+
+    /// The options of a resolver caching up to `max_entries` resolutions for at most `ttl`.
+    fn options(ttl: Duration, max_entries: usize) -> ResolverOpts {
+        let mut opts = ResolverOpts::default();
+        opts.cache_size = max_entries;
+        opts.positive_max_ttl = Some(ttl);
+        opts.negative_max_ttl = Some(ttl);
+        opts
+    }
+
+    impl CachingResolver {
+        /// A resolver with the configuration of the system resolver, or the default one if it
+        /// cannot be read.
+        pub(super) fn new(ttl: Duration, max_entries: usize) -> Self {
+            let config = hickory_resolver::system_conf::read_system_conf()
+                .map(|(config, _)| config)
+                .unwrap_or_default();
+            Self::with_config(config, options(ttl, max_entries))
+        }
+
+        fn with_config(config: ResolverConfig, opts: ResolverOpts) -> Self {
+            Self {
+                resolver: Arc::new(TokioAsyncResolver::tokio(config, opts)),
+            }
+        }
+    }
+
+    impl Resolve for CachingResolver {
+        fn resolve(&self, name: Name) -> Resolving {
+            let resolver = self.resolver.clone();
+            Box::pin(async move {
+                // The port is replaced by the one of the URL being connected to.
+                let addrs: Vec<SocketAddr> = match resolver.lookup_ip(name.as_str()).await {
+                    Ok(lookup) => lookup.iter().map(|ip| SocketAddr::new(ip, 0)).collect(),
+                    Err(_) => tokio::net::lookup_host((name.as_str(), 0)).await?.collect(),
+                };
+                let addrs: Addrs = Box::new(addrs.into_iter());
+                Ok(addrs)
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use hickory_resolver::config::NameServerConfigGroup;
+        use std::{net::IpAddr, str::FromStr};
+
+        async fn resolve(resolver: &CachingResolver, name: &str) -> Vec<IpAddr> {
+            resolver
+                .resolve(Name::from_str(name).unwrap())
+                .await
+                .unwrap()
+                .map(|addr| addr.ip())
+                .collect()
+        }
+
+        /// A resolver without name servers, so that it never makes a DNS query.
+        fn offline_resolver() -> CachingResolver {
+            let mut opts = options(Duration::from_secs(60), 16);
+            opts.use_hosts_file = false;
+            let config = ResolverConfig::from_parts(None, vec![], NameServerConfigGroup::new());
+            CachingResolver::with_config(config, opts)
+        }
+
+        #[test]
+        fn cache_options() {
+            let opts = options(Duration::from_secs(30), 128);
+            assert_eq!(opts.cache_size, 128);
+            assert_eq!(opts.positive_max_ttl, Some(Duration::from_secs(30)));
+        }
+
+        #[tokio::test]
+        async fn resolves_ip_literals() {
+            let resolver = offline_resolver();
+            assert_eq!(
+                resolve(&resolver, "127.0.0.1").await,
+                [IpAddr::from([127, 0, 0, 1])]
+            );
+        }
+
+        #[tokio::test]
+        async fn falls_back_to_system_resolver() {
+            // The caching resolver cannot resolve any name, and the system resolver finds
+            // `localhost` in the hosts file.
+            let resolver = offline_resolver();
+            let addrs = resolve(&resolver, "localhost").await;
+            assert!(!addrs.is_empty());
+            assert!(addrs.iter().all(IpAddr::is_loopback));
+        }
     }
 }
 
@@ -223,6 +607,8 @@ mod test {
     wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
 
     use super::ReqwestTransport;
+    #[cfg(not(target_family = "wasm"))]
+    use crate::{agent::Transport, export::Principal, AgentError};
 
     #[cfg_attr(not(target_family = "wasm"), test)]
     #[cfg_attr(target_family = "wasm", wasm_bindgen_test)]
@@ -249,4 +635,174 @@ mod test {
         test("https://fooic0.app", "https://fooic0.app/api/v2/");
         test("https://fooic0.app.ic0.app", "https://ic0.app/api/v2/");
     }
+
+    /// Start a server answering every request with `response` after `delay`, and return its URL.
+    #[cfg(not(target_family = "wasm"))]
+    async fn serve(response: &'static [u8], delay: std::time::Duration) -> String {
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpListener,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buf = [0; 4096];
+                    let _ = socket.read(&mut buf).await;
+                    tokio::time::sleep(delay).await;
+                    let _ = socket.write_all(response).await;
+                });
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn request_timeout() {
+        use std::time::{Duration, Instant};
+
+        // A server that takes a second to answer every request.
+        let url = serve(
+            b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n",
+            Duration::from_secs(1),
+        )
+        .await;
+
+        let transport = ReqwestTransport::builder(url)
+            .with_query_timeout(Duration::from_millis(100))
+            .with_update_timeout(Duration::from_secs(10))
+            .build()
+            .unwrap();
+        let canister_id = Principal::management_canister();
+
+        let start = Instant::now();
+        let err = transport.query(canister_id, vec![]).await.unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        match err {
+            AgentError::TransportError(e) => {
+                assert!(e.downcast_ref::<reqwest::Error>().unwrap().is_timeout())
+            }
+            e => panic!("expected a timeout, got {e:?}"),
+        }
+
+        transport
+            .read_state(canister_id, vec![])
+            .await
+            .expect("read_state requests have no timeout");
+        transport
+            .with_request_timeout(Duration::from_secs(10), Duration::from_millis(100))
+            .read_state(canister_id, vec![])
+            .await
+            .unwrap_err();
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn max_response_body_size() {
+        use std::time::Duration;
+
+        let canister_id = Principal::management_canister();
+        let with_length = serve(
+            b"HTTP/1.1 200 OK\r\ncontent-length: 20\r\n\r\n01234567890123456789",
+            Duration::ZERO,
+        )
+        .await;
+        let chunked = serve(
+            b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n14\r\n01234567890123456789\r\n0\r\n\r\n",
+            Duration::ZERO,
+        )
+        .await;
+
+        for url in [with_length, chunked] {
+            let transport = ReqwestTransport::builder(url.clone())
+                .with_max_response_body_size(10)
+                .build()
+                .unwrap();
+            assert!(matches!(
+                transport.query(canister_id, vec![]).await,
+                Err(AgentError::ResponseSizeExceededLimit())
+            ));
+
+            let transport = ReqwestTransport::create(url).unwrap();
+            assert_eq!(
+                transport.query(canister_id, vec![]).await.unwrap().len(),
+                20
+            );
+        }
+    }
+
+    #[cfg(all(feature = "tls-client-auth", not(target_family = "wasm")))]
+    #[tokio::test]
+    async fn client_cert() {
+        use super::{Certificate, PrivateKey};
+        use rustls::server::AllowAnyAuthenticatedClient;
+        use std::sync::Arc;
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpListener,
+        };
+
+        // A CA, and a server and a client certificate signed by it, generated with `openssl`.
+        const CA: &[u8] = include_bytes!("testdata/ca.crt.der");
+        const SERVER_CERT: &[u8] = include_bytes!("testdata/server.crt.der");
+        const SERVER_KEY: &[u8] = include_bytes!("testdata/server.key.der");
+        const CLIENT_CERT: &[u8] = include_bytes!("testdata/client.crt.der");
+        const CLIENT_KEY: &[u8] = include_bytes!("testdata/client.key.der");
+
+        // A server only accepting clients with a certificate signed by the CA.
+        let mut client_roots = rustls::RootCertStore::empty();
+        client_roots.add(&rustls::Certificate(CA.to_vec())).unwrap();
+        let config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(client_roots).boxed())
+            .with_single_cert(
+                vec![rustls::Certificate(SERVER_CERT.to_vec())],
+                rustls::PrivateKey(SERVER_KEY.to_vec()),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(mut stream) = acceptor.accept(socket).await else {
+                        return;
+                    };
+                    let mut buf = [0; 4096];
+                    let _ = stream.read(&mut buf).await;
+                    let _ = stream
+                        .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 6\r\n\r\nstatus")
+                        .await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+        let url = format!("https://localhost:{port}");
+
+        let transport = ReqwestTransport::builder(url.clone())
+            .with_extra_root_ca(Certificate(CA.to_vec()))
+            .with_client_cert(
+                Certificate(CLIENT_CERT.to_vec()),
+                PrivateKey(CLIENT_KEY.to_vec()),
+            )
+            .build()
+            .unwrap();
+        assert_eq!(transport.status().await.unwrap(), b"status");
+
+        let without_cert = ReqwestTransport::builder(url)
+            .with_extra_root_ca(Certificate(CA.to_vec()))
+            .build()
+            .unwrap();
+        assert!(matches!(
+            without_cert.status().await,
+            Err(AgentError::TransportError(_))
+        ));
+    }
 }
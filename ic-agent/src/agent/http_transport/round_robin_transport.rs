@@ -0,0 +1,329 @@
+//! A [`Transport`] that spreads requests over several boundary nodes.
+
+use crate::{
+    agent::{request_log::now, AgentFuture, Transport},
+    export::Principal,
+    AgentError, RequestId,
+};
+use rand::Rng;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use url::Url;
+
+/// How a [`RoundRobinTransport`] picks the URL to send each request to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoutingPolicy {
+    /// Cycle through the URLs in order.
+    #[default]
+    RoundRobin,
+    /// Pick a URL at random.
+    Random,
+    /// Pick the URL with the lowest average latency. URLs that have not been used yet are
+    /// picked first.
+    LeastLatency,
+}
+
+/// The recent failures of one of the URLs of a [`RoundRobinTransport`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ErrorCount {
+    /// The number of requests that failed since the last successful one.
+    pub count: u32,
+    /// When the last request failed, as a duration since the Unix epoch.
+    pub last_error: Option<Duration>,
+}
+
+/// A [`Transport`] that sends each request to one of several transports, e.g. one per boundary
+/// node, according to a [`RoutingPolicy`].
+///
+/// A URL whose transport failed to get a response (a transport error, or an HTTP 5xx status)
+/// is skipped for the duration of the error cooldown, 10 seconds by default. Replica rejections
+/// do not count as failures. If every URL failed recently, all of them are used.
+pub struct RoundRobinTransport {
+    transports: Vec<(Url, Arc<dyn Transport>)>,
+    policy: RoutingPolicy,
+    error_cooldown: Duration,
+    next: AtomicUsize,
+    errors: Arc<Mutex<HashMap<Url, ErrorCount>>>,
+    latencies: Mutex<HashMap<Url, Duration>>,
+}
+
+impl RoundRobinTransport {
+    /// Creates a transport routing requests to `transports`, each identified by its URL.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `transports` is empty.
+    pub fn new(transports: Vec<(Url, Arc<dyn Transport>)>) -> Self {
+        assert!(
+            !transports.is_empty(),
+            "RoundRobinTransport requires at least one transport"
+        );
+        Self {
+            transports,
+            policy: RoutingPolicy::default(),
+            error_cooldown: Duration::from_secs(10),
+            next: AtomicUsize::new(0),
+            errors: Arc::default(),
+            latencies: Mutex::default(),
+        }
+    }
+
+    /// Sets the [`RoutingPolicy`] of this transport.
+    pub fn with_routing_policy(self, policy: RoutingPolicy) -> Self {
+        Self { policy, ..self }
+    }
+
+    /// Sets how long a URL is skipped after a failed request.
+    pub fn with_error_cooldown(self, error_cooldown: Duration) -> Self {
+        Self {
+            error_cooldown,
+            ..self
+        }
+    }
+
+    /// The recent failures of each URL. URLs whose last request succeeded are not included.
+    pub fn error_counts(&self) -> HashMap<Url, ErrorCount> {
+        self.errors.lock().unwrap().clone()
+    }
+
+    /// Picks the index of the transport to send the next request to.
+    fn select(&self) -> usize {
+        let now = now();
+        let healthy: Vec<usize> = {
+            let errors = self.errors.lock().unwrap();
+            (0..self.transports.len())
+                .filter(|&i| {
+                    errors
+                        .get(&self.transports[i].0)
+                        .and_then(|e| e.last_error)
+                        .map_or(true, |t| now.saturating_sub(t) >= self.error_cooldown)
+                })
+                .collect()
+        };
+        let candidates = if healthy.is_empty() {
+            (0..self.transports.len()).collect()
+        } else {
+            healthy
+        };
+
+        match self.policy {
+            RoutingPolicy::RoundRobin => {
+                candidates[self.next.fetch_add(1, Ordering::Relaxed) % candidates.len()]
+            }
+            RoutingPolicy::Random => candidates[rand::thread_rng().gen_range(0..candidates.len())],
+            RoutingPolicy::LeastLatency => {
+                let latencies = self.latencies.lock().unwrap();
+                candidates
+                    .into_iter()
+                    .min_by_key(|&i| {
+                        latencies
+                            .get(&self.transports[i].0)
+                            .copied()
+                            .unwrap_or_default()
+                    })
+                    .unwrap()
+            }
+        }
+    }
+
+    /// Records the outcome of a request sent to the transport at `index`.
+    fn record<T>(&self, index: usize, start: Duration, result: &Result<T, AgentError>) {
+        let url = &self.transports[index].0;
+        let end = now();
+        let failed = match result {
+            Err(AgentError::TransportError(_)) => true,
+            Err(AgentError::HttpError(payload)) => payload.status >= 500,
+            _ => false,
+        };
+        if failed {
+            let mut errors = self.errors.lock().unwrap();
+            let error_count = errors.entry(url.clone()).or_default();
+            error_count.count += 1;
+            error_count.last_error = Some(end);
+        } else {
+            self.errors.lock().unwrap().remove(url);
+            // An exponential moving average, so that a single slow request does not disqualify a URL.
+            let sample = end.saturating_sub(start);
+            self.latencies
+                .lock()
+                .unwrap()
+                .entry(url.clone())
+                .and_modify(|latency| *latency = (*latency * 7 + sample) / 8)
+                .or_insert(sample);
+        }
+    }
+
+    fn route<'a, T: 'a>(
+        &'a self,
+        send: impl FnOnce(&'a dyn Transport) -> AgentFuture<'a, T>,
+    ) -> AgentFuture<'a, T> {
+        let index = self.select();
+        let future = send(self.transports[index].1.as_ref());
+        Box::pin(async move {
+            let start = now();
+            let result = future.await;
+            self.record(index, start, &result);
+            result
+        })
+    }
+}
+
+impl fmt::Debug for RoundRobinTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RoundRobinTransport")
+            .field(
+                "urls",
+                &self
+                    .transports
+                    .iter()
+                    .map(|(url, _)| url)
+                    .collect::<Vec<_>>(),
+            )
+            .field("policy", &self.policy)
+            .field("error_cooldown", &self.error_cooldown)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Transport for RoundRobinTransport {
+    fn call(
+        &self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+        request_id: RequestId,
+    ) -> AgentFuture<()> {
+        self.route(move |transport| transport.call(effective_canister_id, envelope, request_id))
+    }
+
+    fn read_state(
+        &self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> AgentFuture<Vec<u8>> {
+        self.route(move |transport| transport.read_state(effective_canister_id, envelope))
+    }
+
+    fn query(&self, effective_canister_id: Principal, envelope: Vec<u8>) -> AgentFuture<Vec<u8>> {
+        self.route(move |transport| transport.query(effective_canister_id, envelope))
+    }
+
+    fn status(&self) -> AgentFuture<Vec<u8>> {
+        self.route(|transport| transport.status())
+    }
+
+    fn replica_time(&self) -> AgentFuture<u64> {
+        self.route(|transport| transport.replica_time())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    struct MockTransport {
+        failing: AtomicBool,
+        requests: AtomicUsize,
+    }
+
+    impl MockTransport {
+        fn new(failing: bool) -> Arc<Self> {
+            Arc::new(Self {
+                failing: AtomicBool::new(failing),
+                requests: AtomicUsize::new(0),
+            })
+        }
+
+        fn respond(&self) -> AgentFuture<Vec<u8>> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            let failing = self.failing.load(Ordering::SeqCst);
+            Box::pin(async move {
+                if failing {
+                    Err(AgentError::TransportError("unreachable".into()))
+                } else {
+                    Ok(vec![])
+                }
+            })
+        }
+    }
+
+    impl Transport for MockTransport {
+        fn call(&self, _: Principal, _: Vec<u8>, _: RequestId) -> AgentFuture<()> {
+            Box::pin(async { Ok(()) })
+        }
+        fn read_state(&self, _: Principal, _: Vec<u8>) -> AgentFuture<Vec<u8>> {
+            self.respond()
+        }
+        fn query(&self, _: Principal, _: Vec<u8>) -> AgentFuture<Vec<u8>> {
+            self.respond()
+        }
+        fn status(&self) -> AgentFuture<Vec<u8>> {
+            self.respond()
+        }
+        // Not derived from the (empty) status, to check that wrapping transports forward it.
+        fn replica_time(&self) -> AgentFuture<u64> {
+            Box::pin(async { Ok(42) })
+        }
+    }
+
+    #[cfg_attr(not(target_family = "wasm"), tokio::test)]
+    #[cfg_attr(target_family = "wasm", wasm_bindgen_test::wasm_bindgen_test)]
+    async fn skips_failing_urls() {
+        let a = MockTransport::new(false);
+        let b = MockTransport::new(true);
+        let url_b = Url::parse("https://b.example.com").unwrap();
+        let transport = RoundRobinTransport::new(vec![
+            (Url::parse("https://a.example.com").unwrap(), a.clone()),
+            (url_b.clone(), b.clone()),
+        ]);
+
+        assert!(transport.status().await.is_ok());
+        assert!(transport.status().await.is_err());
+        assert_eq!(transport.error_counts()[&url_b].count, 1);
+
+        for _ in 0..4 {
+            assert!(transport.status().await.is_ok());
+        }
+        assert_eq!(a.requests.load(Ordering::SeqCst), 5);
+        assert_eq!(b.requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[cfg_attr(not(target_family = "wasm"), tokio::test)]
+    #[cfg_attr(target_family = "wasm", wasm_bindgen_test::wasm_bindgen_test)]
+    async fn forwards_replica_time() {
+        use crate::agent::{
+            request_log::LoggedTransport,
+            retry::{ExponentialBackoffConfig, RetryingTransport},
+        };
+
+        let round_robin = Arc::new(RoundRobinTransport::new(vec![(
+            Url::parse("https://a.example.com").unwrap(),
+            MockTransport::new(false),
+        )]));
+        assert_eq!(round_robin.replica_time().await.unwrap(), 42);
+
+        let logs = Arc::new(AtomicUsize::new(0));
+        let counter = logs.clone();
+        let logged = LoggedTransport {
+            inner: round_robin.clone(),
+            logger: Arc::new(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }),
+        };
+        assert_eq!(logged.replica_time().await.unwrap(), 42);
+        assert_eq!(logs.load(Ordering::SeqCst), 1);
+
+        let retrying = RetryingTransport {
+            inner: round_robin,
+            config: ExponentialBackoffConfig::default(),
+        };
+        assert_eq!(retrying.replica_time().await.unwrap(), 42);
+    }
+}
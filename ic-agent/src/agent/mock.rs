@@ -0,0 +1,360 @@
+//! A [`Transport`] that replies with canned responses, to test code that calls canisters
+//! without a replica.
+//!
+//! ```
+//! # use ic_agent::agent::mock::{MockAgent, MockTransport, Response};
+//! # use ic_agent::export::Principal;
+//! # async fn example() -> Result<(), ic_agent::AgentError> {
+//! let canister_id = Principal::management_canister();
+//! let agent = MockAgent::new(
+//!     MockTransport::new().expect_query(canister_id, Response::query_reply(candid::encode_one("hello").unwrap())),
+//! );
+//! let reply = agent.query(&canister_id, "greet").call().await?;
+//! assert_eq!(candid::decode_one::<String>(&reply).unwrap(), "hello");
+//! agent.verify_all_called();
+//! # Ok(())
+//! # }
+//! ```
+use crate::{
+    agent::{
+        replica_api::{CallReply, QueryResponse},
+        AgentFuture, RejectCode, RejectResponse, Transport,
+    },
+    export::Principal,
+    Agent, AgentError, RequestId,
+};
+use std::{
+    fmt,
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
+
+/// Which requests an expectation of a [`MockTransport`] applies to.
+#[derive(Clone)]
+pub enum Matcher {
+    /// Requests to an endpoint, relative to `/api/v2/`, e.g. `canister/aaaaa-aa/query` or `status`.
+    Path(String),
+    /// Requests whose CBOR envelope starts with these bytes.
+    BodyPrefix(Vec<u8>),
+    /// Requests for which the closure, called with the path and the envelope, returns `true`.
+    Custom(Arc<MatchFn>),
+}
+
+/// The closure of a [`Matcher::Custom`].
+type MatchFn = dyn Fn(&str, &[u8]) -> bool + Send + Sync;
+
+impl Matcher {
+    fn matches(&self, path: &str, body: &[u8]) -> bool {
+        match self {
+            Matcher::Path(p) => p == path,
+            Matcher::BodyPrefix(prefix) => body.starts_with(prefix),
+            Matcher::Custom(f) => f(path, body),
+        }
+    }
+}
+
+impl fmt::Debug for Matcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Matcher::Path(path) => f.debug_tuple("Path").field(path).finish(),
+            Matcher::BodyPrefix(prefix) => f.debug_tuple("BodyPrefix").field(prefix).finish(),
+            Matcher::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// What a [`MockTransport`] returns for a matched request.
+#[derive(Clone)]
+pub enum Response {
+    /// A response body. It is ignored for `call` requests, which have none.
+    Bytes(Vec<u8>),
+    /// An error, created anew for every matched request since [`AgentError`] is not [`Clone`].
+    Error(Arc<dyn Fn() -> AgentError + Send + Sync>),
+}
+
+impl Response {
+    /// The body of a query replied to with the Candid-encoded `arg`.
+    pub fn query_reply(arg: Vec<u8>) -> Self {
+        let response = QueryResponse::Replied {
+            reply: CallReply { arg },
+        };
+        Response::Bytes(serde_cbor::to_vec(&response).expect("a query reply is serializable"))
+    }
+
+    /// The body of a query rejected with `reject_code` and `reject_message`.
+    pub fn query_reject(reject_code: RejectCode, reject_message: impl Into<String>) -> Self {
+        let response = QueryResponse::Rejected(RejectResponse {
+            reject_code,
+            reject_message: reject_message.into(),
+            error_code: None,
+        });
+        Response::Bytes(serde_cbor::to_vec(&response).expect("a query reject is serializable"))
+    }
+
+    /// An error returned by the transport.
+    pub fn error(error: impl Fn() -> AgentError + Send + Sync + 'static) -> Self {
+        Response::Error(Arc::new(error))
+    }
+}
+
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Response::Bytes(bytes) => f.debug_tuple("Bytes").field(bytes).finish(),
+            Response::Error(error) => f.debug_tuple("Error").field(&error()).finish(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Expectation {
+    matcher: Matcher,
+    response: Response,
+    calls: usize,
+}
+
+/// A [`Transport`] that returns the response of the first expectation matching each request,
+/// and fails requests that match none with [`AgentError::MessageError`].
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    expectations: Mutex<Vec<Expectation>>,
+}
+
+impl MockTransport {
+    /// Create a transport without any expectation.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reply to the requests matching `matcher` with `response`.
+    pub fn expect(self, matcher: Matcher, response: Response) -> Self {
+        self.expectations.lock().unwrap().push(Expectation {
+            matcher,
+            response,
+            calls: 0,
+        });
+        self
+    }
+
+    /// Answer update calls to `effective_canister_id` with `response`, which is only used if
+    /// it is an error.
+    pub fn expect_update(self, effective_canister_id: Principal, response: Response) -> Self {
+        self.expect(
+            Matcher::Path(format!("canister/{effective_canister_id}/call")),
+            response,
+        )
+    }
+
+    /// Reply to queries to `effective_canister_id` with `response`, e.g. a
+    /// [`Response::query_reply`].
+    pub fn expect_query(self, effective_canister_id: Principal, response: Response) -> Self {
+        self.expect(
+            Matcher::Path(format!("canister/{effective_canister_id}/query")),
+            response,
+        )
+    }
+
+    /// Reply to `read_state` requests to `effective_canister_id` with `response`. The agent
+    /// verifies the certificate in the response, so it must be signed by the agent's root key.
+    pub fn expect_read_state(self, effective_canister_id: Principal, response: Response) -> Self {
+        self.expect(
+            Matcher::Path(format!("canister/{effective_canister_id}/read_state")),
+            response,
+        )
+    }
+
+    /// Panic if any expectation was never matched.
+    pub fn verify_all_called(&self) {
+        let uncalled = self
+            .expectations
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|expectation| expectation.calls == 0)
+            .map(|expectation| format!("{:?}", expectation.matcher))
+            .collect::<Vec<_>>();
+        assert!(
+            uncalled.is_empty(),
+            "expected requests were not made: {}",
+            uncalled.join(", ")
+        );
+    }
+
+    fn respond(&self, path: String, body: &[u8]) -> Result<Vec<u8>, AgentError> {
+        let mut expectations = self.expectations.lock().unwrap();
+        let expectation = expectations
+            .iter_mut()
+            .find(|expectation| expectation.matcher.matches(&path, body))
+            .ok_or_else(|| AgentError::MessageError(format!("unexpected request to {path}")))?;
+        expectation.calls += 1;
+        match &expectation.response {
+            Response::Bytes(bytes) => Ok(bytes.clone()),
+            Response::Error(error) => Err(error()),
+        }
+    }
+}
+
+impl Transport for MockTransport {
+    fn call(
+        &self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+        _request_id: RequestId,
+    ) -> AgentFuture<()> {
+        let result = self
+            .respond(format!("canister/{effective_canister_id}/call"), &envelope)
+            .map(|_| ());
+        Box::pin(async move { result })
+    }
+
+    fn read_state(
+        &self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> AgentFuture<Vec<u8>> {
+        let result = self.respond(
+            format!("canister/{effective_canister_id}/read_state"),
+            &envelope,
+        );
+        Box::pin(async move { result })
+    }
+
+    fn query(&self, effective_canister_id: Principal, envelope: Vec<u8>) -> AgentFuture<Vec<u8>> {
+        let result = self.respond(format!("canister/{effective_canister_id}/query"), &envelope);
+        Box::pin(async move { result })
+    }
+
+    fn status(&self) -> AgentFuture<Vec<u8>> {
+        let result = self.respond("status".to_string(), &[]);
+        Box::pin(async move { result })
+    }
+}
+
+/// An [`Agent`] using a [`MockTransport`], which it dereferences to.
+#[derive(Debug, Clone)]
+pub struct MockAgent {
+    agent: Agent,
+    transport: Arc<MockTransport>,
+}
+
+impl MockAgent {
+    /// Create an agent with the anonymous identity that sends its requests to `transport`.
+    pub fn new(transport: MockTransport) -> Self {
+        let transport = Arc::new(transport);
+        let agent = Agent::builder()
+            .with_arc_transport(transport.clone())
+            .build()
+            .expect("an agent with a transport can be built");
+        Self { agent, transport }
+    }
+
+    /// The agent, e.g. to build a canister with.
+    pub fn agent(&self) -> &Agent {
+        &self.agent
+    }
+
+    /// Panic if any expectation of the transport was never matched.
+    pub fn verify_all_called(&self) {
+        self.transport.verify_all_called()
+    }
+}
+
+impl Deref for MockAgent {
+    type Target = Agent;
+    fn deref(&self) -> &Self::Target {
+        &self.agent
+    }
+}
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod tests {
+    use super::{MockAgent, MockTransport, Response};
+    use crate::{
+        agent::{NonceFactory, RejectCode, SequentialNonce},
+        export::Principal,
+        Agent, AgentError,
+    };
+
+    #[tokio::test]
+    async fn query() {
+        let canister_id = Principal::management_canister();
+        let agent = MockAgent::new(
+            MockTransport::new()
+                .expect_query(canister_id, Response::query_reply(vec![1, 2, 3]))
+                .expect_update(
+                    canister_id,
+                    Response::error(|| AgentError::MessageError("unavailable".to_string())),
+                ),
+        );
+
+        let reply = agent.query(&canister_id, "greet").call().await.unwrap();
+        assert_eq!(reply, vec![1, 2, 3]);
+        assert!(matches!(
+            agent.update(&canister_id, "greet").call().await,
+            Err(AgentError::MessageError(message)) if message == "unavailable"
+        ));
+        assert!(matches!(
+            agent.status().await,
+            Err(AgentError::MessageError(_))
+        ));
+        agent.verify_all_called();
+    }
+
+    #[tokio::test]
+    async fn query_reject() {
+        let canister_id = Principal::management_canister();
+        let agent = MockAgent::new(MockTransport::new().expect_query(
+            canister_id,
+            Response::query_reject(RejectCode::CanisterReject, "no"),
+        ));
+
+        assert!(matches!(
+            agent.query(&canister_id, "greet").call().await,
+            Err(AgentError::ReplicaError(response)) if response.reject_message == "no"
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "expected requests were not made")]
+    fn verify_all_called() {
+        let canister_id = Principal::management_canister();
+        MockAgent::new(
+            MockTransport::new().expect_query(canister_id, Response::query_reply(vec![])),
+        )
+        .verify_all_called();
+    }
+
+    #[test]
+    fn sequential_nonce() {
+        let canister_id = Principal::management_canister();
+        let agent = Agent::builder()
+            .with_transport(MockTransport::new())
+            .with_nonce_factory(NonceFactory::incrementing())
+            .build()
+            .unwrap();
+        for expected in [0u64, 1] {
+            let signed = agent.update(&canister_id, "greet").sign().unwrap();
+            assert_eq!(signed.nonce, Some(expected.to_le_bytes().to_vec()));
+        }
+
+        // Consecutive sequential nonces are big-endian increments, carrying into the byte
+        // before the last one.
+        let agent = Agent::builder()
+            .with_transport(MockTransport::new())
+            .with_nonce_generator(SequentialNonce::new(255))
+            .build()
+            .unwrap();
+        for expected in [[0, 0, 0, 0, 0, 0, 0, 255], [0, 0, 0, 0, 0, 0, 1, 0]] {
+            let signed = agent.update(&canister_id, "greet").sign().unwrap();
+            assert_eq!(signed.nonce, Some(expected.to_vec()));
+        }
+
+        let agent = Agent::builder()
+            .with_transport(MockTransport::new())
+            .with_nonce_factory(NonceFactory::from_fn(|| b"fixed".to_vec()))
+            .build()
+            .unwrap();
+        let signed = agent.update(&canister_id, "greet").sign().unwrap();
+        assert_eq!(signed.nonce, Some(b"fixed".to_vec()));
+    }
+}
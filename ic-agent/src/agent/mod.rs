@@ -2,20 +2,39 @@
 pub(crate) mod agent_config;
 pub mod agent_error;
 pub(crate) mod builder;
+pub mod cbor;
 pub mod http_transport;
+#[cfg(any(test, feature = "testing"))]
+pub mod mock;
 pub(crate) mod nonce;
+pub(crate) mod polling;
 pub(crate) mod replica_api;
+pub(crate) mod request_log;
 pub(crate) mod response;
 pub(crate) mod response_authentication;
+pub(crate) mod retry;
 pub mod signed;
+pub(crate) mod signed_query;
 pub mod status;
+pub mod subnet;
+#[cfg(all(feature = "unix", unix))]
+pub mod unix_transport;
+pub mod unverified;
+#[cfg(all(feature = "wasm", target_family = "wasm"))]
+pub mod wasm_transport;
 
 pub use agent_config::AgentConfig;
 pub use agent_error::AgentError;
 pub use builder::AgentBuilder;
-pub use nonce::{NonceFactory, NonceGenerator};
+pub use cbor::CborSerializer;
+pub use nonce::{NonceFactory, NonceGenerator, SequentialNonce};
+pub use polling::{PollSchedule, PollStrategy, WaitLimits};
 pub use replica_api::{RejectCode, RejectResponse};
+pub use request_log::RequestLog;
 pub use response::{Replied, RequestStatusResponse};
+pub use retry::ExponentialBackoffConfig;
+pub use signed_query::SignedQueryPolicy;
+pub use unverified::UnverifiedAgent;
 
 #[cfg(test)]
 mod agent_test;
@@ -25,32 +44,39 @@ use crate::{
         replica_api::{
             CallRequestContent, Envelope, QueryContent, ReadStateContent, ReadStateResponse,
         },
+        request_log::LoggedTransport,
         response_authentication::{
             extract_der, lookup_canister_info, lookup_canister_metadata, lookup_request_status,
             lookup_value,
         },
+        retry::RetryingTransport,
+        signed_query::{verify_query_signatures, QuerySignatures, SubnetKeys},
     },
     export::Principal,
     identity::Identity,
     to_request_id, RequestId,
 };
-use backoff::{backoff::Backoff, ExponentialBackoffBuilder};
-use ic_certification::{Certificate, Delegation, Label};
+use ic_certification::{Certificate, CertificateChain, CertificateError, Delegation, Label};
 use serde::Serialize;
 use status::Status;
 use std::{
+    collections::HashMap,
     convert::TryFrom,
     fmt,
     future::Future,
     pin::Pin,
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, RwLock},
     task::{Context, Poll},
     time::Duration,
 };
+use subnet::SubnetMetrics;
 
 const IC_REQUEST_DOMAIN_SEPARATOR: &[u8; 11] = b"\x0Aic-request";
 const IC_STATE_ROOT_DOMAIN_SEPARATOR: &[u8; 14] = b"\x0Dic-state-root";
 
+// The maximum length of the nonce of a request allowed by the replica.
+const MAX_NONCE_LEN: usize = 32;
+
 const IC_ROOT_KEY: &[u8; 133] = b"\x30\x81\x82\x30\x1d\x06\x0d\x2b\x06\x01\x04\x01\x82\xdc\x7c\x05\x03\x01\x02\x01\x06\x0c\x2b\x06\x01\x04\x01\x82\xdc\x7c\x05\x03\x02\x01\x03\x61\x00\x81\x4c\x0e\x6e\xc7\x1f\xab\x58\x3b\x08\xbd\x81\x37\x3c\x25\x5c\x3c\x37\x1b\x2e\x84\x86\x3c\x98\xa4\xf1\xe0\x8b\x74\x23\x5d\x14\xfb\x5d\x9c\x0c\xd5\x46\xd9\x68\x5f\x91\x3a\x0c\x0b\x2c\xc5\x34\x15\x83\xbf\x4b\x43\x92\xe4\x67\xdb\x96\xd6\x5b\x9b\xb4\xcb\x71\x71\x12\xf8\x47\x2e\x0d\x5a\x4d\x14\x50\x5f\xfd\x74\x84\xb0\x12\x91\x09\x1c\x5f\x87\xb9\x88\x83\x46\x3f\x98\x09\x1a\x0b\xaa\xae";
 
 #[cfg(not(target_family = "wasm"))]
@@ -100,6 +126,26 @@ pub trait Transport: Send + Sync {
     /// In the current spec v2, this is a CBOR encoded status message, but we are not
     /// making this API attach semantics to the response.
     fn status(&self) -> AgentFuture<Vec<u8>>;
+
+    /// Fetches the current time of the Replica, in nanoseconds since the Unix epoch.
+    ///
+    /// The default implementation reads the `impl_time` field of the response of [`status`](Self::status).
+    fn replica_time(&self) -> AgentFuture<u64> {
+        Box::pin(async move { replica_time_from_status(&self.status().await?) })
+    }
+}
+
+/// Reads the `impl_time` field of a CBOR encoded status message.
+fn replica_time_from_status(bytes: &[u8]) -> Result<u64, AgentError> {
+    let cbor: serde_cbor::Value =
+        serde_cbor::from_slice(bytes).map_err(AgentError::InvalidCborData)?;
+    let status = Status::try_from(&cbor).map_err(|_| AgentError::InvalidReplicaStatus)?;
+    match status.values.get("impl_time").map(|v| v.as_ref()) {
+        Some(status::Value::Integer(time)) => {
+            u64::try_from(*time).map_err(|_| AgentError::InvalidReplicaStatus)
+        }
+        _ => Err(AgentError::InvalidReplicaStatus),
+    }
 }
 
 impl<I: Transport + ?Sized> Transport for Box<I> {
@@ -124,6 +170,9 @@ impl<I: Transport + ?Sized> Transport for Box<I> {
     fn status(&self) -> AgentFuture<Vec<u8>> {
         (**self).status()
     }
+    fn replica_time(&self) -> AgentFuture<u64> {
+        (**self).replica_time()
+    }
 }
 impl<I: Transport + ?Sized> Transport for Arc<I> {
     fn call(
@@ -147,6 +196,9 @@ impl<I: Transport + ?Sized> Transport for Arc<I> {
     fn status(&self) -> AgentFuture<Vec<u8>> {
         (**self).status()
     }
+    fn replica_time(&self) -> AgentFuture<u64> {
+        (**self).replica_time()
+    }
 }
 
 /// Classification of the result of a request_status_raw (poll) call.
@@ -237,7 +289,15 @@ pub struct Agent {
     identity: Arc<dyn Identity>,
     ingress_expiry: Duration,
     root_key: Arc<RwLock<Vec<u8>>>,
+    /// The public keys of subnets registered with [`Agent::set_root_keys`], by subnet ID.
+    subnet_root_keys: Arc<RwLock<HashMap<Principal, Vec<u8>>>>,
     transport: Arc<dyn Transport>,
+    cbor_serializer: Arc<dyn CborSerializer>,
+    /// The last replica time fetched by [`Agent::replica_time`], with the local time it was fetched at.
+    replica_time: Arc<Mutex<Option<(std::time::SystemTime, u64)>>>,
+    signed_query_policy: SignedQueryPolicy,
+    /// The node keys of the subnets that answered queries, by subnet ID.
+    subnet_keys: Arc<Mutex<HashMap<Principal, Arc<SubnetKeys>>>>,
 }
 
 impl fmt::Debug for Agent {
@@ -257,6 +317,17 @@ impl Agent {
 
     /// Create an instance of an [`Agent`].
     pub fn new(config: agent_config::AgentConfig) -> Result<Agent, AgentError> {
+        let transport = config
+            .transport
+            .ok_or_else(AgentError::MissingReplicaTransport)?;
+        #[cfg(feature = "serde-cbor")]
+        let cbor_serializer = config
+            .cbor_serializer
+            .unwrap_or_else(|| Arc::new(cbor::SerdeCborSerializer));
+        #[cfg(not(feature = "serde-cbor"))]
+        let cbor_serializer = config
+            .cbor_serializer
+            .ok_or_else(AgentError::MissingCborSerializer)?;
         Ok(Agent {
             nonce_factory: config.nonce_factory,
             identity: config.identity,
@@ -264,15 +335,48 @@ impl Agent {
                 .ingress_expiry
                 .unwrap_or_else(|| Duration::from_secs(300)),
             root_key: Arc::new(RwLock::new(IC_ROOT_KEY.to_vec())),
-            transport: config
-                .transport
-                .ok_or_else(AgentError::MissingReplicaTransport)?,
+            subnet_root_keys: Arc::default(),
+            transport: {
+                let transport: Arc<dyn Transport> = match config.request_logger {
+                    Some(logger) => Arc::new(LoggedTransport {
+                        inner: transport,
+                        logger,
+                    }),
+                    None => transport,
+                };
+                match config.backoff {
+                    Some(config) => Arc::new(RetryingTransport {
+                        inner: transport,
+                        config,
+                    }),
+                    None => transport,
+                }
+            },
+            cbor_serializer,
+            replica_time: Arc::default(),
+            signed_query_policy: config.signed_query_policy,
+            subnet_keys: Arc::default(),
         })
     }
 
+    /// A copy of this agent that retries the requests failing with a transient error as
+    /// described by `config`, like [`AgentBuilder::with_exponential_backoff`], e.g. for a few
+    /// important calls only. The retries are on top of those of this agent, if any.
+    pub fn with_exponential_backoff(&self, config: ExponentialBackoffConfig) -> Agent {
+        Agent {
+            transport: Arc::new(RetryingTransport {
+                inner: self.transport.clone(),
+                config,
+            }),
+            ..self.clone()
+        }
+    }
+
     /// Set the transport of the [`Agent`].
     pub fn set_transport<F: 'static + Transport>(&mut self, transport: F) {
         self.transport = Arc::new(transport);
+        self.replica_time = Arc::default();
+        self.subnet_keys = Arc::default();
     }
 
     /// Set the identity provider for signing messages.
@@ -322,23 +426,66 @@ impl Agent {
         self.root_key.read().unwrap().clone()
     }
 
+    /// Request the public keys of `subnet_ids`, from the certified `/subnet/<subnet_id>/public_key`
+    /// paths of the state tree, verified against the root key. They can then be registered with
+    /// [`set_root_keys`](Self::set_root_keys).
+    ///
+    /// Every subnet certifies the public keys of all subnets, so `effective_canister_id` can be
+    /// any existing canister, which routes the request to its subnet.
+    ///
+    /// Returns [`AgentError::LookupPathAbsent`] if one of the subnets does not exist.
+    pub async fn fetch_subnet_public_keys(
+        &self,
+        subnet_ids: &[Principal],
+        effective_canister_id: Principal,
+    ) -> Result<HashMap<Principal, Vec<u8>>, AgentError> {
+        let paths: Vec<Vec<Label>> = subnet_ids
+            .iter()
+            .map(|subnet_id| {
+                vec![
+                    "subnet".into(),
+                    Label::from_bytes(subnet_id.as_slice()),
+                    "public_key".into(),
+                ]
+            })
+            .collect();
+
+        let cert = self.read_state_raw(paths, effective_canister_id).await?;
+
+        subnet_ids
+            .iter()
+            .map(|subnet_id| {
+                let path = [
+                    "subnet".as_bytes(),
+                    subnet_id.as_slice(),
+                    "public_key".as_bytes(),
+                ];
+                Ok((*subnet_id, lookup_value(&cert, path)?.to_vec()))
+            })
+            .collect()
+    }
+
+    /// Register the DER-encoded public keys of subnets, by subnet ID, e.g. as returned by
+    /// [`fetch_subnet_public_keys`](Self::fetch_subnet_public_keys), in addition to those
+    /// registered before.
+    ///
+    /// Certificates delegated to one of these subnets are then only accepted if the delegation
+    /// carries the registered key, so that a compromised root key cannot be used to forge new
+    /// keys for them.
+    pub fn set_root_keys(&self, keys: HashMap<Principal, Vec<u8>>) {
+        self.subnet_root_keys.write().unwrap().extend(keys);
+    }
+
     fn get_expiry_date(&self) -> u64 {
         // TODO(hansl): evaluate if we need this on the agent side (my hunch is we don't).
         let permitted_drift = Duration::from_secs(60);
         (self
             .ingress_expiry
-            .saturating_add({
-                #[cfg(not(target_family = "wasm"))]
-                {
-                    std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .expect("Time wrapped around.")
-                }
-                #[cfg(all(target_family = "wasm", feature = "wasm-bindgen"))]
-                {
-                    Duration::from_nanos((js_sys::Date::now() * 1_000_000.) as _)
-                }
-            })
+            .saturating_add(
+                polling::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .expect("Time wrapped around."),
+            )
             .saturating_sub(permitted_drift))
         .as_nanos() as u64
     }
@@ -392,6 +539,7 @@ impl Agent {
 
     /// The simplest way to do a query call; sends a byte array and will return a byte vector.
     /// The encoding is left as an exercise to the user.
+    #[cfg(test)]
     async fn query_raw(
         &self,
         canister_id: &Principal,
@@ -399,17 +547,163 @@ impl Agent {
         method_name: &str,
         arg: &[u8],
         ingress_expiry_datetime: Option<u64>,
+        sender_override: Option<Principal>,
     ) -> Result<Vec<u8>, AgentError> {
-        let request = self.query_content(canister_id, method_name, arg, ingress_expiry_datetime)?;
-        let serialized_bytes = sign_request(&request, self.identity.clone())?;
-        self.query_endpoint::<replica_api::QueryResponse>(effective_canister_id, serialized_bytes)
-            .await
-            .and_then(|response| match response {
-                replica_api::QueryResponse::Replied { reply } => Ok(reply.arg),
-                replica_api::QueryResponse::Rejected(response) => {
-                    Err(AgentError::ReplicaError(response))
+        self.query_raw_with_policy(
+            canister_id,
+            effective_canister_id,
+            method_name,
+            arg,
+            ingress_expiry_datetime,
+            sender_override,
+            self.signed_query_policy,
+        )
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn query_raw_with_policy(
+        &self,
+        canister_id: &Principal,
+        effective_canister_id: Principal,
+        method_name: &str,
+        arg: &[u8],
+        ingress_expiry_datetime: Option<u64>,
+        sender_override: Option<Principal>,
+        signed_query_policy: SignedQueryPolicy,
+    ) -> Result<Vec<u8>, AgentError> {
+        let request = self.query_content(
+            canister_id,
+            method_name,
+            arg,
+            ingress_expiry_datetime,
+            sender_override,
+        )?;
+        let serialized_bytes =
+            sign_request(&request, self.identity.clone(), &*self.cbor_serializer)?;
+        let response = if signed_query_policy == SignedQueryPolicy::Disabled {
+            self.query_endpoint::<replica_api::QueryResponse>(
+                effective_canister_id,
+                serialized_bytes,
+            )
+            .await?
+        } else {
+            let request_id = to_request_id(&request)?;
+            let bytes = self
+                .transport
+                .query(effective_canister_id, serialized_bytes)
+                .await?;
+            let response: replica_api::QueryResponse =
+                serde_cbor::from_slice(&bytes).map_err(AgentError::InvalidCborData)?;
+            let QuerySignatures { signatures } =
+                serde_cbor::from_slice(&bytes).map_err(AgentError::InvalidCborData)?;
+            let verify = |keys: &SubnetKeys| {
+                verify_query_signatures(
+                    signed_query_policy,
+                    &request_id,
+                    &response,
+                    &signatures,
+                    keys,
+                )
+            };
+            if signatures.is_empty() {
+                // Nothing to verify the keys against, only whether signatures are required.
+                verify(&SubnetKeys {
+                    canister_ranges: vec![],
+                    node_keys: HashMap::new(),
+                })?;
+            } else if let Some(keys) = self.cached_subnet_keys(effective_canister_id) {
+                match verify(&keys) {
+                    Err(AgentError::InvalidQuerySignature(_)) => {
+                        // The nodes of the subnet may have rotated their keys since they were
+                        // cached.
+                        self.subnet_keys
+                            .lock()
+                            .unwrap()
+                            .retain(|_, cached| !Arc::ptr_eq(cached, &keys));
+                        verify(&*self.fetch_subnet_keys(effective_canister_id).await?)?;
+                    }
+                    result => result?,
                 }
-            })
+            } else {
+                verify(&*self.fetch_subnet_keys(effective_canister_id).await?)?;
+            }
+            response
+        };
+        match response {
+            replica_api::QueryResponse::Replied { reply } => Ok(reply.arg),
+            replica_api::QueryResponse::Rejected(response) => {
+                Err(AgentError::ReplicaError(response))
+            }
+        }
+    }
+
+    /// The cached node keys of the subnet hosting `canister_id`, if a query to that subnet was
+    /// verified before.
+    fn cached_subnet_keys(&self, canister_id: Principal) -> Option<Arc<SubnetKeys>> {
+        self.subnet_keys
+            .lock()
+            .unwrap()
+            .values()
+            .find(|keys| principal_is_within_ranges(&canister_id, &keys.canister_ranges))
+            .cloned()
+    }
+
+    /// The node keys of the subnet hosting `canister_id`, read from the state tree and cached
+    /// until a signature fails to verify with them.
+    async fn fetch_subnet_keys(
+        &self,
+        canister_id: Principal,
+    ) -> Result<Arc<SubnetKeys>, AgentError> {
+        let subnet_id = self.read_canister_subnet_id(canister_id).await?;
+        let subnet_path: Vec<Label> =
+            vec!["subnet".into(), Label::from_bytes(subnet_id.as_slice())];
+        let cert = self
+            .read_state_raw(vec![subnet_path.clone()], canister_id)
+            .await?;
+
+        let canister_ranges = lookup_value(
+            &cert,
+            [
+                "subnet".as_bytes(),
+                subnet_id.as_slice(),
+                "canister_ranges".as_bytes(),
+            ],
+        )?;
+        let canister_ranges: Vec<(Principal, Principal)> =
+            serde_cbor::from_slice(canister_ranges).map_err(AgentError::InvalidCborData)?;
+        let mut node_path = subnet_path;
+        node_path.push("node".into());
+        let nodes = match cert.tree.lookup_subtree(&node_path) {
+            ic_certification::SubtreeLookupResult::Found(nodes) => nodes,
+            _ => return Err(AgentError::LookupPathAbsent(node_path)),
+        };
+        let mut node_keys = HashMap::new();
+        for path in nodes.list_paths() {
+            if let [node_id, key] = &path[..] {
+                if key.as_bytes() != b"public_key" {
+                    continue;
+                }
+                if let ic_certification::LookupResult::Found(public_key) =
+                    nodes.lookup_path([node_id.as_bytes(), key.as_bytes()])
+                {
+                    node_keys.insert(
+                        Principal::try_from_slice(node_id.as_bytes())?,
+                        public_key.to_vec(),
+                    );
+                }
+            }
+        }
+
+        let keys = Arc::new(SubnetKeys {
+            canister_ranges,
+            node_keys,
+        });
+        self.subnet_keys
+            .lock()
+            .unwrap()
+            .insert(subnet_id, keys.clone());
+        Ok(keys)
     }
 
     /// Send the signed query to the network. Will return a byte vector.
@@ -438,9 +732,14 @@ impl Agent {
         method_name: &str,
         arg: &[u8],
         ingress_expiry_datetime: Option<u64>,
+        sender_override: Option<Principal>,
     ) -> Result<QueryContent, AgentError> {
+        let sender = match sender_override {
+            Some(sender) => sender,
+            None => self.identity.sender().map_err(AgentError::SigningError)?,
+        };
         Ok(QueryContent::QueryRequest {
-            sender: self.identity.sender().map_err(AgentError::SigningError)?,
+            sender,
             canister_id: *canister_id,
             method_name: method_name.to_string(),
             arg: arg.to_vec(),
@@ -457,11 +756,18 @@ impl Agent {
         method_name: &str,
         arg: &[u8],
         ingress_expiry_datetime: Option<u64>,
+        nonce_len: Option<usize>,
     ) -> Result<RequestId, AgentError> {
-        let request =
-            self.update_content(canister_id, method_name, arg, ingress_expiry_datetime)?;
+        let request = self.update_content(
+            canister_id,
+            method_name,
+            arg,
+            ingress_expiry_datetime,
+            nonce_len,
+        )?;
         let request_id = to_request_id(&request)?;
-        let serialized_bytes = sign_request(&request, self.identity.clone())?;
+        let serialized_bytes =
+            sign_request(&request, self.identity.clone(), &*self.cbor_serializer)?;
 
         self.call_endpoint(effective_canister_id, request_id, serialized_bytes)
             .await
@@ -488,12 +794,24 @@ impl Agent {
         method_name: &str,
         arg: &[u8],
         ingress_expiry_datetime: Option<u64>,
+        nonce_len: Option<usize>,
     ) -> Result<CallRequestContent, AgentError> {
+        let nonce = match nonce_len {
+            None => self.nonce_factory.generate(),
+            Some(0) => None,
+            Some(len) if len <= MAX_NONCE_LEN => Some(nonce::random_nonce(len)),
+            Some(len) => {
+                return Err(AgentError::MessageError(format!(
+                    "Nonces are at most {} bytes long, got {}.",
+                    MAX_NONCE_LEN, len
+                )))
+            }
+        };
         Ok(CallRequestContent::CallRequest {
             canister_id: *canister_id,
             method_name: method_name.into(),
             arg: arg.to_vec(),
-            nonce: self.nonce_factory.generate().map(|b| b.as_slice().into()),
+            nonce: nonce.map(|b| b.as_slice().into()),
             sender: self.identity.sender().map_err(AgentError::SigningError)?,
             ingress_expiry: ingress_expiry_datetime.unwrap_or_else(|| self.get_expiry_date()),
         })
@@ -533,53 +851,125 @@ impl Agent {
         request_id: RequestId,
         effective_canister_id: Principal,
     ) -> Result<Vec<u8>, AgentError> {
-        let mut retry_policy = ExponentialBackoffBuilder::new()
-            .with_initial_interval(Duration::from_millis(500))
-            .with_max_interval(Duration::from_secs(1))
-            .with_multiplier(1.4)
-            .with_max_elapsed_time(Some(Duration::from_secs(60 * 5)))
-            .build();
-        let mut request_accepted = false;
+        self.wait_certified(request_id, effective_canister_id)
+            .await
+            .map(|(reply, _)| reply)
+    }
+
+    /// Like [`wait`](Self::wait), but also return the certificate the reply was read from, e.g.
+    /// to verify it against a known root key independently of the agent, or to keep it as a
+    /// proof of the reply.
+    pub async fn wait_certified(
+        &self,
+        request_id: RequestId,
+        effective_canister_id: Principal,
+    ) -> Result<(Vec<u8>, Certificate), AgentError> {
+        self.wait_with_strategy(
+            request_id,
+            effective_canister_id,
+            None,
+            WaitLimits::default(),
+        )
+        .await
+    }
+
+    /// Like [`wait_certified`](Self::wait_certified), but wait between polls as decided by
+    /// `strategy`, or as [`wait`](Self::wait) does if it is `None`, and give up with
+    /// [`AgentError::TimeoutWaitingForResponse`] at the limits of `limits`. The time spent
+    /// polling counts towards the 5 minutes the call is waited for at most, not only the delays.
+    pub async fn wait_with_strategy(
+        &self,
+        request_id: RequestId,
+        effective_canister_id: Principal,
+        strategy: Option<&mut dyn PollStrategy>,
+        limits: WaitLimits,
+    ) -> Result<(Vec<u8>, Certificate), AgentError> {
+        let strategy = strategy.map(|strategy| Box::new(strategy) as Box<dyn PollStrategy + '_>);
+        let mut schedule = PollSchedule::new(strategy, limits);
         loop {
-            match self.poll(&request_id, effective_canister_id).await? {
-                PollResult::Submitted => {}
-                PollResult::Accepted => {
-                    if !request_accepted {
-                        // The system will return RequestStatusResponse::Unknown
-                        // (PollResult::Submitted) until the request is accepted
-                        // and we generally cannot know how long that will take.
-                        // State transitions between Received and Processing may be
-                        // instantaneous. Therefore, once we know the request is accepted,
-                        // we should restart the backoff so the request does not time out.
-
-                        retry_policy.reset();
-                        request_accepted = true;
-                    }
+            let paths = vec![vec!["request_status".into(), request_id.to_vec().into()]];
+            let cert = self.read_state_raw(paths, effective_canister_id).await?;
+            match lookup_request_status(&cert, &request_id)? {
+                RequestStatusResponse::Unknown => schedule.unknown()?,
+                RequestStatusResponse::Received | RequestStatusResponse::Processing => {
+                    schedule.accepted()
+                }
+                RequestStatusResponse::Replied {
+                    reply: Replied::CallReplied(reply),
+                } => return Ok((reply, cert)),
+                RequestStatusResponse::Rejected(response) => {
+                    return Err(AgentError::ReplicaError(response))
+                }
+                RequestStatusResponse::Done => {
+                    return Err(AgentError::RequestStatusDoneNoReply(String::from(
+                        request_id,
+                    )))
                 }
-                PollResult::Completed(result) => return Ok(result),
             };
+            schedule.wait().await?;
+        }
+    }
 
-            match retry_policy.next_backoff() {
-                #[cfg(not(target_family = "wasm"))]
-                Some(duration) => tokio::time::sleep(duration).await,
-                #[cfg(all(target_family = "wasm", feature = "wasm-bindgen"))]
-                Some(duration) => {
-                    wasm_bindgen_futures::JsFuture::from(js_sys::Promise::new(&mut |rs, rj| {
-                        if let Err(e) = web_sys::window()
-                            .expect("global window unavailable")
-                            .set_timeout_with_callback_and_timeout_and_arguments_0(
-                                &rs,
-                                duration.as_millis() as _,
-                            )
-                        {
-                            use wasm_bindgen::UnwrapThrowExt;
-                            rj.call1(&rj, &e).unwrap_throw();
-                        }
-                    }))
-                    .await
-                    .expect("unable to setTimeout");
-                }
-                None => return Err(AgentError::TimeoutWaitingForResponse()),
+    /// Wait for several update calls, like [`wait`](Self::wait), but poll the status of all of
+    /// them with a single `read_state` call at each interval. Calls that are finished are no
+    /// longer polled. The results are in the order of `request_ids`; the outer error is only
+    /// returned if the `read_state` call itself fails.
+    ///
+    /// Like [`wait_with_strategy`](Self::wait_with_strategy), a call the replica still does
+    /// not know after `limits.ingress_expiry`, e.g. the earliest expiry of the calls, fails with
+    /// [`AgentError::TimeoutWaitingForResponse`], and the calls still pending at the deadline
+    /// fail with it too.
+    pub async fn wait_many(
+        &self,
+        effective_canister_id: Principal,
+        request_ids: Vec<RequestId>,
+        limits: WaitLimits,
+    ) -> Result<Vec<Result<Vec<u8>, AgentError>>, AgentError> {
+        let mut schedule = PollSchedule::new(None, limits);
+        let mut results: Vec<Option<Result<Vec<u8>, AgentError>>> =
+            request_ids.iter().map(|_| None).collect();
+        loop {
+            let pending: Vec<usize> = (0..results.len())
+                .filter(|&i| results[i].is_none())
+                .collect();
+            if pending.is_empty() {
+                return Ok(results.into_iter().flatten().collect());
+            }
+
+            let paths = pending
+                .iter()
+                .map(|&i| vec!["request_status".into(), request_ids[i].to_vec().into()])
+                .collect();
+            let cert = self.read_state_raw(paths, effective_canister_id).await?;
+            for i in pending {
+                let request_id = &request_ids[i];
+                results[i] = match lookup_request_status(&cert, request_id) {
+                    Ok(RequestStatusResponse::Unknown) => schedule.unknown().err().map(Err),
+                    Ok(RequestStatusResponse::Received | RequestStatusResponse::Processing) => {
+                        schedule.accepted();
+                        None
+                    }
+                    Ok(RequestStatusResponse::Replied {
+                        reply: Replied::CallReplied(arg),
+                    }) => Some(Ok(arg)),
+                    Ok(RequestStatusResponse::Rejected(response)) => {
+                        Some(Err(AgentError::ReplicaError(response)))
+                    }
+                    Ok(RequestStatusResponse::Done) => Some(Err(
+                        AgentError::RequestStatusDoneNoReply(String::from(*request_id)),
+                    )),
+                    Err(e) => Some(Err(e)),
+                };
+            }
+
+            if results.iter().all(Option::is_some) {
+                continue;
+            }
+            if schedule.wait().await.is_err() {
+                return Ok(results
+                    .into_iter()
+                    .map(|result| result.unwrap_or(Err(AgentError::TimeoutWaitingForResponse())))
+                    .collect());
             }
         }
     }
@@ -591,7 +981,8 @@ impl Agent {
         effective_canister_id: Principal,
     ) -> Result<Certificate, AgentError> {
         let request = self.read_state_content(paths)?;
-        let serialized_bytes = sign_request(&request, self.identity.clone())?;
+        let serialized_bytes =
+            sign_request(&request, self.identity.clone(), &*self.cbor_serializer)?;
 
         let read_state_response: ReadStateResponse = self
             .read_state_endpoint(effective_canister_id, serialized_bytes)
@@ -602,6 +993,34 @@ impl Agent {
         Ok(cert)
     }
 
+    /// Request the values at several paths of the state tree in a single `read_state` call,
+    /// instead of one call per path. The values are returned in the order of `paths`, with
+    /// [`None`] for paths that are absent from the state tree.
+    pub async fn read_state_multi(
+        &self,
+        effective_canister_id: Principal,
+        paths: Vec<Vec<Vec<u8>>>,
+    ) -> Result<Vec<Option<Vec<u8>>>, AgentError> {
+        let labels = paths
+            .iter()
+            .map(|path| {
+                path.iter()
+                    .map(|segment| Label::from(segment.clone()))
+                    .collect()
+            })
+            .collect();
+        let cert = self.read_state_raw(labels, effective_canister_id).await?;
+
+        paths
+            .into_iter()
+            .map(|path| match lookup_value(&cert, path) {
+                Ok(value) => Ok(Some(value.to_vec())),
+                Err(AgentError::LookupPathAbsent(_)) => Ok(None),
+                Err(e) => Err(e),
+            })
+            .collect()
+    }
+
     fn read_state_content(&self, paths: Vec<Vec<Label>>) -> Result<ReadStateContent, AgentError> {
         Ok(ReadStateContent::ReadStateRequest {
             sender: self.identity.sender().map_err(AgentError::SigningError)?,
@@ -617,18 +1036,35 @@ impl Agent {
         cert: &Certificate,
         effective_canister_id: Principal,
     ) -> Result<(), AgentError> {
-        let sig = &cert.signature;
-
-        let root_hash = cert.tree.digest();
-        let mut msg = vec![];
-        msg.extend_from_slice(IC_STATE_ROOT_DOMAIN_SEPARATOR);
-        msg.extend_from_slice(&root_hash);
-
         let der_key = self.check_delegation(&cert.delegation, effective_canister_id)?;
-        let key = extract_der(der_key)?;
+        verify_certificate_signature(cert, der_key)
+    }
 
-        ic_verify_bls_signature::verify_bls_signature(sig, &msg, &key)
-            .map_err(|_| AgentError::CertificateVerificationFailed())
+    /// Verify a CBOR-encoded certificate about `effective_canister_id`, e.g. one stored
+    /// alongside a response, against the root key of this agent, without making any network
+    /// call.
+    ///
+    /// This applies the same checks as [`verify`](Self::verify): the subnet of a delegated
+    /// certificate must be authorized for `effective_canister_id`, and carry the key registered
+    /// for it with [`set_root_keys`](Self::set_root_keys), if any.
+    pub fn verify_response_certificate(
+        &self,
+        certificate_cbor: &[u8],
+        effective_canister_id: Principal,
+    ) -> Result<CertificateVerification, AgentError> {
+        let chain = CertificateChain::parse(certificate_cbor)?;
+        let cert = chain.leaf();
+        self.verify(cert, effective_canister_id)?;
+
+        let mut time = lookup_value(cert, ["time".as_bytes()])?;
+        Ok(CertificateVerification {
+            root_hash: cert.tree.digest(),
+            certified_at_ns: leb128::read::unsigned(&mut time)?,
+            subnet_id: chain
+                .leaf_subnet_id()
+                .map(Principal::try_from_slice)
+                .transpose()?,
+        })
     }
 
     fn check_delegation(
@@ -641,6 +1077,9 @@ impl Agent {
             Some(delegation) => {
                 let cert: Certificate = serde_cbor::from_slice(&delegation.certificate)
                     .map_err(AgentError::InvalidCborData)?;
+                if cert.delegation.is_some() {
+                    return Err(CertificateError::NestedDelegation.into());
+                }
                 self.verify(&cert, effective_canister_id)?;
                 let canister_range_lookup = [
                     "subnet".as_bytes(),
@@ -660,7 +1099,15 @@ impl Agent {
                     delegation.subnet_id.as_ref(),
                     "public_key".as_bytes(),
                 ];
-                lookup_value(&cert, public_key_path).map(|pk| pk.to_vec())
+                let public_key = lookup_value(&cert, public_key_path)?;
+                let subnet_id = Principal::try_from_slice(&delegation.subnet_id)?;
+                if let Some(registered_key) = self.subnet_root_keys.read().unwrap().get(&subnet_id)
+                {
+                    if registered_key.as_slice() != public_key {
+                        return Err(AgentError::CertificateVerificationFailed());
+                    }
+                }
+                Ok(public_key.to_vec())
             }
         }
     }
@@ -700,6 +1147,50 @@ impl Agent {
         lookup_canister_metadata(cert, canister_id, path)
     }
 
+    /// Request the ID of the subnet that hosts `canister_id`, from the certified
+    /// `/canister/<canister_id>/subnet_id` path of the state tree.
+    ///
+    /// Returns [`AgentError::CanisterNotFound`] if the path is absent from the certificate.
+    pub async fn read_canister_subnet_id(
+        &self,
+        canister_id: Principal,
+    ) -> Result<Principal, AgentError> {
+        let paths: Vec<Vec<Label>> = vec![vec![
+            "canister".into(),
+            Label::from_bytes(canister_id.as_slice()),
+            "subnet_id".into(),
+        ]];
+
+        let cert = self.read_state_raw(paths, canister_id).await?;
+
+        match lookup_canister_info(cert, canister_id, "subnet_id") {
+            Ok(subnet_id) => Ok(Principal::try_from_slice(&subnet_id)?),
+            Err(AgentError::LookupPathAbsent(_)) => Err(AgentError::CanisterNotFound(canister_id)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Request the metrics of the subnet `subnet_id`, from the certified
+    /// `/subnet/<subnet_id>/metrics` path of the state tree. A subnet only certifies its own
+    /// metrics, so `effective_canister_id` must be a canister hosted on that subnet.
+    pub async fn read_subnet_metrics(
+        &self,
+        subnet_id: Principal,
+        effective_canister_id: Principal,
+    ) -> Result<SubnetMetrics, AgentError> {
+        let path: Vec<Label> = vec![
+            "subnet".into(),
+            Label::from_bytes(subnet_id.as_slice()),
+            "metrics".into(),
+        ];
+
+        let cert = self
+            .read_state_raw(vec![path.clone()], effective_canister_id)
+            .await?;
+
+        SubnetMetrics::from_cbor(lookup_value(&cert, path)?)
+    }
+
     /// Fetches the status of a particular request by its ID.
     pub async fn request_status_raw(
         &self,
@@ -711,7 +1202,7 @@ impl Agent {
 
         let cert = self.read_state_raw(paths, effective_canister_id).await?;
 
-        lookup_request_status(cert, request_id)
+        lookup_request_status(&cert, request_id)
     }
 
     /// Send the signed request_status to the network. Will return [`RequestStatusResponse`].
@@ -732,7 +1223,7 @@ impl Agent {
         let cert: Certificate = serde_cbor::from_slice(&read_state_response.certificate)
             .map_err(AgentError::InvalidCborData)?;
         self.verify(&cert, effective_canister_id)?;
-        lookup_request_status(cert, request_id)
+        lookup_request_status(&cert, request_id)
     }
 
     /// Returns an UpdateBuilder enabling the construction of an update call without
@@ -755,6 +1246,26 @@ impl Agent {
         Status::try_from(&cbor).map_err(|_| AgentError::InvalidReplicaStatus)
     }
 
+    /// Returns the current time of the replica, in nanoseconds since the Unix epoch, e.g. to
+    /// account for clock skew when computing ingress expiries.
+    ///
+    /// The time is fetched from the transport at most every 5 seconds. In between, the last
+    /// fetched time is returned, advanced by the local time elapsed since.
+    pub async fn replica_time(&self) -> Result<u64, AgentError> {
+        const TTL: Duration = Duration::from_secs(5);
+
+        let now = polling::now();
+        if let Some((fetched_at, time)) = *self.replica_time.lock().unwrap() {
+            let elapsed = now.duration_since(fetched_at).unwrap_or_default();
+            if elapsed < TTL {
+                return Ok(time + elapsed.as_nanos() as u64);
+            }
+        }
+        let time = self.transport.replica_time().await?;
+        *self.replica_time.lock().unwrap() = Some((now, time));
+        Ok(time)
+    }
+
     /// Returns a QueryBuilder enabling the construction of a query call without
     /// passing all arguments.
     pub fn query<S: Into<String>>(&self, canister_id: &Principal, method_name: S) -> QueryBuilder {
@@ -771,7 +1282,11 @@ impl Agent {
         let paths: Vec<Vec<Label>> =
             vec![vec!["request_status".into(), request_id.to_vec().into()]];
         let read_state_content = self.read_state_content(paths)?;
-        let signed_request_status = sign_request(&read_state_content, self.identity.clone())?;
+        let signed_request_status = sign_request(
+            &read_state_content,
+            self.identity.clone(),
+            &*self.cbor_serializer,
+        )?;
         match read_state_content {
             ReadStateContent::ReadStateRequest {
                 ingress_expiry,
@@ -803,7 +1318,11 @@ fn construct_message(request_id: &RequestId) -> Vec<u8> {
     buf
 }
 
-fn sign_request<'a, V>(request: &V, identity: Arc<dyn Identity>) -> Result<Vec<u8>, AgentError>
+fn sign_request<'a, V>(
+    request: &V,
+    identity: Arc<dyn Identity>,
+    cbor_serializer: &dyn CborSerializer,
+) -> Result<Vec<u8>, AgentError>
 where
     V: 'a + Serialize,
 {
@@ -815,14 +1334,49 @@ where
         content: request,
         sender_pubkey: signature.public_key,
         sender_sig: signature.signature,
+        sender_delegation: signature.delegations,
     };
 
-    let mut serialized_bytes = Vec::new();
-    let mut serializer = serde_cbor::Serializer::new(&mut serialized_bytes);
-    serializer.self_describe()?;
-    envelope.serialize(&mut serializer)?;
+    Ok(cbor_serializer.serialize(&envelope)?)
+}
+
+/// Wait for `duration`, with tokio or, on WASM targets, the browser's `setTimeout`.
+pub(crate) async fn sleep(duration: Duration) {
+    #[cfg(not(target_family = "wasm"))]
+    tokio::time::sleep(duration).await;
+    #[cfg(all(target_family = "wasm", feature = "wasm-bindgen"))]
+    wasm_bindgen_futures::JsFuture::from(js_sys::Promise::new(&mut |rs, rj| {
+        if let Err(e) = web_sys::window()
+            .expect("global window unavailable")
+            .set_timeout_with_callback_and_timeout_and_arguments_0(&rs, duration.as_millis() as _)
+        {
+            use wasm_bindgen::UnwrapThrowExt;
+            rj.call1(&rj, &e).unwrap_throw();
+        }
+    }))
+    .await
+    .expect("unable to setTimeout");
+}
+
+/// The result of [`Agent::verify_response_certificate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateVerification {
+    /// The root hash of the certified state tree.
+    pub root_hash: [u8; 32],
+    /// The time the state was certified at, in nanoseconds since the Unix epoch.
+    pub certified_at_ns: u64,
+    /// The subnet that signed the certificate, if it was signed through a delegation
+    /// rather than by the root key.
+    pub subnet_id: Option<Principal>,
+}
 
-    Ok(serialized_bytes)
+fn verify_certificate_signature(cert: &Certificate, der_key: Vec<u8>) -> Result<(), AgentError> {
+    let mut msg = vec![];
+    msg.extend_from_slice(IC_STATE_ROOT_DOMAIN_SEPARATOR);
+    msg.extend_from_slice(&cert.tree.digest());
+    let key = extract_der(der_key)?;
+    ic_verify_bls_signature::verify_bls_signature(&cert.signature, &msg, &key)
+        .map_err(|_| AgentError::CertificateVerificationFailed())
 }
 
 /// Inspect the bytes to be sent as a query
@@ -1006,6 +1560,8 @@ pub struct QueryBuilder<'agent> {
     pub arg: Vec<u8>,
     /// The Unix timestamp that the request will expire at.
     pub ingress_expiry_datetime: Option<u64>,
+    sender_override: Option<Principal>,
+    signed_query_policy: Option<SignedQueryPolicy>,
 }
 
 impl<'agent> QueryBuilder<'agent> {
@@ -1018,6 +1574,8 @@ impl<'agent> QueryBuilder<'agent> {
             method_name,
             arg: vec![],
             ingress_expiry_datetime: None,
+            sender_override: None,
+            signed_query_policy: None,
         }
     }
 
@@ -1027,6 +1585,23 @@ impl<'agent> QueryBuilder<'agent> {
         self
     }
 
+    /// Verify the node signatures of the response according to `policy`, instead of the policy
+    /// of the agent set with [`AgentBuilder::with_signed_query_verification`].
+    pub fn with_signed_query_policy(&mut self, policy: SignedQueryPolicy) -> &mut Self {
+        self.signed_query_policy = Some(policy);
+        self
+    }
+
+    /// Sends the query as `sender` instead of the principal of the agent's identity. The query
+    /// is still signed by the identity, so this is only accepted by replicas that do not check
+    /// the signature against the sender, e.g. in testing setups. Only available in tests and
+    /// with the `testing` feature.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn with_sender_override(&mut self, sender: Principal) -> &mut Self {
+        self.sender_override = Some(sender);
+        self
+    }
+
     /// Sets the argument blob to pass to the canister. For most canisters this should be a Candid-serialized tuple.
     pub fn with_arg<A: AsRef<[u8]>>(&mut self, arg: A) -> &mut Self {
         self.arg = arg.as_ref().to_vec();
@@ -1068,12 +1643,15 @@ impl<'agent> QueryBuilder<'agent> {
     /// Make a query call. This will return a byte vector.
     pub async fn call(&self) -> Result<Vec<u8>, AgentError> {
         self.agent
-            .query_raw(
+            .query_raw_with_policy(
                 &self.canister_id,
                 self.effective_canister_id,
                 self.method_name.as_str(),
                 self.arg.as_slice(),
                 self.ingress_expiry_datetime,
+                self.sender_override,
+                self.signed_query_policy
+                    .unwrap_or(self.agent.signed_query_policy),
             )
             .await
     }
@@ -1086,9 +1664,14 @@ impl<'agent> QueryBuilder<'agent> {
             &self.method_name,
             &self.arg,
             self.ingress_expiry_datetime,
+            self.sender_override,
         )?;
 
-        let signed_query = sign_request(&request, self.agent.identity.clone())?;
+        let signed_query = sign_request(
+            &request,
+            self.agent.identity.clone(),
+            &*self.agent.cbor_serializer,
+        )?;
         match request {
             QueryContent::QueryRequest {
                 ingress_expiry,
@@ -1139,6 +1722,27 @@ impl<'a> UpdateCall<'a> {
             .await
     }
 }
+/// A hook called with the request ID of an update call, which is the SHA-256 hash of its
+/// content, right before the call is signed. See [`UpdateBuilder::with_pre_sign_hook`].
+#[derive(Clone)]
+pub struct PreSignHook(Arc<PreSignFn>);
+
+/// The closure of a [PreSignHook].
+type PreSignFn = dyn Fn(&[u8]) -> Result<(), AgentError> + Send + Sync;
+
+impl PreSignHook {
+    /// Wrap `hook` to be passed to [`UpdateBuilder::with_pre_sign_hook`].
+    pub fn new(hook: impl Fn(&[u8]) -> Result<(), AgentError> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+}
+
+impl fmt::Debug for PreSignHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PreSignHook").finish_non_exhaustive()
+    }
+}
+
 /// An Update Request Builder.
 ///
 /// This makes it easier to do update calls without actually passing all arguments or specifying
@@ -1156,6 +1760,8 @@ pub struct UpdateBuilder<'agent> {
     pub arg: Vec<u8>,
     /// The Unix timestamp that the request will expire at.
     pub ingress_expiry_datetime: Option<u64>,
+    nonce_len: Option<usize>,
+    pre_sign_hook: Option<PreSignHook>,
 }
 
 impl<'agent> UpdateBuilder<'agent> {
@@ -1168,6 +1774,8 @@ impl<'agent> UpdateBuilder<'agent> {
             method_name,
             arg: vec![],
             ingress_expiry_datetime: None,
+            nonce_len: None,
+            pre_sign_hook: None,
         }
     }
 
@@ -1183,6 +1791,25 @@ impl<'agent> UpdateBuilder<'agent> {
         self
     }
 
+    /// Call `hook` with the request ID of the call, i.e. the SHA-256 hash of its content, right
+    /// before signing it, e.g. to have it approved on a hardware device. If the hook returns an
+    /// error, the call is aborted with that error, and the identity is never asked to sign.
+    pub fn with_pre_sign_hook(&mut self, hook: PreSignHook) -> &mut Self {
+        self.pre_sign_hook = Some(hook);
+        self
+    }
+
+    /// Use a random nonce of `len` bytes instead of the nonce from the agent's nonce factory.
+    /// A length of 0 sends the request without a nonce, so that its request ID only depends on
+    /// its content.
+    ///
+    /// The replica allows nonces of at most 32 bytes: with a greater `len`, signing or making
+    /// the call fails with [`AgentError::MessageError`].
+    pub fn with_nonce_len(&mut self, len: usize) -> &mut Self {
+        self.nonce_len = Some(len);
+        self
+    }
+
     /// Takes a SystemTime converts it to a Duration by calling
     /// duration_since(UNIX_EPOCH) to learn about where in time this SystemTime lies.
     /// The Duration is converted to nanoseconds and stored in ingress_expiry_datetime
@@ -1224,16 +1851,30 @@ impl<'agent> UpdateBuilder<'agent> {
     /// Make an update call. This will return a RequestId.
     /// The RequestId should then be used for request_status (most likely in a loop).
     pub fn call(&self) -> UpdateCall {
-        let request_id_future = self.agent.update_raw(
-            &self.canister_id,
-            self.effective_canister_id,
-            self.method_name.as_str(),
-            self.arg.as_slice(),
-            self.ingress_expiry_datetime,
-        );
+        let request_id_future: AgentFuture<RequestId> = match self.pre_sign_hook {
+            None => Box::pin(self.agent.update_raw(
+                &self.canister_id,
+                self.effective_canister_id,
+                self.method_name.as_str(),
+                self.arg.as_slice(),
+                self.ingress_expiry_datetime,
+                self.nonce_len,
+            )),
+            // `sign` calls the hook; `update_raw` does not know about it.
+            Some(_) => {
+                let signed = self.sign();
+                let agent = self.agent;
+                let effective_canister_id = self.effective_canister_id;
+                Box::pin(async move {
+                    agent
+                        .update_signed(effective_canister_id, signed?.signed_update)
+                        .await
+                })
+            }
+        };
         UpdateCall {
             agent: self.agent,
-            request_id: Box::pin(request_id_future),
+            request_id: request_id_future,
             effective_canister_id: self.effective_canister_id,
         }
     }
@@ -1246,9 +1887,17 @@ impl<'agent> UpdateBuilder<'agent> {
             &self.method_name,
             &self.arg,
             self.ingress_expiry_datetime,
+            self.nonce_len,
         )?;
-        let signed_update = sign_request(&request, self.agent.identity.clone())?;
         let request_id = to_request_id(&request)?;
+        if let Some(PreSignHook(hook)) = &self.pre_sign_hook {
+            hook(request_id.as_slice())?;
+        }
+        let signed_update = sign_request(
+            &request,
+            self.agent.identity.clone(),
+            &*self.agent.cbor_serializer,
+        )?;
         match request {
             CallRequestContent::CallRequest {
                 nonce,
@@ -39,6 +39,13 @@ impl NonceFactory {
         }
     }
 
+    /// Creates a nonce factory that calls `f` for every nonce, e.g. to sequence or log them.
+    pub fn from_fn(f: impl Fn() -> Vec<u8> + Send + Sync + 'static) -> NonceFactory {
+        Self {
+            inner: Arc::new(Func(move || Some(f()))),
+        }
+    }
+
     /// Generates a nonce, if one is available. Otherwise, returns None.
     pub fn generate(&self) -> Option<Vec<u8>> {
         NonceGenerator::generate(self)
@@ -84,6 +91,13 @@ impl NonceGenerator for RandomBlob {
     }
 }
 
+/// Generates a random nonce of `len` bytes.
+pub(crate) fn random_nonce(len: usize) -> Vec<u8> {
+    let mut nonce = vec![0; len];
+    OsRng.fill(&mut nonce[..]);
+    nonce
+}
+
 #[derive(Default)]
 pub struct Empty;
 impl NonceGenerator for Empty {
@@ -110,6 +124,31 @@ impl NonceGenerator for Incrementing {
     }
 }
 
+/// A [`NonceGenerator`] producing the 8-byte big-endian encodings of consecutive integers, for
+/// deterministic tests, e.g. of the request IDs of update calls. Unlike
+/// [`NonceFactory::incrementing`], whose nonces are little-endian, the nonces sort in the order
+/// they were generated.
+#[derive(Debug, Default)]
+pub struct SequentialNonce {
+    next: AtomicU64,
+}
+
+impl SequentialNonce {
+    /// Create a generator whose first nonce is `start`.
+    pub fn new(start: u64) -> Self {
+        Self {
+            next: AtomicU64::new(start),
+        }
+    }
+}
+
+impl NonceGenerator for SequentialNonce {
+    fn generate(&self) -> Option<Vec<u8>> {
+        let nonce = self.next.fetch_add(1, Ordering::Relaxed);
+        Some(nonce.to_be_bytes().to_vec())
+    }
+}
+
 impl<N: NonceGenerator + ?Sized> NonceGenerator for Box<N> {
     fn generate(&self) -> Option<Vec<u8>> {
         (**self).generate()
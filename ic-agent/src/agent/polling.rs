@@ -0,0 +1,158 @@
+use super::sleep;
+use crate::AgentError;
+use backoff::{backoff::Backoff, ExponentialBackoff, ExponentialBackoffBuilder};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+/// How long to wait between two polls of the status of an update call, e.g. in
+/// [`Agent::wait_with_strategy`](super::Agent::wait_with_strategy).
+pub trait PollStrategy: Send + Sync {
+    /// The delay to wait after the poll number `iteration`, starting at 1, or `None` to give up
+    /// with [`AgentError::TimeoutWaitingForResponse`](crate::AgentError::TimeoutWaitingForResponse).
+    /// The iterations start again at 1 once the call is accepted by the replica.
+    fn next_delay(&mut self, iteration: u32) -> Option<Duration>;
+}
+
+impl<T: PollStrategy + ?Sized> PollStrategy for &mut T {
+    fn next_delay(&mut self, iteration: u32) -> Option<Duration> {
+        (**self).next_delay(iteration)
+    }
+}
+
+impl<T: PollStrategy + ?Sized> PollStrategy for Box<T> {
+    fn next_delay(&mut self, iteration: u32) -> Option<Duration> {
+        (**self).next_delay(iteration)
+    }
+}
+
+/// When to give up waiting for the reply to an update call, in addition to the 5 minutes
+/// [`Agent::wait`](super::Agent::wait) waits for at most.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WaitLimits {
+    /// Give up at this time, e.g. the deadline of the request the call is made for.
+    pub deadline: Option<SystemTime>,
+    /// The ingress expiry of the call. If the replica still does not know the call after it, the
+    /// call was never received, and cannot be anymore.
+    pub ingress_expiry: Option<SystemTime>,
+}
+
+/// The longest time to wait for a call, from the time it is accepted.
+const MAX_WAIT: Duration = Duration::from_secs(60 * 5);
+
+/// When to poll the status of an update call, and when to give up: the loop of
+/// [`Agent::wait_with_strategy`](super::Agent::wait_with_strategy), for callers polling the status
+/// themselves, e.g. to report its changes.
+///
+/// The call is waited for at most 5 minutes, counting the time spent polling, and this restarts
+/// once it is accepted.
+pub struct PollSchedule<'a> {
+    strategy: Box<dyn PollStrategy + 'a>,
+    limits: WaitLimits,
+    give_up_at: SystemTime,
+    iteration: u32,
+    accepted: bool,
+}
+
+impl fmt::Debug for PollSchedule<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PollSchedule")
+            .field("limits", &self.limits)
+            .field("iteration", &self.iteration)
+            .field("accepted", &self.accepted)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> PollSchedule<'a> {
+    /// A schedule following `strategy`, or the backoff of [`Agent::wait`](super::Agent::wait)
+    /// if it is `None`, within `limits`.
+    pub fn new(strategy: Option<Box<dyn PollStrategy + 'a>>, limits: WaitLimits) -> Self {
+        Self {
+            strategy: strategy.unwrap_or_else(|| Box::new(DefaultPoll::new())),
+            limits,
+            give_up_at: now() + MAX_WAIT,
+            iteration: 1,
+            accepted: false,
+        }
+    }
+
+    /// Record that the replica does not know the call, which fails with
+    /// [`AgentError::TimeoutWaitingForResponse`] once it expired, as it cannot be received anymore.
+    pub fn unknown(&self) -> Result<(), AgentError> {
+        match self.limits.ingress_expiry {
+            Some(expiry) if now() > expiry => Err(AgentError::TimeoutWaitingForResponse()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record that the replica received the call. The first time, the schedule restarts.
+    pub fn accepted(&mut self) {
+        if !self.accepted {
+            // The system will return RequestStatusResponse::Unknown
+            // (PollResult::Submitted) until the request is accepted
+            // and we generally cannot know how long that will take.
+            // State transitions between Received and Processing may be
+            // instantaneous. Therefore, once we know the request is accepted,
+            // we should restart the backoff so the request does not time out.
+            self.give_up_at = now() + MAX_WAIT;
+            self.iteration = 1;
+            self.accepted = true;
+        }
+    }
+
+    /// Wait until the next poll, or fail with [`AgentError::TimeoutWaitingForResponse`] if the
+    /// strategy gives up, or a limit is reached.
+    pub async fn wait(&mut self) -> Result<(), AgentError> {
+        let give_up_at = match self.limits.deadline {
+            Some(deadline) => deadline.min(self.give_up_at),
+            None => self.give_up_at,
+        };
+        let remaining = give_up_at.duration_since(now()).unwrap_or(Duration::ZERO);
+        match self.strategy.next_delay(self.iteration) {
+            Some(delay) if !remaining.is_zero() => {
+                sleep(delay.min(remaining)).await;
+                self.iteration += 1;
+                Ok(())
+            }
+            _ => Err(AgentError::TimeoutWaitingForResponse()),
+        }
+    }
+}
+
+/// The strategy of [`Agent::wait`](super::Agent::wait): wait 500ms after the first poll, then
+/// 1.4 times longer after each poll, up to 1s.
+struct DefaultPoll(ExponentialBackoff);
+
+impl DefaultPoll {
+    fn new() -> Self {
+        Self(
+            ExponentialBackoffBuilder::new()
+                .with_initial_interval(Duration::from_millis(500))
+                .with_max_interval(Duration::from_secs(1))
+                .with_multiplier(1.4)
+                .with_max_elapsed_time(None)
+                .build(),
+        )
+    }
+}
+
+impl PollStrategy for DefaultPoll {
+    fn next_delay(&mut self, iteration: u32) -> Option<Duration> {
+        if iteration == 1 {
+            self.0.reset();
+        }
+        self.0.next_backoff()
+    }
+}
+
+/// The current time, from the browser's clock on WASM targets.
+pub(crate) fn now() -> SystemTime {
+    #[cfg(not(target_family = "wasm"))]
+    {
+        SystemTime::now()
+    }
+    #[cfg(all(target_family = "wasm", feature = "wasm-bindgen"))]
+    {
+        std::time::UNIX_EPOCH + Duration::from_nanos((js_sys::Date::now() * 1_000_000.) as _)
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{export::Principal, AgentError};
+use crate::{export::Principal, identity::SignedDelegation, AgentError};
 use ic_certification::Label;
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
@@ -15,6 +15,9 @@ pub struct Envelope<T: Serialize> {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(with = "serde_bytes")]
     pub sender_sig: Option<Vec<u8>>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender_delegation: Option<Vec<SignedDelegation>>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -178,6 +181,27 @@ pub enum RejectCode {
     CanisterError = 5,
 }
 
+impl RejectCode {
+    /// Whether the rejection is due to a temporary condition of the system, so that the same
+    /// call may succeed if it is retried later.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, RejectCode::SysTransient)
+    }
+}
+
+impl std::fmt::Display for RejectCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            RejectCode::SysFatal => "SYS_FATAL",
+            RejectCode::SysTransient => "SYS_TRANSIENT",
+            RejectCode::DestinationInvalid => "DESTINATION_INVALID",
+            RejectCode::CanisterReject => "CANISTER_REJECT",
+            RejectCode::CanisterError => "CANISTER_ERROR",
+        };
+        write!(f, "{} ({})", name, *self as u8)
+    }
+}
+
 impl TryFrom<u64> for RejectCode {
     type Error = AgentError;
 
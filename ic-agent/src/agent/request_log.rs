@@ -0,0 +1,138 @@
+use crate::{
+    agent::{AgentFuture, Transport},
+    export::Principal,
+    AgentError, RequestId,
+};
+use std::{sync::Arc, time::Duration};
+
+/// A record of a single exchange with the replica, passed to the logger set with
+/// [`with_request_logger`](super::AgentBuilder::with_request_logger).
+#[derive(Debug, Clone)]
+pub struct RequestLog {
+    /// The Unix timestamp, in nanoseconds, at which the request was sent.
+    pub timestamp: u64,
+    /// The HTTP method of the request.
+    pub method: &'static str,
+    /// The endpoint of the request, relative to `/api/v2/`.
+    pub url: String,
+    /// The size of the request body, in bytes.
+    pub request_body_len: usize,
+    /// The HTTP status of the response. Transports only report the status of failed
+    /// requests, so this is `None` if the request succeeded or no response was received.
+    pub response_status: Option<u16>,
+    /// The size of the response body, in bytes.
+    pub response_body_len: usize,
+    /// How long the exchange took.
+    pub duration: Duration,
+}
+
+/// A [`Transport`] that reports every exchange of the wrapped transport to a logger.
+pub(crate) struct LoggedTransport {
+    pub(crate) inner: Arc<dyn Transport>,
+    pub(crate) logger: Arc<dyn Fn(RequestLog) + Send + Sync>,
+}
+
+impl LoggedTransport {
+    fn log<V>(
+        &self,
+        method: &'static str,
+        url: String,
+        request_body_len: usize,
+        start: Duration,
+        result: &Result<V, AgentError>,
+        response_body_len: impl FnOnce(&V) -> usize,
+    ) {
+        let (response_status, response_body_len) = match result {
+            Ok(v) => (None, response_body_len(v)),
+            Err(AgentError::HttpError(payload)) => (Some(payload.status), payload.content.len()),
+            Err(_) => (None, 0),
+        };
+        (self.logger)(RequestLog {
+            timestamp: start.as_nanos() as u64,
+            method,
+            url,
+            request_body_len,
+            response_status,
+            response_body_len,
+            duration: now().saturating_sub(start),
+        });
+    }
+}
+
+impl Transport for LoggedTransport {
+    fn call(
+        &self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+        request_id: RequestId,
+    ) -> AgentFuture<()> {
+        Box::pin(async move {
+            let url = format!("canister/{effective_canister_id}/call");
+            let request_body_len = envelope.len();
+            let start = now();
+            let result = self
+                .inner
+                .call(effective_canister_id, envelope, request_id)
+                .await;
+            self.log("POST", url, request_body_len, start, &result, |_| 0);
+            result
+        })
+    }
+
+    fn read_state(
+        &self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> AgentFuture<Vec<u8>> {
+        Box::pin(async move {
+            let url = format!("canister/{effective_canister_id}/read_state");
+            let request_body_len = envelope.len();
+            let start = now();
+            let result = self.inner.read_state(effective_canister_id, envelope).await;
+            self.log("POST", url, request_body_len, start, &result, Vec::len);
+            result
+        })
+    }
+
+    fn query(&self, effective_canister_id: Principal, envelope: Vec<u8>) -> AgentFuture<Vec<u8>> {
+        Box::pin(async move {
+            let url = format!("canister/{effective_canister_id}/query");
+            let request_body_len = envelope.len();
+            let start = now();
+            let result = self.inner.query(effective_canister_id, envelope).await;
+            self.log("POST", url, request_body_len, start, &result, Vec::len);
+            result
+        })
+    }
+
+    fn status(&self) -> AgentFuture<Vec<u8>> {
+        Box::pin(async move {
+            let start = now();
+            let result = self.inner.status().await;
+            self.log("GET", "status".to_string(), 0, start, &result, Vec::len);
+            result
+        })
+    }
+
+    fn replica_time(&self) -> AgentFuture<u64> {
+        Box::pin(async move {
+            let start = now();
+            let result = self.inner.replica_time().await;
+            self.log("GET", "status".to_string(), 0, start, &result, |_| 0);
+            result
+        })
+    }
+}
+
+pub(crate) fn now() -> Duration {
+    #[cfg(not(target_family = "wasm"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("Time wrapped around.")
+    }
+    #[cfg(all(target_family = "wasm", feature = "wasm-bindgen"))]
+    {
+        Duration::from_nanos((js_sys::Date::now() * 1_000_000.) as _)
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{
+    agent::{sleep, AgentFuture, Transport},
+    export::Principal,
+    RequestId,
+};
+use rand::Rng;
+use std::{sync::Arc, time::Duration};
+
+/// How the agent retries transport calls that fail with a transient error, set with
+/// [`with_exponential_backoff`](super::AgentBuilder::with_exponential_backoff).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExponentialBackoffConfig {
+    /// The maximum number of attempts of each call, including the first one.
+    pub max_attempts: u32,
+    /// The delay before the first retry, in milliseconds. It doubles after each retry.
+    pub base_delay_ms: u64,
+    /// The maximum delay between two attempts, in milliseconds.
+    pub max_delay_ms: u64,
+    /// Whether to wait a random duration between half of the delay and the full delay, so
+    /// that clients that failed at the same time do not all retry at the same time.
+    pub jitter: bool,
+}
+
+impl Default for ExponentialBackoffConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay_ms: 100,
+            max_delay_ms: 2_000,
+            jitter: true,
+        }
+    }
+}
+
+impl ExponentialBackoffConfig {
+    /// The delay before the retry following the attempt `attempt`, counting from 0.
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay_ms
+            .saturating_mul(1u64.checked_shl(attempt).unwrap_or(u64::MAX))
+            .min(self.max_delay_ms);
+        let delay = if self.jitter && delay > 1 {
+            rand::thread_rng().gen_range(delay / 2..=delay)
+        } else {
+            delay
+        };
+        Duration::from_millis(delay)
+    }
+}
+
+/// A [`Transport`] that retries the calls of the wrapped transport that fail with a
+/// [transient](crate::AgentError::is_transient) error.
+pub(crate) struct RetryingTransport {
+    pub(crate) inner: Arc<dyn Transport>,
+    pub(crate) config: ExponentialBackoffConfig,
+}
+
+impl RetryingTransport {
+    fn retry<'a, T: 'a + Send>(
+        &'a self,
+        send: impl 'a + Fn() -> AgentFuture<'a, T> + Send + Sync,
+    ) -> AgentFuture<'a, T> {
+        Box::pin(async move {
+            let mut attempt = 0;
+            loop {
+                match send().await {
+                    Err(e) if e.is_transient() && attempt + 1 < self.config.max_attempts => {
+                        sleep(self.config.delay(attempt)).await;
+                        attempt += 1;
+                    }
+                    result => return result,
+                }
+            }
+        })
+    }
+}
+
+impl Transport for RetryingTransport {
+    fn call(
+        &self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+        request_id: RequestId,
+    ) -> AgentFuture<()> {
+        self.retry(move || {
+            self.inner
+                .call(effective_canister_id, envelope.clone(), request_id)
+        })
+    }
+
+    fn read_state(
+        &self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> AgentFuture<Vec<u8>> {
+        self.retry(move || {
+            self.inner
+                .read_state(effective_canister_id, envelope.clone())
+        })
+    }
+
+    fn query(&self, effective_canister_id: Principal, envelope: Vec<u8>) -> AgentFuture<Vec<u8>> {
+        self.retry(move || self.inner.query(effective_canister_id, envelope.clone()))
+    }
+
+    fn status(&self) -> AgentFuture<Vec<u8>> {
+        self.retry(|| self.inner.status())
+    }
+
+    fn replica_time(&self) -> AgentFuture<u64> {
+        self.retry(|| self.inner.replica_time())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_is_capped() {
+        let config = ExponentialBackoffConfig {
+            max_attempts: 10,
+            base_delay_ms: 100,
+            max_delay_ms: 1_000,
+            jitter: false,
+        };
+        assert_eq!(config.delay(0), Duration::from_millis(100));
+        assert_eq!(config.delay(2), Duration::from_millis(400));
+        assert_eq!(config.delay(5), Duration::from_millis(1_000));
+        assert_eq!(config.delay(80), Duration::from_millis(1_000));
+
+        let config = ExponentialBackoffConfig {
+            jitter: true,
+            ..config
+        };
+        let delay = config.delay(2);
+        assert!(Duration::from_millis(200) <= delay && delay <= Duration::from_millis(400));
+    }
+}
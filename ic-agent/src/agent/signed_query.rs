@@ -0,0 +1,379 @@
+//! Verification of the node signatures of query responses.
+//!
+//! Unlike update calls, query responses are not certified by the subnet. Instead, the replica
+//! that answered signs them with its node key, which the subnet certifies at
+//! `/subnet/<subnet_id>/node/<node_id>/public_key` in the state tree.
+
+use crate::{
+    agent::{replica_api::QueryResponse, AgentError},
+    export::Principal,
+    to_request_id, RequestId,
+};
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const IC_RESPONSE_DOMAIN_SEPARATOR: &[u8; 12] = b"\x0Bic-response";
+
+// The DER prefix of an Ed25519 public key, as in RFC 8410.
+const ED25519_DER_PREFIX: &[u8; 12] = b"\x30\x2a\x30\x05\x06\x03\x2b\x65\x70\x03\x21\x00";
+
+/// Whether the [`Agent`](super::Agent) verifies the node signatures of query responses.
+/// Set with [`AgentBuilder::with_signed_query_verification`](super::AgentBuilder::with_signed_query_verification).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignedQueryPolicy {
+    /// Query responses are trusted as is.
+    #[default]
+    Disabled,
+    /// The signatures of query responses are verified, but unsigned responses are accepted,
+    /// e.g. from replicas that do not sign them yet.
+    ///
+    /// This only detects tampering by whoever strips the signatures along with it: a response
+    /// whose signatures were removed on the way, e.g. by a boundary node, is accepted as is. Use
+    /// [`VerifySignatureOrFail`](Self::VerifySignatureOrFail) against such tampering.
+    VerifySignature,
+    /// The signatures of query responses are verified, and unsigned responses are rejected
+    /// with [`AgentError::MissingQuerySignature`].
+    VerifySignatureOrFail,
+}
+
+/// A signature of a query response by a node.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct NodeSignature {
+    timestamp: u64,
+    #[serde(with = "serde_bytes")]
+    signature: Vec<u8>,
+    identity: Principal,
+}
+
+/// The signatures of a query response, which are ignored when it is decoded as a
+/// [`QueryResponse`].
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct QuerySignatures {
+    #[serde(default)]
+    pub(crate) signatures: Vec<NodeSignature>,
+}
+
+/// The certified node keys of a subnet, cached by the agent per subnet.
+#[derive(Debug, Clone)]
+pub(crate) struct SubnetKeys {
+    pub(crate) canister_ranges: Vec<(Principal, Principal)>,
+    pub(crate) node_keys: HashMap<Principal, Vec<u8>>,
+}
+
+#[derive(Serialize)]
+struct Reply<'a> {
+    #[serde(with = "serde_bytes")]
+    arg: &'a [u8],
+}
+
+#[derive(Serialize)]
+struct RepliedContent<'a> {
+    status: &'static str,
+    reply: Reply<'a>,
+    timestamp: u64,
+    #[serde(with = "serde_bytes")]
+    request_id: &'a [u8],
+}
+
+#[derive(Serialize)]
+struct RejectedContent<'a> {
+    status: &'static str,
+    reject_code: u64,
+    reject_message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'a str>,
+    timestamp: u64,
+    #[serde(with = "serde_bytes")]
+    request_id: &'a [u8],
+}
+
+/// Verify that every signature of `response` to the query `request_id` was made by a node of
+/// the subnet whose keys are `keys`. With [`SignedQueryPolicy::VerifySignatureOrFail`], there
+/// must be at least one.
+pub(crate) fn verify_query_signatures(
+    policy: SignedQueryPolicy,
+    request_id: &RequestId,
+    response: &QueryResponse,
+    signatures: &[NodeSignature],
+    keys: &SubnetKeys,
+) -> Result<(), AgentError> {
+    if signatures.is_empty() && policy == SignedQueryPolicy::VerifySignatureOrFail {
+        return Err(AgentError::MissingQuerySignature);
+    }
+    for signature in signatures {
+        let hash = match response {
+            QueryResponse::Replied { reply } => to_request_id(&RepliedContent {
+                status: "replied",
+                reply: Reply { arg: &reply.arg },
+                timestamp: signature.timestamp,
+                request_id: request_id.as_slice(),
+            }),
+            QueryResponse::Rejected(reject) => to_request_id(&RejectedContent {
+                status: "rejected",
+                reject_code: reject.reject_code as u64,
+                reject_message: &reject.reject_message,
+                error_code: reject.error_code.as_deref(),
+                timestamp: signature.timestamp,
+                request_id: request_id.as_slice(),
+            }),
+        }?;
+        let mut msg = IC_RESPONSE_DOMAIN_SEPARATOR.to_vec();
+        msg.extend_from_slice(hash.as_slice());
+
+        let invalid = || AgentError::InvalidQuerySignature(signature.identity);
+        let key = keys
+            .node_keys
+            .get(&signature.identity)
+            .and_then(|der| der.strip_prefix(ED25519_DER_PREFIX.as_slice()))
+            .and_then(|key| <&[u8; 32]>::try_from(key).ok())
+            .and_then(|key| VerifyingKey::from_bytes(key).ok())
+            .ok_or_else(invalid)?;
+        let node_signature = Signature::from_slice(&signature.signature).map_err(|_| invalid())?;
+        key.verify_strict(&msg, &node_signature)
+            .map_err(|_| invalid())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::{replica_api::CallReply, RejectCode, RejectResponse};
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn node_keys(key: &SigningKey, node_id: Principal) -> SubnetKeys {
+        let mut der = ED25519_DER_PREFIX.to_vec();
+        der.extend_from_slice(key.verifying_key().as_bytes());
+        SubnetKeys {
+            canister_ranges: vec![],
+            node_keys: [(node_id, der)].into_iter().collect(),
+        }
+    }
+
+    fn sign(key: &SigningKey, node_id: Principal, content: &impl Serialize) -> NodeSignature {
+        let mut msg = IC_RESPONSE_DOMAIN_SEPARATOR.to_vec();
+        msg.extend_from_slice(to_request_id(content).unwrap().as_slice());
+        NodeSignature {
+            timestamp: 1,
+            signature: key.sign(&msg).to_bytes().to_vec(),
+            identity: node_id,
+        }
+    }
+
+    #[test]
+    fn replied() {
+        let key = SigningKey::from_bytes(&[3; 32]);
+        let node_id = Principal::from_slice(&[1; 29]);
+        let keys = node_keys(&key, node_id);
+        let request_id = RequestId::new(&[4; 32]);
+        let response = QueryResponse::Replied {
+            reply: CallReply { arg: vec![5, 6] },
+        };
+        let signature = sign(
+            &key,
+            node_id,
+            &RepliedContent {
+                status: "replied",
+                reply: Reply { arg: &[5, 6] },
+                timestamp: 1,
+                request_id: request_id.as_slice(),
+            },
+        );
+
+        let policy = SignedQueryPolicy::VerifySignatureOrFail;
+        verify_query_signatures(policy, &request_id, &response, &[signature.clone()], &keys)
+            .unwrap();
+        assert!(matches!(
+            verify_query_signatures(policy, &request_id, &response, &[], &keys),
+            Err(AgentError::MissingQuerySignature)
+        ));
+        verify_query_signatures(
+            SignedQueryPolicy::VerifySignature,
+            &request_id,
+            &response,
+            &[],
+            &keys,
+        )
+        .unwrap();
+
+        let forged = QueryResponse::Replied {
+            reply: CallReply { arg: vec![7] },
+        };
+        assert!(matches!(
+            verify_query_signatures(policy, &request_id, &forged, &[signature.clone()], &keys),
+            Err(AgentError::InvalidQuerySignature(id)) if id == node_id
+        ));
+        let unknown_node = NodeSignature {
+            identity: Principal::from_slice(&[2; 29]),
+            ..signature
+        };
+        assert!(
+            verify_query_signatures(policy, &request_id, &response, &[unknown_node], &keys)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rejected() {
+        let key = SigningKey::from_bytes(&[3; 32]);
+        let node_id = Principal::from_slice(&[1; 29]);
+        let request_id = RequestId::new(&[4; 32]);
+        let response = QueryResponse::Rejected(RejectResponse {
+            reject_code: RejectCode::CanisterError,
+            reject_message: "trapped".to_string(),
+            error_code: Some("IC0503".to_string()),
+        });
+        let signature = sign(
+            &key,
+            node_id,
+            &RejectedContent {
+                status: "rejected",
+                reject_code: 5,
+                reject_message: "trapped",
+                error_code: Some("IC0503"),
+                timestamp: 1,
+                request_id: request_id.as_slice(),
+            },
+        );
+        verify_query_signatures(
+            SignedQueryPolicy::VerifySignature,
+            &request_id,
+            &response,
+            &[signature],
+            &node_keys(&key, node_id),
+        )
+        .unwrap();
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn signed_query_policy_override() {
+        use crate::agent::mock::{MockTransport, Response};
+
+        let canister_id = Principal::management_canister();
+        let agent = crate::Agent::builder()
+            .with_transport(
+                MockTransport::new().expect_query(canister_id, Response::query_reply(vec![1])),
+            )
+            .with_signed_query_verification(SignedQueryPolicy::VerifySignatureOrFail)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            agent.query(&canister_id, "greet").call().await,
+            Err(AgentError::MissingQuerySignature)
+        );
+        let reply = agent
+            .query(&canister_id, "greet")
+            .with_signed_query_policy(SignedQueryPolicy::Disabled)
+            .call()
+            .await
+            .unwrap();
+        assert_eq!(reply, [1]);
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[tokio::test]
+    async fn subnet_keys() {
+        use crate::agent::{
+            mock::{MockTransport, Response},
+            replica_api::QueryContent,
+        };
+        use std::{
+            sync::Arc,
+            time::{Duration, UNIX_EPOCH},
+        };
+
+        #[derive(Serialize)]
+        struct WireSignature {
+            timestamp: u64,
+            #[serde(with = "serde_bytes")]
+            signature: Vec<u8>,
+            identity: Principal,
+        }
+        #[derive(Serialize)]
+        struct SignedReply<'a> {
+            status: &'static str,
+            reply: Reply<'a>,
+            signatures: Vec<WireSignature>,
+        }
+
+        let canister_id = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 1, 1]);
+        let subnet_id = Principal::from_slice(&[9; 29]);
+        let node_id = Principal::from_slice(&[1; 29]);
+        let key = SigningKey::from_bytes(&[3; 32]);
+        let rotated_key = SigningKey::from_bytes(&[4; 32]);
+        let expiry = UNIX_EPOCH + Duration::from_secs(2_000_000_000);
+        let request_id = to_request_id(&QueryContent::QueryRequest {
+            ingress_expiry: 2_000_000_000_000_000_000,
+            sender: Principal::anonymous(),
+            canister_id,
+            method_name: "greet".to_string(),
+            arg: vec![],
+        })
+        .unwrap();
+        let signature = sign(
+            &key,
+            node_id,
+            &RepliedContent {
+                status: "replied",
+                reply: Reply { arg: &[1] },
+                timestamp: 1,
+                request_id: request_id.as_slice(),
+            },
+        );
+        let body = serde_cbor::to_vec(&SignedReply {
+            status: "replied",
+            reply: Reply { arg: &[1] },
+            signatures: vec![WireSignature {
+                timestamp: signature.timestamp,
+                signature: signature.signature,
+                identity: signature.identity,
+            }],
+        })
+        .unwrap();
+
+        let agent = crate::Agent::builder()
+            .with_transport(
+                MockTransport::new()
+                    .expect_query(canister_id, Response::Bytes(body))
+                    .expect_read_state(
+                        canister_id,
+                        Response::error(|| AgentError::MessageError("no state".to_string())),
+                    ),
+            )
+            .with_signed_query_verification(SignedQueryPolicy::VerifySignatureOrFail)
+            .build()
+            .unwrap();
+        let query = || async {
+            agent
+                .query(&canister_id, "greet")
+                .expire_at(expiry)
+                .call()
+                .await
+        };
+        let cache = |key: &SigningKey| {
+            let keys = SubnetKeys {
+                canister_ranges: vec![(canister_id, canister_id)],
+                ..node_keys(key, node_id)
+            };
+            let mut cached = agent.subnet_keys.lock().unwrap();
+            cached.clear();
+            cached.insert(subnet_id, Arc::new(keys));
+        };
+
+        // Without cached keys, they are read from the state tree of the canister's subnet.
+        let no_state = Err(AgentError::MessageError("no state".to_string()));
+        assert_eq!(query().await, no_state);
+
+        // Cached keys are used as is.
+        cache(&key);
+        assert_eq!(query().await, Ok(vec![1]));
+
+        // Cached keys that do not verify the signature are dropped and read again.
+        cache(&rotated_key);
+        assert_eq!(query().await, no_state);
+        assert!(agent.subnet_keys.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,161 @@
+//! Utilities for working with the subnets of the Internet Computer.
+
+use crate::{export::Principal, AgentError};
+use serde_cbor::Value;
+use std::collections::BTreeMap;
+
+/// Find the subnet that hosts `canister_id`, given the canister ID ranges assigned to each subnet.
+///
+/// Each entry of `ranges` is a `(subnet_id, (start, end))` tuple, where `start` and `end` are the
+/// first and last canister IDs (inclusive) assigned to the subnet. As in the IC's routing table,
+/// canister IDs are compared by their bytes, so the big-endian canister index encoded in them
+/// determines the order. Returns [None] if no range contains the canister.
+pub fn subnet_id_from_canister_id(
+    canister_id: &Principal,
+    ranges: &[(Principal, (Principal, Principal))],
+) -> Option<Principal> {
+    let id = canister_id.as_slice();
+    ranges
+        .iter()
+        .find(|(_, (start, end))| start.as_slice() <= id && id <= end.as_slice())
+        .map(|(subnet_id, _)| *subnet_id)
+}
+
+/// The metrics of a subnet, from the certified `/subnet/<subnet_id>/metrics` path of the state
+/// tree. Read with [`Agent::read_subnet_metrics`](crate::Agent::read_subnet_metrics).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubnetMetrics {
+    /// The number of canisters on the subnet.
+    pub num_canisters: u64,
+    /// The total size of the state of the canisters on the subnet, in bytes.
+    pub canister_state_bytes: u64,
+    /// The total number of cycles consumed by the canisters on the subnet, including deleted ones.
+    pub consumed_cycles_total: u128,
+    /// The total number of update transactions executed on the subnet.
+    pub update_transactions_total: u64,
+}
+
+impl SubnetMetrics {
+    /// Decode the CBOR map the metrics are stored as, which is keyed by field index. The
+    /// consumed cycles are a map of their low and high 64 bits, the latter being optional.
+    pub(crate) fn from_cbor(bytes: &[u8]) -> Result<Self, AgentError> {
+        let value: Value = serde_cbor::from_slice(bytes)?;
+        let metrics = as_map(&value)?;
+        let consumed_cycles_total = as_map(field(metrics, 2)?)?;
+        let low = as_u64(field(consumed_cycles_total, 0)?)?;
+        let high = match consumed_cycles_total.get(&Value::Integer(1)) {
+            Some(high) => as_u64(high)?,
+            None => 0,
+        };
+        Ok(Self {
+            num_canisters: as_u64(field(metrics, 0)?)?,
+            canister_state_bytes: as_u64(field(metrics, 1)?)?,
+            consumed_cycles_total: (u128::from(high) << 64) | u128::from(low),
+            update_transactions_total: as_u64(field(metrics, 3)?)?,
+        })
+    }
+}
+
+fn malformed() -> AgentError {
+    AgentError::MessageError("Malformed subnet metrics.".to_string())
+}
+
+fn as_map(value: &Value) -> Result<&BTreeMap<Value, Value>, AgentError> {
+    match value {
+        Value::Map(map) => Ok(map),
+        _ => Err(malformed()),
+    }
+}
+
+fn field(map: &BTreeMap<Value, Value>, index: i128) -> Result<&Value, AgentError> {
+    map.get(&Value::Integer(index)).ok_or_else(malformed)
+}
+
+fn as_u64(value: &Value) -> Result<u64, AgentError> {
+    match value {
+        Value::Integer(n) => u64::try_from(*n).map_err(|_| malformed()),
+        _ => Err(malformed()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics(consumed_cycles_total: Vec<(i128, i128)>) -> Vec<u8> {
+        let consumed_cycles_total = consumed_cycles_total
+            .into_iter()
+            .map(|(k, v)| (Value::Integer(k), Value::Integer(v)))
+            .collect();
+        let metrics = [
+            (0, Value::Integer(12)),
+            (1, Value::Integer(4096)),
+            (2, Value::Map(consumed_cycles_total)),
+            (3, Value::Integer(100)),
+        ]
+        .into_iter()
+        .map(|(k, v)| (Value::Integer(k), v))
+        .collect();
+        serde_cbor::to_vec(&Value::Map(metrics)).unwrap()
+    }
+
+    #[test]
+    fn decodes_subnet_metrics() {
+        assert_eq!(
+            SubnetMetrics::from_cbor(&metrics(vec![(0, 5), (1, 2)])).unwrap(),
+            SubnetMetrics {
+                num_canisters: 12,
+                canister_state_bytes: 4096,
+                consumed_cycles_total: (2 << 64) + 5,
+                update_transactions_total: 100,
+            }
+        );
+        assert_eq!(
+            SubnetMetrics::from_cbor(&metrics(vec![(0, 5)]))
+                .unwrap()
+                .consumed_cycles_total,
+            5
+        );
+        assert!(SubnetMetrics::from_cbor(&metrics(vec![])).is_err());
+        assert!(SubnetMetrics::from_cbor(b"\x01").is_err());
+    }
+
+    #[test]
+    fn finds_subnet_for_canister() {
+        let subnet_a = Principal::from_slice(&[1; 29]);
+        let subnet_b = Principal::from_slice(&[2; 29]);
+        let ranges = [
+            (
+                subnet_a,
+                (
+                    Principal::from_text("rwlgt-iiaaa-aaaaa-aaaaa-cai").unwrap(),
+                    Principal::from_text("renrk-eyaaa-aaaaa-aaada-cai").unwrap(),
+                ),
+            ),
+            (
+                subnet_b,
+                (
+                    Principal::from_text("qoctq-giaaa-aaaaa-aaaea-cai").unwrap(),
+                    Principal::from_text("abjo3-bqaaa-aaaaa-ap77q-cai").unwrap(),
+                ),
+            ),
+        ];
+
+        // canister index 1
+        let canister = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        assert_eq!(
+            subnet_id_from_canister_id(&canister, &ranges),
+            Some(subnet_a)
+        );
+        // canister index 8
+        let canister = Principal::from_text("qoctq-giaaa-aaaaa-aaaea-cai").unwrap();
+        assert_eq!(
+            subnet_id_from_canister_id(&canister, &ranges),
+            Some(subnet_b)
+        );
+        assert_eq!(
+            subnet_id_from_canister_id(&Principal::management_canister(), &ranges),
+            None
+        );
+    }
+}
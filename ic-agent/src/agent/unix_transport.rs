@@ -0,0 +1,174 @@
+//! A [`Transport`] that connects to a local replica over a Unix domain socket.
+
+use std::{
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use hyper::{
+    client::connect::{Connected, Connection},
+    service::Service,
+    Body, Client, Uri,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::UnixStream,
+};
+
+use crate::{
+    agent::{http_transport::hyper_transport::HyperTransport, AgentFuture, Transport},
+    export::Principal,
+    AgentError, RequestId,
+};
+
+/// A [`Transport`] making HTTP calls to a replica listening on a Unix domain socket, e.g. a
+/// local replica, without going through the TCP stack.
+#[derive(Debug)]
+pub struct UnixTransport(HyperTransport<Body, Client<UnixConnector, Body>>);
+
+impl UnixTransport {
+    /// Creates a replica transport connecting to the Unix domain socket at `socket_path`.
+    ///
+    /// The socket is only connected to when the first request is made.
+    pub fn create(socket_path: impl AsRef<Path>) -> Result<Self, AgentError> {
+        let connector = UnixConnector {
+            socket_path: Arc::new(socket_path.as_ref().to_path_buf()),
+        };
+        // The host is only used for the `Host` header, the socket is connected to instead.
+        HyperTransport::create_with_service(
+            Uri::from_static("http://localhost"),
+            Client::builder().build(connector),
+        )
+        .map(Self)
+    }
+
+    /// Sets a max response body size limit
+    pub fn with_max_response_body_size(self, max_response_body_size: usize) -> Self {
+        Self(self.0.with_max_response_body_size(max_response_body_size))
+    }
+}
+
+impl Transport for UnixTransport {
+    fn call(
+        &self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+        request_id: RequestId,
+    ) -> AgentFuture<()> {
+        self.0.call(effective_canister_id, envelope, request_id)
+    }
+
+    fn read_state(
+        &self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> AgentFuture<Vec<u8>> {
+        self.0.read_state(effective_canister_id, envelope)
+    }
+
+    fn query(&self, effective_canister_id: Principal, envelope: Vec<u8>) -> AgentFuture<Vec<u8>> {
+        self.0.query(effective_canister_id, envelope)
+    }
+
+    fn status(&self) -> AgentFuture<Vec<u8>> {
+        self.0.status()
+    }
+}
+
+/// A [`hyper`] connector opening a connection to a Unix domain socket, whatever the URI.
+#[derive(Debug, Clone)]
+struct UnixConnector {
+    socket_path: Arc<PathBuf>,
+}
+
+impl Service<Uri> for UnixConnector {
+    type Response = UnixConnection;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<UnixConnection, io::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _uri: Uri) -> Self::Future {
+        let socket_path = self.socket_path.clone();
+        Box::pin(async move { UnixStream::connect(&*socket_path).await.map(UnixConnection) })
+    }
+}
+
+/// A connection made by a [`UnixConnector`].
+#[derive(Debug)]
+struct UnixConnection(UnixStream);
+
+impl Connection for UnixConnection {
+    fn connected(&self) -> Connected {
+        Connected::new()
+    }
+}
+
+impl AsyncRead for UnixConnection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for UnixConnection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::UnixTransport;
+    use crate::agent::Transport;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::UnixListener,
+    };
+
+    #[tokio::test]
+    async fn status() {
+        let socket_path =
+            std::env::temp_dir().join(format!("ic-agent-unix-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 4096];
+            let n = socket.read(&mut buf).await.unwrap();
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 6\r\n\r\nstatus")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&buf[..n]).into_owned()
+        });
+
+        let transport = UnixTransport::create(&socket_path).unwrap();
+        assert_eq!(transport.status().await.unwrap(), b"status");
+        let request = server.await.unwrap();
+        assert!(request.starts_with("GET /api/v2/status HTTP/1.1\r\n"));
+
+        std::fs::remove_file(&socket_path).unwrap();
+    }
+}
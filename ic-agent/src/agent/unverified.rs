@@ -0,0 +1,112 @@
+//! Calls returning the raw CBOR responses of the replica, without verifying them.
+
+use crate::{
+    agent::{sign_request, Agent},
+    export::Principal,
+    to_request_id, AgentError, RequestId,
+};
+use ic_certification::Label;
+
+/// Makes calls whose responses are returned as the raw CBOR bodies sent by the replica, e.g.
+/// for proxies, forensic tools or canister debuggers. Created with [`Agent::unverified`].
+///
+/// **The responses are neither verified nor decoded**: certificates are not checked against
+/// the root key, and query signatures are not checked either, so a malicious replica or
+/// boundary node can forge any of them. Do not act on their content without verifying it.
+#[derive(Debug, Clone, Copy)]
+pub struct UnverifiedAgent<'agent> {
+    agent: &'agent Agent,
+}
+
+impl Agent {
+    /// Make calls returning raw, unverified responses. See [UnverifiedAgent].
+    ///
+    /// Their responses may be forged, so they should only be used by tools that do not act on
+    /// them.
+    pub fn unverified(&self) -> UnverifiedAgent<'_> {
+        UnverifiedAgent { agent: self }
+    }
+}
+
+impl<'agent> UnverifiedAgent<'agent> {
+    /// Query `method` of `canister_id` with `arg`, and return the CBOR body of the response,
+    /// be it a reply or a rejection.
+    pub async fn query_raw(
+        &self,
+        canister_id: Principal,
+        method: &str,
+        arg: &[u8],
+        effective_canister_id: Principal,
+    ) -> Result<Vec<u8>, AgentError> {
+        let agent = self.agent;
+        let request = agent.query_content(&canister_id, method, arg, None, None)?;
+        let serialized_bytes =
+            sign_request(&request, agent.identity.clone(), &*agent.cbor_serializer)?;
+        agent
+            .transport
+            .query(effective_canister_id, serialized_bytes)
+            .await
+    }
+
+    /// Submit an update call to `method` of `canister_id` with `arg`, and return its request
+    /// ID. The replica does not send anything back but an acknowledgement, so the result of the
+    /// call has to be read with [`read_state_raw`](Self::read_state_raw), at the path
+    /// `request_status/<request_id>`.
+    pub async fn update_raw(
+        &self,
+        canister_id: Principal,
+        method: &str,
+        arg: &[u8],
+        effective_canister_id: Principal,
+    ) -> Result<RequestId, AgentError> {
+        let agent = self.agent;
+        let request = agent.update_content(&canister_id, method, arg, None, None)?;
+        let request_id = to_request_id(&request)?;
+        let serialized_bytes =
+            sign_request(&request, agent.identity.clone(), &*agent.cbor_serializer)?;
+        agent
+            .call_endpoint(effective_canister_id, request_id, serialized_bytes)
+            .await
+    }
+
+    /// Read `paths` of the state tree, and return the CBOR body of the response, whose
+    /// `certificate` field holds the CBOR-encoded certificate. Unlike
+    /// [`Agent::read_state_raw`], the certificate is not verified.
+    pub async fn read_state_raw(
+        &self,
+        paths: Vec<Vec<Label>>,
+        effective_canister_id: Principal,
+    ) -> Result<Vec<u8>, AgentError> {
+        let agent = self.agent;
+        let request = agent.read_state_content(paths)?;
+        let serialized_bytes =
+            sign_request(&request, agent.identity.clone(), &*agent.cbor_serializer)?;
+        agent
+            .transport
+            .read_state(effective_canister_id, serialized_bytes)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        agent::mock::{MockTransport, Response},
+        export::Principal,
+        Agent,
+    };
+
+    #[tokio::test]
+    async fn query_raw() {
+        let canister_id = Principal::management_canister();
+        let transport = MockTransport::new()
+            .expect_query(canister_id, Response::Bytes(b"not even CBOR".to_vec()));
+        let agent = Agent::builder().with_transport(transport).build().unwrap();
+        let response = agent
+            .unverified()
+            .query_raw(canister_id, "greet", &[], canister_id)
+            .await
+            .unwrap();
+        assert_eq!(response, b"not even CBOR");
+    }
+}
@@ -0,0 +1,305 @@
+//! A [`Transport`] that connects using the `fetch` API of the browser, in a window or in a worker.
+
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use js_sys::{ArrayBuffer, Uint8Array};
+use url::Url;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Headers, Request, RequestInit, Response, Window, WorkerGlobalScope};
+
+use crate::{
+    agent::{
+        http_transport::{response_body, IC0_DOMAIN, IC0_SUB_DOMAIN},
+        AgentFuture, Transport,
+    },
+    export::Principal,
+    AgentError, RequestId,
+};
+
+/// An HTTP request made by a [`WasmTransport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchRequest {
+    /// The HTTP method, `GET` or `POST`.
+    pub method: &'static str,
+    /// The URL of the endpoint.
+    pub url: String,
+    /// The CBOR-encoded body, if any.
+    pub body: Option<Vec<u8>>,
+}
+
+/// The HTTP response to a [`FetchRequest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchResponse {
+    /// The HTTP status code.
+    pub status: u16,
+    /// The value of the `Content-Type` header, if any.
+    pub content_type: Option<String>,
+    /// The body of the response.
+    pub body: Vec<u8>,
+}
+
+/// The future returned by a [`FetchHandler`].
+pub type FetchFuture = Pin<Box<dyn Future<Output = Result<FetchResponse, AgentError>>>>;
+
+/// A function making the HTTP requests of a [`WasmTransport`], in place of `fetch`.
+pub type FetchHandler = Arc<dyn Fn(FetchRequest) -> FetchFuture + Send + Sync>;
+
+/// A [`Transport`] using the `fetch` API of the browser to make HTTP calls to the Internet
+/// Computer. It works in a window as well as in a web worker.
+///
+/// Javascript objects are only created while a request is made, so the transport itself is
+/// [`Send`] and [`Sync`].
+pub struct WasmTransport {
+    url: Url,
+    fetch: Option<FetchHandler>,
+}
+
+impl std::fmt::Debug for WasmTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WasmTransport")
+            .field("url", &self.url)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WasmTransport {
+    /// Creates a replica transport from a HTTP URL.
+    pub fn create(url: &str) -> Result<Self, AgentError> {
+        Self::builder(url).build()
+    }
+
+    /// Creates a [`WasmTransportBuilder`] for a transport to the replica at the HTTP URL.
+    pub fn builder<U: Into<String>>(url: U) -> WasmTransportBuilder {
+        WasmTransportBuilder {
+            url: url.into(),
+            fetch: None,
+        }
+    }
+
+    async fn execute(
+        &self,
+        method: &'static str,
+        endpoint: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>, AgentError> {
+        let request = FetchRequest {
+            method,
+            url: self.url.join(endpoint)?.to_string(),
+            body,
+        };
+        let response = match &self.fetch {
+            Some(fetch) => fetch(request).await?,
+            None => fetch(request).await?,
+        };
+        response_body(
+            endpoint,
+            response.status,
+            response.content_type,
+            response.body,
+        )
+    }
+}
+
+/// A builder for a [`WasmTransport`], created with [`WasmTransport::builder`].
+pub struct WasmTransportBuilder {
+    url: String,
+    fetch: Option<FetchHandler>,
+}
+
+impl std::fmt::Debug for WasmTransportBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WasmTransportBuilder")
+            .field("url", &self.url)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WasmTransportBuilder {
+    /// Makes the requests with `fetch` instead of the `fetch` API of the browser, e.g. to mock
+    /// the replica in tests.
+    pub fn with_fetch_handler(
+        self,
+        fetch: impl Fn(FetchRequest) -> FetchFuture + Send + Sync + 'static,
+    ) -> Self {
+        WasmTransportBuilder {
+            fetch: Some(Arc::new(fetch)),
+            ..self
+        }
+    }
+
+    /// Creates the transport, failing if the URL is invalid.
+    pub fn build(self) -> Result<WasmTransport, AgentError> {
+        let url = Url::parse(&self.url)
+            .and_then(|mut url| {
+                // rewrite *.ic0.app to ic0.app
+                if let Some(domain) = url.domain() {
+                    if domain.ends_with(IC0_SUB_DOMAIN) {
+                        url.set_host(Some(IC0_DOMAIN))?;
+                    }
+                }
+                url.join("api/v2/")
+            })
+            .map_err(|_| AgentError::InvalidReplicaUrl(self.url.clone()))?;
+        Ok(WasmTransport {
+            url,
+            fetch: self.fetch,
+        })
+    }
+}
+
+fn js_error(error: JsValue) -> AgentError {
+    AgentError::TransportError(format!("{error:?}").into())
+}
+
+/// Make `request` with the `fetch` API of the browser.
+async fn fetch(request: FetchRequest) -> Result<FetchResponse, AgentError> {
+    let headers = Headers::new().map_err(js_error)?;
+    headers
+        .set("Content-Type", "application/cbor")
+        .map_err(js_error)?;
+    let mut init = RequestInit::new();
+    init.method(request.method).headers(&headers);
+    if let Some(body) = &request.body {
+        init.body(Some(&JsValue::from(Uint8Array::from(body.as_slice()))));
+    }
+    let js_request = Request::new_with_str_and_init(&request.url, &init).map_err(js_error)?;
+
+    // `fetch` is a method of the global scope, which is a `Window` or a `WorkerGlobalScope`
+    let global = js_sys::global();
+    let promise = if let Some(window) = global.dyn_ref::<Window>() {
+        window.fetch_with_request(&js_request)
+    } else if let Some(worker) = global.dyn_ref::<WorkerGlobalScope>() {
+        worker.fetch_with_request(&js_request)
+    } else {
+        return Err(AgentError::TransportError(
+            "The fetch API is only available in a window or a worker.".into(),
+        ));
+    };
+    let response: Response = JsFuture::from(promise)
+        .await
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(js_error)?;
+    let content_type = response.headers().get("Content-Type").map_err(js_error)?;
+    let body: ArrayBuffer = JsFuture::from(response.array_buffer().map_err(js_error)?)
+        .await
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(js_error)?;
+    Ok(FetchResponse {
+        status: response.status(),
+        content_type,
+        body: Uint8Array::new(&body).to_vec(),
+    })
+}
+
+impl Transport for WasmTransport {
+    fn call(
+        &self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+        _request_id: RequestId,
+    ) -> AgentFuture<()> {
+        Box::pin(async move {
+            let endpoint = format!("canister/{effective_canister_id}/call");
+            self.execute("POST", &endpoint, Some(envelope)).await?;
+            Ok(())
+        })
+    }
+
+    fn read_state(
+        &self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> AgentFuture<Vec<u8>> {
+        Box::pin(async move {
+            let endpoint = format!("canister/{effective_canister_id}/read_state");
+            self.execute("POST", &endpoint, Some(envelope)).await
+        })
+    }
+
+    fn query(&self, effective_canister_id: Principal, envelope: Vec<u8>) -> AgentFuture<Vec<u8>> {
+        Box::pin(async move {
+            let endpoint = format!("canister/{effective_canister_id}/query");
+            self.execute("POST", &endpoint, Some(envelope)).await
+        })
+    }
+
+    fn status(&self) -> AgentFuture<Vec<u8>> {
+        Box::pin(async move { self.execute("GET", "status", None).await })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn invalid_url() {
+        assert!(matches!(
+            WasmTransport::create("not a url"),
+            Err(AgentError::InvalidReplicaUrl(_))
+        ));
+        assert_eq!(
+            WasmTransport::create("https://foo.ic0.app")
+                .unwrap()
+                .url
+                .as_str(),
+            "https://ic0.app/api/v2/"
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn fetch_handler() {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let recorded = requests.clone();
+        let transport = WasmTransport::builder("https://ic0.app")
+            .with_fetch_handler(move |request| {
+                recorded.lock().unwrap().push(request);
+                Box::pin(async {
+                    Ok(FetchResponse {
+                        status: 200,
+                        content_type: None,
+                        body: b"status".to_vec(),
+                    })
+                })
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(transport.status().await.unwrap(), b"status");
+        assert_eq!(
+            requests.lock().unwrap()[0],
+            FetchRequest {
+                method: "GET",
+                url: "https://ic0.app/api/v2/status".to_string(),
+                body: None,
+            }
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn http_error() {
+        let transport = WasmTransport::builder("https://ic0.app")
+            .with_fetch_handler(|_| {
+                Box::pin(async {
+                    Ok(FetchResponse {
+                        status: 503,
+                        content_type: Some("text/plain".to_string()),
+                        body: b"overloaded".to_vec(),
+                    })
+                })
+            })
+            .build()
+            .unwrap();
+
+        assert!(matches!(
+            transport.status().await,
+            Err(AgentError::HttpError(payload))
+                if payload.status == 503 && payload.content == b"overloaded"
+        ));
+    }
+}
@@ -1,3 +1,67 @@
 //! A module to re-export types that are visible through the ic-agent API.
 #[doc(inline)]
 pub use candid::types::principal::{Principal, PrincipalError};
+
+/// Extension methods for [`Principal`].
+pub trait PrincipalExt: Sized {
+    /// Construct a principal from its raw bytes (not its textual representation), as they
+    /// appear in the Candid wire encoding. Fails if the bytes are too long to be a principal.
+    fn from_blob(bytes: &[u8]) -> Result<Self, PrincipalError>;
+
+    /// The raw bytes of the principal. Equivalent to `as_slice().to_vec()`.
+    fn to_blob(&self) -> Vec<u8>;
+
+    /// The raw bytes of the principal in lowercase hex, in groups of `group_size` bytes
+    /// separated by `sep`, e.g. `00000000-00000001-0101` for `rrkah-fqaaa-aaaaa-aaaaq-cai` in
+    /// groups of 4 bytes separated by `-`.
+    /// This is an alternative to the textual representation, for tooling that does not support
+    /// its base32 encoding, such as QR codes or other blockchain explorers.
+    ///
+    /// Panics if `group_size` is 0.
+    fn to_hex_groups(&self, sep: char, group_size: usize) -> String;
+}
+
+impl PrincipalExt for Principal {
+    fn from_blob(bytes: &[u8]) -> Result<Self, PrincipalError> {
+        Principal::try_from_slice(bytes)
+    }
+
+    fn to_blob(&self) -> Vec<u8> {
+        self.as_slice().to_vec()
+    }
+
+    fn to_hex_groups(&self, sep: char, group_size: usize) -> String {
+        let groups: Vec<String> = self
+            .as_slice()
+            .chunks(group_size)
+            .map(hex::encode)
+            .collect();
+        groups.join(&sep.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn principal_blob_roundtrip() {
+        let principal = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        let blob = principal.to_blob();
+        assert_eq!(blob, principal.as_slice());
+        assert_eq!(Principal::from_blob(&blob).unwrap(), principal);
+        assert!(Principal::from_blob(&[0; 30]).is_err());
+    }
+
+    #[test]
+    fn principal_hex_groups() {
+        let principal = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        assert_eq!(principal.to_hex_groups('-', 4), "00000000-00000001-0101");
+        assert_eq!(
+            principal.to_hex_groups(':', 1),
+            "00:00:00:00:00:00:00:01:01:01"
+        );
+        assert_eq!(principal.to_hex_groups('-', 32), "00000000000000010101");
+        assert_eq!(Principal::management_canister().to_hex_groups('-', 4), "");
+    }
+}
@@ -15,6 +15,7 @@ impl Identity for AnonymousIdentity {
         Ok(Signature {
             signature: None,
             public_key: None,
+            delegations: None,
         })
     }
 }
@@ -0,0 +1,192 @@
+use crate::{export::Principal, to_request_id, Identity, Signature};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+const IC_REQUEST_DELEGATION_DOMAIN_SEPARATOR: &[u8; 27] = b"\x1Aic-request-auth-delegation";
+
+/// A delegation from a key to another key, the `pubkey`, allowing it to sign requests on behalf
+/// of the delegating key until `expiration`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Delegation {
+    /// The DER-encoded public key the delegation is made to.
+    #[serde(with = "serde_bytes")]
+    pub pubkey: Vec<u8>,
+    /// The Unix timestamp, in nanoseconds, after which the delegation is no longer valid.
+    pub expiration: u64,
+    /// The canisters the delegated key may call. If `None`, it may call any canister.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub targets: Option<Vec<Principal>>,
+}
+
+/// A [Delegation], signed by the delegating key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedDelegation {
+    /// The delegation.
+    pub delegation: Delegation,
+    /// The signature of the delegation by the delegating key.
+    #[serde(with = "serde_bytes")]
+    pub signature: Vec<u8>,
+}
+
+/// An identity that signs requests with a session identity, on behalf of a key that delegated
+/// to it, e.g. through Internet Identity.
+///
+/// The caller will be represented via [`Principal::self_authenticating`] of the delegating key,
+/// not of the session identity.
+pub struct DelegatedIdentity {
+    from_key: Vec<u8>,
+    to: Box<dyn Identity>,
+    chain: Vec<SignedDelegation>,
+}
+
+impl DelegatedIdentity {
+    /// Create an identity signing with `to`, on behalf of the DER-encoded public key `from_key`,
+    /// which delegated to it through `chain`. The first delegation of the chain must be signed
+    /// by `from_key`, and the last one must be made to the public key of `to`.
+    pub fn new(from_key: Vec<u8>, to: Box<dyn Identity>, chain: Vec<SignedDelegation>) -> Self {
+        Self {
+            from_key,
+            to,
+            chain,
+        }
+    }
+
+    /// Sign a new delegation from `from` to `to`, valid until the Unix timestamp `expiration`,
+    /// in nanoseconds, and only for calls to `targets` if set. If `from` is itself delegated,
+    /// its delegation chain is extended.
+    pub fn create(
+        from: &dyn Identity,
+        to: Box<dyn Identity>,
+        expiration: u64,
+        targets: Option<Vec<Principal>>,
+    ) -> Result<Self, String> {
+        let delegation = Delegation {
+            pubkey: to
+                .public_key()
+                .ok_or("The identity to delegate to has no public key.")?,
+            expiration,
+            targets,
+        };
+        let hash = to_request_id(&delegation).map_err(|e| e.to_string())?;
+        let mut msg = IC_REQUEST_DELEGATION_DOMAIN_SEPARATOR.to_vec();
+        msg.extend_from_slice(hash.as_slice());
+        let signature = from.sign(&msg)?;
+
+        let mut chain = signature.delegations.unwrap_or_default();
+        chain.push(SignedDelegation {
+            delegation,
+            signature: signature
+                .signature
+                .ok_or("The delegating identity cannot sign.")?,
+        });
+        Ok(Self {
+            from_key: signature
+                .public_key
+                .ok_or("The delegating identity has no public key.")?,
+            to,
+            chain,
+        })
+    }
+
+    /// The delegations from the delegating key to the session identity.
+    pub fn delegation_chain(&self) -> &[SignedDelegation] {
+        &self.chain
+    }
+}
+
+impl fmt::Debug for DelegatedIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DelegatedIdentity")
+            .field("from_key", &self.from_key)
+            .field("chain", &self.chain)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Identity for DelegatedIdentity {
+    fn sender(&self) -> Result<Principal, String> {
+        Ok(Principal::self_authenticating(&self.from_key))
+    }
+
+    fn public_key(&self) -> Option<Vec<u8>> {
+        Some(self.from_key.clone())
+    }
+
+    fn sign(&self, blob: &[u8]) -> Result<Signature, String> {
+        let signature = self.to.sign(blob)?;
+        let mut delegations = self.chain.clone();
+        delegations.extend(signature.delegations.unwrap_or_default());
+        Ok(Signature {
+            public_key: Some(self.from_key.clone()),
+            signature: signature.signature,
+            delegations: Some(delegations),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{agent::mock::MockTransport, identity::BasicIdentity, Agent};
+    use ed25519_dalek::SigningKey;
+    use serde_cbor::Value;
+
+    fn get<'a>(value: &'a Value, key: &str) -> &'a Value {
+        match value {
+            Value::Map(map) => &map[&Value::Text(key.to_string())],
+            _ => panic!("{:?} is not a map", value),
+        }
+    }
+
+    #[test]
+    fn delegated_envelope() {
+        let root_key = SigningKey::from_bytes(&[1; 32]);
+        let root_public_key = root_key.verifying_key();
+        let root = BasicIdentity::from_signing_key(root_key);
+        let session = BasicIdentity::from_signing_key(SigningKey::from_bytes(&[2; 32]));
+        let session_public_key = session.public_key().unwrap();
+        let canister_id = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+
+        let identity =
+            DelegatedIdentity::create(&root, Box::new(session), u64::MAX, Some(vec![canister_id]))
+                .unwrap();
+        assert_eq!(identity.sender(), root.sender());
+
+        // The delegation is signed by the root key.
+        let [signed_delegation] = identity.delegation_chain() else {
+            panic!("expected a single delegation");
+        };
+        let mut msg = IC_REQUEST_DELEGATION_DOMAIN_SEPARATOR.to_vec();
+        msg.extend_from_slice(
+            to_request_id(&signed_delegation.delegation)
+                .unwrap()
+                .as_slice(),
+        );
+        let signature = ed25519_dalek::Signature::from_slice(&signed_delegation.signature).unwrap();
+        root_public_key.verify_strict(&msg, &signature).unwrap();
+
+        let agent = Agent::builder()
+            .with_transport(MockTransport::new())
+            .with_identity(identity)
+            .build()
+            .unwrap();
+        let signed = agent.update(&canister_id, "greet").sign().unwrap();
+        assert_eq!(signed.sender, root.sender().unwrap());
+
+        let envelope: Value = serde_cbor::from_slice(&signed.signed_update).unwrap();
+        assert_eq!(
+            get(&envelope, "sender_pubkey"),
+            &Value::Bytes(root.public_key().unwrap())
+        );
+        let Value::Array(delegations) = get(&envelope, "sender_delegation") else {
+            panic!("sender_delegation is not an array");
+        };
+        assert_eq!(delegations.len(), 1);
+        let delegation = get(&delegations[0], "delegation");
+        assert_eq!(get(delegation, "pubkey"), &Value::Bytes(session_public_key));
+        assert_eq!(
+            get(delegation, "targets"),
+            &Value::Array(vec![Value::Bytes(canister_id.as_slice().to_vec())])
+        );
+    }
+}
@@ -17,9 +17,14 @@ pub enum PemError {
     PemError(#[from] pem::PemError),
 
     /// The key was rejected by Ring.
+    #[cfg(feature = "ring")]
     #[error("A key was rejected by Ring: {0}")]
     KeyRejected(#[from] ring::error::KeyRejected),
 
+    /// The key was rejected by ed25519-dalek.
+    #[error("A key was rejected by ed25519-dalek: {0}")]
+    Ed25519KeyRejected(pkcs8::Error),
+
     /// The key was rejected by k256.
     #[error("A key was rejected by k256: {0}")]
     ErrorStack(#[from] k256::pkcs8::Error),
@@ -1,8 +1,9 @@
 //! Types and traits dealing with identity across the Internet Computer.
-use crate::export::Principal;
+use crate::{export::Principal, AgentError};
 
 pub(crate) mod anonymous;
 pub(crate) mod basic;
+pub(crate) mod delegated;
 pub(crate) mod secp256k1;
 
 #[cfg(feature = "pem")]
@@ -10,6 +11,7 @@ pub(crate) mod error;
 
 pub use anonymous::AnonymousIdentity;
 pub use basic::BasicIdentity;
+pub use delegated::{DelegatedIdentity, Delegation, SignedDelegation};
 pub use secp256k1::Secp256k1Identity;
 
 #[cfg(feature = "pem")]
@@ -22,6 +24,8 @@ pub struct Signature {
     pub public_key: Option<Vec<u8>>,
     /// The signature bytes.
     pub signature: Option<Vec<u8>>,
+    /// The delegations from `public_key` to the key that made the signature, if they differ.
+    pub delegations: Option<Vec<SignedDelegation>>,
 }
 
 /// An Identity takes a request id and returns the [Signature]. It knows or
@@ -34,7 +38,30 @@ pub trait Identity: Send + Sync {
     /// Only one sender can be used per request.
     fn sender(&self) -> Result<Principal, String>;
 
+    /// Returns the sender, like [`sender`](Self::sender), with its error as an [`AgentError`].
+    fn principal(&self) -> Result<Principal, AgentError> {
+        self.sender().map_err(AgentError::SigningError)
+    }
+
+    /// Returns the DER-encoded public key of the identity, if it has one, e.g. to delegate to
+    /// it with [`DelegatedIdentity::create`].
+    fn public_key(&self) -> Option<Vec<u8>> {
+        None
+    }
+
     /// Sign a blob, the concatenation of the domain separator & request ID,
     /// creating the sender signature.
     fn sign(&self, blob: &[u8]) -> Result<Signature, String>;
 }
+
+/// Returns the principal of `identity`.
+///
+/// # Panics
+///
+/// Panics if the identity cannot provide its principal, e.g. because a hardware key is
+/// unavailable. Use [`Identity::principal`] to handle this case.
+pub fn principal_of(identity: &dyn Identity) -> Principal {
+    identity
+        .principal()
+        .expect("the identity cannot provide its principal")
+}
@@ -125,7 +125,7 @@ pub use agent::{
     NonceFactory, NonceGenerator,
 };
 #[doc(inline)]
-pub use identity::{Identity, Signature};
+pub use identity::{principal_of, Identity, Signature};
 #[doc(inline)]
 pub use request_id::{to_request_id, RequestId, RequestIdError};
 
@@ -14,6 +14,9 @@ pub enum RequestIdFromStringError {
     FromHexError(hex::FromHexError),
 }
 
+/// An error from parsing a hexadecimal RequestId with [`RequestId::from_hex`](super::RequestId::from_hex).
+pub type RequestIdParseError = RequestIdFromStringError;
+
 /// An error during the calculation of the RequestId.
 ///
 /// Since we use serde for serializing a data type into a hash, this has to support traits that
@@ -6,10 +6,10 @@
 //!
 //! A single method is exported, to_request_id, which returns a RequestId
 //! (a 256 bits slice) or an error.
-use error::RequestIdFromStringError;
+use error::{RequestIdFromStringError, RequestIdParseError};
 use serde::{ser, Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::{collections::BTreeMap, iter::Extend, str::FromStr};
+use std::{collections::BTreeMap, fmt, iter::Extend, str::FromStr};
 
 pub mod error;
 #[doc(inline)]
@@ -36,6 +36,22 @@ impl RequestId {
     pub(crate) fn to_vec(self) -> Vec<u8> {
         self.0.to_vec()
     }
+
+    /// Returns the ID as a lowercase hexadecimal string. Equivalent to `to_string()`.
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+
+    /// Parses an ID from a 64-character hexadecimal string. Equivalent to `str::parse`.
+    pub fn from_hex(s: &str) -> Result<Self, RequestIdParseError> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
 }
 
 impl FromStr for RequestId {
@@ -55,7 +71,7 @@ impl FromStr for RequestId {
 
 impl From<RequestId> for String {
     fn from(id: RequestId) -> String {
-        hex::encode(id.0)
+        id.to_hex()
     }
 }
 
@@ -64,7 +80,8 @@ enum Hasher {
     /// that may directly contain a Struct.
     RequestId(Sha256),
 
-    /// A structure to be included in the hash.  May not contain other structures.
+    /// A structure to be included in the hash.  A structure nested in it is hashed as a
+    /// nested map, whose hash is the hash of its fields like the request id's.
     Struct {
         // We use a BTreeMap here as there is no indication that keys might not be duplicated,
         // and we want to make sure they're overwritten in that case.
@@ -114,10 +131,10 @@ impl Hasher {
 ///   . Strings and string slices.
 ///   . Vector of u8 (byte strings).
 ///   . A structure as the base level. Its typename and fields are not validated.
+///   . A structure inside a structure, hashed as a nested map.
 ///
 /// Additionally, this will fail if there are unsupported data structure, for example
-/// if a UnitVariant of another type than Blob is used, or a structure inside a
-/// structure.
+/// if a UnitVariant of another type than Blob is used.
 ///
 /// This does not validate whether a message is valid. This is very important as
 /// the message format might change faster than the ID calculation.
@@ -181,7 +198,7 @@ impl RequestIdSerializer {
                 let mut parent = *parent;
 
                 match &mut parent {
-                    Hasher::RequestId(hasher) => {
+                    Hasher::RequestId(hasher) | Hasher::Value(hasher) => {
                         for kv in keyvalues {
                             hasher.update(&kv);
                         }
@@ -437,7 +454,7 @@ impl<'a> ser::Serializer for &'a mut RequestIdSerializer {
     ) -> Result<Self::SerializeStruct, Self::Error> {
         let parent_encoder = self.element_encoder.take();
         match &parent_encoder {
-            Some(Hasher::RequestId(_)) => {
+            Some(Hasher::RequestId(_)) | Some(Hasher::Value(_)) => {
                 self.element_encoder = Some(Hasher::fields(Box::new(parent_encoder.unwrap())));
                 Ok(self)
             }
@@ -688,6 +705,17 @@ mod tests {
     use crate::export::Principal;
     use std::convert::TryFrom;
 
+    #[test]
+    fn hex_roundtrip() {
+        let hex = "8781291c347db32a9d8c10eb62b710fce5a93be676474c42babc74c51858f94b";
+        let request_id = RequestId::from_hex(hex).unwrap();
+        assert_eq!(request_id.to_string(), hex);
+        assert_eq!(request_id.to_hex(), hex);
+        assert_eq!(hex.parse::<RequestId>().unwrap(), request_id);
+        assert!(RequestId::from_hex("8781291c").is_err());
+        assert!(RequestId::from_hex("not hex").is_err());
+    }
+
     /// The actual example used in the public spec in the Request ID section.
     #[test]
     fn public_spec_example() {
@@ -843,6 +871,38 @@ mod tests {
         */
     }
 
+    #[test]
+    fn nested_struct() {
+        #[derive(Serialize)]
+        struct Reply {
+            #[serde(with = "serde_bytes")]
+            arg: Vec<u8>,
+        }
+        #[derive(Serialize)]
+        struct Response {
+            status: &'static str,
+            reply: Reply,
+        }
+        let data = Response {
+            status: "replied",
+            reply: Reply {
+                arg: b"DIDL\x00\x00".to_vec(),
+            },
+        };
+
+        // The value of a nested struct is hashed as a map: its hash is the hash of its sorted
+        // fields, like a request id.
+        let hash = |bytes: &[u8]| Sha256::digest(bytes).to_vec();
+        let reply = [hash(b"arg"), hash(b"DIDL\x00\x00")].concat();
+        let mut fields = vec![
+            [hash(b"status"), hash(b"replied")].concat(),
+            [hash(b"reply"), hash(&reply)].concat(),
+        ];
+        fields.sort();
+        let request_id = to_request_id(&data).unwrap();
+        assert_eq!(request_id.0.to_vec(), hash(&fields.concat()));
+    }
+
     /// We do not support creating a request id from a map.
     /// It adds complexity, and isn't that useful anyway because a real request would
     /// have to have different kinds of values (strings, principals, arrays) and
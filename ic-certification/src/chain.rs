@@ -0,0 +1,256 @@
+//! Certificates signed through a chain of delegations.
+//!
+//! A certificate may be signed by a subnet rather than by the root key, in which case it carries
+//! a [`Delegation`](crate::Delegation): a certificate, signed by the root key, in which the
+//! subnet's public key is certified. As the interface specification requires, the delegation
+//! certificate may not carry a delegation itself.
+
+use crate::{Certificate, HashTree, LookupResult};
+use std::fmt;
+
+/// An error produced while parsing or verifying a [`CertificateChain`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CertificateError {
+    /// A certificate of the chain is not valid CBOR.
+    InvalidCbor(String),
+    /// The delegation certificate at `depth` does not certify the public key of the subnet
+    /// it delegates to.
+    MissingSubnetPublicKey {
+        /// The depth of the delegation certificate, the leaf being at depth 0.
+        depth: usize,
+        /// The subnet the certificate at `depth - 1` is signed by.
+        subnet_id: Vec<u8>,
+    },
+    /// The signature of the certificate at `depth` is invalid.
+    InvalidSignature {
+        /// The depth of the certificate, the leaf being at depth 0.
+        depth: usize,
+    },
+    /// The delegation certificate carries a delegation itself.
+    NestedDelegation,
+}
+
+impl fmt::Display for CertificateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidCbor(e) => write!(f, "A certificate is not valid CBOR: {}", e),
+            Self::MissingSubnetPublicKey { depth, subnet_id } => write!(
+                f,
+                "The certificate at depth {} does not certify the public key of subnet {}",
+                depth,
+                hex::encode(subnet_id)
+            ),
+            Self::InvalidSignature { depth } => {
+                write!(
+                    f,
+                    "The signature of the certificate at depth {} is invalid",
+                    depth
+                )
+            }
+            Self::NestedDelegation => {
+                write!(f, "The delegation certificate carries a delegation itself")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CertificateError {}
+
+/// A certificate along with the certificate of its delegation, if any, which is signed by the
+/// root key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateChain {
+    /// The certificates, from the leaf to the one signed by the root key.
+    certificates: Vec<Certificate>,
+}
+
+impl CertificateChain {
+    /// Parse a CBOR-encoded certificate and the certificate of its delegation, if any.
+    ///
+    /// The delegation certificate is checked to certify the public key of the subnet it
+    /// delegates to, and to carry no delegation itself, but signatures are only checked by
+    /// [`verify`](Self::verify).
+    pub fn parse(cbor: &[u8]) -> Result<CertificateChain, CertificateError> {
+        let certificate = parse_certificate(cbor)?;
+        let delegation_certificate = match &certificate.delegation {
+            None => None,
+            Some(delegation) => {
+                let delegation_certificate = parse_certificate(&delegation.certificate)?;
+                if delegation_certificate.delegation.is_some() {
+                    return Err(CertificateError::NestedDelegation);
+                }
+                if subnet_public_key(&delegation_certificate, &delegation.subnet_id).is_none() {
+                    return Err(CertificateError::MissingSubnetPublicKey {
+                        depth: 1,
+                        subnet_id: delegation.subnet_id.clone(),
+                    });
+                }
+                Some(delegation_certificate)
+            }
+        };
+        Ok(CertificateChain {
+            certificates: std::iter::once(certificate)
+                .chain(delegation_certificate)
+                .collect(),
+        })
+    }
+
+    /// Verify the signature of every certificate of the chain, starting with the one signed by
+    /// `root_key`. The leaf certificate is then verified against the subnet public key
+    /// certified by the delegation certificate, if any.
+    ///
+    /// `verify_signature` is called with a certificate and the DER-encoded public key it should
+    /// be signed by, and returns whether the signature of its root hash is valid.
+    pub fn verify(
+        &self,
+        root_key: &[u8],
+        mut verify_signature: impl FnMut(&Certificate, &[u8]) -> bool,
+    ) -> Result<(), CertificateError> {
+        let mut key = root_key;
+        for (depth, certificate) in self.certificates.iter().enumerate().rev() {
+            if !verify_signature(certificate, key) {
+                return Err(CertificateError::InvalidSignature { depth });
+            }
+            if depth > 0 {
+                let delegation = self.certificates[depth - 1]
+                    .delegation
+                    .as_ref()
+                    .expect("the leaf of a chain of two certificates has a delegation");
+                key = subnet_public_key(certificate, &delegation.subnet_id)
+                    .expect("the subnet public key was checked when parsing");
+            }
+        }
+        Ok(())
+    }
+
+    /// The certificate the chain was parsed from.
+    pub fn leaf(&self) -> &Certificate {
+        &self.certificates[0]
+    }
+
+    /// The hash tree of the certificate the chain was parsed from.
+    pub fn leaf_tree(&self) -> &HashTree {
+        &self.leaf().tree
+    }
+
+    /// The principal, as bytes, of the subnet that signed the leaf certificate, or `None` if it
+    /// was signed by the root key.
+    pub fn leaf_subnet_id(&self) -> Option<&[u8]> {
+        self.leaf()
+            .delegation
+            .as_ref()
+            .map(|delegation| delegation.subnet_id.as_slice())
+    }
+
+    /// The certificates of the chain: the leaf, then the delegation certificate, if any.
+    pub fn certificates(&self) -> &[Certificate] {
+        &self.certificates
+    }
+}
+
+fn parse_certificate(cbor: &[u8]) -> Result<Certificate, CertificateError> {
+    serde_cbor::from_slice(cbor).map_err(|e| CertificateError::InvalidCbor(e.to_string()))
+}
+
+fn subnet_public_key<'a>(certificate: &'a Certificate, subnet_id: &[u8]) -> Option<&'a [u8]> {
+    match certificate
+        .tree
+        .lookup_path(["subnet".as_bytes(), subnet_id, "public_key".as_bytes()])
+    {
+        LookupResult::Found(key) => Some(key),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{empty, fork, label, leaf, Delegation};
+
+    const ROOT_KEY: &[u8] = b"root key";
+
+    /// A certificate "signed" by `key`, with the fake signature scheme of `verify_fake`.
+    fn certificate(tree: HashTree, key: &[u8], delegation: Option<Delegation>) -> Certificate {
+        Certificate {
+            tree,
+            signature: key.to_vec(),
+            delegation,
+        }
+    }
+
+    fn verify_fake(certificate: &Certificate, key: &[u8]) -> bool {
+        certificate.signature == key
+    }
+
+    fn delegation(
+        subnet_id: &[u8],
+        key: &[u8],
+        signed_by: &[u8],
+        next: Option<Delegation>,
+    ) -> Delegation {
+        let tree = label(
+            "subnet",
+            label(subnet_id.to_vec(), label("public_key", leaf(key.to_vec()))),
+        );
+        Delegation {
+            subnet_id: subnet_id.to_vec(),
+            certificate: serde_cbor::to_vec(&certificate(tree, signed_by, next)).unwrap(),
+        }
+    }
+
+    fn leaf_tree() -> HashTree {
+        fork(label("time", leaf(vec![1])), empty())
+    }
+
+    #[test]
+    fn delegated() {
+        let delegation = delegation(b"subnet", b"subnet key", ROOT_KEY, None);
+        let cbor =
+            serde_cbor::to_vec(&certificate(leaf_tree(), b"subnet key", Some(delegation))).unwrap();
+
+        let chain = CertificateChain::parse(&cbor).unwrap();
+        assert_eq!(chain.certificates().len(), 2);
+        assert_eq!(chain.leaf_tree(), &leaf_tree());
+        assert_eq!(chain.leaf_subnet_id(), Some(b"subnet".as_ref()));
+        chain.verify(ROOT_KEY, verify_fake).unwrap();
+        assert_eq!(
+            chain.verify(b"other key", verify_fake),
+            Err(CertificateError::InvalidSignature { depth: 1 })
+        );
+    }
+
+    #[test]
+    fn nested_delegation() {
+        let outer = delegation(b"outer", b"outer key", ROOT_KEY, None);
+        let inner = delegation(b"inner", b"inner key", b"outer key", Some(outer));
+        let cbor =
+            serde_cbor::to_vec(&certificate(leaf_tree(), b"inner key", Some(inner))).unwrap();
+        assert_eq!(
+            CertificateChain::parse(&cbor),
+            Err(CertificateError::NestedDelegation)
+        );
+    }
+
+    #[test]
+    fn no_delegation() {
+        let cbor = serde_cbor::to_vec(&certificate(leaf_tree(), ROOT_KEY, None)).unwrap();
+        let chain = CertificateChain::parse(&cbor).unwrap();
+        assert_eq!(chain.leaf_subnet_id(), None);
+        chain.verify(ROOT_KEY, verify_fake).unwrap();
+    }
+
+    #[test]
+    fn missing_subnet_public_key() {
+        let mut inner = delegation(b"inner", b"inner key", ROOT_KEY, None);
+        inner.subnet_id = b"other".to_vec();
+        let cbor =
+            serde_cbor::to_vec(&certificate(leaf_tree(), b"inner key", Some(inner))).unwrap();
+        assert_eq!(
+            CertificateChain::parse(&cbor),
+            Err(CertificateError::MissingSubnetPublicKey {
+                depth: 1,
+                subnet_id: b"other".to_vec()
+            })
+        );
+    }
+}
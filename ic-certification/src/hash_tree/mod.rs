@@ -204,6 +204,9 @@ impl<Storage: AsRef<[u8]>> HashTree<Storage> {
 impl<Storage: Clone + AsRef<[u8]>> HashTree<Storage> {
     /// Given a (verified) tree, the client can fetch the subtree at a given path, which is a
     /// sequence of labels (blobs).
+    ///
+    /// The path does not need to lead to a leaf; looking up a path prefix returns everything
+    /// below it, which can then be enumerated with [`list_paths`](Self::list_paths).
     pub fn lookup_subtree<'p, P, I>(&self, path: P) -> SubtreeLookupResult<Storage>
     where
         P: IntoIterator<Item = &'p I>,
@@ -506,3 +506,41 @@ fn can_lookup_subtrees_8() {
     assert_eq!(lookup_subtree(&tree, ["label 5"]), Found(empty()));
     assert_eq!(lookup_subtree(&tree, ["label 6"]), Unknown);
 }
+
+#[test]
+fn can_lookup_subtrees_by_prefix() {
+    use SubtreeLookupResult::*;
+
+    let metadata: HashTree<Vec<u8>> = fork(
+        label("candid:service", leaf(b"service : {}".to_vec())),
+        label("git_commit_id", leaf(b"abcdef".to_vec())),
+    );
+    let tree: HashTree<Vec<u8>> = label(
+        "canister",
+        fork(
+            label("canister 1", label("metadata", metadata.clone())),
+            pruned([1; 32]),
+        ),
+    );
+
+    assert_eq!(
+        lookup_subtree(&tree, ["canister", "canister 1", "metadata"]),
+        Found(metadata)
+    );
+    assert_eq!(
+        lookup_subtree(&tree, ["canister", "canister 0", "metadata"]),
+        Absent
+    );
+    assert_eq!(
+        lookup_subtree(&tree, ["canister", "canister 2", "metadata"]),
+        Unknown
+    );
+
+    match lookup_subtree(&tree, ["canister", "canister 1", "metadata"]) {
+        Found(subtree) => assert_eq!(
+            subtree.list_paths(),
+            vec![vec!["candid:service".into()], vec!["git_commit_id".into()]]
+        ),
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
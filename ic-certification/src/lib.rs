@@ -8,10 +8,18 @@ use hash_tree::Sha256Digest;
 use hex::FromHexError;
 
 pub mod certificate;
+#[cfg(feature = "serde")]
+pub mod chain;
 pub mod hash_tree;
+pub mod witness;
 
+#[cfg(feature = "serde")]
+#[doc(inline)]
+pub use chain::{CertificateChain, CertificateError};
 #[doc(inline)]
 pub use hash_tree::LookupResult;
+#[doc(inline)]
+pub use witness::{Witness, WitnessError};
 
 /// A HashTree representing a full tree.
 pub type HashTree = hash_tree::HashTree<Vec<u8>>;
@@ -0,0 +1,209 @@
+//! Compact proofs that a value is present in a [`HashTree`].
+//!
+//! A witness is a copy of a tree in which every branch that is not needed to reach a given path
+//! has been replaced by its digest. It has the same root hash as the original tree, so it can be
+//! checked against a certified root hash without revealing the rest of the tree.
+
+use crate::{
+    hash_tree::{fork, label, leaf, pruned, HashTreeNode, Sha256Digest},
+    HashTree, LookupResult,
+};
+use std::fmt;
+
+/// An error produced while creating or verifying a [`Witness`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WitnessError {
+    /// The path is not present in the tree.
+    PathNotFound,
+    /// The path leads to a subtree instead of a leaf.
+    NotALeaf,
+    /// The tree is pruned along the path, so it cannot prove anything about it.
+    PathPruned,
+    /// The root hash of the witness does not match the expected root hash.
+    RootHashMismatch,
+    /// The value at the path does not match the expected value.
+    ValueMismatch,
+}
+
+impl fmt::Display for WitnessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PathNotFound => f.write_str("The path was not found in the tree"),
+            Self::NotALeaf => f.write_str("The path does not lead to a leaf"),
+            Self::PathPruned => f.write_str("The tree is pruned along the path"),
+            Self::RootHashMismatch => {
+                f.write_str("The root hash of the witness does not match the expected root hash")
+            }
+            Self::ValueMismatch => {
+                f.write_str("The value at the path does not match the expected value")
+            }
+        }
+    }
+}
+
+impl std::error::Error for WitnessError {}
+
+/// A pruned [`HashTree`] proving that a value exists at a path. Produced by [`Witness::for_path`].
+///
+/// With the `serde` feature, it serializes to the same CBOR format as a [`HashTree`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct Witness {
+    tree: HashTree,
+}
+
+impl Witness {
+    /// Compute the minimal pruned tree proving that `path` leads to a leaf in `tree`.
+    pub fn for_path(tree: &HashTree, path: &[&[u8]]) -> Result<Witness, WitnessError> {
+        Ok(Witness {
+            tree: witness_node(tree.as_ref(), path)?,
+        })
+    }
+
+    /// Check that this witness has the root hash `root_hash`, and that `value` is at `path`.
+    pub fn verify(
+        &self,
+        root_hash: Sha256Digest,
+        path: &[&[u8]],
+        value: &[u8],
+    ) -> Result<(), WitnessError> {
+        if self.tree.digest() != root_hash {
+            return Err(WitnessError::RootHashMismatch);
+        }
+        match self.tree.lookup_path(path) {
+            LookupResult::Found(v) if v == value => Ok(()),
+            LookupResult::Found(_) => Err(WitnessError::ValueMismatch),
+            LookupResult::Absent => Err(WitnessError::PathNotFound),
+            LookupResult::Unknown => Err(WitnessError::PathPruned),
+            LookupResult::Error => Err(WitnessError::NotALeaf),
+        }
+    }
+
+    /// The pruned tree backing this witness.
+    pub fn tree(&self) -> &HashTree {
+        &self.tree
+    }
+}
+
+impl From<Witness> for HashTree {
+    fn from(witness: Witness) -> HashTree {
+        witness.tree
+    }
+}
+
+/// Prune everything in `node` except the nodes along `path`.
+fn witness_node(node: &HashTreeNode<Vec<u8>>, path: &[&[u8]]) -> Result<HashTree, WitnessError> {
+    match path.split_first() {
+        None => match node {
+            HashTreeNode::Leaf(v) => Ok(leaf(v.clone())),
+            HashTreeNode::Pruned(_) => Err(WitnessError::PathPruned),
+            HashTreeNode::Empty() => Err(WitnessError::PathNotFound),
+            HashTreeNode::Fork(_) | HashTreeNode::Labeled(_, _) => Err(WitnessError::NotALeaf),
+        },
+        Some((segment, rest)) => {
+            witness_label(node, segment, rest)?.ok_or(WitnessError::PathNotFound)
+        }
+    }
+}
+
+/// Find the node labeled `segment` below this fork, pruning its siblings. Returns [None] if the
+/// label is not below this node.
+fn witness_label(
+    node: &HashTreeNode<Vec<u8>>,
+    segment: &[u8],
+    rest: &[&[u8]],
+) -> Result<Option<HashTree>, WitnessError> {
+    match node {
+        HashTreeNode::Labeled(l, child) if l.as_bytes() == segment => {
+            Ok(Some(label(l.clone(), witness_node(child, rest)?)))
+        }
+        HashTreeNode::Fork(nodes) => {
+            if let Some(left) = witness_label(&nodes.0, segment, rest)? {
+                Ok(Some(fork(left, pruned(nodes.1.digest()))))
+            } else if let Some(right) = witness_label(&nodes.1, segment, rest)? {
+                Ok(Some(fork(pruned(nodes.0.digest()), right)))
+            } else {
+                Ok(None)
+            }
+        }
+        HashTreeNode::Pruned(_) => Err(WitnessError::PathPruned),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash_tree::empty;
+
+    fn create_tree() -> HashTree {
+        fork(
+            fork(
+                label(
+                    "a",
+                    fork(
+                        fork(label("x", leaf(*b"hello")), empty()),
+                        label("y", leaf(*b"world")),
+                    ),
+                ),
+                label("b", leaf(*b"good")),
+            ),
+            fork(label("c", empty()), label("d", leaf(*b"morning"))),
+        )
+    }
+
+    #[test]
+    fn witness_proves_path() {
+        let tree = create_tree();
+        let witness = Witness::for_path(&tree, &[b"a", b"y"]).unwrap();
+
+        assert_eq!(witness.tree().digest(), tree.digest());
+        assert_eq!(
+            witness.tree().lookup_path([b"b"]),
+            LookupResult::Unknown,
+            "siblings should be pruned"
+        );
+        assert_eq!(
+            witness.verify(tree.digest(), &[b"a", b"y"], b"world"),
+            Ok(())
+        );
+        assert_eq!(
+            witness.verify(tree.digest(), &[b"a", b"y"], b"hello"),
+            Err(WitnessError::ValueMismatch)
+        );
+        assert_eq!(
+            witness.verify([0; 32], &[b"a", b"y"], b"world"),
+            Err(WitnessError::RootHashMismatch)
+        );
+    }
+
+    #[test]
+    fn witness_requires_leaf() {
+        let tree = create_tree();
+        assert_eq!(
+            Witness::for_path(&tree, &[b"a"]),
+            Err(WitnessError::NotALeaf)
+        );
+        assert_eq!(
+            Witness::for_path(&tree, &[b"e"]),
+            Err(WitnessError::PathNotFound)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn witness_cbor_roundtrip() {
+        let tree = create_tree();
+        let witness = Witness::for_path(&tree, &[b"d"]).unwrap();
+
+        let cbor = serde_cbor::to_vec(&witness).unwrap();
+        let decoded: Witness = serde_cbor::from_slice(&cbor).unwrap();
+
+        assert_eq!(decoded, witness);
+        assert_eq!(decoded.verify(tree.digest(), &[b"d"], b"morning"), Ok(()));
+    }
+}
@@ -136,6 +136,9 @@ impl Identity for HardwareIdentity {
     fn sender(&self) -> Result<Principal, String> {
         Ok(Principal::self_authenticating(&self.public_key))
     }
+    fn public_key(&self) -> Option<Vec<u8>> {
+        Some(self.public_key.clone())
+    }
     fn sign(&self, msg: &[u8]) -> Result<Signature, String> {
         let hash = Sha256::digest(msg);
         let signature = self.sign_hash(&hash)?;
@@ -143,6 +146,7 @@ impl Identity for HardwareIdentity {
         Ok(Signature {
             public_key: Some(self.public_key.clone()),
             signature: Some(signature),
+            delegations: None,
         })
     }
 }
@@ -0,0 +1,100 @@
+use crate::canister::Canister;
+use ic_agent::AgentError;
+use std::ops::Deref;
+
+/// The fees charged for the ingress messages of update calls, which depend on the subnet of the
+/// canister. See the [cost of cycles](https://internetcomputer.org/docs/current/developer-docs/gas-cost).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeSchedule {
+    /// The fee charged for receiving an ingress message, in cycles.
+    pub ingress_message_reception_fee: u128,
+    /// The fee charged per byte of an ingress message, in cycles.
+    pub ingress_byte_reception_fee: u128,
+}
+
+impl FeeSchedule {
+    /// The fees of a 13-node application subnet.
+    pub const APPLICATION_SUBNET: FeeSchedule = FeeSchedule {
+        ingress_message_reception_fee: 1_200_000,
+        ingress_byte_reception_fee: 2_000,
+    };
+
+    /// The fees of an application subnet of `nodes` nodes. Fees scale linearly with the size of
+    /// the subnet, from those of a 13-node subnet.
+    pub fn for_subnet_size(nodes: u32) -> Self {
+        let scale = |fee: u128| fee * u128::from(nodes) / 13;
+        Self {
+            ingress_message_reception_fee: scale(
+                Self::APPLICATION_SUBNET.ingress_message_reception_fee,
+            ),
+            ingress_byte_reception_fee: scale(Self::APPLICATION_SUBNET.ingress_byte_reception_fee),
+        }
+    }
+
+    /// Estimate the cycles charged for an update call to `method_name` with the argument `arg`.
+    pub fn estimate_update_cost(&self, method_name: &str, arg: &[u8]) -> u128 {
+        self.ingress_message_reception_fee
+            + self.ingress_byte_reception_fee * (method_name.len() + arg.len()) as u128
+    }
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        Self::APPLICATION_SUBNET
+    }
+}
+
+/// The budget of a [BudgetedCanister].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CycleBudget {
+    pub(crate) limit: u128,
+    pub(crate) fees: FeeSchedule,
+}
+
+/// A [Canister] that refuses to send update calls estimated to cost more cycles than a budget.
+/// Created with [`Canister::with_cycle_budget`].
+///
+/// The replica cannot simulate an update call, so the estimate only covers the fees for
+/// receiving the ingress message, which depend on its size and on the [FeeSchedule] of the
+/// subnet. The cost of executing the call is unknown until it is made, so the check is
+/// advisory: the canister may still be charged more. Query calls are not checked.
+///
+/// An update call over budget fails with [`AgentError::CycleBudgetExceeded`] without being sent.
+#[derive(Debug, Clone)]
+pub struct BudgetedCanister<'agent>(pub(crate) Canister<'agent>);
+
+impl<'agent> BudgetedCanister<'agent> {
+    /// Unwrap the budgeted canister, e.g. to build an interface like
+    /// [`ManagementCanister`](crate::interfaces::ManagementCanister) around it. Update calls made
+    /// through the result are still checked against the budget.
+    pub fn into_inner(self) -> Canister<'agent> {
+        self.0
+    }
+}
+
+impl<'agent> Deref for BudgetedCanister<'agent> {
+    type Target = Canister<'agent>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// The argument of an update call made by a canister with the given budget.
+pub(crate) fn apply_budget(
+    budget: Option<CycleBudget>,
+    method_name: &str,
+    arg: Result<Vec<u8>, AgentError>,
+) -> Result<Vec<u8>, AgentError> {
+    let arg = arg?;
+    match budget {
+        Some(CycleBudget { limit, fees }) => {
+            let estimated = fees.estimate_update_cost(method_name, &arg);
+            if estimated > limit {
+                Err(AgentError::CycleBudgetExceeded { estimated, limit })
+            } else {
+                Ok(arg)
+            }
+        }
+        None => Ok(arg),
+    }
+}
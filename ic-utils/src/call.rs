@@ -1,13 +1,33 @@
+use crate::metrics::{self, MetricsRegistry};
+use crate::polling::{BoxedPollStrategy, PollStrategy};
 use async_trait::async_trait;
 use candid::{decode_args, decode_one, utils::ArgumentDecoder, CandidType};
-use ic_agent::{agent::UpdateBuilder, export::Principal, Agent, AgentError, RequestId};
+use futures_util::Stream;
+use ic_agent::{
+    agent::{ExponentialBackoffConfig, PreSignHook, SignedQueryPolicy, UpdateBuilder, WaitLimits},
+    export::Principal,
+    Agent, AgentError, Certificate, RequestId,
+};
 use serde::de::DeserializeOwned;
+use std::borrow::Cow;
 use std::fmt;
 use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 mod expiry;
 pub use expiry::Expiry;
 
+#[cfg(all(feature = "json", not(target_family = "wasm")))]
+mod idempotent;
+#[cfg(all(feature = "json", not(target_family = "wasm")))]
+pub use idempotent::{IdempotentCallError, IdempotentCaller};
+
+mod status;
+pub use status::CallStatus;
+
 /// A type that implements synchronous calls (ie. 'query' calls).
 #[cfg_attr(target_family = "wasm", async_trait(?Send))]
 #[cfg_attr(not(target_family = "wasm"), async_trait)]
@@ -153,6 +173,10 @@ where
     pub(crate) method_name: String,
     pub(crate) arg: Result<Vec<u8>, AgentError>,
     pub(crate) expiry: Expiry,
+    pub(crate) deadline: Option<SystemTime>,
+    pub(crate) metrics: Option<Arc<dyn MetricsRegistry>>,
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) sender_override: Option<Principal>,
     pub(crate) phantom_out: std::marker::PhantomData<Out>,
 }
 
@@ -161,12 +185,149 @@ where
     Out: for<'de> ArgumentDecoder<'de> + Send,
 {
     /// Perform the call, consuming the the abstraction. This is a private method.
-    async fn call_raw(self) -> Result<Vec<u8>, AgentError> {
+    pub(crate) async fn call_raw(self) -> Result<Vec<u8>, AgentError> {
+        self.call_raw_with_policy(None).await
+    }
+
+    async fn call_raw_with_policy(
+        self,
+        policy: Option<SignedQueryPolicy>,
+    ) -> Result<Vec<u8>, AgentError> {
+        let metrics = self.metrics.clone().map(|m| (m, self.method_name.clone()));
         let mut builder = self.agent.query(&self.canister_id, &self.method_name);
         self.expiry.apply_to_query(&mut builder);
         builder.with_arg(&self.arg?);
         builder.with_effective_canister_id(self.effective_canister_id);
-        builder.call().await
+        #[cfg(any(test, feature = "testing"))]
+        if let Some(sender) = self.sender_override {
+            builder.with_sender_override(sender);
+        }
+        if let Some(policy) = policy {
+            builder.with_signed_query_policy(policy);
+        }
+        metrics::record(metrics, builder.call()).await
+    }
+
+    /// Perform the call like [`SyncCall::call`], but without verifying the node signatures of
+    /// the response, whatever the [`SignedQueryPolicy`] of the agent, to save the latency of
+    /// fetching the node keys of the subnet.
+    ///
+    /// **This is insecure in production**: a malicious boundary node can forge the reply.
+    pub async fn call_without_verification(self) -> Result<Out, AgentError> {
+        let result = self
+            .call_raw_with_policy(Some(SignedQueryPolicy::Disabled))
+            .await?;
+
+        decode_args(&result).map_err(|e| AgentError::CandidError(Box::new(e)))
+    }
+
+    /// Convert this query into an update call to the same method with the same argument, e.g.
+    /// to get a certified reply from a method that can be called either way. Update calls go
+    /// through consensus, so they are slower, but the reply cannot be forged by a single replica.
+    pub fn into_async_caller(self) -> AsyncCaller<'agent, Out> {
+        AsyncCaller {
+            agent: self.agent,
+            effective_canister_id: self.effective_canister_id,
+            canister_id: self.canister_id,
+            method_name: self.method_name,
+            arg: self.arg,
+            expiry: self.expiry,
+            deadline: self.deadline,
+            metrics: self.metrics,
+            request_id_override: None,
+            nonce_len: None,
+            backoff: None,
+            pre_sign_hook: None,
+            poll_strategy: None,
+            #[cfg(feature = "tracing")]
+            trace_span: None,
+            phantom_out: std::marker::PhantomData,
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+type BoxedCallFuture<'agent> =
+    Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + Send + 'agent>>;
+
+#[cfg(target_family = "wasm")]
+type BoxedCallFuture<'agent> = Pin<Box<dyn Future<Output = Result<Vec<u8>, AgentError>> + 'agent>>;
+
+/// A type-erased query call, produced by [`SyncCallBuilder::build_boxed`](crate::canister::SyncCallBuilder::build_boxed).
+/// Awaiting it returns the raw bytes of the reply, which can be decoded with the appropriate type
+/// after the fact (e.g. with [`candid::decode_args`]).
+pub struct BoxedSyncCaller<'agent> {
+    inner: BoxedCallFuture<'agent>,
+}
+
+impl<'agent> BoxedSyncCaller<'agent> {
+    pub(crate) fn new<Out>(caller: SyncCaller<'agent, Out>) -> Self
+    where
+        Out: 'agent + for<'de> ArgumentDecoder<'de> + Send,
+    {
+        Self {
+            inner: Box::pin(caller.call_raw()),
+        }
+    }
+}
+
+impl fmt::Debug for BoxedSyncCaller<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BoxedSyncCaller").finish_non_exhaustive()
+    }
+}
+
+impl Future for BoxedSyncCaller<'_> {
+    type Output = Result<Vec<u8>, AgentError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.inner.as_mut().poll(cx)
+    }
+}
+
+/// A query method executed in replicated mode, i.e. sent as an update call instead of a query,
+/// so that its reply comes with the certificate it was read from. Produced by
+/// [`ReplicatedQueryBuilder::build`](crate::canister::ReplicatedQueryBuilder::build).
+///
+/// This is not a certified query: nothing is read from the certified data of the canister.
+/// The call goes through consensus like any update call: it takes seconds rather than
+/// milliseconds, the canister is charged cycles for it, and the changes the method makes to the
+/// state of the canister are committed instead of being discarded as they are for queries.
+#[derive(Debug)]
+pub struct ReplicatedQueryCaller<'agent, Out>
+where
+    Out: for<'de> ArgumentDecoder<'de> + Send,
+{
+    inner: AsyncCaller<'agent, Out>,
+}
+
+impl<'agent, Out> ReplicatedQueryCaller<'agent, Out>
+where
+    Out: for<'de> ArgumentDecoder<'de> + Send,
+{
+    pub(crate) fn new(caller: SyncCaller<'agent, Out>) -> Self {
+        Self {
+            inner: caller.into_async_caller(),
+        }
+    }
+
+    /// Perform the call, and return its reply along with the certificate of the state tree it
+    /// was read from. The agent has verified the certificate against its root key, but it can
+    /// also be verified independently, e.g. against a root key the agent does not know.
+    pub async fn call(self) -> Result<(Out, Certificate), AgentError> {
+        let metrics = self.inner.metrics();
+        let agent = self.inner.agent;
+        let effective_canister_id = self.inner.effective_canister_id;
+        let builder = self.inner.build_call()?;
+        let (reply, certificate) = metrics::record(metrics, async move {
+            let request_id = builder.call().await?;
+            agent
+                .wait_certified(request_id, effective_canister_id)
+                .await
+        })
+        .await?;
+        let out = decode_args(&reply).map_err(|e| AgentError::CandidError(Box::new(e)))?;
+        Ok((out, certificate))
     }
 }
 
@@ -189,6 +350,33 @@ where
     }
 }
 
+/// Wait for the reply to the call `request_id`, polling its status as decided by `poll_strategy`
+/// if any, or with the backoff of [`Agent::wait`] otherwise, until `limits`.
+pub(crate) async fn wait_reply(
+    agent: &Agent,
+    request_id: RequestId,
+    effective_canister_id: Principal,
+    mut poll_strategy: Option<BoxedPollStrategy>,
+    limits: WaitLimits,
+) -> Result<Vec<u8>, AgentError> {
+    let strategy = poll_strategy
+        .as_mut()
+        .map(|strategy| &mut *strategy.0 as &mut dyn PollStrategy);
+    agent
+        .wait_with_strategy(request_id, effective_canister_id, strategy, limits)
+        .await
+        .map(|(reply, _)| reply)
+}
+
+/// When to give up waiting for a call made with the given deadline, and signed with the given
+/// ingress expiry, in nanoseconds since the Unix epoch.
+pub(crate) fn wait_limits(deadline: Option<SystemTime>, ingress_expiry: Option<u64>) -> WaitLimits {
+    WaitLimits {
+        deadline,
+        ingress_expiry: ingress_expiry.map(|ns| UNIX_EPOCH + Duration::from_nanos(ns)),
+    }
+}
+
 /// An async caller, encapsulating a call to an update method.
 #[derive(Debug)]
 pub struct AsyncCaller<'agent, Out>
@@ -201,6 +389,15 @@ where
     pub(crate) method_name: String,
     pub(crate) arg: Result<Vec<u8>, AgentError>,
     pub(crate) expiry: Expiry,
+    pub(crate) deadline: Option<SystemTime>,
+    pub(crate) metrics: Option<Arc<dyn MetricsRegistry>>,
+    pub(crate) request_id_override: Option<RequestId>,
+    pub(crate) nonce_len: Option<usize>,
+    pub(crate) backoff: Option<ExponentialBackoffConfig>,
+    pub(crate) pre_sign_hook: Option<PreSignHook>,
+    pub(crate) poll_strategy: Option<BoxedPollStrategy>,
+    #[cfg(feature = "tracing")]
+    pub(crate) trace_span: Option<String>,
     pub(crate) phantom_out: std::marker::PhantomData<Out>,
 }
 
@@ -211,33 +408,157 @@ where
     /// Build an UpdateBuilder call that can be used directly with the [Agent]. This is
     /// essentially downleveling this type into the lower level [ic-agent] abstraction.
     pub fn build_call(self) -> Result<UpdateBuilder<'agent>, AgentError> {
-        let mut builder = self.agent.update(&self.canister_id, &self.method_name);
+        let agent = self.agent;
+        self.build_call_with(agent)
+    }
+
+    /// Like [`build_call`](Self::build_call), but for a call made by `agent`.
+    fn build_call_with(self, agent: &Agent) -> Result<UpdateBuilder<'_>, AgentError> {
+        let mut builder = agent.update(&self.canister_id, &self.method_name);
         self.expiry.apply_to_update(&mut builder);
         builder.with_arg(&self.arg?);
         builder.with_effective_canister_id(self.effective_canister_id);
+        if let Some(len) = self.nonce_len {
+            builder.with_nonce_len(len);
+        }
+        if let Some(hook) = self.pre_sign_hook {
+            builder.with_pre_sign_hook(hook);
+        }
         Ok(builder)
     }
 
+    pub(crate) fn metrics(&self) -> Option<(Arc<dyn MetricsRegistry>, String)> {
+        self.metrics.clone().map(|m| (m, self.method_name.clone()))
+    }
+
+    /// The agent making the call, which retries its requests if a backoff is set.
+    pub(crate) fn retrying_agent(&self) -> Cow<'agent, Agent> {
+        match self.backoff {
+            Some(config) => Cow::Owned(self.agent.with_exponential_backoff(config)),
+            None => Cow::Borrowed(self.agent),
+        }
+    }
+
+    /// Wait between polls of the status of the call as decided by `strategy` in
+    /// [`call_and_wait`](Self::call_and_wait), instead of the backoff of [`Agent::wait`].
+    /// See [`AsyncCallBuilder::with_poll_strategy`](crate::canister::AsyncCallBuilder::with_poll_strategy).
+    pub fn with_poll_strategy(mut self, strategy: impl PollStrategy + 'static) -> Self {
+        self.poll_strategy = Some(BoxedPollStrategy(Box::new(strategy)));
+        self
+    }
+
     /// See [`AsyncCall::call`].
     pub async fn call(self) -> Result<RequestId, AgentError> {
-        self.build_call()?.call().await
+        let metrics = self.metrics();
+        let agent = self.retrying_agent();
+        let request_id_override = self.request_id_override;
+        let builder = self.build_call_with(&agent)?;
+        metrics::record(metrics, async move {
+            let request_id = builder.call().await?;
+            Ok(request_id_override.unwrap_or(request_id))
+        })
+        .await
+    }
+
+    /// Submit the call and return its [RequestId] without polling for the result.
+    /// This is the same as [`call`](Self::call), but makes the intent explicit.
+    ///
+    /// There is no way to know whether the call succeeded, or even whether the replica
+    /// accepted it for execution, without separately polling `request_status` with the
+    /// returned ID (e.g. with [`Agent::wait`]).
+    pub async fn call_and_forget(self) -> Result<RequestId, AgentError> {
+        self.call().await
+    }
+
+    /// Submit the call and wait for the raw reply, in the span set by
+    /// [`with_trace_span`](crate::canister::AsyncCallBuilder::with_trace_span) if any.
+    pub(crate) async fn call_and_wait_raw(self) -> Result<Vec<u8>, AgentError> {
+        #[cfg(feature = "tracing")]
+        {
+            use tracing::Instrument;
+            let span = self.trace_span.as_ref().map(|name| {
+                tracing::info_span!(
+                    "canister_call",
+                    name = %name,
+                    canister_id = %self.canister_id,
+                    method_name = %self.method_name,
+                )
+            });
+            if let Some(span) = span {
+                return self.call_and_wait_untraced().instrument(span).await;
+            }
+        }
+        self.call_and_wait_untraced().await
+    }
+
+    async fn call_and_wait_untraced(mut self) -> Result<Vec<u8>, AgentError> {
+        let metrics = self.metrics();
+        let agent = self.retrying_agent();
+        let effective_canister_id = self.effective_canister_id;
+        let request_id_override = self.request_id_override;
+        let poll_strategy = self.poll_strategy.take();
+        let deadline = self.deadline;
+        let builder = self.build_call_with(&agent)?;
+        metrics::record(metrics, async {
+            let signed = builder.sign()?;
+            let limits = wait_limits(deadline, Some(signed.ingress_expiry));
+            let request_id = agent
+                .update_signed(effective_canister_id, signed.signed_update)
+                .await?;
+            let request_id = request_id_override.unwrap_or(request_id);
+            wait_reply(
+                &agent,
+                request_id,
+                effective_canister_id,
+                poll_strategy,
+                limits,
+            )
+            .await
+        })
+        .await
     }
 
     /// See [`AsyncCall::call_and_wait`].
     pub async fn call_and_wait(self) -> Result<Out, AgentError> {
-        self.build_call()?
-            .call_and_wait()
+        self.call_and_wait_raw()
             .await
             .and_then(|r| decode_args(&r).map_err(|e| AgentError::CandidError(Box::new(e))))
     }
 
+    /// Submit the call and poll its status until it is replied to, rejected, or done, yielding
+    /// every change of status along the way, e.g. to show the progress of the call. The stream
+    /// is not [`Unpin`], so it needs to be pinned to be iterated, e.g. with [`Box::pin`].
+    ///
+    /// A rejection is reported as [`CallStatus::Rejected`] instead of an error. The status is
+    /// polled like [`call_and_wait`](Self::call_and_wait) does.
+    pub fn call_and_collect_status(
+        mut self,
+    ) -> impl 'agent + Stream<Item = Result<CallStatus, AgentError>>
+    where
+        Out: 'agent,
+    {
+        let agent = self.retrying_agent().into_owned();
+        let effective_canister_id = self.effective_canister_id;
+        let request_id_override = self.request_id_override;
+        let poll_strategy = self.poll_strategy.take();
+        let deadline = self.deadline;
+        let submitter = agent.clone();
+        status::status_stream(agent, effective_canister_id, poll_strategy, async move {
+            let signed = self.build_call_with(&submitter)?.sign()?;
+            let limits = wait_limits(deadline, Some(signed.ingress_expiry));
+            let request_id = submitter
+                .update_signed(effective_canister_id, signed.signed_update)
+                .await?;
+            Ok((request_id_override.unwrap_or(request_id), limits))
+        })
+    }
+
     /// Equivalent to calling [`AsyncCall::call_and_wait`] with the expected return type `(T,)`.
     pub async fn call_and_wait_one<T>(self) -> Result<T, AgentError>
     where
         T: DeserializeOwned + CandidType,
     {
-        self.build_call()?
-            .call_and_wait()
+        self.call_and_wait_raw()
             .await
             .and_then(|r| decode_one(&r).map_err(|e| AgentError::CandidError(Box::new(e))))
     }
@@ -250,6 +571,18 @@ where
     {
         MappedAsyncCaller::new(self, map)
     }
+
+    /// Convert any [`AgentError`] returned by the call with `map_error`, e.g. into the error type
+    /// of a wrapping library, so it does not need to be done at every call site.
+    pub fn map_error<E>(
+        self,
+        map_error: impl 'agent + Fn(AgentError) -> E + Send + Sync,
+    ) -> MapErrorAsyncCaller<'agent, Out, E> {
+        MapErrorAsyncCaller {
+            inner: self,
+            map_error: Arc::new(map_error),
+        }
+    }
 }
 
 #[cfg_attr(target_family = "wasm", async_trait(?Send))]
@@ -473,3 +806,69 @@ where
         self.call_and_wait().await
     }
 }
+
+/// An [AsyncCaller] whose errors are converted to another type. Produced by [`AsyncCaller::map_error`].
+pub struct MapErrorAsyncCaller<'agent, Out, E>
+where
+    Out: for<'de> ArgumentDecoder<'de> + Send,
+{
+    inner: AsyncCaller<'agent, Out>,
+    map_error: Arc<dyn 'agent + Fn(AgentError) -> E + Send + Sync>,
+}
+
+impl<Out, E> fmt::Debug for MapErrorAsyncCaller<'_, Out, E>
+where
+    Out: for<'de> ArgumentDecoder<'de> + Send + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MapErrorAsyncCaller")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'agent, Out, E> MapErrorAsyncCaller<'agent, Out, E>
+where
+    Out: for<'de> ArgumentDecoder<'de> + Send,
+{
+    /// See [`AsyncCall::call`].
+    pub async fn call(self) -> Result<RequestId, E> {
+        self.inner.call().await.map_err(|e| (self.map_error)(e))
+    }
+
+    /// See [`AsyncCall::call_and_wait`].
+    pub async fn call_and_wait(self) -> Result<Out, E> {
+        self.inner
+            .call_and_wait()
+            .await
+            .map_err(|e| (self.map_error)(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{self, canister_id, expect_method, is_sent, sent};
+    use ic_agent::agent::mock::{MockAgent, MockTransport, Response};
+
+    #[tokio::test]
+    async fn into_async_caller() {
+        // Only the update endpoint answers, with the same method and argument as the query.
+        let agent = MockAgent::new(expect_method(
+            MockTransport::new(),
+            "call",
+            canister_id(),
+            "greet",
+            candid::encode_one("world").unwrap(),
+            Response::error(sent),
+        ));
+        let canister = test_util::canister(&agent);
+
+        let caller = canister
+            .query_("greet")
+            .with_arg("world")
+            .build::<(String,)>()
+            .into_async_caller();
+        assert!(is_sent(&caller.call().await));
+        agent.verify_all_called();
+    }
+}
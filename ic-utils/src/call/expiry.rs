@@ -28,6 +28,15 @@ impl Expiry {
         Self::DateTime(dt)
     }
 
+    /// This expiry, moved earlier to `d` from now if it is later or unspecified.
+    pub(crate) fn at_most_after(self, d: std::time::Duration) -> Self {
+        match self {
+            Expiry::Unspecified => Expiry::Delay(d),
+            Expiry::Delay(delay) => Expiry::Delay(delay.min(d)),
+            Expiry::DateTime(dt) => Expiry::DateTime(dt.min(std::time::SystemTime::now() + d)),
+        }
+    }
+
     pub(crate) fn apply_to_update(self, u: &mut UpdateBuilder<'_>) {
         match self {
             Expiry::Unspecified => {}
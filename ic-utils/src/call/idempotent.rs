@@ -0,0 +1,382 @@
+use super::{wait_limits, wait_reply, AsyncCaller};
+use crate::metrics;
+use candid::{decode_args, utils::ArgumentDecoder};
+use ic_agent::{export::Principal, AgentError, RequestId};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// An error returned by an [IdempotentCaller].
+#[derive(Debug, Error)]
+pub enum IdempotentCallError {
+    /// The call failed.
+    #[error(transparent)]
+    Agent(#[from] AgentError),
+
+    /// The state file could not be read or written.
+    #[error("Could not access the state file: {0}")]
+    Io(#[from] io::Error),
+
+    /// The state file is not a valid state file.
+    #[error("Invalid state file: {0}")]
+    InvalidState(#[from] serde_json::Error),
+
+    /// The state file records a pending call to another canister, method or argument, which
+    /// would be lost if the file was overwritten.
+    #[error("The state file records a pending call to `{method_name}` of {canister_id}")]
+    StateMismatch {
+        /// The canister of the pending call.
+        canister_id: Principal,
+        /// The method of the pending call.
+        method_name: String,
+    },
+}
+
+/// A call recorded in a state file before it is submitted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct PendingCall {
+    canister_id: Principal,
+    method_name: String,
+    /// The hex-encoded SHA-256 hash of the argument.
+    arg_hash: String,
+    /// The hex-encoded request ID.
+    request_id: String,
+    /// The Unix timestamp, in seconds, at which the call was first submitted.
+    submitted_at: u64,
+    /// The signed envelope, to submit it again if the process stopped before it was sent.
+    #[serde(with = "serde_bytes")]
+    signed_update: Vec<u8>,
+    /// The ingress expiry of the envelope, in nanoseconds since the Unix epoch. Missing from the
+    /// state files of older versions.
+    #[serde(default)]
+    ingress_expiry: Option<u64>,
+}
+
+/// An update call that is made at most once across restarts of the process, created with
+/// [`IdempotentCaller::new`].
+///
+/// The call is signed, and its envelope is written to a state file before it is submitted. If
+/// the state file records the same call, i.e. to the same method of the same canister with the
+/// same argument, the recorded envelope is submitted again instead, and its result is polled.
+/// The replica executes an envelope only once, so a call interrupted at any point is executed
+/// exactly once.
+///
+/// The state file is removed once waiting for the call ends, whether it was replied to,
+/// rejected, or waiting for it failed. It is kept if the envelope could not be submitted, so
+/// that the same envelope is submitted again on the next attempt.
+///
+/// The envelope is submitted and polled like [`AsyncCaller::call_and_wait`] would, following
+/// the backoff, poll strategy and metrics registry of the caller.
+#[derive(Debug)]
+pub struct IdempotentCaller<'agent, Out>
+where
+    Out: for<'de> ArgumentDecoder<'de> + Send,
+{
+    caller: AsyncCaller<'agent, Out>,
+    state_file: PathBuf,
+    pending: Option<PendingCall>,
+}
+
+impl<'agent, Out> IdempotentCaller<'agent, Out>
+where
+    Out: for<'de> ArgumentDecoder<'de> + Send,
+{
+    /// Make `caller` idempotent, recording it in `state_file`. If the file records the same
+    /// call, it is resumed. If it records another call, [`IdempotentCallError::StateMismatch`]
+    /// is returned.
+    pub fn new(
+        caller: AsyncCaller<'agent, Out>,
+        state_file: impl AsRef<Path>,
+    ) -> Result<Self, IdempotentCallError> {
+        let state_file = state_file.as_ref().to_path_buf();
+        let pending = match fs::read(&state_file) {
+            Ok(state) => Some(serde_json::from_slice::<PendingCall>(&state)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(pending) = &pending {
+            let same_arg = match &caller.arg {
+                Ok(arg) => pending.arg_hash == arg_hash(arg),
+                Err(_) => false,
+            };
+            if pending.canister_id != caller.canister_id
+                || pending.method_name != caller.method_name
+                || !same_arg
+            {
+                return Err(IdempotentCallError::StateMismatch {
+                    canister_id: pending.canister_id,
+                    method_name: pending.method_name.clone(),
+                });
+            }
+        }
+        Ok(Self {
+            caller,
+            state_file,
+            pending,
+        })
+    }
+
+    /// Whether the call is resumed from the state file, rather than submitted for the first
+    /// time.
+    pub fn is_resumed(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Submit the call, or the recorded one, and wait for its reply.
+    pub async fn call_and_wait(mut self) -> Result<Out, IdempotentCallError> {
+        let metrics = self.caller.metrics();
+        let agent = self.caller.retrying_agent().into_owned();
+        let effective_canister_id = self.caller.effective_canister_id;
+        let request_id_override = self.caller.request_id_override;
+        let poll_strategy = self.caller.poll_strategy.take();
+        let deadline = self.caller.deadline;
+        let (request_id, signed_update, ingress_expiry) = match self.pending {
+            Some(pending) => {
+                let request_id = RequestId::from_hex(&pending.request_id)
+                    .map_err(|e| AgentError::MessageError(e.to_string()))?;
+                (request_id, pending.signed_update, pending.ingress_expiry)
+            }
+            None => {
+                let signed = self.caller.build_call()?.sign()?;
+                write_state(
+                    &self.state_file,
+                    &PendingCall {
+                        canister_id: signed.canister_id,
+                        method_name: signed.method_name,
+                        arg_hash: arg_hash(&signed.arg),
+                        request_id: signed.request_id.to_hex(),
+                        submitted_at: SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .map_or(0, |d| d.as_secs()),
+                        signed_update: signed.signed_update.clone(),
+                        ingress_expiry: Some(signed.ingress_expiry),
+                    },
+                )?;
+                (
+                    signed.request_id,
+                    signed.signed_update,
+                    Some(signed.ingress_expiry),
+                )
+            }
+        };
+        let limits = wait_limits(deadline, ingress_expiry);
+        let mut submitted = false;
+        let result = metrics::record(metrics, async {
+            // The envelope is signed once, so submitting it again cannot execute it twice.
+            agent
+                .update_signed(effective_canister_id, signed_update)
+                .await?;
+            submitted = true;
+            wait_reply(
+                &agent,
+                request_id_override.unwrap_or(request_id),
+                effective_canister_id,
+                poll_strategy,
+                limits,
+            )
+            .await
+        })
+        .await;
+        if submitted {
+            match fs::remove_file(&self.state_file) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        let reply = result?;
+        decode_args(&reply).map_err(|e| AgentError::CandidError(Box::new(e)).into())
+    }
+}
+
+fn arg_hash(arg: &[u8]) -> String {
+    Sha256::digest(arg)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Write `pending` to `path` atomically, by writing it to a temporary file next to it first.
+fn write_state(path: &Path, pending: &PendingCall) -> Result<(), IdempotentCallError> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(&serde_json::to_vec(pending)?)?;
+    // Make sure the state is on disk before it replaces the previous one.
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use ic_agent::agent::mock::{MockAgent, MockTransport};
+
+    #[test]
+    fn resume_state() {
+        let agent = MockAgent::new(MockTransport::new());
+        let canister = test_util::canister(&agent);
+        let caller = |method_name| {
+            canister
+                .update_(method_name)
+                .with_arg("hello")
+                .build::<()>()
+        };
+
+        let state_file =
+            std::env::temp_dir().join(format!("ic-utils-idempotent-{}.json", std::process::id()));
+        let _ = fs::remove_file(&state_file);
+        assert!(!IdempotentCaller::new(caller("greet"), &state_file)
+            .unwrap()
+            .is_resumed());
+
+        let greet = caller("greet");
+        let pending = PendingCall {
+            canister_id: greet.canister_id,
+            method_name: "greet".to_string(),
+            arg_hash: arg_hash(greet.arg.as_ref().unwrap()),
+            request_id: RequestId::new(&[1; 32]).to_hex(),
+            submitted_at: 1,
+            signed_update: vec![1, 2, 3],
+            ingress_expiry: Some(2),
+        };
+        write_state(&state_file, &pending).unwrap();
+        let read: PendingCall = serde_json::from_slice(&fs::read(&state_file).unwrap()).unwrap();
+        assert_eq!(read, pending);
+
+        assert!(IdempotentCaller::new(greet, &state_file)
+            .unwrap()
+            .is_resumed());
+        assert!(matches!(
+            IdempotentCaller::new(caller("other"), &state_file),
+            Err(IdempotentCallError::StateMismatch { method_name, .. }) if method_name == "greet"
+        ));
+        let other_arg = canister.update_("greet").with_arg("bye").build::<()>();
+        assert!(IdempotentCaller::new(other_arg, &state_file).is_err());
+
+        fs::remove_file(&state_file).unwrap();
+    }
+
+    fn http_error(status: u16) -> AgentError {
+        AgentError::HttpError(ic_agent::agent::agent_error::HttpErrorPayload {
+            status,
+            content_type: None,
+            content: vec![],
+        })
+    }
+
+    #[tokio::test]
+    async fn call_and_wait() {
+        use ic_agent::agent::mock::{Matcher, Response};
+        use ic_agent::agent::{ExponentialBackoffConfig, RejectCode, RejectResponse};
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let state_file = std::env::temp_dir().join(format!(
+            "ic-utils-idempotent-call-{}.json",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&state_file);
+        let canister_id = test_util::canister_id();
+        let call_path = format!("canister/{canister_id}/call");
+        let recorded = state_file.clone();
+        let failed_once = AtomicBool::new(false);
+        let agent = MockAgent::new(
+            MockTransport::new()
+                // The first submission fails with a transient error, which is retried. The call
+                // must be recorded before it is submitted.
+                .expect(
+                    Matcher::Custom(Arc::new(move |path, _| {
+                        path == call_path
+                            && recorded.exists()
+                            && !failed_once.swap(true, Ordering::SeqCst)
+                    })),
+                    Response::error(|| http_error(503)),
+                )
+                .expect_update(canister_id, Response::Bytes(vec![]))
+                .expect_read_state(
+                    canister_id,
+                    Response::error(|| {
+                        AgentError::ReplicaError(RejectResponse {
+                            reject_code: RejectCode::CanisterReject,
+                            reject_message: "no".to_string(),
+                            error_code: None,
+                        })
+                    }),
+                ),
+        );
+        let canister = test_util::canister(&agent);
+        let caller = canister
+            .update_("greet")
+            .with_arg("hello")
+            .with_exponential_backoff(ExponentialBackoffConfig {
+                max_attempts: 2,
+                base_delay_ms: 1,
+                max_delay_ms: 1,
+                jitter: false,
+            })
+            .build::<()>();
+        let caller = IdempotentCaller::new(caller, &state_file).unwrap();
+        assert!(matches!(
+            caller.call_and_wait().await,
+            Err(IdempotentCallError::Agent(AgentError::ReplicaError(_)))
+        ));
+        agent.verify_all_called();
+        // The call was rejected, so it is not resumed.
+        assert!(!state_file.exists());
+    }
+
+    #[tokio::test]
+    async fn resume_failure_keeps_state() {
+        use ic_agent::agent::mock::Response;
+
+        let state_file = std::env::temp_dir().join(format!(
+            "ic-utils-idempotent-resume-{}.json",
+            std::process::id()
+        ));
+        let canister_id = test_util::canister_id();
+        let agent = MockAgent::new(
+            MockTransport::new().expect_update(canister_id, Response::error(|| http_error(400))),
+        );
+        let canister = test_util::canister(&agent);
+        let signed = canister
+            .update_("greet")
+            .with_arg("hello")
+            .build::<()>()
+            .build_call()
+            .unwrap()
+            .sign()
+            .unwrap();
+        write_state(
+            &state_file,
+            &PendingCall {
+                canister_id: signed.canister_id,
+                method_name: signed.method_name,
+                arg_hash: arg_hash(&signed.arg),
+                request_id: signed.request_id.to_hex(),
+                submitted_at: 1,
+                signed_update: signed.signed_update,
+                ingress_expiry: None,
+            },
+        )
+        .unwrap();
+
+        let caller = canister.update_("greet").with_arg("hello").build::<()>();
+        let caller = IdempotentCaller::new(caller, &state_file).unwrap();
+        assert!(caller.is_resumed());
+        assert!(matches!(
+            caller.call_and_wait().await,
+            Err(IdempotentCallError::Agent(AgentError::HttpError(payload))) if payload.status == 400
+        ));
+        agent.verify_all_called();
+        // The envelope was not accepted, so it is submitted again next time.
+        assert!(state_file.exists());
+        fs::remove_file(&state_file).unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+use crate::polling::BoxedPollStrategy;
+use futures_util::stream::{self, Stream};
+use ic_agent::{
+    agent::{PollSchedule, RejectCode, Replied, RequestStatusResponse, WaitLimits},
+    export::Principal,
+    Agent, AgentError, RequestId,
+};
+use std::future::Future;
+
+/// A status of an update call, yielded by [`AsyncCaller::call_and_collect_status`](super::AsyncCaller::call_and_collect_status).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CallStatus {
+    /// The call was submitted, but the replica does not know about it yet.
+    Submitted,
+    /// The call was received, and will probably get processed.
+    Received,
+    /// The call is being processed.
+    Processing,
+    /// The call was replied to, with the raw reply.
+    Replied(Vec<u8>),
+    /// The call was rejected.
+    Rejected {
+        /// The reject code.
+        code: RejectCode,
+        /// The rejection message.
+        message: String,
+    },
+    /// The call was completed long enough ago that its reply or rejection was purged.
+    Done,
+}
+
+impl CallStatus {
+    fn is_final(&self) -> bool {
+        matches!(
+            self,
+            CallStatus::Replied(_) | CallStatus::Rejected { .. } | CallStatus::Done
+        )
+    }
+}
+
+enum State<Submit> {
+    Submit(Submit, Option<BoxedPollStrategy>),
+    Poll {
+        request_id: RequestId,
+        last: CallStatus,
+        schedule: PollSchedule<'static>,
+        polled: bool,
+    },
+    Finished,
+}
+
+/// Submit a call with `submit`, then poll its status until it is final, yielding every change.
+/// The status is polled as decided by `poll_strategy`, like [`Agent::wait_with_strategy`] does,
+/// within the limits returned by `submit`.
+pub(crate) fn status_stream<'agent>(
+    agent: Agent,
+    effective_canister_id: Principal,
+    poll_strategy: Option<BoxedPollStrategy>,
+    submit: impl 'agent + Future<Output = Result<(RequestId, WaitLimits), AgentError>>,
+) -> impl 'agent + Stream<Item = Result<CallStatus, AgentError>> {
+    stream::unfold(State::Submit(submit, poll_strategy), move |state| {
+        let agent = agent.clone();
+        async move {
+            let (request_id, mut last, mut schedule, mut polled) = match state {
+                State::Submit(submit, poll_strategy) => {
+                    return Some(match submit.await {
+                        Ok((request_id, limits)) => (
+                            Ok(CallStatus::Submitted),
+                            State::Poll {
+                                request_id,
+                                last: CallStatus::Submitted,
+                                schedule: PollSchedule::new(
+                                    poll_strategy.map(|strategy| strategy.0),
+                                    limits,
+                                ),
+                                polled: false,
+                            },
+                        ),
+                        Err(e) => (Err(e), State::Finished),
+                    })
+                }
+                State::Poll {
+                    request_id,
+                    last,
+                    schedule,
+                    polled,
+                } => (request_id, last, schedule, polled),
+                State::Finished => return None,
+            };
+            loop {
+                if polled {
+                    if let Err(e) = schedule.wait().await {
+                        return Some((Err(e), State::Finished));
+                    }
+                }
+                polled = true;
+
+                let status = match agent
+                    .request_status_raw(&request_id, effective_canister_id)
+                    .await
+                {
+                    Ok(RequestStatusResponse::Unknown) => match schedule.unknown() {
+                        Ok(()) => continue,
+                        Err(e) => return Some((Err(e), State::Finished)),
+                    },
+                    Ok(RequestStatusResponse::Received) => CallStatus::Received,
+                    Ok(RequestStatusResponse::Processing) => CallStatus::Processing,
+                    Ok(RequestStatusResponse::Replied {
+                        reply: Replied::CallReplied(reply),
+                    }) => CallStatus::Replied(reply),
+                    Ok(RequestStatusResponse::Rejected(response)) => CallStatus::Rejected {
+                        code: response.reject_code,
+                        message: response.reject_message,
+                    },
+                    Ok(RequestStatusResponse::Done) => CallStatus::Done,
+                    Err(e) => return Some((Err(e), State::Finished)),
+                };
+                if status.is_final() {
+                    return Some((Ok(status), State::Finished));
+                }
+                schedule.accepted();
+                if status == last {
+                    continue;
+                }
+                last = status.clone();
+                return Some((
+                    Ok(status),
+                    State::Poll {
+                        request_id,
+                        last,
+                        schedule,
+                        polled,
+                    },
+                ));
+            }
+        }
+    })
+}
@@ -1,11 +1,45 @@
-use crate::call::{AsyncCaller, SyncCaller};
+use crate::budget::{apply_budget, BudgetedCanister, CycleBudget, FeeSchedule};
+use crate::call::{
+    AsyncCaller, BoxedSyncCaller, CallStatus, ReplicatedQueryCaller, SyncCall, SyncCaller,
+};
+use crate::context::{apply_deadline, Context, ContextualCanister};
+use crate::interfaces::Interface;
+use crate::metrics::{InstrumentedCanister, MetricsRegistry, Stopwatch};
+use crate::polling::{BoxedPollStrategy, PollStrategy};
+use crate::routing::effective_canister_id_for_call;
 use candid::utils::ArgumentEncoder;
-use candid::{parser::value::IDLValue, ser::IDLBuilder, utils::ArgumentDecoder, CandidType};
-use ic_agent::{export::Principal, Agent, AgentError, RequestId};
+use candid::{
+    parser::value::{IDLArgs, IDLValue},
+    ser::IDLBuilder,
+    utils::{decode_args, ArgumentDecoder},
+    CandidType,
+};
+use futures_util::{future::join_all, Stream};
+use ic_agent::{
+    agent::{signed::SignedUpdate, ExponentialBackoffConfig, PreSignHook, RejectCode},
+    export::Principal,
+    Agent, AgentError, RequestId,
+};
+#[cfg(feature = "json")]
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
 
+mod annotations;
+mod upgrade;
+
+pub use upgrade::UpgradeCompatibilityError;
+
+/// The [error code](https://internetcomputer.org/docs/current/references/ic-interface-spec/#error-codes)
+/// of a call to a method the canister does not export.
+const METHOD_NOT_FOUND: &str = "IC0302";
+
 /// An error happened while building a canister.
 #[derive(Debug, Error)]
 pub enum CanisterBuilderError {
@@ -20,6 +54,21 @@ pub enum CanisterBuilderError {
     /// The canister ID was not provided.
     #[error("Must specify a Canister ID")]
     MustSpecifyCanisterId(),
+
+    /// The agent given to [`Canister::into_owned`] does not make calls as the agent of the
+    /// canister does, or the canister given to [`CanisterPool::insert`] does not use the agent of
+    /// the pool.
+    #[error("The agent is not compatible with the agent of the canister: {0}")]
+    IncompatibleAgent(String),
+
+    /// The canister given to [`CanisterPool::insert`] is not the canister it is keyed by.
+    #[error("Cannot insert canister {actual} as canister {expected}")]
+    CanisterIdMismatch {
+        /// The canister ID the handle was to be inserted as.
+        expected: Principal,
+        /// The canister ID of the handle.
+        actual: Principal,
+    },
 }
 
 /// A canister builder, which can be used to create a canister abstraction.
@@ -70,7 +119,14 @@ impl<'agent> CanisterBuilder<'agent> {
         let agent = self
             .agent
             .ok_or(CanisterBuilderError::MustSpecifyAnAgent())?;
-        Ok(Canister { agent, canister_id })
+        Ok(Canister {
+            agent,
+            canister_id,
+            metrics: None,
+            deadline: None,
+            effective_id_resolver: None,
+            cycle_budget: None,
+        })
     }
 }
 
@@ -80,24 +136,60 @@ impl<'agent> CanisterBuilder<'agent> {
 ///
 /// This is the higher level construct for talking to a canister on the Internet
 /// Computer.
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Canister<'agent> {
     pub(super) agent: &'agent Agent,
     pub(super) canister_id: Principal,
+    pub(super) metrics: Option<Arc<dyn MetricsRegistry>>,
+    pub(super) deadline: Option<std::time::SystemTime>,
+    pub(super) effective_id_resolver: Option<Arc<EffectiveIdResolver>>,
+    pub(super) cycle_budget: Option<CycleBudget>,
+}
+
+/// Picks the effective canister ID of a call from its method name, see
+/// [`Canister::with_effective_id_resolver`].
+type EffectiveIdResolver = dyn Fn(&str) -> Principal + Send + Sync;
+
+impl fmt::Debug for Canister<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Canister")
+            .field("agent", &self.agent)
+            .field("canister_id", &self.canister_id)
+            .field("metrics", &self.metrics)
+            .field("deadline", &self.deadline)
+            .field(
+                "effective_id_resolver",
+                &self.effective_id_resolver.as_ref().map(|_| ".."),
+            )
+            .field("cycle_budget", &self.cycle_budget)
+            .finish()
+    }
 }
 
 impl<'agent> Canister<'agent> {
     /// Get the canister ID of this canister.
+    #[deprecated(note = "Use `canister_id` instead.")]
     pub fn canister_id_<'canister: 'agent>(&'canister self) -> &Principal {
         &self.canister_id
     }
 
+    /// Get the canister ID of this canister.
+    pub fn canister_id(&self) -> Principal {
+        self.canister_id
+    }
+
+    /// Get the agent this canister makes calls with, e.g. to make raw agent calls like
+    /// [`read_state_raw`](Agent::read_state_raw).
+    pub fn agent(&self) -> &'agent Agent {
+        self.agent
+    }
+
     /// Create an AsyncCallBuilder to do an update call.
     pub fn update_<'canister: 'agent>(
         &'canister self,
         method_name: &str,
     ) -> AsyncCallBuilder<'agent, 'canister> {
-        AsyncCallBuilder::new(self, method_name)
+        AsyncCallBuilder::new(Cow::Borrowed(self), method_name)
     }
 
     /// Create a SyncCallBuilder to do a query call.
@@ -105,7 +197,98 @@ impl<'agent> Canister<'agent> {
         &'canister self,
         method_name: &str,
     ) -> SyncCallBuilder<'agent, 'canister> {
-        SyncCallBuilder::new(self, method_name)
+        SyncCallBuilder::new(Cow::Borrowed(self), method_name)
+    }
+
+    /// Submit an update call to `method_name` and return its [RequestId] immediately, without
+    /// waiting for the call to be executed.
+    ///
+    /// There is no way to know whether the call succeeded without separately polling
+    /// `request_status`, e.g. with [`wait`](Self::wait).
+    pub async fn call_and_forget<'canister: 'agent>(
+        &'canister self,
+        method_name: &str,
+        arg: impl ArgumentEncoder,
+    ) -> Result<RequestId, AgentError> {
+        let mut builder = self.update_(method_name);
+        builder.arg = Argument::from_candid(arg);
+        builder.build::<()>().call_and_forget().await
+    }
+
+    /// Call the update method `method_name` with `arg` and wait for its reply. This is a
+    /// shortcut for `update_(method_name).with_arg(..).build().call_and_wait()`, for calls that
+    /// need none of the options of [AsyncCallBuilder].
+    pub async fn call_update<'canister: 'agent, Output>(
+        &'canister self,
+        method_name: &str,
+        arg: impl ArgumentEncoder,
+    ) -> Result<Output, AgentError>
+    where
+        Output: 'canister + for<'de> ArgumentDecoder<'de> + Send + Sync,
+    {
+        let mut builder = self.update_(method_name);
+        builder.arg = Argument::from_candid(arg);
+        builder.build().call_and_wait().await
+    }
+
+    /// Call the query method `method_name` with `arg`. This is a shortcut for
+    /// `query_(method_name).with_arg(..).build().call()`, for calls that need none of the
+    /// options of [SyncCallBuilder].
+    pub async fn call_query<'canister: 'agent, Output>(
+        &'canister self,
+        method_name: &str,
+        arg: impl ArgumentEncoder,
+    ) -> Result<Output, AgentError>
+    where
+        Output: 'canister + for<'de> ArgumentDecoder<'de> + Send + Sync,
+    {
+        let mut builder = self.query_(method_name);
+        builder.arg = Argument::from_candid(arg);
+        builder.build().call().await
+    }
+
+    /// Call the query method `method_name` with the same argument on every canister in
+    /// `canister_ids`, e.g. to check a balance across many token canisters. The queries are
+    /// made concurrently, and each result is paired with the ID of the canister it came from,
+    /// in the order of `canister_ids`.
+    ///
+    /// The argument is encoded once for all the queries. If it cannot be, no canister is called,
+    /// and the encoding error is returned.
+    pub fn call_many<Output>(
+        agent: &'agent Agent,
+        canister_ids: Vec<Principal>,
+        method_name: &str,
+        arg: impl ArgumentEncoder,
+    ) -> impl Future<Output = Result<Vec<(Principal, Result<Output, AgentError>)>, AgentError>> + 'agent
+    where
+        Output: 'agent + for<'de> ArgumentDecoder<'de>,
+    {
+        let arg = Argument::from_candid(arg).serialize();
+        let method_name = method_name.to_string();
+        async move {
+            let arg = arg?;
+            let callers = canister_ids.into_iter().map(|canister_id| SyncCaller {
+                agent,
+                effective_canister_id: canister_id,
+                canister_id,
+                method_name: method_name.clone(),
+                arg: Ok(arg.clone()),
+                expiry: Default::default(),
+                deadline: None,
+                metrics: None,
+                #[cfg(any(test, feature = "testing"))]
+                sender_override: None,
+                phantom_out: std::marker::PhantomData::<()>,
+            });
+            Ok(join_all(callers.map(|caller| async move {
+                let canister_id = caller.canister_id;
+                let reply = caller.call_raw().await.and_then(|reply| {
+                    decode_args(&reply).map_err(|e| AgentError::CandidError(Box::new(e)))
+                });
+                (canister_id, reply)
+            }))
+            .await)
+        }
     }
 
     /// Call request_status on the RequestId in a loop and return the response as a byte vector.
@@ -116,38 +299,664 @@ impl<'agent> Canister<'agent> {
         self.agent.wait(request_id, self.canister_id).await
     }
 
+    /// Read the data the canister certified with `set_certified_data`, from the certified
+    /// `/canister/<canister_id>/certified_data` path of the state tree, verified against the
+    /// root key of the agent. Returns [`AgentError::NoCertifiedData`] if the canister has not
+    /// set any.
+    pub async fn get_certified_data(&self) -> Result<Vec<u8>, AgentError> {
+        match self
+            .agent
+            .read_state_canister_info(self.canister_id, "certified_data")
+            .await
+        {
+            Ok(data) if !data.is_empty() => Ok(data),
+            Ok(_) | Err(AgentError::LookupPathAbsent(_)) => {
+                Err(AgentError::NoCertifiedData(self.canister_id))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read the annotations of `method_name` from the Candid interface of the canister, which is
+    /// fetched with the `__get_candid_interface_tmp_hack` query method.
+    ///
+    /// Candid only has mode annotations, so the map holds a single `mode` key, which is `query`,
+    /// `composite_query`, `oneway`, or `update` for methods without an annotation.
+    pub async fn method_annotations<'canister: 'agent>(
+        &'canister self,
+        method_name: &str,
+    ) -> Result<HashMap<String, String>, AgentError> {
+        let (candid,): (String,) = self
+            .query_("__get_candid_interface_tmp_hack")
+            .build()
+            .call()
+            .await?;
+        annotations::method_annotations(&candid, method_name)
+            .map_err(|e| AgentError::CandidError(Box::new(e)))?
+            .ok_or_else(|| {
+                AgentError::MessageError(format!(
+                    "Method {} not found in the Candid interface of the canister",
+                    method_name
+                ))
+            })
+    }
+
+    /// Check that the canister is responsive, and return the round-trip time of a query to its
+    /// `__get_candid_interface_tmp_hack` method, which most canisters export. If the canister
+    /// has no such method, the round-trip time of reading the module hash of the canister from
+    /// the state tree is returned instead, which anyone may do. Other rejections, e.g. of a
+    /// stopped canister, are returned as errors. There is no clock to measure with on WASM
+    /// targets, so the returned duration is always zero there.
+    pub async fn ping<'canister: 'agent>(&'canister self) -> Result<Duration, AgentError> {
+        let stopwatch = Stopwatch::start();
+        match self
+            .query_("__get_candid_interface_tmp_hack")
+            .build_boxed()
+            .await
+        {
+            Ok(_) => return Ok(stopwatch.elapsed()),
+            Err(AgentError::ReplicaError(reject))
+                if reject.reject_code == RejectCode::DestinationInvalid
+                    && reject
+                        .error_code
+                        .as_deref()
+                        .map_or(true, |code| code == METHOD_NOT_FOUND) => {}
+            Err(e) => return Err(e),
+        }
+
+        let stopwatch = Stopwatch::start();
+        self.agent
+            .read_state_canister_info(self.canister_id, "module_hash")
+            .await?;
+        Ok(stopwatch.elapsed())
+    }
+
+    /// Check whether `new_wasm` can be installed over the module of this canister in upgrade
+    /// mode without breaking its clients. The management canister has no way to check this, so
+    /// the `candid:service` metadata of `new_wasm` is checked to be a subtype of that of the
+    /// installed module, with Candid's subtyping rules.
+    ///
+    /// The check passes if nothing is installed, or if `new_wasm` is the installed module. It
+    /// fails if either module has no `candid:service` metadata, as there is nothing to check.
+    /// Reading private metadata requires the agent's identity to be a controller.
+    pub async fn verify_upgrade_compatible(
+        &self,
+        new_wasm: &[u8],
+    ) -> Result<(), UpgradeCompatibilityError> {
+        upgrade::verify_upgrade_compatible(self.agent, self.canister_id, new_wasm).await
+    }
+
+    /// Call each of the query methods in `fields` with its argument, and collect the replies
+    /// into a [CanisterSnapshot] keyed by method name. The snapshot can be serialized to JSON,
+    /// e.g. to diff the state of a canister between runs.
+    ///
+    /// The queries are made one after the other, so the snapshot is not atomic.
+    #[cfg(feature = "json")]
+    pub async fn snapshot<'canister: 'agent, A>(
+        &'canister self,
+        fields: &[(&str, A)],
+    ) -> Result<CanisterSnapshot, AgentError>
+    where
+        A: ArgumentEncoder + Clone,
+    {
+        let taken_at = std::time::SystemTime::now();
+        let mut values = HashMap::new();
+        for (method_name, arg) in fields {
+            let mut builder = self.query_(method_name);
+            builder.arg = Argument::from_candid(arg.clone());
+            let reply = builder.build_boxed().await?;
+            let args =
+                IDLArgs::from_bytes(&reply).map_err(|e| AgentError::CandidError(Box::new(e)))?;
+            let value = match &args.args[..] {
+                [value] => idl_to_json(value),
+                values => serde_json::Value::Array(values.iter().map(idl_to_json).collect()),
+            };
+            values.insert(method_name.to_string(), value);
+        }
+        Ok(CanisterSnapshot {
+            taken_at,
+            fields: values,
+        })
+    }
+
+    /// Call `method_name` with arguments in the Candid textual format, e.g. `("hello", 42)`, as a
+    /// query or an update call depending on `mode`, and return the values of the reply as a JSON
+    /// array, converted as in [CanisterSnapshot]. This is meant for REPLs and scripts, where the
+    /// types of the method are not known at compile time.
+    #[cfg(feature = "json")]
+    pub async fn call_with_text<'canister: 'agent>(
+        &'canister self,
+        method_name: &str,
+        candid_text: &str,
+        mode: CallMode,
+    ) -> Result<serde_json::Value, AgentError> {
+        let reply = match mode {
+            CallMode::Query => {
+                self.query_(method_name)
+                    .with_arg_candid_text(candid_text)
+                    .build_boxed()
+                    .await?
+            }
+            CallMode::Update => {
+                self.update_(method_name)
+                    .with_arg_candid_text(candid_text)
+                    .build::<()>()
+                    .call_and_wait_raw()
+                    .await?
+            }
+        };
+        let args = IDLArgs::from_bytes(&reply).map_err(|e| AgentError::CandidError(Box::new(e)))?;
+        Ok(serde_json::Value::Array(
+            args.args.iter().map(idl_to_json).collect(),
+        ))
+    }
+
+    /// Creates a copy of this canister that makes its calls with `agent`, e.g. to make the same
+    /// call as another identity.
+    ///
+    /// ```no_run
+    /// # use ic_agent::Agent;
+    /// use ic_utils::{call::SyncCall, Canister};
+    ///
+    /// # async fn balances(alice: Agent, bob: Agent) -> Result<(), Box<dyn std::error::Error>> {
+    /// let canister = Canister::builder()
+    ///     .with_agent(&alice)
+    ///     .with_canister_id("rrkah-fqaaa-aaaaa-aaaaq-cai")
+    ///     .build()?;
+    /// let (alice_balance,): (u64,) = canister.query_("balance").build().call().await?;
+    /// let (bob_balance,): (u64,) = canister
+    ///     .clone_with_agent(&bob)
+    ///     .query_("balance")
+    ///     .build()
+    ///     .call()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn clone_with_agent<'b>(&self, agent: &'b Agent) -> Canister<'b> {
+        Canister {
+            agent,
+            canister_id: self.canister_id,
+            metrics: self.metrics.clone(),
+            deadline: self.deadline,
+            effective_id_resolver: self.effective_id_resolver.clone(),
+            cycle_budget: self.cycle_budget,
+        }
+    }
+
     /// Creates a copy of this canister, changing the canister ID to the provided principal.
     pub fn clone_with_(&self, id: Principal) -> Self {
         Self {
             agent: self.agent,
             canister_id: id,
+            metrics: self.metrics.clone(),
+            deadline: self.deadline,
+            effective_id_resolver: self.effective_id_resolver.clone(),
+            cycle_budget: self.cycle_budget,
+        }
+    }
+
+    /// Convert this canister into an [OwnedCanister] that makes its calls with `agent`, e.g. a
+    /// clone of the agent this canister was created with.
+    ///
+    /// Fails with [`CanisterBuilderError::IncompatibleAgent`] if `agent` does not call as the
+    /// same principal, or does not trust the same root key, as the agent of this canister.
+    pub fn into_owned(self, agent: Arc<Agent>) -> Result<OwnedCanister, CanisterBuilderError> {
+        if agent.get_principal().ok() != self.agent.get_principal().ok() {
+            return Err(CanisterBuilderError::IncompatibleAgent(
+                "it has a different identity".to_string(),
+            ));
+        }
+        if agent.read_root_key() != self.agent.read_root_key() {
+            return Err(CanisterBuilderError::IncompatibleAgent(
+                "it has a different root key".to_string(),
+            ));
+        }
+        Ok(OwnedCanister {
+            agent,
+            canister_id: self.canister_id,
+            metrics: self.metrics,
+            deadline: self.deadline,
+            effective_id_resolver: self.effective_id_resolver,
+            cycle_budget: self.cycle_budget,
+        })
+    }
+
+    /// Creates a copy of this canister that reports the outcome and latency of every call made
+    /// through it to `registry`.
+    pub fn with_metrics(&self, registry: Arc<dyn MetricsRegistry>) -> InstrumentedCanister<'agent> {
+        InstrumentedCanister(Self {
+            metrics: Some(registry),
+            ..self.clone()
+        })
+    }
+
+    /// Creates a copy of this canister that calls `resolver` with the method name of every query
+    /// and update call to pick its [effective canister ID](https://smartcontracts.org/docs/interface-spec/index.html#http-effective-canister-id),
+    /// instead of using the canister ID. The effective canister ID of a single call can still be
+    /// overridden with `with_effective_canister_id`.
+    pub fn with_effective_id_resolver(
+        &self,
+        resolver: impl Fn(&str) -> Principal + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            effective_id_resolver: Some(Arc::new(resolver)),
+            ..self.clone()
+        }
+    }
+
+    /// The argument and effective canister ID of a call to `method_name` with `arg`: the
+    /// `effective_canister_id` it was given if any, the one picked by the resolver if any, or the
+    /// one the call is routed to otherwise, see [`effective_canister_id_for_call`]. The argument
+    /// is an error if the call cannot be routed.
+    fn route(
+        &self,
+        method_name: &str,
+        effective_canister_id: Option<Principal>,
+        arg: Result<Vec<u8>, AgentError>,
+    ) -> (Result<Vec<u8>, AgentError>, Principal) {
+        match (effective_canister_id, &self.effective_id_resolver, arg) {
+            (Some(effective_canister_id), _, arg) => (arg, effective_canister_id),
+            (None, Some(resolver), arg) => (arg, resolver(method_name)),
+            (None, None, Ok(arg)) => {
+                match effective_canister_id_for_call(&self.canister_id, method_name, &arg) {
+                    Ok(effective_canister_id) => (Ok(arg), effective_canister_id),
+                    Err(e) => (Err(AgentError::CandidError(Box::new(e))), self.canister_id),
+                }
+            }
+            (None, None, Err(e)) => (Err(e), self.canister_id),
         }
     }
 
+    /// Creates a copy of this canister whose query and update calls expire at the deadline of
+    /// `ctx`, so that they do not outlive the server request they are made for. Calls built once
+    /// the deadline has passed fail with [`AgentError::ContextDeadlineExceeded`] without being sent.
+    pub fn with_context(&self, ctx: Context) -> ContextualCanister<'agent> {
+        ContextualCanister(Self {
+            deadline: ctx.deadline(),
+            ..self.clone()
+        })
+    }
+
+    /// Creates a copy of this canister whose update calls fail with
+    /// [`AgentError::CycleBudgetExceeded`] without being sent if
+    /// they are estimated to cost more than `max_cycles` with the fees of `fees`. See
+    /// [BudgetedCanister] for how the cost is estimated.
+    pub fn with_cycle_budget(
+        &self,
+        max_cycles: u128,
+        fees: FeeSchedule,
+    ) -> BudgetedCanister<'agent> {
+        BudgetedCanister(Self {
+            cycle_budget: Some(CycleBudget {
+                limit: max_cycles,
+                fees,
+            }),
+            ..self.clone()
+        })
+    }
+
+    /// Wrap this canister in the interface `I`, e.g.
+    /// `Canister::builder().with_agent(&agent).with_canister_id("aaaaa-aa").build()?.bind::<ManagementCanister>()`.
+    pub fn bind<I: Interface<'agent>>(self) -> I {
+        I::new(self)
+    }
+
     /// Create a CanisterBuilder instance to build a canister abstraction.
     pub fn builder() -> CanisterBuilder<'agent> {
         Default::default()
     }
 }
 
+/// A [Canister] that holds its [Agent] through an [Arc] instead of a reference, so that it has
+/// no lifetime parameter and can be stored in long-lived application state, or moved into
+/// spawned tasks. Created with [`Canister::into_owned`].
+#[derive(Clone)]
+pub struct OwnedCanister {
+    agent: Arc<Agent>,
+    canister_id: Principal,
+    metrics: Option<Arc<dyn MetricsRegistry>>,
+    deadline: Option<std::time::SystemTime>,
+    effective_id_resolver: Option<Arc<EffectiveIdResolver>>,
+    cycle_budget: Option<CycleBudget>,
+}
+
+impl fmt::Debug for OwnedCanister {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OwnedCanister")
+            .field("agent", &self.agent)
+            .field("canister_id", &self.canister_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl OwnedCanister {
+    /// Get the canister ID of this canister.
+    pub fn canister_id(&self) -> Principal {
+        self.canister_id
+    }
+
+    /// Get the agent this canister makes calls with.
+    pub fn agent(&self) -> &Arc<Agent> {
+        &self.agent
+    }
+
+    /// Borrow this canister as a [Canister], e.g. to build an interface around it.
+    pub fn as_canister(&self) -> Canister<'_> {
+        Canister {
+            agent: &self.agent,
+            canister_id: self.canister_id,
+            metrics: self.metrics.clone(),
+            deadline: self.deadline,
+            effective_id_resolver: self.effective_id_resolver.clone(),
+            cycle_budget: self.cycle_budget,
+        }
+    }
+
+    /// Create an AsyncCallBuilder to do an update call.
+    pub fn update_(&self, method_name: &str) -> AsyncCallBuilder<'_, '_> {
+        AsyncCallBuilder::new(Cow::Owned(self.as_canister()), method_name)
+    }
+
+    /// Create a SyncCallBuilder to do a query call.
+    pub fn query_(&self, method_name: &str) -> SyncCallBuilder<'_, '_> {
+        SyncCallBuilder::new(Cow::Owned(self.as_canister()), method_name)
+    }
+
+    /// Call request_status on the RequestId in a loop and return the response as a byte vector.
+    pub async fn wait(&self, request_id: RequestId) -> Result<Vec<u8>, AgentError> {
+        self.agent.wait(request_id, self.canister_id).await
+    }
+}
+
+/// A set of [Canister] handles sharing a single [Agent], keyed by canister ID.
+///
+/// This avoids going through [`CanisterBuilder`] every time a handle to a known canister is needed.
+#[derive(Debug, Clone)]
+pub struct CanisterPool<'agent> {
+    agent: &'agent Agent,
+    canisters: HashMap<Principal, Canister<'agent>>,
+}
+
+impl<'agent> CanisterPool<'agent> {
+    /// Create an empty pool of canisters that use `agent`.
+    pub fn new(agent: &'agent Agent) -> Self {
+        Self {
+            agent,
+            canisters: HashMap::new(),
+        }
+    }
+
+    /// Get the handle for the canister `id`, creating it with the pool's agent if it is not in the pool yet.
+    pub fn get_or_insert(&mut self, id: Principal) -> &Canister<'agent> {
+        let agent = self.agent;
+        self.canisters.entry(id).or_insert_with(|| Canister {
+            agent,
+            canister_id: id,
+            metrics: None,
+            deadline: None,
+            effective_id_resolver: None,
+            cycle_budget: None,
+        })
+    }
+
+    /// Insert a handle for the canister `id`, replacing any existing one.
+    ///
+    /// Fails with [`CanisterBuilderError::CanisterIdMismatch`] if `canister` is not the canister
+    /// `id`, and with [`CanisterBuilderError::IncompatibleAgent`] if it does not use the pool's
+    /// agent.
+    pub fn insert(
+        &mut self,
+        id: Principal,
+        canister: Canister<'agent>,
+    ) -> Result<(), CanisterBuilderError> {
+        if canister.canister_id != id {
+            return Err(CanisterBuilderError::CanisterIdMismatch {
+                expected: id,
+                actual: canister.canister_id,
+            });
+        }
+        if !std::ptr::eq(canister.agent, self.agent) {
+            return Err(CanisterBuilderError::IncompatibleAgent(
+                "it is not the agent of the pool".to_string(),
+            ));
+        }
+        self.canisters.insert(id, canister);
+        Ok(())
+    }
+
+    /// Remove the handle for the canister `id` from the pool, returning it if it was present.
+    pub fn remove(&mut self, id: &Principal) -> Option<Canister<'agent>> {
+        self.canisters.remove(id)
+    }
+
+    /// Iterate over all the canisters in the pool, in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Principal, &Canister<'agent>)> {
+        self.canisters.iter()
+    }
+}
+
+/// Whether [`Canister::call_with_text`] makes a query or an update call.
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallMode {
+    /// A query call.
+    Query,
+    /// An update call, waiting for its reply.
+    Update,
+}
+
+/// The replies of a set of query methods, taken by [`Canister::snapshot`].
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CanisterSnapshot {
+    /// When the snapshot was started.
+    pub taken_at: std::time::SystemTime,
+    /// The reply of each query method, keyed by method name. A reply with more than one value
+    /// is represented as an array.
+    pub fields: HashMap<String, serde_json::Value>,
+}
+
+/// Convert a Candid value to JSON. Numbers that may not fit in a JSON number, along with
+/// principals and other values without a JSON equivalent, are represented as strings.
+#[cfg(feature = "json")]
+fn idl_to_json(value: &IDLValue) -> serde_json::Value {
+    use serde_json::Value;
+
+    match value {
+        IDLValue::Bool(b) => Value::Bool(*b),
+        IDLValue::Null | IDLValue::None | IDLValue::Reserved => Value::Null,
+        IDLValue::Text(s) => Value::String(s.clone()),
+        IDLValue::Opt(v) => idl_to_json(v),
+        IDLValue::Vec(vs) => Value::Array(vs.iter().map(idl_to_json).collect()),
+        IDLValue::Record(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|field| (field.id.to_string(), idl_to_json(&field.val)))
+                .collect(),
+        ),
+        IDLValue::Variant(variant) => {
+            let field = &variant.0;
+            Value::Object(
+                std::iter::once((field.id.to_string(), idl_to_json(&field.val))).collect(),
+            )
+        }
+        IDLValue::Principal(p) | IDLValue::Service(p) => Value::String(p.to_text()),
+        IDLValue::Nat(n) => Value::String(n.0.to_string()),
+        IDLValue::Int(i) => Value::String(i.0.to_string()),
+        IDLValue::Nat8(n) => Value::from(*n),
+        IDLValue::Nat16(n) => Value::from(*n),
+        IDLValue::Nat32(n) => Value::from(*n),
+        IDLValue::Nat64(n) => Value::String(n.to_string()),
+        IDLValue::Int8(i) => Value::from(*i),
+        IDLValue::Int16(i) => Value::from(*i),
+        IDLValue::Int32(i) => Value::from(*i),
+        IDLValue::Int64(i) => Value::String(i.to_string()),
+        IDLValue::Float32(f) => Value::from(*f),
+        IDLValue::Float64(f) => Value::from(*f),
+        other => Value::String(other.to_string()),
+    }
+}
+
 /// The type of argument passed to a canister call. This can either be a raw argument,
-/// in which case it's a vector of bytes that will be passed verbatim, or an IDL
-/// Builder which will result in an error or a raw argument at the call site.
+/// in which case it's a vector of bytes that will be passed verbatim, or Candid messages
+/// which will be merged into a single one at the call site.
 ///
 /// This enumeration is meant to be private. You should use [Argument] for holding
 /// argument values.
+///
+/// Values are encoded by an [IDLBuilder] as they are added, rather than kept in one, which can
+/// only be serialized once, so that an argument can be cloned and serialized through a shared
+/// reference. The messages are merged without decoding their values, see [merge_messages].
+#[derive(Debug, Clone)]
 enum ArgumentType {
     Raw(Vec<u8>),
-    Idl(IDLBuilder),
+    Encoded(Vec<Vec<u8>>),
 }
 
-impl fmt::Debug for ArgumentType {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Self::Raw(v) => f.debug_tuple("ArgumentType::Raw").field(v).finish(),
-            Self::Idl(_) => f.debug_struct("ArgumentType::Idl").finish_non_exhaustive(),
+/// Merge complete Candid messages into one holding all of their values, in order.
+///
+/// The type table of each message is appended to the merged one, with the indices it refers
+/// to shifted past the entries of the messages before it, so that the types are kept exactly
+/// as they were encoded, including recursive ones, and the values can be copied as they are.
+fn merge_messages(messages: &[Vec<u8>]) -> Result<Vec<u8>, AgentError> {
+    match messages {
+        [] => IDLBuilder::new()
+            .serialize_to_vec()
+            .map_err(|e| AgentError::CandidError(Box::new(e))),
+        [message] => Ok(message.clone()),
+        _ => {
+            let mut table = Vec::new();
+            let mut table_len = 0;
+            let mut args = Vec::new();
+            let mut args_len = 0;
+            let mut values = Vec::new();
+            for message in messages {
+                let mut header = Header::new(message)?;
+                let entries = header.leb()?;
+                for _ in 0..entries {
+                    header.entry(table_len, &mut table)?;
+                }
+                let arg_types = header.leb()?;
+                for _ in 0..arg_types {
+                    header.type_ref(table_len, &mut args)?;
+                }
+                values.extend_from_slice(header.rest());
+                table_len += entries;
+                args_len += arg_types;
+            }
+            let mut merged = b"DIDL".to_vec();
+            write_leb(&mut merged, table_len);
+            merged.append(&mut table);
+            write_leb(&mut merged, args_len);
+            merged.append(&mut args);
+            merged.append(&mut values);
+            Ok(merged)
+        }
+    }
+}
+
+/// Reads the header of a Candid message, the type table and the types of the arguments, for
+/// [merge_messages].
+struct Header<'a>(&'a [u8]);
+
+impl<'a> Header<'a> {
+    fn new(message: &'a [u8]) -> Result<Self, AgentError> {
+        message
+            .strip_prefix(b"DIDL")
+            .map(Self)
+            .ok_or_else(|| invalid_message("missing the DIDL magic number"))
+    }
+
+    fn leb(&mut self) -> Result<u64, AgentError> {
+        leb128::read::unsigned(&mut self.0).map_err(|_| invalid_message("invalid LEB128 number"))
+    }
+
+    fn sleb(&mut self) -> Result<i64, AgentError> {
+        leb128::read::signed(&mut self.0).map_err(|_| invalid_message("invalid SLEB128 number"))
+    }
+
+    /// Copy a reference to a type, which is an index in the type table if it is not negative.
+    fn type_ref(&mut self, offset: u64, out: &mut Vec<u8>) -> Result<(), AgentError> {
+        let index = self.sleb()?;
+        let index = if index >= 0 {
+            index + offset as i64
+        } else {
+            index
+        };
+        leb128::write::signed(out, index).expect("writing to a Vec cannot fail");
+        Ok(())
+    }
+
+    fn copy_leb(&mut self, out: &mut Vec<u8>) -> Result<u64, AgentError> {
+        let n = self.leb()?;
+        write_leb(out, n);
+        Ok(n)
+    }
+
+    fn copy_bytes(&mut self, len: u64, out: &mut Vec<u8>) -> Result<(), AgentError> {
+        let len = usize::try_from(len).map_err(|_| invalid_message("length out of range"))?;
+        if self.0.len() < len {
+            return Err(invalid_message("truncated message"));
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        out.extend_from_slice(bytes);
+        self.0 = rest;
+        Ok(())
+    }
+
+    /// Copy an entry of the type table.
+    fn entry(&mut self, offset: u64, out: &mut Vec<u8>) -> Result<(), AgentError> {
+        let opcode = self.sleb()?;
+        leb128::write::signed(out, opcode).expect("writing to a Vec cannot fail");
+        match opcode {
+            // opt, vec
+            -18 | -19 => self.type_ref(offset, out),
+            // record, variant
+            -20 | -21 => {
+                for _ in 0..self.copy_leb(out)? {
+                    self.copy_leb(out)?;
+                    self.type_ref(offset, out)?;
+                }
+                Ok(())
+            }
+            // func
+            -22 => {
+                for _ in 0..2 {
+                    for _ in 0..self.copy_leb(out)? {
+                        self.type_ref(offset, out)?;
+                    }
+                }
+                let annotations = self.copy_leb(out)?;
+                self.copy_bytes(annotations, out)
+            }
+            // service
+            -23 => {
+                for _ in 0..self.copy_leb(out)? {
+                    let name_len = self.copy_leb(out)?;
+                    self.copy_bytes(name_len, out)?;
+                    self.type_ref(offset, out)?;
+                }
+                Ok(())
+            }
+            _ => Err(invalid_message("unsupported type table entry")),
         }
     }
+
+    /// The values of the message, after its header.
+    fn rest(&self) -> &'a [u8] {
+        self.0
+    }
+}
+
+fn write_leb(out: &mut Vec<u8>, n: u64) {
+    leb128::write::unsigned(out, n).expect("writing to a Vec cannot fail");
+}
+
+fn invalid_message(reason: &str) -> AgentError {
+    AgentError::CandidError(Box::new(candid::Error::msg(format!(
+        "invalid Candid message: {reason}"
+    ))))
 }
 
 /// A builder for a canister argument, allowing you to append elements to an argument tuple with chaining syntax.
@@ -158,32 +967,30 @@ impl Argument {
     /// Add an IDL Argument. If the current value of Argument is Raw, will set the
     /// result to an error. If the current value is an error, will do nothing.
     pub fn push_idl_arg<A: CandidType>(&mut self, arg: A) {
-        match self.0 {
-            Ok(ArgumentType::Idl(ref mut idl_builder)) => {
-                let result = idl_builder.arg(&arg);
-                if let Err(e) = result {
-                    self.0 = Err(AgentError::CandidError(Box::new(e)))
-                }
-            }
-            Ok(ArgumentType::Raw(_)) => {
-                self.0 = Err(AgentError::MessageError(
-                    "Cannot overwrite a Raw Argument with a non-raw argument.".to_owned(),
-                ))
-            }
-            _ => {}
-        }
+        let mut builder = IDLBuilder::new();
+        let message = builder
+            .arg(&arg)
+            .and_then(|builder| builder.serialize_to_vec());
+        self.push_message(message);
     }
 
     /// Add an IDLValue Argument. If the current value of Argument is Raw, will set the
     /// result to an error. If the current value is an error, will do nothing.
     pub fn push_value_arg(&mut self, arg: IDLValue) {
-        match self.0 {
-            Ok(ArgumentType::Idl(ref mut idl_builder)) => {
-                let result = idl_builder.value_arg(&arg);
-                if let Err(e) = result {
-                    self.0 = Err(AgentError::CandidError(Box::new(e)))
-                }
-            }
+        let mut builder = IDLBuilder::new();
+        let message = builder
+            .value_arg(&arg)
+            .and_then(|builder| builder.serialize_to_vec());
+        self.push_message(message);
+    }
+
+    /// Add the values of a complete Candid message.
+    fn push_message(&mut self, message: candid::Result<Vec<u8>>) {
+        match &mut self.0 {
+            Ok(ArgumentType::Encoded(messages)) => match message {
+                Ok(message) => messages.push(message),
+                Err(e) => self.0 = Err(AgentError::CandidError(Box::new(e))),
+            },
             Ok(ArgumentType::Raw(_)) => {
                 self.0 = Err(AgentError::MessageError(
                     "Cannot overwrite a Raw Argument with a non-raw argument.".to_owned(),
@@ -193,6 +1000,19 @@ impl Argument {
         }
     }
 
+    /// Add the values of an argument in the Candid textual format, e.g. `("hello", 42)`. If the
+    /// text cannot be parsed, will set the result to an error.
+    fn push_candid_text(&mut self, text: &str) {
+        match text.parse::<IDLArgs>() {
+            Ok(args) => self.push_message(args.to_bytes()),
+            Err(e) => {
+                if self.0.is_ok() {
+                    self.0 = Err(AgentError::CandidError(Box::new(e)));
+                }
+            }
+        }
+    }
+
     /// Set the argument as raw, replacing any value that was there before. If the
     /// current argument was an error, does nothing.
     pub fn set_raw_arg(&mut self, arg: Vec<u8>) {
@@ -204,14 +1024,58 @@ impl Argument {
     /// Encodes the completed argument into an IDL blob.
     pub fn serialize(self) -> Result<Vec<u8>, AgentError> {
         match self.0 {
-            Ok(ArgumentType::Idl(mut idl_builder)) => idl_builder
-                .serialize_to_vec()
-                .map_err(|e| AgentError::CandidError(Box::new(e))),
+            Ok(ArgumentType::Encoded(mut messages)) if messages.len() == 1 => {
+                Ok(messages.remove(0))
+            }
+            Ok(ArgumentType::Encoded(messages)) => merge_messages(&messages),
             Ok(ArgumentType::Raw(vec)) => Ok(vec),
             Err(e) => Err(e),
         }
     }
 
+    /// Encodes the argument into an IDL blob without consuming it, e.g. to log it. As
+    /// [`AgentError`] is not [`Clone`], the error of an argument in an error state is returned
+    /// by its message.
+    pub fn try_peek_serialized(&self) -> Result<Vec<u8>, AgentError> {
+        match &self.0 {
+            Ok(ArgumentType::Encoded(messages)) => merge_messages(messages),
+            Ok(ArgumentType::Raw(vec)) => Ok(vec.clone()),
+            Err(e) => Err(AgentError::MessageError(e.to_string())),
+        }
+    }
+
+    /// Concatenates two arguments into one. All of `a`'s values come first, followed by
+    /// all of `b`'s values, in the order they were added.
+    ///
+    /// If either argument is in an error state, the error is propagated. Raw arguments
+    /// cannot be zipped, and result in an error.
+    pub fn zip(a: Argument, b: Argument) -> Argument {
+        match (a.0, b.0) {
+            (Err(e), _) | (_, Err(e)) => Self(Err(e)),
+            (Ok(ArgumentType::Encoded(mut a)), Ok(ArgumentType::Encoded(mut b))) => {
+                a.append(&mut b);
+                Self(Ok(ArgumentType::Encoded(a)))
+            }
+            _ => Self(Err(AgentError::MessageError(
+                "Cannot zip a Raw Argument.".to_owned(),
+            ))),
+        }
+    }
+
+    /// Creates an argument from pre-serialized Candid components, e.g. produced by a foreign
+    /// language binding. Each component is a complete Candid message (`DIDL` blob) holding one or
+    /// more values, and the values of all components are concatenated into a single argument list.
+    ///
+    /// If any component is not valid Candid, the argument is in an error state.
+    pub fn from_component_bytes(components: Vec<Vec<u8>>) -> Argument {
+        let mut arg = Argument::new();
+        for component in components {
+            let message = IDLArgs::from_bytes(&component).map(|_| component);
+            arg.push_message(message);
+        }
+        arg
+    }
+
     /// Resets the argument to an empty builder.
     pub fn reset(&mut self) {
         *self = Default::default();
@@ -233,15 +1097,48 @@ impl Argument {
         Self(
             tuple
                 .encode(&mut builder)
-                .map(|_| ArgumentType::Idl(builder))
+                .and_then(|_| builder.serialize_to_vec())
+                .map(|message| ArgumentType::Encoded(vec![message]))
                 .map_err(|e| AgentError::CandidError(Box::new(e))),
         )
     }
+
+    /// Decodes the serialized argument back into the Candid textual format, e.g. to display
+    /// it. This is the inverse of [`with_arg_candid_text`](SyncCallBuilder::with_arg_candid_text),
+    /// although the values are annotated with their types. Raw arguments are decoded too, so this fails
+    /// if they are not valid Candid.
+    pub fn to_candid_text(&self) -> Result<String, AgentError> {
+        let blob = self.try_peek_serialized()?;
+        IDLArgs::from_bytes(&blob)
+            .map(|args| args.to_string())
+            .map_err(|e| AgentError::CandidError(Box::new(e)))
+    }
 }
 
 impl Default for Argument {
     fn default() -> Self {
-        Self(Ok(ArgumentType::Idl(IDLBuilder::new())))
+        Self(Ok(ArgumentType::Encoded(Vec::new())))
+    }
+}
+
+impl fmt::Display for Argument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Ok(ArgumentType::Raw(raw)) => raw.iter().try_for_each(|b| write!(f, "{:02x}", b)),
+            Ok(ArgumentType::Encoded(_)) => f.write_str("<IDL args>"),
+            Err(e) => write!(f, "<error: {}>", e),
+        }
+    }
+}
+
+impl Clone for Argument {
+    /// Clone the argument, e.g. to make the same call several times. The error of an argument in
+    /// an error state is cloned by its message.
+    fn clone(&self) -> Self {
+        match &self.0 {
+            Ok(arg) => Self(Ok(arg.clone())),
+            Err(e) => Self(Err(AgentError::MessageError(e.to_string()))),
+        }
     }
 }
 
@@ -250,23 +1147,28 @@ impl Default for Argument {
 /// See [SyncCaller] for a description of this structure once built.
 #[derive(Debug)]
 pub struct SyncCallBuilder<'agent, 'canister: 'agent> {
-    canister: &'canister Canister<'agent>,
+    canister: Cow<'canister, Canister<'agent>>,
     method_name: String,
-    effective_canister_id: Principal,
+    effective_canister_id: Option<Principal>,
     arg: Argument,
+    #[cfg(any(test, feature = "testing"))]
+    sender_override: Option<Principal>,
 }
 
 impl<'agent, 'canister: 'agent> SyncCallBuilder<'agent, 'canister> {
     /// Create a new instance of an AsyncCallBuilder.
     pub(super) fn new<M: Into<String>>(
-        canister: &'canister Canister<'agent>,
+        canister: Cow<'canister, Canister<'agent>>,
         method_name: M,
     ) -> Self {
+        let method_name = method_name.into();
         Self {
+            effective_canister_id: None,
             canister,
-            method_name: method_name.into(),
-            effective_canister_id: canister.canister_id_().to_owned(),
+            method_name,
             arg: Default::default(),
+            #[cfg(any(test, feature = "testing"))]
+            sender_override: None,
         }
     }
 }
@@ -292,6 +1194,14 @@ impl<'agent, 'canister: 'agent> SyncCallBuilder<'agent, 'canister> {
         self
     }
 
+    /// Add the values of an argument in the Candid textual format, e.g. `("hello", 42)`, to
+    /// the candid argument list. If the text cannot be parsed, or there is a raw argument set
+    /// (using [`with_arg_raw`](SyncCallBuilder::with_arg_raw)), this will fail.
+    pub fn with_arg_candid_text(mut self, text: &str) -> SyncCallBuilder<'agent, 'canister> {
+        self.arg.push_candid_text(text);
+        self
+    }
+
     /// Replace the argument with raw argument bytes. This will overwrite the current
     /// argument set, so calling this method twice will discard the first argument.
     pub fn with_arg_raw(mut self, arg: Vec<u8>) -> SyncCallBuilder<'agent, 'canister> {
@@ -304,7 +1214,19 @@ impl<'agent, 'canister: 'agent> SyncCallBuilder<'agent, 'canister> {
         mut self,
         canister_id: Principal,
     ) -> SyncCallBuilder<'agent, 'canister> {
-        self.effective_canister_id = canister_id;
+        self.effective_canister_id = Some(canister_id);
+        self
+    }
+
+    /// Send the query as `sender` instead of the principal of the agent's identity, without
+    /// changing the identity that signs it. Only available in tests and with the `testing`
+    /// feature.
+    ///
+    /// A replica checks the signature against the sender, so this is only useful against
+    /// testing setups that skip the check, to see how a canister answers a specific principal.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn with_sender_override(mut self, sender: Principal) -> SyncCallBuilder<'agent, 'canister> {
+        self.sender_override = Some(sender);
         self
     }
 
@@ -313,41 +1235,100 @@ impl<'agent, 'canister: 'agent> SyncCallBuilder<'agent, 'canister> {
     where
         Output: for<'de> ArgumentDecoder<'de> + Send + Sync,
     {
-        let c = self.canister;
+        let c = &self.canister;
+        let (arg, effective_canister_id) = c.route(
+            &self.method_name,
+            self.effective_canister_id,
+            self.arg.serialize(),
+        );
+        let (arg, expiry) = apply_deadline(c.deadline, arg);
         SyncCaller {
             agent: c.agent,
-            effective_canister_id: self.effective_canister_id,
+            effective_canister_id,
             canister_id: c.canister_id,
             method_name: self.method_name.clone(),
-            arg: self.arg.serialize(),
-            expiry: Default::default(),
+            arg,
+            expiry,
+            deadline: c.deadline,
+            metrics: c.metrics.clone(),
+            #[cfg(any(test, feature = "testing"))]
+            sender_override: self.sender_override,
             phantom_out: std::marker::PhantomData,
         }
     }
+
+    /// Builds a [BoxedSyncCaller] from this builder's state. Unlike [`build`](Self::build),
+    /// the result does not depend on the return type, so calls to methods with different
+    /// return types can be stored together. It resolves to the raw reply bytes.
+    pub fn build_boxed(self) -> BoxedSyncCaller<'canister> {
+        BoxedSyncCaller::new(self.build::<()>())
+    }
+
+    /// Execute the method in replicated mode, i.e. send it as an update call instead of a query,
+    /// so that its reply is returned along with its certificate, as the replies of queries are
+    /// not certified.
+    ///
+    /// This is replicated execution, not a certified query: the call goes through consensus, is
+    /// charged cycles to the canister, and commits the changes the method makes to the canister
+    /// state. See [ReplicatedQueryCaller].
+    pub fn replicated(self) -> ReplicatedQueryBuilder<'agent, 'canister> {
+        ReplicatedQueryBuilder(self)
+    }
 }
 
-/// A builder for an asynchronous call (ie. update) to the Internet Computer.
+/// A builder for a query method executed in replicated mode, i.e. as an update call, so that
+/// its reply is returned with its certificate, created with [`SyncCallBuilder::replicated`].
+#[derive(Debug)]
+pub struct ReplicatedQueryBuilder<'agent, 'canister: 'agent>(SyncCallBuilder<'agent, 'canister>);
+
+impl<'agent, 'canister: 'agent> ReplicatedQueryBuilder<'agent, 'canister> {
+    /// Builds a [ReplicatedQueryCaller] from this builder's state.
+    pub fn build<Output>(self) -> ReplicatedQueryCaller<'canister, Output>
+    where
+        Output: for<'de> ArgumentDecoder<'de> + Send + Sync,
+    {
+        ReplicatedQueryCaller::new(self.0.build())
+    }
+}
+
+/// A builder for an asynchronous call (ie. update) to the Internet Computer.
 ///
 /// See [AsyncCaller] for a description of this structure.
 #[derive(Debug)]
 pub struct AsyncCallBuilder<'agent, 'canister: 'agent> {
-    canister: &'canister Canister<'agent>,
+    canister: Cow<'canister, Canister<'agent>>,
     method_name: String,
-    effective_canister_id: Principal,
+    effective_canister_id: Option<Principal>,
     arg: Argument,
+    request_id_override: Option<RequestId>,
+    nonce_len: Option<usize>,
+    ingress_expiry: Option<Duration>,
+    backoff: Option<ExponentialBackoffConfig>,
+    pre_sign_hook: Option<PreSignHook>,
+    poll_strategy: Option<BoxedPollStrategy>,
+    #[cfg(feature = "tracing")]
+    trace_span: Option<String>,
 }
 
 impl<'agent, 'canister: 'agent> AsyncCallBuilder<'agent, 'canister> {
     /// Create a new instance of an AsyncCallBuilder.
     pub(super) fn new(
-        canister: &'canister Canister<'agent>,
+        canister: Cow<'canister, Canister<'agent>>,
         method_name: &str,
     ) -> AsyncCallBuilder<'agent, 'canister> {
         Self {
-            canister,
             method_name: method_name.to_string(),
-            effective_canister_id: canister.canister_id_().to_owned(),
+            effective_canister_id: None,
+            canister,
             arg: Default::default(),
+            request_id_override: None,
+            nonce_len: None,
+            ingress_expiry: None,
+            backoff: None,
+            pre_sign_hook: None,
+            poll_strategy: None,
+            #[cfg(feature = "tracing")]
+            trace_span: None,
         }
     }
 }
@@ -363,6 +1344,21 @@ impl<'agent, 'canister: 'agent> AsyncCallBuilder<'agent, 'canister> {
         self
     }
 
+    /// Add an argument to the candid argument list. This requires Candid arguments, if
+    /// there is a raw argument set (using [`with_arg_raw`](AsyncCallBuilder::with_arg_raw)), this will fail.
+    pub fn with_value_arg(mut self, arg: IDLValue) -> AsyncCallBuilder<'agent, 'canister> {
+        self.arg.push_value_arg(arg);
+        self
+    }
+
+    /// Add the values of an argument in the Candid textual format, e.g. `("hello", 42)`, to
+    /// the candid argument list. If the text cannot be parsed, or there is a raw argument set
+    /// (using [`with_arg_raw`](AsyncCallBuilder::with_arg_raw)), this will fail.
+    pub fn with_arg_candid_text(mut self, text: &str) -> AsyncCallBuilder<'agent, 'canister> {
+        self.arg.push_candid_text(text);
+        self
+    }
+
     /// Replace the argument with raw argument bytes. This will overwrite the current
     /// argument set, so calling this method twice will discard the first argument.
     pub fn with_arg_raw(mut self, arg: Vec<u8>) -> AsyncCallBuilder<'agent, 'canister> {
@@ -375,39 +1371,1094 @@ impl<'agent, 'canister: 'agent> AsyncCallBuilder<'agent, 'canister> {
         mut self,
         canister_id: Principal,
     ) -> AsyncCallBuilder<'agent, 'canister> {
-        self.effective_canister_id = canister_id;
+        self.effective_canister_id = Some(canister_id);
+        self
+    }
+
+    /// Use a random nonce of `len` bytes instead of the nonce from the agent's nonce factory,
+    /// e.g. to keep the request small. See [`UpdateBuilder::with_nonce_len`](ic_agent::agent::UpdateBuilder::with_nonce_len).
+    ///
+    /// The call fails with [`AgentError::MessageError`] if `len` is greater than 32.
+    pub fn with_nonce_len(mut self, len: usize) -> AsyncCallBuilder<'agent, 'canister> {
+        self.nonce_len = Some(len);
+        self
+    }
+
+    /// Make the call expire `expiry` after it is signed, instead of after the agent's ingress
+    /// expiry, e.g. to fail fast, or to submit a call signed with [`dry_run`](Self::dry_run)
+    /// later. If the canister has the deadline of a [Context], the call expires at the earlier
+    /// of the two.
+    ///
+    /// The replica rejects calls that expire further in the future than its maximum ingress
+    /// window, which is 5 minutes on the IC.
+    pub fn with_ingress_expiry(mut self, expiry: Duration) -> AsyncCallBuilder<'agent, 'canister> {
+        self.ingress_expiry = Some(expiry);
+        self
+    }
+
+    /// Retry the requests of the call that fail with a transient error as described by
+    /// `config`, both when submitting it and when polling for its result, like
+    /// [`AgentBuilder::with_exponential_backoff`](ic_agent::agent::AgentBuilder::with_exponential_backoff)
+    /// does for every call of an agent. The call is signed once, so submitting it again cannot
+    /// execute it twice.
+    pub fn with_exponential_backoff(
+        mut self,
+        config: ExponentialBackoffConfig,
+    ) -> AsyncCallBuilder<'agent, 'canister> {
+        self.backoff = Some(config);
+        self
+    }
+
+    /// Wait between polls of the status of the call as decided by `strategy`, e.g.
+    /// [`ConstantPoll`](crate::polling::ConstantPoll), instead of the backoff of
+    /// [`Agent::wait`](ic_agent::Agent::wait). Whatever the strategy, the call is given up on
+    /// with [`AgentError::TimeoutWaitingForResponse`] after waiting for 5 minutes in total.
+    pub fn with_poll_strategy(
+        mut self,
+        strategy: impl PollStrategy + 'static,
+    ) -> AsyncCallBuilder<'agent, 'canister> {
+        self.poll_strategy = Some(BoxedPollStrategy(Box::new(strategy)));
+        self
+    }
+
+    /// Wrap [`call_and_wait`](AsyncCaller::call_and_wait) in an `INFO` [tracing] span, with the
+    /// fields `name`, `canister_id` and `method_name`. Span names must be static in `tracing`,
+    /// so the span itself is always named `canister_call`, and `name` is recorded as a field.
+    #[cfg(feature = "tracing")]
+    pub fn with_trace_span(
+        mut self,
+        name: impl Into<String>,
+    ) -> AsyncCallBuilder<'agent, 'canister> {
+        self.trace_span = Some(name.into());
+        self
+    }
+
+    /// Call `hook` with the SHA-256 hash of the content of the call, which is its request ID,
+    /// right before signing it, e.g. to have it approved on a hardware device. If the hook
+    /// returns an error, the call is aborted with that error, and the identity never signs it.
+    /// See [`UpdateBuilder::with_pre_sign_hook`](ic_agent::agent::UpdateBuilder::with_pre_sign_hook).
+    pub fn with_pre_sign_hook(
+        mut self,
+        hook: impl Fn(&[u8]) -> Result<(), AgentError> + Send + Sync + 'static,
+    ) -> AsyncCallBuilder<'agent, 'canister> {
+        self.pre_sign_hook = Some(PreSignHook::new(hook));
+        self
+    }
+
+    /// Send the call without a nonce, so that its request ID is deterministic. Equivalent to
+    /// `with_nonce_len(0)`.
+    pub fn with_no_nonce(self) -> AsyncCallBuilder<'agent, 'canister> {
+        self.with_nonce_len(0)
+    }
+
+    /// Use `request_id` instead of the computed request ID when returning from
+    /// [`call`](AsyncCaller::call) and when polling for the result. Only available in tests
+    /// and with the `testing` feature.
+    ///
+    /// The actual request ID is the hash of the request content, so it cannot be chosen: the
+    /// signed envelope is unchanged, and the replica still knows the request by its real ID. This
+    /// is only useful against a mocked transport, e.g. to get stable request IDs in snapshots.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn with_request_id(mut self, request_id: RequestId) -> AsyncCallBuilder<'agent, 'canister> {
+        self.request_id_override = Some(request_id);
         self
     }
 
+    /// Signs and serializes the call envelope exactly as [`AsyncCaller::call`] would, but
+    /// does not submit it. Useful for validating the argument encoding, or for displaying
+    /// the request before it is sent.
+    pub fn dry_run(self) -> Result<DryRunResult, AgentError> {
+        let c = &self.canister;
+        let (arg, effective_canister_id) = c.route(
+            &self.method_name,
+            self.effective_canister_id,
+            self.arg.serialize(),
+        );
+        let (arg, mut expiry) = apply_deadline(c.deadline, arg);
+        if let Some(ingress_expiry) = self.ingress_expiry {
+            expiry = expiry.at_most_after(ingress_expiry);
+        }
+        let mut builder = c.agent.update(&c.canister_id, &self.method_name);
+        builder.with_arg(arg?);
+        builder.with_effective_canister_id(effective_canister_id);
+        if let Some(len) = self.nonce_len {
+            builder.with_nonce_len(len);
+        }
+        expiry.apply_to_update(&mut builder);
+        if let Some(hook) = self.pre_sign_hook {
+            builder.with_pre_sign_hook(hook);
+        }
+        builder.sign().map(DryRunResult::from)
+    }
+
+    /// Builds the call and submits it, yielding every change of its status. See
+    /// [`AsyncCaller::call_and_collect_status`].
+    pub fn call_and_collect_status(
+        self,
+    ) -> impl 'canister + Stream<Item = Result<CallStatus, AgentError>> {
+        self.build::<()>().call_and_collect_status()
+    }
+
     /// Builds an [AsyncCaller] from this builder's state.
     pub fn build<Output>(self) -> AsyncCaller<'canister, Output>
     where
         Output: for<'de> ArgumentDecoder<'de> + Send + Sync,
     {
-        let c = self.canister;
+        let c = &self.canister;
+        let arg = apply_budget(c.cycle_budget, &self.method_name, self.arg.serialize());
+        let (arg, effective_canister_id) =
+            c.route(&self.method_name, self.effective_canister_id, arg);
+        let (arg, mut expiry) = apply_deadline(c.deadline, arg);
+        if let Some(ingress_expiry) = self.ingress_expiry {
+            expiry = expiry.at_most_after(ingress_expiry);
+        }
         AsyncCaller {
             agent: c.agent,
-            effective_canister_id: self.effective_canister_id,
+            effective_canister_id,
             canister_id: c.canister_id,
             method_name: self.method_name.clone(),
-            arg: self.arg.serialize(),
-            expiry: Default::default(),
+            arg,
+            expiry,
+            deadline: c.deadline,
+            metrics: c.metrics.clone(),
+            request_id_override: self.request_id_override,
+            nonce_len: self.nonce_len,
+            backoff: self.backoff,
+            pre_sign_hook: self.pre_sign_hook,
+            poll_strategy: self.poll_strategy,
+            #[cfg(feature = "tracing")]
+            trace_span: self.trace_span,
             phantom_out: std::marker::PhantomData,
         }
     }
 }
 
+/// The result of [`AsyncCallBuilder::dry_run`]: a signed update envelope that was not submitted.
+#[derive(Debug, Clone)]
+pub struct DryRunResult {
+    /// The request ID the call would have.
+    pub request_id: RequestId,
+    /// The CBOR-encoded [authentication envelope](https://smartcontracts.org/docs/interface-spec/index.html#authentication) for the request.
+    pub signed_envelope: Vec<u8>,
+    /// The principal ID of the caller.
+    pub sender: Principal,
+    /// The Unix timestamp that the request will expire at.
+    pub ingress_expiry: u64,
+}
+
+impl From<SignedUpdate> for DryRunResult {
+    fn from(signed: SignedUpdate) -> Self {
+        Self {
+            request_id: signed.request_id,
+            signed_envelope: signed.signed_update,
+            sender: signed.sender,
+            ingress_expiry: signed.ingress_expiry,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::super::interfaces::ManagementCanister;
-    use crate::call::AsyncCall;
-    use ic_agent::agent::http_transport::ReqwestTransport;
+    use super::Argument;
     use ic_agent::identity::BasicIdentity;
 
-    #[ignore]
+    #[tokio::test]
+    async fn async_call_builder_value_arg() {
+        use crate::test_util::{self, canister_id, expect_arg, is_sent};
+        use candid::parser::value::IDLValue;
+        use ic_agent::agent::mock::{MockAgent, MockTransport};
+
+        let arg = candid::encode_one("hello").unwrap();
+        let agent = MockAgent::new(expect_arg(MockTransport::new(), "call", canister_id(), arg));
+        let canister = test_util::canister(&agent);
+
+        let result = canister
+            .update_("greet")
+            .with_value_arg(IDLValue::Text("hello".to_string()))
+            .build::<()>()
+            .call()
+            .await;
+        assert!(is_sent(&result));
+
+        // Mixing with a raw argument fails before anything is sent.
+        let result = canister
+            .update_("greet")
+            .with_arg_raw(vec![])
+            .with_value_arg(IDLValue::Nat32(1))
+            .build::<()>()
+            .call()
+            .await;
+        assert!(matches!(result, Err(ic_agent::AgentError::MessageError(_))));
+        agent.verify_all_called();
+    }
+
+    #[tokio::test]
+    async fn call_many() {
+        use super::Canister;
+        use crate::test_util::sent;
+        use candid::types::{Serializer, Type};
+        use ic_agent::agent::mock::{MockAgent, MockTransport, Response};
+        use ic_agent::{export::Principal, AgentError};
+
+        let first = Principal::from_slice(&[1; 10]);
+        let second = Principal::from_slice(&[2; 10]);
+        let agent = MockAgent::new(
+            MockTransport::new()
+                .expect_query(
+                    first,
+                    Response::query_reply(candid::encode_one("hello").unwrap()),
+                )
+                .expect_query(second, Response::error(sent)),
+        );
+
+        let results =
+            Canister::call_many::<(String,)>(&agent, vec![first, second], "greet", ("world",))
+                .await
+                .unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(matches!(&results[0], (id, Ok((reply,))) if *id == first && reply == "hello"));
+        assert!(matches!(&results[1], (id, Err(AgentError::MessageError(_))) if *id == second));
+        agent.verify_all_called();
+
+        struct Unencodable;
+        impl candid::CandidType for Unencodable {
+            fn _ty() -> Type {
+                Type::Text
+            }
+            fn idl_serialize<S: Serializer>(&self, _serializer: S) -> Result<(), S::Error> {
+                Err(serde::ser::Error::custom("unencodable"))
+            }
+        }
+
+        // No canister is called with an argument that cannot be encoded.
+        let agent = MockAgent::new(MockTransport::new());
+        let result =
+            Canister::call_many::<()>(&agent, vec![first, second], "greet", (Unencodable,)).await;
+        assert!(matches!(result, Err(AgentError::CandidError(_))));
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn trace_span() {
+        use crate::test_util::{self, canister_id, is_sent, sent};
+        use ic_agent::agent::mock::{Matcher, MockAgent, MockTransport, Response};
+        use std::fmt::Debug;
+        use std::sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        };
+        use tracing::{
+            field::{Field, Visit},
+            span, Event, Metadata, Subscriber,
+        };
+
+        /// Records the fields of the spans, and whether one is entered.
+        #[derive(Default)]
+        struct Spans {
+            fields: Mutex<Vec<(&'static str, String)>>,
+            entered: AtomicBool,
+        }
+
+        struct Recorder(Arc<Spans>);
+
+        impl Visit for &Spans {
+            fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                let value = format!("{value:?}");
+                self.fields.lock().unwrap().push((field.name(), value));
+            }
+        }
+
+        impl Subscriber for Recorder {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+                let name = span.metadata().name().to_string();
+                self.0.fields.lock().unwrap().push(("span", name));
+                span.record(&mut &*self.0);
+                span::Id::from_u64(1)
+            }
+            fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+            fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+            fn event(&self, _event: &Event<'_>) {}
+            fn enter(&self, _span: &span::Id) {
+                self.0.entered.store(true, Ordering::SeqCst);
+            }
+            fn exit(&self, _span: &span::Id) {
+                self.0.entered.store(false, Ordering::SeqCst);
+            }
+        }
+
+        let spans = Arc::new(Spans::default());
+        let _guard = tracing::subscriber::set_default(Recorder(spans.clone()));
+        // The call is only expected while the span is entered.
+        let expected_path = format!("canister/{}/call", canister_id());
+        let in_span = spans.clone();
+        let agent = MockAgent::new(MockTransport::new().expect(
+            Matcher::Custom(Arc::new(move |path, _| {
+                path == expected_path && in_span.entered.load(Ordering::SeqCst)
+            })),
+            Response::error(sent),
+        ));
+        let canister = test_util::canister(&agent);
+
+        let result = canister
+            .update_("greet")
+            .with_trace_span("greeting")
+            .build::<()>()
+            .call_and_wait()
+            .await;
+        assert!(is_sent(&result));
+        assert!(!spans.entered.load(Ordering::SeqCst));
+        assert_eq!(
+            *spans.fields.lock().unwrap(),
+            vec![
+                ("span", "canister_call".to_string()),
+                ("name", "greeting".to_string()),
+                ("canister_id", canister_id().to_string()),
+                ("method_name", "greet".to_string()),
+            ]
+        );
+        agent.verify_all_called();
+    }
+
+    #[tokio::test]
+    async fn call_update_and_query() {
+        use crate::test_util::{self, canister_id, expect_method, is_sent, sent};
+        use ic_agent::agent::mock::{MockAgent, MockTransport, Response};
+
+        let arg = candid::encode_args(("world", 1u8)).unwrap();
+        let transport = expect_method(
+            MockTransport::new(),
+            "query",
+            canister_id(),
+            "greet",
+            arg.clone(),
+            Response::query_reply(candid::encode_one("hello").unwrap()),
+        );
+        let agent = MockAgent::new(expect_method(
+            transport,
+            "call",
+            canister_id(),
+            "greet",
+            arg,
+            Response::error(sent),
+        ));
+        let canister = test_util::canister(&agent);
+
+        let (reply,): (String,) = canister.call_query("greet", ("world", 1u8)).await.unwrap();
+        assert_eq!(reply, "hello");
+        let result: Result<(), _> = canister.call_update("greet", ("world", 1u8)).await;
+        assert!(is_sent(&result));
+        agent.verify_all_called();
+    }
+
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn call_with_text() {
+        use super::CallMode;
+        use crate::test_util::{self, canister_id};
+        use ic_agent::agent::mock::{MockAgent, MockTransport, Response};
+
+        let agent = MockAgent::new(MockTransport::new().expect_query(
+            canister_id(),
+            Response::query_reply(candid::encode_args(("hello", 42u32)).unwrap()),
+        ));
+        let canister = test_util::canister(&agent);
+
+        let reply = canister
+            .call_with_text("greet", r#"("world")"#, CallMode::Query)
+            .await
+            .unwrap();
+        assert_eq!(reply, serde_json::json!(["hello", 42]));
+        agent.verify_all_called();
+    }
+
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn call_with_invalid_text() {
+        use super::CallMode;
+        use crate::test_util;
+        use ic_agent::agent::mock::{MockAgent, MockTransport};
+
+        // Without expectations, any request that reaches the transport fails differently.
+        let agent = MockAgent::new(MockTransport::new());
+        let canister = test_util::canister(&agent);
+
+        // The argument is parsed before anything is sent.
+        for mode in [CallMode::Query, CallMode::Update] {
+            let result = canister.call_with_text("greet", "(\"hello\"", mode).await;
+            assert!(matches!(result, Err(ic_agent::AgentError::CandidError(_))));
+        }
+    }
+
+    #[test]
+    fn clone_argument() {
+        let mut idl = Argument::from_candid((1u32, "a"));
+        let clone = idl.clone();
+        let expected = Argument::from_candid((1u32, "a")).serialize().unwrap();
+        assert_eq!(clone.serialize().unwrap(), expected);
+        // Cloning does not change the original, which is still encoded the same way.
+        assert_eq!(idl.try_peek_serialized().unwrap(), expected);
+        idl.push_idl_arg(true);
+        assert_eq!(
+            idl.serialize().unwrap(),
+            Argument::from_candid((1u32, "a", true))
+                .serialize()
+                .unwrap()
+        );
+
+        // The types of the values are kept, even where they cannot be told from the values.
+        let mut typed = Argument::new();
+        typed.push_idl_arg(Vec::<u32>::new());
+        typed.push_idl_arg(None::<String>);
+        let expected = Argument::from_candid((Vec::<u32>::new(), None::<String>))
+            .serialize()
+            .unwrap();
+        let mut clone = typed.clone();
+        assert_eq!(typed.serialize().unwrap(), expected);
+        clone.push_idl_arg(1u8);
+        assert_eq!(
+            clone.serialize().unwrap(),
+            Argument::from_candid((Vec::<u32>::new(), None::<String>, 1u8))
+                .serialize()
+                .unwrap()
+        );
+
+        let raw = Argument::from_raw(vec![1, 2, 3]);
+        let clone = raw.clone();
+        assert_eq!(raw.serialize().unwrap(), vec![1, 2, 3]);
+        assert_eq!(clone.serialize().unwrap(), vec![1, 2, 3]);
+
+        let mut error = Argument::from_raw(vec![]);
+        error.push_idl_arg(1u32);
+        assert!(error.clone().serialize().is_err());
+    }
+
+    #[test]
+    fn argument_round_trip() {
+        #[derive(Debug, PartialEq, candid::CandidType, candid::Deserialize)]
+        struct Tree {
+            value: u32,
+            children: Vec<Tree>,
+        }
+
+        #[derive(Debug, PartialEq, candid::CandidType, candid::Deserialize)]
+        struct Account {
+            owner: String,
+            balance: u64,
+            memo: Option<Vec<u8>>,
+        }
+
+        let tree = || Tree {
+            value: 1,
+            children: vec![
+                Tree {
+                    value: 2,
+                    children: vec![],
+                },
+                Tree {
+                    value: 3,
+                    children: vec![Tree {
+                        value: 4,
+                        children: vec![],
+                    }],
+                },
+            ],
+        };
+        let account = || Account {
+            owner: "alice".to_owned(),
+            balance: 42,
+            memo: Some(vec![1, 2]),
+        };
+
+        let mut arg = Argument::new();
+        arg.push_idl_arg(tree());
+        arg.push_idl_arg(account());
+        let mut tail = Argument::new();
+        tail.push_idl_arg(tree());
+        tail.push_idl_arg(7u8);
+        let arg = Argument::zip(arg.clone(), tail);
+
+        let blob = arg.serialize().unwrap();
+        let (a, b, c, d): (Tree, Account, Tree, u8) = candid::decode_args(&blob).unwrap();
+        assert_eq!((a, b, c, d), (tree(), account(), tree(), 7));
+    }
+
+    #[test]
+    fn peek_argument() {
+        let arg = Argument::from_candid((1u32,));
+        assert_eq!(arg.to_string(), "<IDL args>");
+        let peeked = arg.try_peek_serialized().unwrap();
+        assert_eq!(peeked, Argument::from_candid((1u32,)).serialize().unwrap());
+        assert_eq!(arg.serialize().unwrap(), peeked);
+
+        let arg = Argument::from_raw(vec![0x44, 0x49, 0x0a]);
+        assert_eq!(arg.to_string(), "44490a");
+        assert_eq!(arg.try_peek_serialized().unwrap(), vec![0x44, 0x49, 0x0a]);
+    }
+
+    #[tokio::test]
+    async fn candid_text_arg() {
+        use crate::call::SyncCall;
+        use crate::test_util::{self, canister_id, expect_arg, is_sent};
+        use ic_agent::agent::mock::{MockAgent, MockTransport};
+
+        let arg = candid::encode_args(("hello", true)).unwrap();
+        let agent = MockAgent::new(expect_arg(MockTransport::new(), "call", canister_id(), arg));
+        let canister = test_util::canister(&agent);
+
+        let result = canister
+            .update_("greet")
+            .with_arg_candid_text(r#"("hello", true)"#)
+            .build::<()>()
+            .call()
+            .await;
+        assert!(is_sent(&result));
+
+        let result: Result<(), _> = canister
+            .query_("greet")
+            .with_arg_candid_text("(\"hello\"")
+            .build()
+            .call()
+            .await;
+        assert!(matches!(result, Err(ic_agent::AgentError::CandidError(_))));
+        agent.verify_all_called();
+    }
+
+    #[test]
+    fn candid_text_round_trip() {
+        let mut arg = Argument::new();
+        arg.push_candid_text(r#"(record { id = 1; name = "Alice" }, true)"#);
+        let text = arg.to_candid_text().unwrap();
+        let mut parsed = Argument::new();
+        parsed.push_candid_text(&text);
+        assert_eq!(parsed.serialize().unwrap(), arg.serialize().unwrap());
+
+        let mut invalid = Argument::new();
+        invalid.push_candid_text("(1");
+        assert!(invalid.serialize().is_err());
+        assert!(Argument::from_raw(vec![1, 2, 3]).to_candid_text().is_err());
+    }
+
+    #[test]
+    fn zip_arguments() {
+        let zipped = Argument::zip(
+            Argument::from_candid((1u32,)),
+            Argument::from_candid(("a", true)),
+        );
+        assert_eq!(
+            zipped.serialize().unwrap(),
+            Argument::from_candid((1u32, "a", true))
+                .serialize()
+                .unwrap()
+        );
+
+        let raw = Argument::zip(Argument::from_candid((1u32,)), Argument::from_raw(vec![]));
+        assert!(raw.serialize().is_err());
+    }
+
+    #[test]
+    fn argument_from_component_bytes() {
+        let components = vec![
+            candid::encode_one(1u32).unwrap(),
+            candid::encode_args(("a", true)).unwrap(),
+        ];
+        let blob = Argument::from_component_bytes(components)
+            .serialize()
+            .unwrap();
+        assert!(blob.starts_with(b"DIDL"));
+        let decoded: (u32, String, bool) = candid::utils::decode_args(&blob).unwrap();
+        assert_eq!(decoded, (1, "a".to_string(), true));
+
+        let invalid = Argument::from_component_bytes(vec![b"not candid".to_vec()]);
+        assert!(invalid.serialize().is_err());
+    }
+
+    #[test]
+    fn into_owned() {
+        use super::CanisterBuilderError;
+        use crate::test_util;
+        use ic_agent::agent::mock::{MockAgent, MockTransport};
+        use ic_agent::identity::Secp256k1Identity;
+        use std::sync::Arc;
+
+        let agent = MockAgent::new(MockTransport::new());
+        let canister = test_util::canister(&agent);
+
+        let owned = canister
+            .clone()
+            .into_owned(Arc::new(agent.agent().clone()))
+            .unwrap();
+        assert_eq!(owned.canister_id(), canister.canister_id());
+
+        let key = k256::SecretKey::from_slice(&[1; 32]).unwrap();
+        let other_identity = ic_agent::Agent::builder()
+            .with_transport(MockTransport::new())
+            .with_identity(Secp256k1Identity::from_private_key(key))
+            .build()
+            .unwrap();
+        assert!(matches!(
+            canister.clone().into_owned(Arc::new(other_identity)),
+            Err(CanisterBuilderError::IncompatibleAgent(_))
+        ));
+
+        let other_root_key = MockAgent::new(MockTransport::new()).agent().clone();
+        other_root_key.set_root_key(vec![1, 2, 3]);
+        assert!(matches!(
+            canister.into_owned(Arc::new(other_root_key)),
+            Err(CanisterBuilderError::IncompatibleAgent(_))
+        ));
+    }
+
+    #[test]
+    fn canister_pool_insert() {
+        use super::{CanisterBuilderError, CanisterPool};
+        use crate::test_util;
+        use ic_agent::agent::mock::{MockAgent, MockTransport};
+        use ic_agent::export::Principal;
+
+        let agent = MockAgent::new(MockTransport::new());
+        let mut pool = CanisterPool::new(agent.agent());
+        let canister = test_util::canister(&agent);
+
+        pool.insert(canister.canister_id(), canister.clone())
+            .unwrap();
+        assert_eq!(pool.iter().count(), 1);
+
+        let other_id = Principal::management_canister();
+        assert!(matches!(
+            pool.insert(other_id, canister),
+            Err(CanisterBuilderError::CanisterIdMismatch { expected, actual })
+                if expected == other_id && actual == test_util::canister_id()
+        ));
+
+        let other_agent = MockAgent::new(MockTransport::new());
+        let other_canister = test_util::canister(&other_agent);
+        assert!(matches!(
+            pool.insert(other_canister.canister_id(), other_canister),
+            Err(CanisterBuilderError::IncompatibleAgent(_))
+        ));
+        assert_eq!(pool.iter().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn effective_id_resolver() {
+        use super::Canister;
+        use crate::call::SyncCall;
+        use crate::test_util::{expect_arg, is_sent};
+        use ic_agent::agent::mock::{MockAgent, MockTransport};
+        use ic_agent::export::Principal;
+
+        #[derive(candid::CandidType)]
+        struct In {
+            canister_id: Principal,
+        }
+        let routed = Principal::from_slice(&[1; 10]);
+        let arg = candid::encode_one(In {
+            canister_id: routed,
+        })
+        .unwrap();
+        // Each call fails as sent only if it reaches the expected effective canister ID.
+        let agent = MockAgent::new(
+            [
+                ("call", routed),
+                ("call", Principal::anonymous()),
+                ("query", Principal::management_canister()),
+            ]
+            .into_iter()
+            .fold(MockTransport::new(), |transport, (endpoint, id)| {
+                expect_arg(transport, endpoint, id, arg.clone())
+            }),
+        );
+        let canister = Canister::builder()
+            .with_agent(&agent)
+            .with_canister_id(Principal::management_canister())
+            .build()
+            .unwrap();
+
+        // Without a resolver, calls to the management canister are routed by their argument.
+        let result = canister
+            .update_("start_canister")
+            .with_arg(In {
+                canister_id: routed,
+            })
+            .build::<()>()
+            .call()
+            .await;
+        assert!(is_sent(&result));
+        let result = canister
+            .update_("start_canister")
+            .with_arg(In {
+                canister_id: routed,
+            })
+            .with_effective_canister_id(Principal::anonymous())
+            .build::<()>()
+            .call()
+            .await;
+        assert!(is_sent(&result));
+        // A call that manages a canister fails before it is sent if the canister cannot be told
+        // from its argument, rather than being sent to the management canister ID.
+        let result = canister
+            .update_("start_canister")
+            .with_arg(())
+            .build::<()>()
+            .call()
+            .await;
+        assert!(matches!(result, Err(ic_agent::AgentError::CandidError(_))));
+
+        let canister = canister.with_effective_id_resolver(move |method_name| {
+            if method_name == "create_canister" {
+                routed
+            } else {
+                Principal::management_canister()
+            }
+        });
+        let result = canister
+            .update_("create_canister")
+            .with_arg(In {
+                canister_id: routed,
+            })
+            .build::<()>()
+            .call()
+            .await;
+        assert!(is_sent(&result));
+        let result: Result<(), _> = canister
+            .query_("canister_status")
+            .with_arg(In {
+                canister_id: routed,
+            })
+            .build()
+            .call()
+            .await;
+        assert!(is_sent(&result));
+        agent.verify_all_called();
+    }
+
+    #[tokio::test]
+    async fn cycle_budget() {
+        use crate::budget::FeeSchedule;
+        use crate::call::SyncCall;
+        use crate::test_util::{self, canister_id, expect_arg, is_sent};
+        use ic_agent::agent::mock::{MockAgent, MockTransport};
+        use ic_agent::AgentError;
+
+        let arg = vec![0; 100];
+        let agent = MockAgent::new(
+            ["call", "query"]
+                .into_iter()
+                .fold(MockTransport::new(), |transport, endpoint| {
+                    expect_arg(transport, endpoint, canister_id(), arg.clone())
+                }),
+        );
+        let canister = test_util::canister(&agent);
+
+        let fees = FeeSchedule::default();
+        let estimated = fees.estimate_update_cost("greet", &arg);
+        assert_eq!(estimated, 1_200_000 + 2_000 * 105);
+        let budgeted = canister.with_cycle_budget(estimated, fees);
+        let result = budgeted
+            .update_("greet")
+            .with_arg_raw(arg.clone())
+            .build::<()>()
+            .call()
+            .await;
+        assert!(is_sent(&result));
+
+        // The same call costs more on a larger subnet, and is not sent.
+        let fees = FeeSchedule::for_subnet_size(34);
+        let over_budget = fees.estimate_update_cost("greet", &arg);
+        assert!(over_budget > estimated);
+        let budgeted = canister.with_cycle_budget(estimated, fees);
+        let result = budgeted
+            .update_("greet")
+            .with_arg_raw(arg.clone())
+            .build::<()>()
+            .call()
+            .await;
+        match result {
+            Err(AgentError::CycleBudgetExceeded {
+                estimated: e,
+                limit,
+            }) => assert_eq!((e, limit), (over_budget, estimated)),
+            other => panic!("unexpected result: {other:?}"),
+        }
+        // Queries are free.
+        let result: Result<(), _> = budgeted
+            .query_("greet")
+            .with_arg_raw(arg)
+            .build()
+            .call()
+            .await;
+        assert!(is_sent(&result));
+        agent.verify_all_called();
+    }
+
+    #[tokio::test]
+    async fn context_deadline() {
+        use crate::context::Context;
+        use crate::test_util::{self, canister_id, expect_arg, is_sent};
+        use ic_agent::agent::mock::{MockAgent, MockTransport};
+        use ic_agent::AgentError;
+        use std::time::{Duration, SystemTime};
+
+        let arg = candid::encode_args(()).unwrap();
+        let agent = MockAgent::new(expect_arg(MockTransport::new(), "call", canister_id(), arg));
+        let canister = test_util::canister(&agent);
+
+        let deadline = SystemTime::now() + Duration::from_secs(60);
+        let contextual = canister.with_context(Context::with_deadline(deadline));
+        let result = contextual.update_("greet").build::<()>().call().await;
+        assert!(is_sent(&result));
+
+        // Once the deadline passed, the call is not sent.
+        let expired = canister.with_context(Context::with_deadline(SystemTime::UNIX_EPOCH));
+        let result = expired.update_("greet").build::<()>().call().await;
+        assert_eq!(result.err(), Some(AgentError::ContextDeadlineExceeded()));
+        agent.verify_all_called();
+    }
+
+    #[test]
+    fn ingress_expiry() {
+        use crate::test_util;
+        use crate::{call::Expiry, context::Context};
+        use ic_agent::agent::mock::{MockAgent, MockTransport};
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        let agent = MockAgent::new(MockTransport::new());
+        let canister = test_util::canister(&agent);
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let signed = canister
+            .update_("greet")
+            .with_ingress_expiry(Duration::from_secs(3600))
+            .dry_run()
+            .unwrap();
+        let expiry = Duration::from_nanos(signed.ingress_expiry);
+        assert!(expiry > now + Duration::from_secs(3000));
+        assert!(expiry < now + Duration::from_secs(3700));
+
+        let signed = canister.update_("greet").dry_run().unwrap();
+        assert!(Duration::from_nanos(signed.ingress_expiry) < now + Duration::from_secs(600));
+
+        // With a context, the call expires at the earlier of its deadline and the ingress expiry.
+        let deadline = SystemTime::now() + Duration::from_secs(60);
+        let contextual = canister.with_context(Context::with_deadline(deadline));
+        let caller = contextual
+            .update_("greet")
+            .with_ingress_expiry(Duration::from_secs(3600))
+            .build::<()>();
+        assert_eq!(caller.expiry, Expiry::at(deadline));
+        let signed = contextual
+            .update_("greet")
+            .with_ingress_expiry(Duration::from_secs(3600))
+            .dry_run()
+            .unwrap();
+        let expiry = Duration::from_nanos(signed.ingress_expiry);
+        assert!(expiry <= deadline.duration_since(UNIX_EPOCH).unwrap());
+        assert!(expiry > now + Duration::from_secs(50));
+
+        let caller = contextual
+            .update_("greet")
+            .with_ingress_expiry(Duration::from_secs(10))
+            .build::<()>();
+        assert!(matches!(caller.expiry, Expiry::DateTime(at) if at < deadline));
+    }
+
+    #[tokio::test]
+    async fn pre_sign_hook() {
+        use crate::test_util;
+        use ic_agent::agent::mock::{MockAgent, MockTransport};
+        use ic_agent::AgentError;
+
+        // Without expectations, any request that reaches the transport fails differently.
+        let agent = MockAgent::new(MockTransport::new());
+        let canister = test_util::canister(&agent);
+
+        let result = canister
+            .update_("greet")
+            .with_pre_sign_hook(|_| Err(AgentError::MessageError("denied".to_string())))
+            .build::<()>()
+            .call()
+            .await;
+        assert!(matches!(result, Err(AgentError::MessageError(message)) if message == "denied"));
+    }
+
+    #[tokio::test]
+    async fn collect_status_of_invalid_call() {
+        use crate::test_util;
+        use futures_util::StreamExt;
+        use ic_agent::agent::mock::{MockAgent, MockTransport};
+
+        let agent = MockAgent::new(MockTransport::new());
+        let canister = test_util::canister(&agent);
+
+        let statuses = canister
+            .update_("greet")
+            .with_arg_raw(vec![])
+            .with_arg(1u32)
+            .call_and_collect_status()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(statuses.len(), 1);
+        assert!(statuses[0].is_err());
+    }
+
+    #[tokio::test]
+    async fn exponential_backoff() {
+        use crate::test_util;
+        use ic_agent::agent::mock::{Matcher, MockTransport, Response};
+        use ic_agent::agent::{agent_error::HttpErrorPayload, ExponentialBackoffConfig};
+        use ic_agent::AgentError;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        fn http_error(status: u16) -> AgentError {
+            AgentError::HttpError(HttpErrorPayload {
+                status,
+                content_type: None,
+                content: vec![],
+            })
+        }
+
+        let config = ExponentialBackoffConfig {
+            max_attempts: 3,
+            base_delay_ms: 1,
+            max_delay_ms: 1,
+            jitter: false,
+        };
+        let canister_id = test_util::canister_id();
+        for (failures, status, backoff, ok, calls) in [
+            (2, 503, Some(config), true, 3),
+            (3, 503, Some(config), false, 3),
+            (1, 503, None, false, 1),
+            (1, 400, Some(config), false, 1),
+        ] {
+            // The first `failures` submissions fail with `status`, the next ones are accepted.
+            let submissions = Arc::new(AtomicUsize::new(0));
+            let counter = submissions.clone();
+            let call_path = format!("canister/{canister_id}/call");
+            let transport = MockTransport::new()
+                .expect(
+                    Matcher::Custom(Arc::new(move |path, _| {
+                        path == call_path && counter.fetch_add(1, Ordering::SeqCst) < failures
+                    })),
+                    Response::error(move || http_error(status)),
+                )
+                .expect_update(canister_id, Response::Bytes(vec![]));
+            let agent = ic_agent::Agent::builder()
+                .with_transport(transport)
+                .build()
+                .unwrap();
+            let canister = test_util::canister(&agent);
+
+            let mut builder = canister.update_("greet");
+            if let Some(config) = backoff {
+                builder = builder.with_exponential_backoff(config);
+            }
+            let result = builder.build::<()>().call().await;
+            assert_eq!(result.is_ok(), ok);
+            assert_eq!(submissions.load(Ordering::SeqCst), calls);
+        }
+    }
+
+    #[tokio::test]
+    async fn mock_query() {
+        use crate::call::SyncCall;
+        use crate::test_util::{self, canister_id};
+        use ic_agent::agent::mock::{MockAgent, MockTransport, Response};
+
+        let agent = MockAgent::new(MockTransport::new().expect_query(
+            canister_id(),
+            Response::query_reply(candid::encode_one("hello").unwrap()),
+        ));
+        let canister = test_util::canister(&agent);
+
+        let (reply,): (String,) = canister.query_("greet").build().call().await.unwrap();
+        assert_eq!(reply, "hello");
+        agent.verify_all_called();
+    }
+
+    #[tokio::test]
+    async fn ping() {
+        use crate::test_util;
+        use ic_agent::agent::mock::{MockAgent, MockTransport, Response};
+        use ic_agent::agent::RejectCode;
+        use ic_agent::AgentError;
+        use std::time::Duration;
+
+        async fn ping_with(transport: MockTransport) -> Result<Duration, AgentError> {
+            let agent = MockAgent::new(transport);
+            let result = test_util::canister(&agent).ping().await;
+            agent.verify_all_called();
+            result
+        }
+
+        let canister_id = test_util::canister_id();
+        let reply = Response::query_reply(candid::encode_one("service : {}").unwrap());
+        assert!(
+            ping_with(MockTransport::new().expect_query(canister_id, reply))
+                .await
+                .is_ok()
+        );
+
+        // A canister that cannot answer is not alive, and nothing else is tried.
+        let stopped = Response::query_reject(RejectCode::CanisterError, "Canister is stopped");
+        assert!(matches!(
+            ping_with(MockTransport::new().expect_query(canister_id, stopped)).await,
+            Err(AgentError::ReplicaError(reject)) if reject.reject_code == RejectCode::CanisterError
+        ));
+
+        // Without the method, the state tree is read instead of calling the management canister.
+        let no_method = Response::query_reject(
+            RejectCode::DestinationInvalid,
+            "Canister has no query method '__get_candid_interface_tmp_hack'",
+        );
+        let read_state = Response::error(|| AgentError::MessageError("read_state".to_string()));
+        assert!(matches!(
+            ping_with(
+                MockTransport::new()
+                    .expect_query(canister_id, no_method)
+                    .expect_read_state(canister_id, read_state)
+            )
+            .await,
+            Err(AgentError::MessageError(message)) if message == "read_state"
+        ));
+    }
+
+    #[tokio::test]
+    async fn replicated_query() {
+        use crate::call::ReplicatedQueryCaller;
+        use crate::test_util::{self, canister_id, expect_arg, is_sent};
+        use ic_agent::agent::mock::{MockAgent, MockTransport};
+
+        let arg = candid::encode_one("world").unwrap();
+        let agent = MockAgent::new(expect_arg(MockTransport::new(), "call", canister_id(), arg));
+        let canister = test_util::canister(&agent);
+
+        let caller: ReplicatedQueryCaller<'_, (String,)> = canister
+            .query_("greet")
+            .with_arg("world")
+            .replicated()
+            .build();
+        // The call goes through the update endpoint, not the query one.
+        assert!(is_sent(&caller.call().await));
+        agent.verify_all_called();
+    }
+
     #[tokio::test]
     async fn simple() {
         use super::Canister;
+        use ic_agent::agent::mock::{MockTransport, Response};
+        use ic_agent::agent::RejectResponse;
+        use ic_agent::export::Principal;
+        use ic_agent::{AgentError, RequestId};
+        use std::{str::FromStr, sync::Arc};
+
+        // A request status certificate signed by the mainnet, for a call to a method the
+        // canister does not have.
+        const REQUEST_STATUS: &[u8] = include_bytes!("testdata/request_status_reject.cbor");
+        const REQUEST_ID: &str = "EDAD510EAAA08ED2ACD4781324E6446269DA6753EC17760F206BBE81C465FF52";
 
         let rng = ring::rand::SystemRandom::new();
         let key_pair = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng)
@@ -418,53 +2469,178 @@ mod tests {
                 .expect("Could not read the key pair."),
         );
 
+        let canister_id = Principal::from_text("ivg37-qiaaa-aaaab-aaaga-cai").unwrap();
+        let transport = Arc::new(
+            MockTransport::new()
+                .expect_update(canister_id, Response::Bytes(Vec::new()))
+                .expect_read_state(canister_id, Response::Bytes(REQUEST_STATUS.to_vec())),
+        );
         let agent = ic_agent::Agent::builder()
-            .with_transport(ReqwestTransport::create("http://localhost:8001").unwrap())
+            .with_arc_transport(transport.clone())
             .with_identity(identity)
             .build()
             .unwrap();
-        agent.fetch_root_key().await.unwrap();
 
-        let management_canister = ManagementCanister::from_canister(
-            Canister::builder()
-                .with_agent(&agent)
-                .with_canister_id("aaaaa-aa")
-                .build()
-                .unwrap(),
-        );
-
-        let (new_canister_id,) = management_canister
-            .create_canister()
-            .call_and_wait()
-            .await
+        let canister = Canister::builder()
+            .with_agent(&agent)
+            .with_canister_id(canister_id)
+            .build()
             .unwrap();
 
-        let (status,) = management_canister
-            .canister_status(&new_canister_id)
+        // The call is signed and submitted, and its reject is read from the verified certificate.
+        let result = canister
+            .update_("register")
+            .with_request_id(RequestId::from_str(REQUEST_ID).unwrap())
+            .build::<()>()
             .call_and_wait()
-            .await
+            .await;
+        assert!(matches!(
+            result,
+            Err(AgentError::ReplicaError(RejectResponse { reject_message, .. }))
+                if reject_message.contains("has no update method 'register'")
+        ));
+        transport.verify_all_called();
+    }
+
+    #[tokio::test]
+    async fn collect_status_and_wait_many() {
+        use super::Canister;
+        use crate::call::CallStatus;
+        use futures_util::StreamExt;
+        use ic_agent::agent::mock::{MockTransport, Response};
+        use ic_agent::export::Principal;
+        use ic_agent::{AgentError, RequestId};
+        use std::{str::FromStr, sync::Arc};
+
+        const REQUEST_STATUS: &[u8] = include_bytes!("testdata/request_status_reject.cbor");
+        const REQUEST_ID: &str = "EDAD510EAAA08ED2ACD4781324E6446269DA6753EC17760F206BBE81C465FF52";
+
+        let canister_id = Principal::from_text("ivg37-qiaaa-aaaab-aaaga-cai").unwrap();
+        let request_id = RequestId::from_str(REQUEST_ID).unwrap();
+        let transport = Arc::new(
+            MockTransport::new()
+                .expect_update(canister_id, Response::Bytes(Vec::new()))
+                .expect_read_state(canister_id, Response::Bytes(REQUEST_STATUS.to_vec())),
+        );
+        let agent = ic_agent::Agent::builder()
+            .with_arc_transport(transport.clone())
+            .build()
+            .unwrap();
+        let canister = Canister::builder()
+            .with_agent(&agent)
+            .with_canister_id(canister_id)
+            .build()
             .unwrap();
 
-        assert_eq!(format!("{}", status.status), "Running");
+        // The stream ends with the final status read from the certificate.
+        let statuses: Vec<_> = canister
+            .update_("register")
+            .with_request_id(request_id)
+            .call_and_collect_status()
+            .collect()
+            .await;
+        assert_eq!(statuses.len(), 2);
+        assert_eq!(statuses[0].as_ref().unwrap(), &CallStatus::Submitted);
+        assert!(matches!(
+            &statuses[1],
+            Ok(CallStatus::Rejected { message, .. }) if message.contains("has no update method 'register'")
+        ));
 
-        let canister_wasm = b"\0asm\x01\0\0\0";
-        management_canister
-            .install_code(&new_canister_id, canister_wasm)
-            .call_and_wait()
+        let results = agent
+            .wait_many(canister_id, vec![request_id], Default::default())
             .await
             .unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(matches!(&results[0], Err(AgentError::ReplicaError(_))));
+        transport.verify_all_called();
+    }
 
+    #[tokio::test]
+    async fn poll_limits() {
+        use super::Canister;
+        use crate::context::Context;
+        use crate::polling::ConstantPoll;
+        use ic_agent::agent::mock::{Matcher, MockTransport, Response};
+        use ic_agent::export::Principal;
+        use ic_agent::AgentError;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+
+        // The certificate does not know the status of the calls made here, so they look like
+        // they were never received.
+        const REQUEST_STATUS: &[u8] = include_bytes!("testdata/request_status_reject.cbor");
+
+        let canister_id = Principal::from_text("ivg37-qiaaa-aaaab-aaaga-cai").unwrap();
+        let agent_with_polls = |poll_time: Duration| {
+            let polls = Arc::new(AtomicUsize::new(0));
+            let counter = polls.clone();
+            let read_state_path = format!("canister/{canister_id}/read_state");
+            let transport = MockTransport::new()
+                .expect_update(canister_id, Response::Bytes(vec![]))
+                .expect(
+                    Matcher::Custom(Arc::new(move |path, _| {
+                        if path != read_state_path {
+                            return false;
+                        }
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        std::thread::sleep(poll_time);
+                        true
+                    })),
+                    Response::Bytes(REQUEST_STATUS.to_vec()),
+                );
+            let agent = ic_agent::Agent::builder()
+                .with_transport(transport)
+                .build()
+                .unwrap();
+            (agent, polls)
+        };
+
+        // A poll strategy that never waits still gives up at the deadline of the context, as
+        // the time spent polling counts.
+        let (agent, polls) = agent_with_polls(Duration::from_millis(20));
         let canister = Canister::builder()
             .with_agent(&agent)
-            .with_canister_id(new_canister_id)
+            .with_canister_id(canister_id)
             .build()
             .unwrap();
+        let start = Instant::now();
+        let result = canister
+            .with_context(Context::with_timeout(Duration::from_millis(200)))
+            .update_("register")
+            .with_poll_strategy(ConstantPoll(Duration::ZERO))
+            .build::<()>()
+            .call_and_wait()
+            .await;
+        assert!(matches!(
+            result,
+            Err(AgentError::TimeoutWaitingForResponse())
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(polls.load(Ordering::SeqCst) >= 2);
 
-        assert!(canister
-            .update_("hello")
+        // Without a deadline, the call is given up on once it expired without being received,
+        // however long the poll strategy would wait. The expiry of a call is set 60 seconds
+        // earlier than asked, to allow for the replica's clock drift.
+        let (agent, polls) = agent_with_polls(Duration::ZERO);
+        let canister = Canister::builder()
+            .with_agent(&agent)
+            .with_canister_id(canister_id)
+            .build()
+            .unwrap();
+        let start = Instant::now();
+        let result = canister
+            .update_("register")
+            .with_ingress_expiry(Duration::from_secs(60) + Duration::from_millis(100))
+            .with_poll_strategy(ConstantPoll(Duration::from_millis(50)))
             .build::<()>()
             .call_and_wait()
-            .await
-            .is_err());
+            .await;
+        assert!(matches!(
+            result,
+            Err(AgentError::TimeoutWaitingForResponse())
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!((2..10).contains(&polls.load(Ordering::SeqCst)));
     }
 }
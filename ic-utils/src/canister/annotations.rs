@@ -0,0 +1,79 @@
+use candid::{check_prog, parser::types::FuncMode, IDLProg, TypeEnv};
+use std::collections::HashMap;
+
+/// Find the annotations of `method_name` in the service of the Candid interface `candid`.
+///
+/// Candid only has mode annotations, so the map holds a single `mode` key, which is `query`,
+/// `composite_query`, `oneway`, or `update` for methods without one. Returns `None` if the
+/// interface has no service, or its service has no such method, and an error if the interface
+/// is not valid Candid.
+pub(super) fn method_annotations(
+    candid: &str,
+    method_name: &str,
+) -> Result<Option<HashMap<String, String>>, candid::Error> {
+    let prog = candid.parse::<IDLProg>()?;
+    let mut env = TypeEnv::new();
+    let actor = match check_prog(&mut env, &prog)? {
+        Some(actor) => actor,
+        None => return Ok(None),
+    };
+    let function = match env.get_method(&actor, method_name) {
+        Ok(function) => function,
+        Err(_) => return Ok(None),
+    };
+    let mode = match function.modes.first() {
+        None => "update",
+        Some(mode) if *mode == FuncMode::Query => "query",
+        Some(mode) if *mode == FuncMode::Oneway => "oneway",
+        Some(_) => "composite_query",
+    };
+    Ok(Some(HashMap::from([(
+        "mode".to_string(),
+        mode.to_string(),
+    )])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::method_annotations;
+
+    const CANDID: &str = r#"
+type account = record { owner : principal; balance : nat };
+type transfer_fn = func (principal, nat) -> (variant { ok; err : text });
+service : (record { minter : principal }) -> {
+  balance : (principal) -> (nat) query;
+  "total supply" : () -> (nat) query;
+  transfer : transfer_fn;
+  notify : (account) -> () oneway;
+  mint : (record { to : principal; amount : nat }) -> ();
+}
+"#;
+
+    fn mode(method_name: &str) -> Option<String> {
+        method_annotations(CANDID, method_name)
+            .unwrap()
+            .map(|annotations| annotations["mode"].clone())
+    }
+
+    #[test]
+    fn modes() {
+        assert_eq!(mode("balance").as_deref(), Some("query"));
+        assert_eq!(mode("total supply").as_deref(), Some("query"));
+        assert_eq!(mode("transfer").as_deref(), Some("update"));
+        assert_eq!(mode("notify").as_deref(), Some("oneway"));
+        assert_eq!(mode("mint").as_deref(), Some("update"));
+        assert_eq!(mode("owner"), None);
+    }
+
+    #[test]
+    fn invalid_interface() {
+        assert!(method_annotations("service : { greet : (text) -> (text) query", "greet").is_err());
+        // The name of a service at the end of a comment is not mistaken for the service.
+        let candid = "service : { greet : () -> () query }\n// see the service";
+        assert_eq!(
+            method_annotations(candid, "greet").unwrap().unwrap()["mode"],
+            "query"
+        );
+        assert_eq!(method_annotations("type t = nat;", "greet").unwrap(), None);
+    }
+}
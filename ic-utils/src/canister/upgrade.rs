@@ -0,0 +1,171 @@
+use crate::wasm::{CanisterWasm, WasmValidationError};
+use candid::{check_prog, types::subtype::subtype, IDLProg, TypeEnv};
+use ic_agent::{export::Principal, Agent, AgentError};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use thiserror::Error;
+
+const CANDID_SERVICE: &str = "candid:service";
+
+/// An error returned by [`Canister::verify_upgrade_compatible`](super::Canister::verify_upgrade_compatible).
+#[derive(Debug, Error)]
+pub enum UpgradeCompatibilityError {
+    /// The new module is not a valid WASM module.
+    #[error("Invalid WASM module: {0}")]
+    InvalidWasm(#[from] WasmValidationError),
+
+    /// The module hash or the interface of the installed module could not be read.
+    #[error(transparent)]
+    Agent(#[from] AgentError),
+
+    /// The installed or the new module has no `candid:service` metadata, so there is no
+    /// interface to check the upgrade against.
+    #[error(
+        "The {} module has no candid:service metadata",
+        if *installed { "installed" } else { "new" }
+    )]
+    MissingInterface {
+        /// Whether the metadata is missing from the installed module, or from the new one.
+        installed: bool,
+    },
+
+    /// The interface of the installed or of the new module is not valid Candid.
+    #[error("Could not parse the Candid interface: {0}")]
+    MalformedInterface(candid::Error),
+
+    /// The interface of the new module is not a subtype of the interface of the installed
+    /// module, so existing clients of the canister would break.
+    #[error("The upgrade is not compatible: {0}")]
+    Incompatible(candid::Error),
+}
+
+pub(super) async fn verify_upgrade_compatible(
+    agent: &Agent,
+    canister_id: Principal,
+    new_wasm: &[u8],
+) -> Result<(), UpgradeCompatibilityError> {
+    let new_wasm = CanisterWasm::from_bytes(new_wasm.to_vec())?;
+    let module_hash = match agent
+        .read_state_canister_info(canister_id, "module_hash")
+        .await
+    {
+        Ok(module_hash) => module_hash,
+        // Nothing is installed, so there is nothing to be compatible with.
+        Err(AgentError::LookupPathAbsent(_)) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if module_hash == Sha256::digest(new_wasm.as_bytes()).as_slice() {
+        return Ok(());
+    }
+
+    let new_interface = new_wasm
+        .metadata_sections()
+        .into_iter()
+        .find(|section| section.name == CANDID_SERVICE)
+        .ok_or(UpgradeCompatibilityError::MissingInterface { installed: false })?
+        .content;
+    let old_interface = match agent
+        .read_state_canister_metadata(canister_id, CANDID_SERVICE)
+        .await
+    {
+        Ok(old_interface) => old_interface,
+        Err(AgentError::LookupPathAbsent(_)) => {
+            return Err(UpgradeCompatibilityError::MissingInterface { installed: true })
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    check(&to_str(&old_interface)?, &to_str(&new_interface)?)
+}
+
+fn to_str(bytes: &[u8]) -> Result<String, UpgradeCompatibilityError> {
+    String::from_utf8(bytes.to_vec()).map_err(|e| {
+        UpgradeCompatibilityError::MalformedInterface(candid::Error::msg(e.to_string()))
+    })
+}
+
+/// Check that the service of the Candid interface `new` is a subtype of the one of `old`, as
+/// the replica's clients would see it, e.g. allowing new methods and new optional fields.
+fn check(old: &str, new: &str) -> Result<(), UpgradeCompatibilityError> {
+    let (mut env, new_service) = service(new)?;
+    let (old_env, old_service) = service(old)?;
+    let old_service = env.merge_type(old_env, old_service);
+    subtype(&mut HashSet::new(), &env, &new_service, &old_service)
+        .map_err(UpgradeCompatibilityError::Incompatible)
+}
+
+/// The service of the Candid interface `candid`, along with the types it uses.
+fn service(candid: &str) -> Result<(TypeEnv, candid::types::Type), UpgradeCompatibilityError> {
+    let malformed = UpgradeCompatibilityError::MalformedInterface;
+    let prog = candid.parse::<IDLProg>().map_err(malformed)?;
+    let mut env = TypeEnv::new();
+    let service = check_prog(&mut env, &prog)
+        .map_err(malformed)?
+        .ok_or_else(|| malformed(candid::Error::msg("The interface has no service.")))?;
+    Ok((env, service))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, UpgradeCompatibilityError};
+
+    const OLD: &str = r#"
+type user = record { name : text; age : nat };
+service : {
+  count : () -> (nat) query;
+  user : (principal) -> (opt user) query;
+  register : (user) -> ();
+}
+"#;
+
+    #[test]
+    fn compatible() {
+        // Types may be renamed or written differently, and methods and optional fields added.
+        let new = r#"
+type person = record { age : nat; name : text; email : opt text };
+type registration = record { name : text; age : nat };
+service : {
+  count : () -> (nat) query;
+  user : (principal) -> (opt person) query;
+  register : (registration) -> ();
+  reset : () -> ();
+}
+"#;
+        check(OLD, new).unwrap();
+    }
+
+    #[test]
+    fn incompatible() {
+        // A method was removed.
+        let new = r#"
+type user = record { name : text; age : nat };
+service : {
+  count : () -> (nat) query;
+  user : (principal) -> (opt user) query;
+}
+"#;
+        assert!(matches!(
+            check(OLD, new),
+            Err(UpgradeCompatibilityError::Incompatible(_))
+        ));
+
+        // A reply changed type.
+        let new = OLD.replace("-> (nat) query", "-> (text) query");
+        assert!(matches!(
+            check(OLD, &new),
+            Err(UpgradeCompatibilityError::Incompatible(_))
+        ));
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(matches!(
+            check(OLD, "service : { count : () -> (nat) query"),
+            Err(UpgradeCompatibilityError::MalformedInterface(_))
+        ));
+        assert!(matches!(
+            check("type t = nat;", OLD),
+            Err(UpgradeCompatibilityError::MalformedInterface(_))
+        ));
+    }
+}
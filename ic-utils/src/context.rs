@@ -0,0 +1,120 @@
+use crate::{call::Expiry, canister::Canister};
+use ic_agent::AgentError;
+use std::{
+    ops::Deref,
+    time::{Duration, SystemTime},
+};
+
+/// The context of the server request an IC call is made on behalf of. Currently this only
+/// holds the deadline of the request, e.g. from a `Grpc-Timeout` or `X-Request-Deadline` header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Context {
+    deadline: Option<SystemTime>,
+}
+
+impl Context {
+    /// A context without a deadline.
+    pub fn background() -> Self {
+        Self::default()
+    }
+
+    /// A context that must be done by `deadline`.
+    pub fn with_deadline(deadline: SystemTime) -> Self {
+        Self {
+            deadline: Some(deadline),
+        }
+    }
+
+    /// A context that must be done `timeout` from now.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self::with_deadline(SystemTime::now() + timeout)
+    }
+
+    /// The deadline of this context, if any.
+    pub fn deadline(&self) -> Option<SystemTime> {
+        self.deadline
+    }
+}
+
+/// A [Canister] whose calls expire at the deadline of a [Context]. Created with
+/// [`Canister::with_context`]. Deadlines more than 5 minutes away, the maximum ingress expiry
+/// of the IC, are capped to it.
+///
+/// Calls built once the deadline has passed fail with [`AgentError::ContextDeadlineExceeded`]
+/// without being sent.
+#[derive(Debug, Clone)]
+pub struct ContextualCanister<'agent>(pub(crate) Canister<'agent>);
+
+impl<'agent> ContextualCanister<'agent> {
+    /// Unwrap the contextual canister, e.g. to build an interface like
+    /// [`ManagementCanister`](crate::interfaces::ManagementCanister) around it. Calls made
+    /// through the result still expire at the deadline.
+    pub fn into_inner(self) -> Canister<'agent> {
+        self.0
+    }
+}
+
+impl<'agent> Deref for ContextualCanister<'agent> {
+    type Target = Canister<'agent>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// The maximum ingress expiry accepted by the IC. Calls expiring later are rejected.
+const MAX_INGRESS_EXPIRY: Duration = Duration::from_secs(5 * 60);
+
+/// The argument and expiry of a call made by a canister with the given deadline. Deadlines
+/// further away than the maximum ingress expiry are capped to it, so the call is accepted.
+pub(crate) fn apply_deadline(
+    deadline: Option<SystemTime>,
+    arg: Result<Vec<u8>, AgentError>,
+) -> (Result<Vec<u8>, AgentError>, Expiry) {
+    let now = SystemTime::now();
+    match deadline {
+        None => (arg, Expiry::Unspecified),
+        Some(deadline) if deadline <= now => (
+            Err(AgentError::ContextDeadlineExceeded()),
+            Expiry::Unspecified,
+        ),
+        Some(deadline) => (arg, Expiry::at(deadline.min(now + MAX_INGRESS_EXPIRY))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_deadline, MAX_INGRESS_EXPIRY};
+    use crate::call::Expiry;
+    use ic_agent::AgentError;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn deadline_is_capped() {
+        let before = SystemTime::now();
+        let deadline = before + Duration::from_secs(60);
+        assert_eq!(
+            apply_deadline(Some(deadline), Ok(vec![])),
+            (Ok(vec![]), Expiry::at(deadline))
+        );
+
+        let (arg, expiry) = apply_deadline(Some(before + Duration::from_secs(3600)), Ok(vec![]));
+        let after = SystemTime::now();
+        assert_eq!(arg, Ok(vec![]));
+        assert!(matches!(
+            expiry,
+            Expiry::DateTime(at) if at >= before + MAX_INGRESS_EXPIRY && at <= after + MAX_INGRESS_EXPIRY
+        ));
+
+        assert_eq!(
+            apply_deadline(Some(SystemTime::UNIX_EPOCH), Ok(vec![])),
+            (
+                Err(AgentError::ContextDeadlineExceeded()),
+                Expiry::Unspecified
+            )
+        );
+        assert_eq!(
+            apply_deadline(None, Ok(vec![])),
+            (Ok(vec![]), Expiry::Unspecified)
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use ic_agent::{agent::Transport, export::Principal, AgentError, RequestId};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+};
+
+#[cfg(not(target_family = "wasm"))]
+type TransportFuture<'a, V> = Pin<Box<dyn Future<Output = Result<V, AgentError>> + Send + 'a>>;
+
+#[cfg(target_family = "wasm")]
+type TransportFuture<'a, V> = Pin<Box<dyn Future<Output = Result<V, AgentError>> + 'a>>;
+
+/// Decides which calls sent through a [FaultyTransport] fail, to test how callers handle errors.
+pub trait FaultInjector: Send + Sync {
+    /// Return the error the `attempt`th call to `method` should fail with, counting from 1, or
+    /// `None` to make the call normally.
+    fn should_fail(&self, method: &str, attempt: u32) -> Option<AgentError>;
+}
+
+/// A [FaultInjector] that fails the `n`th call sent through the transport, counting from 1 and
+/// whatever its method, with `error`. Every other call is made normally.
+#[derive(Debug)]
+pub struct NthCallFaultInjector {
+    n: u32,
+    calls: AtomicU32,
+    error: Mutex<Option<AgentError>>,
+}
+
+impl NthCallFaultInjector {
+    /// Fail the `n`th call with `error`.
+    pub fn new(n: u32, error: AgentError) -> Self {
+        Self {
+            n,
+            calls: AtomicU32::new(0),
+            error: Mutex::new(Some(error)),
+        }
+    }
+}
+
+impl FaultInjector for NthCallFaultInjector {
+    fn should_fail(&self, _method: &str, _attempt: u32) -> Option<AgentError> {
+        if self.calls.fetch_add(1, Ordering::SeqCst) + 1 == self.n {
+            self.error.lock().unwrap().take()
+        } else {
+            None
+        }
+    }
+}
+
+/// A [Transport] whose query and update calls may fail with errors chosen by a
+/// [FaultInjector], e.g. to build an agent for tests with
+/// [`AgentBuilder::with_transport`](ic_agent::agent::AgentBuilder::with_transport).
+///
+/// The injector is asked for every query and update call, with its method name. Failed calls
+/// return the injected error without being sent to the wrapped transport. Reading the state
+/// tree, e.g. to poll the status of a call, and the status endpoint are never failed.
+pub struct FaultyTransport {
+    inner: Arc<dyn Transport>,
+    injector: Arc<dyn FaultInjector>,
+    attempts: Mutex<HashMap<String, u32>>,
+}
+
+impl FaultyTransport {
+    /// Wrap `inner`, failing the calls `injector` decides to fail.
+    pub fn new<T: 'static + Transport>(inner: T, injector: Arc<dyn FaultInjector>) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            injector,
+            attempts: Mutex::default(),
+        }
+    }
+
+    /// The error the call in `envelope` should fail with, if any.
+    fn fault(&self, envelope: &[u8]) -> Option<AgentError> {
+        #[derive(Deserialize)]
+        struct Envelope {
+            content: Content,
+        }
+        #[derive(Deserialize)]
+        struct Content {
+            method_name: String,
+        }
+
+        // An envelope that cannot be decoded is left to the replica to reject.
+        let method_name = serde_cbor::from_slice::<Envelope>(envelope)
+            .ok()?
+            .content
+            .method_name;
+        let attempt = {
+            let mut attempts = self.attempts.lock().unwrap();
+            let attempt = attempts.entry(method_name.clone()).or_insert(0);
+            *attempt += 1;
+            *attempt
+        };
+        self.injector.should_fail(&method_name, attempt)
+    }
+}
+
+impl fmt::Debug for FaultyTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FaultyTransport")
+            .field("attempts", &self.attempts)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Transport for FaultyTransport {
+    fn call(
+        &self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+        request_id: RequestId,
+    ) -> TransportFuture<()> {
+        match self.fault(&envelope) {
+            Some(error) => Box::pin(async move { Err(error) }),
+            None => self.inner.call(effective_canister_id, envelope, request_id),
+        }
+    }
+
+    fn read_state(
+        &self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> TransportFuture<Vec<u8>> {
+        self.inner.read_state(effective_canister_id, envelope)
+    }
+
+    fn query(
+        &self,
+        effective_canister_id: Principal,
+        envelope: Vec<u8>,
+    ) -> TransportFuture<Vec<u8>> {
+        match self.fault(&envelope) {
+            Some(error) => Box::pin(async move { Err(error) }),
+            None => self.inner.query(effective_canister_id, envelope),
+        }
+    }
+
+    fn status(&self) -> TransportFuture<Vec<u8>> {
+        self.inner.status()
+    }
+
+    fn replica_time(&self) -> TransportFuture<u64> {
+        self.inner.replica_time()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FaultyTransport, NthCallFaultInjector};
+    use crate::call::SyncCall;
+    use crate::test_util::{self, is_sent, sent};
+    use ic_agent::agent::mock::{MockTransport, Response};
+    use ic_agent::{Agent, AgentError};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn faulty_transport() {
+        let canister_id = test_util::canister_id();
+        let transport = Arc::new(
+            MockTransport::new()
+                .expect_query(
+                    canister_id,
+                    Response::query_reply(candid::encode_one("hello").unwrap()),
+                )
+                .expect_update(canister_id, Response::error(sent)),
+        );
+        let error = AgentError::MessageError("injected".to_string());
+        let agent = Agent::builder()
+            .with_transport(FaultyTransport::new(
+                transport.clone(),
+                Arc::new(NthCallFaultInjector::new(2, error)),
+            ))
+            .build()
+            .unwrap();
+        let canister = test_util::canister(&agent);
+
+        let (reply,): (String,) = canister.query_("greet").build().call().await.unwrap();
+        assert_eq!(reply, "hello");
+        // The second call fails without reaching the replica, the third one reaches it.
+        assert!(matches!(
+            canister.update_("greet").build::<()>().call().await,
+            Err(AgentError::MessageError(message)) if message == "injected"
+        ));
+        assert!(is_sent(
+            &canister.update_("greet").build::<()>().call().await
+        ));
+        transport.verify_all_called();
+    }
+}
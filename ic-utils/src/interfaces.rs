@@ -1,7 +1,62 @@
+pub mod blackhole;
+pub mod cycles_ledger;
+pub mod dip721;
 pub mod http_request;
 pub mod management_canister;
+pub mod sns_governance;
 pub mod wallet;
 
+pub use blackhole::BlackholeCanister;
+pub use cycles_ledger::CyclesLedgerCanister;
+pub use dip721::Dip721Canister;
 pub use http_request::HttpRequestCanister;
 pub use management_canister::ManagementCanister;
+pub use sns_governance::SnsGovernanceCanister;
 pub use wallet::WalletCanister;
+
+use crate::Canister;
+
+/// A typed wrapper around a [Canister], for use with [`Canister::bind`].
+///
+/// [WalletCanister] does not implement this trait, as it needs to query the wallet's version
+/// when it is created; use [`WalletCanister::from_canister`] instead.
+pub trait Interface<'agent>: Sized {
+    /// Wrap `canister` in this interface.
+    fn new(canister: Canister<'agent>) -> Self;
+}
+
+impl<'agent> Interface<'agent> for ManagementCanister<'agent> {
+    fn new(canister: Canister<'agent>) -> Self {
+        Self::from_canister(canister)
+    }
+}
+
+impl<'agent> Interface<'agent> for HttpRequestCanister<'agent> {
+    fn new(canister: Canister<'agent>) -> Self {
+        Self::from_canister(canister)
+    }
+}
+
+impl<'agent> Interface<'agent> for SnsGovernanceCanister<'agent> {
+    fn new(canister: Canister<'agent>) -> Self {
+        Self::from_canister(canister)
+    }
+}
+
+impl<'agent> Interface<'agent> for CyclesLedgerCanister<'agent> {
+    fn new(canister: Canister<'agent>) -> Self {
+        Self::from_canister(canister)
+    }
+}
+
+impl<'agent> Interface<'agent> for BlackholeCanister<'agent> {
+    fn new(canister: Canister<'agent>) -> Self {
+        Self::from_canister(canister)
+    }
+}
+
+impl<'agent> Interface<'agent> for Dip721Canister<'agent> {
+    fn new(canister: Canister<'agent>) -> Self {
+        Self::from_canister(canister)
+    }
+}
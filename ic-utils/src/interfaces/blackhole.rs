@@ -0,0 +1,120 @@
+//! The canister interface for the [blackhole canister](https://github.com/ninegua/ic-blackhole),
+//! an immutable canister that can be made a controller of other canisters so that anyone can
+//! read their status.
+
+use crate::{call::AsyncCall, interfaces::management_canister::CanisterStatus, Canister};
+use candid::{CandidType, Deserialize, Nat};
+use ic_agent::{export::Principal, Agent};
+use std::ops::Deref;
+
+/// The ID of the blackhole canister on the IC mainnet.
+pub const BLACKHOLE_CANISTER_ID: &str = "e3mmv-5qaaa-aaaah-aadma-cai";
+
+/// The blackhole canister.
+#[derive(Debug, Clone)]
+pub struct BlackholeCanister<'agent>(Canister<'agent>);
+
+impl<'agent> Deref for BlackholeCanister<'agent> {
+    type Target = Canister<'agent>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// The argument of a `canister_status` call.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+struct CanisterStatusArgs {
+    canister_id: Principal,
+}
+
+/// The status of a canister, as reported by the blackhole canister.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct BlackholeCanisterStatus {
+    /// The status of the canister.
+    pub status: CanisterStatus,
+    /// The SHA-256 hash of the canister's installed code, if any.
+    pub module_hash: Option<Vec<u8>>,
+    /// The total size, in bytes, of the memory the canister is using.
+    pub memory_size: Nat,
+    /// The canister's cycle balance.
+    pub cycles: Nat,
+}
+
+impl<'agent> BlackholeCanister<'agent> {
+    /// Create an instance of a `BlackholeCanister` interface pointing to the mainnet blackhole canister.
+    pub fn create(agent: &'agent Agent) -> Self {
+        Self(
+            Canister::builder()
+                .with_agent(agent)
+                .with_canister_id(BLACKHOLE_CANISTER_ID)
+                .build()
+                .unwrap(),
+        )
+    }
+
+    /// Create a `BlackholeCanister` interface from an existing canister object.
+    pub fn from_canister(canister: Canister<'agent>) -> Self {
+        Self(canister)
+    }
+}
+
+impl<'agent> BlackholeCanister<'agent> {
+    /// Get the status of a canister that the blackhole canister controls.
+    pub fn canister_status<'canister: 'agent>(
+        &'canister self,
+        canister_id: Principal,
+    ) -> impl 'agent + AsyncCall<(BlackholeCanisterStatus,)> {
+        self.update_("canister_status")
+            .with_arg(CanisterStatusArgs { canister_id })
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{expect_method, is_sent, sent};
+    use candid::parser::value::IDLArgs;
+    use ic_agent::agent::mock::{MockAgent, MockTransport, Response};
+
+    #[tokio::test]
+    async fn canister_status() {
+        let blackhole = Principal::from_text(BLACKHOLE_CANISTER_ID).unwrap();
+        let canister_id = Principal::from_slice(&[1; 10]);
+        let agent = MockAgent::new(expect_method(
+            MockTransport::new(),
+            "call",
+            blackhole,
+            "canister_status",
+            candid::encode_one(CanisterStatusArgs { canister_id }).unwrap(),
+            Response::error(sent),
+        ));
+
+        let result = BlackholeCanister::create(&agent)
+            .canister_status(canister_id)
+            .call()
+            .await;
+        assert!(is_sent(&result));
+        agent.verify_all_called();
+    }
+
+    #[test]
+    fn decode_status() {
+        // A reply of the blackhole canister, with fields that are not decoded.
+        let reply: IDLArgs = r#"(record {
+            status = variant { running };
+            settings = record { controllers = vec { principal "aaaaa-aa" } };
+            module_hash = opt blob "\01\02";
+            memory_size = 100 : nat;
+            cycles = 5000 : nat;
+        })"#
+        .parse()
+        .unwrap();
+        let status: BlackholeCanisterStatus =
+            candid::decode_one(&reply.to_bytes().unwrap()).unwrap();
+        assert_eq!(status.status, CanisterStatus::Running);
+        assert_eq!(status.module_hash, Some(vec![1, 2]));
+        assert_eq!(status.memory_size, Nat::from(100u64));
+        assert_eq!(status.cycles, Nat::from(5_000u64));
+    }
+}
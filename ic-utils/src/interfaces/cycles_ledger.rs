@@ -0,0 +1,330 @@
+//! The canister interface for the cycles ledger, which holds cycles as an ICRC-1 token.
+
+use crate::{
+    call::{AsyncCall, SyncCall},
+    Canister,
+};
+use candid::{CandidType, Deserialize, Nat};
+use ic_agent::{export::Principal, Agent};
+use std::ops::Deref;
+
+/// The cycles ledger canister.
+#[derive(Debug, Clone)]
+pub struct CyclesLedgerCanister<'agent>(Canister<'agent>);
+
+impl<'agent> Deref for CyclesLedgerCanister<'agent> {
+    type Target = Canister<'agent>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// An ICRC-1 account.
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct Account {
+    /// The owner of the account.
+    pub owner: Principal,
+    /// The subaccount of the account. Defaults to the default subaccount if unspecified.
+    pub subaccount: Option<Vec<u8>>,
+}
+
+/// The argument of a `deposit` call.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct DepositArgs {
+    /// The account to credit the deposited cycles to.
+    pub to: Account,
+    /// A memo to record with the transaction.
+    pub memo: Option<Vec<u8>>,
+}
+
+/// The result of a `deposit` call.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct DepositResult {
+    /// The new balance of the account.
+    pub balance: Nat,
+    /// The ledger block containing the deposit.
+    pub block_index: Nat,
+}
+
+/// The argument of a `withdraw` call.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct WithdrawArgs {
+    /// The amount of cycles to withdraw.
+    pub amount: Nat,
+    /// The subaccount of the caller to withdraw from.
+    pub from_subaccount: Option<Vec<u8>>,
+    /// The canister to send the cycles to.
+    pub to: Principal,
+    /// The time of the request, in nanoseconds since the epoch, for deduplication.
+    pub created_at_time: Option<u64>,
+}
+
+/// The argument of a `withdraw_from` call.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct WithdrawFromArgs {
+    /// The amount of cycles to withdraw.
+    pub amount: Nat,
+    /// The account to withdraw from, which must have approved the caller.
+    pub from: Account,
+    /// The subaccount of the caller that was approved.
+    pub spender_subaccount: Option<Vec<u8>>,
+    /// The canister to send the cycles to.
+    pub to: Principal,
+    /// The time of the request, in nanoseconds since the epoch, for deduplication.
+    pub created_at_time: Option<u64>,
+}
+
+/// The reject code of a failed inter-canister call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CandidType, Deserialize)]
+pub enum RejectionCode {
+    /// No error.
+    NoError,
+    /// A fatal system error.
+    SysFatal,
+    /// A transient system error.
+    SysTransient,
+    /// The destination does not exist.
+    DestinationInvalid,
+    /// The destination rejected the call.
+    CanisterReject,
+    /// The destination trapped.
+    CanisterError,
+    /// An unknown error.
+    Unknown,
+}
+
+/// An error returned by `withdraw`.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub enum WithdrawError {
+    /// A ledger-specific error.
+    GenericError {
+        /// A description of the error.
+        message: String,
+        /// The error code.
+        error_code: Nat,
+    },
+    /// The ledger cannot process the request right now.
+    TemporarilyUnavailable,
+    /// The cycles could not be sent to the destination. The fee was still charged.
+    FailedToWithdraw {
+        /// The block charging the fee, if any.
+        fee_block: Option<Nat>,
+        /// The reject code of the transfer to the destination.
+        rejection_code: RejectionCode,
+        /// The reject message of the transfer to the destination.
+        rejection_reason: String,
+    },
+    /// The request is a duplicate of an earlier one.
+    Duplicate {
+        /// The block of the earlier request.
+        duplicate_of: Nat,
+    },
+    /// The fee of the request was wrong.
+    BadFee {
+        /// The fee the ledger expected.
+        expected_fee: Nat,
+    },
+    /// The destination is not a valid canister.
+    InvalidReceiver {
+        /// The destination of the request.
+        receiver: Principal,
+    },
+    /// `created_at_time` is in the future.
+    CreatedInFuture {
+        /// The current time of the ledger.
+        ledger_time: u64,
+    },
+    /// `created_at_time` is too far in the past.
+    TooOld,
+    /// The account does not hold enough cycles.
+    InsufficientFunds {
+        /// The balance of the account.
+        balance: Nat,
+    },
+}
+
+/// An error returned by `withdraw_from`.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub enum WithdrawFromError {
+    /// A ledger-specific error.
+    GenericError {
+        /// A description of the error.
+        message: String,
+        /// The error code.
+        error_code: Nat,
+    },
+    /// The ledger cannot process the request right now.
+    TemporarilyUnavailable,
+    /// The cycles could not be sent to the destination.
+    FailedToWithdrawFrom {
+        /// The block withdrawing the cycles, if any.
+        withdraw_from_block: Option<Nat>,
+        /// The block refunding the cycles, if any.
+        refund_block: Option<Nat>,
+        /// The block refunding the allowance, if any.
+        approval_refund_block: Option<Nat>,
+        /// The reject code of the transfer to the destination.
+        rejection_code: RejectionCode,
+        /// The reject message of the transfer to the destination.
+        rejection_reason: String,
+    },
+    /// The request is a duplicate of an earlier one.
+    Duplicate {
+        /// The block of the earlier request.
+        duplicate_of: Nat,
+    },
+    /// The account does not hold enough cycles.
+    InsufficientFunds {
+        /// The balance of the account.
+        balance: Nat,
+    },
+    /// The caller's allowance is too low.
+    InsufficientAllowance {
+        /// The remaining allowance of the caller.
+        allowance: Nat,
+    },
+    /// The destination is not a valid canister.
+    InvalidReceiver {
+        /// The destination of the request.
+        receiver: Principal,
+    },
+    /// `created_at_time` is in the future.
+    CreatedInFuture {
+        /// The current time of the ledger.
+        ledger_time: u64,
+    },
+    /// `created_at_time` is too far in the past.
+    TooOld,
+}
+
+impl<'agent> CyclesLedgerCanister<'agent> {
+    /// Create an instance of a `CyclesLedgerCanister` interface pointing to the specified Canister ID.
+    pub fn create(agent: &'agent Agent, canister_id: Principal) -> Self {
+        Self(
+            Canister::builder()
+                .with_agent(agent)
+                .with_canister_id(canister_id)
+                .build()
+                .unwrap(),
+        )
+    }
+
+    /// Create a `CyclesLedgerCanister` interface from an existing canister object.
+    pub fn from_canister(canister: Canister<'agent>) -> Self {
+        Self(canister)
+    }
+}
+
+impl<'agent> CyclesLedgerCanister<'agent> {
+    /// Get the cycle balance of an account, with the ICRC-1 `icrc1_balance_of` method.
+    pub fn balance_of<'canister: 'agent>(
+        &'canister self,
+        account: Account,
+    ) -> impl 'agent + SyncCall<(Nat,)> {
+        self.query_("icrc1_balance_of").with_arg(account).build()
+    }
+
+    /// Deposit the cycles attached to the call into the account `to`.
+    ///
+    /// The cycles ledger traps instead of returning an error if the deposit fails. As ingress
+    /// messages cannot carry cycles, this is only useful through a proxy such as a wallet.
+    pub fn deposit<'canister: 'agent>(
+        &'canister self,
+        to: Account,
+        memo: Option<Vec<u8>>,
+    ) -> impl 'agent + AsyncCall<(DepositResult,)> {
+        self.update_("deposit")
+            .with_arg(DepositArgs { to, memo })
+            .build()
+    }
+
+    /// Send cycles from an account of the caller to a canister. Returns the index of the block
+    /// recording the withdrawal.
+    pub fn withdraw<'canister: 'agent>(
+        &'canister self,
+        args: WithdrawArgs,
+    ) -> impl 'agent + AsyncCall<(Result<Nat, WithdrawError>,)> {
+        self.update_("withdraw").with_arg(args).build()
+    }
+
+    /// Send cycles from an account that approved the caller to a canister. Returns the index of
+    /// the block recording the withdrawal.
+    pub fn withdraw_from<'canister: 'agent>(
+        &'canister self,
+        args: WithdrawFromArgs,
+    ) -> impl 'agent + AsyncCall<(Result<Nat, WithdrawFromError>,)> {
+        self.update_("withdraw_from").with_arg(args).build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{canister_id, expect_method, is_sent, sent};
+    use candid::parser::value::IDLArgs;
+    use ic_agent::agent::mock::{MockAgent, MockTransport, Response};
+
+    #[tokio::test]
+    async fn balance_of() {
+        let account = Account {
+            owner: Principal::anonymous(),
+            subaccount: None,
+        };
+        let agent = MockAgent::new(expect_method(
+            MockTransport::new(),
+            "query",
+            canister_id(),
+            "icrc1_balance_of",
+            candid::encode_one(&account).unwrap(),
+            Response::query_reply(candid::encode_one(Nat::from(5_000u64)).unwrap()),
+        ));
+        let ledger = CyclesLedgerCanister::create(&agent, canister_id());
+
+        let (balance,) = ledger.balance_of(account).call().await.unwrap();
+        assert_eq!(balance, Nat::from(5_000u64));
+        agent.verify_all_called();
+    }
+
+    #[tokio::test]
+    async fn withdraw() {
+        let args = WithdrawArgs {
+            amount: Nat::from(1_000u64),
+            from_subaccount: None,
+            to: Principal::management_canister(),
+            created_at_time: Some(1),
+        };
+        let agent = MockAgent::new(expect_method(
+            MockTransport::new(),
+            "call",
+            canister_id(),
+            "withdraw",
+            candid::encode_one(&args).unwrap(),
+            Response::error(sent),
+        ));
+        let ledger = CyclesLedgerCanister::create(&agent, canister_id());
+
+        assert!(is_sent(&ledger.withdraw(args).call().await));
+        agent.verify_all_called();
+    }
+
+    #[test]
+    fn withdraw_error() {
+        // A reply as encoded by the cycles ledger, from the Candid of its interface.
+        let reply: IDLArgs = r#"(variant { Err = variant { FailedToWithdraw = record {
+            fee_block = opt (3 : nat);
+            rejection_code = variant { CanisterReject };
+            rejection_reason = "no";
+        } } })"#
+            .parse()
+            .unwrap();
+        let result: Result<Nat, WithdrawError> =
+            candid::decode_one(&reply.to_bytes().unwrap()).unwrap();
+        assert!(matches!(
+            result,
+            Err(WithdrawError::FailedToWithdraw {
+                rejection_code: RejectionCode::CanisterReject,
+                ..
+            })
+        ));
+    }
+}
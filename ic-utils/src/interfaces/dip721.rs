@@ -0,0 +1,186 @@
+//! The canister interface for [DIP-721](https://github.com/Psychedelic/DIP721) NFT canisters.
+
+use crate::{
+    call::{AsyncCall, SyncCall},
+    Canister,
+};
+use candid::{CandidType, Deserialize, Int, Nat};
+use ic_agent::{export::Principal, Agent, AgentError};
+use std::ops::Deref;
+
+/// A DIP-721 NFT canister.
+#[derive(Debug, Clone)]
+pub struct Dip721Canister<'agent>(Canister<'agent>);
+
+impl<'agent> Deref for Dip721Canister<'agent> {
+    type Target = Canister<'agent>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// A value of a token property or a nested property.
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+#[allow(missing_docs)]
+pub enum GenericValue {
+    Nat64Content(u64),
+    Nat32Content(u32),
+    BoolContent(bool),
+    Nat8Content(u8),
+    Int64Content(i64),
+    IntContent(Int),
+    NatContent(Nat),
+    Nat16Content(u16),
+    Int32Content(i32),
+    Int8Content(i8),
+    FloatContent(f64),
+    Int16Content(i16),
+    BlobContent(#[serde(with = "serde_bytes")] Vec<u8>),
+    NestedContent(Vec<(String, GenericValue)>),
+    Principal(Principal),
+    TextContent(String),
+}
+
+/// An error returned by a DIP-721 canister.
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum NftError {
+    UnauthorizedOperator,
+    SelfTransfer,
+    TokenNotFound,
+    UnauthorizedOwner,
+    TxNotFound,
+    SelfApprove,
+    OperatorNotFound,
+    ExistedNFT,
+    OwnerNotFound,
+    Other(String),
+}
+
+/// The metadata of a single token, returned by [`Dip721Canister::token_metadata`].
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct TokenMetadata {
+    /// The ID of the token.
+    pub token_identifier: Nat,
+    /// The owner of the token, if it was not burned.
+    pub owner: Option<Principal>,
+    /// The principal approved to transfer the token on behalf of its owner, if any.
+    pub operator: Option<Principal>,
+    /// Whether the token was burned.
+    pub is_burned: bool,
+    /// The properties of the token, such as its location or its thumbnail.
+    pub properties: Vec<(String, GenericValue)>,
+    /// When the token was minted, in nanoseconds since the epoch.
+    pub minted_at: u64,
+    /// The principal that minted the token.
+    pub minted_by: Principal,
+    /// When the token was last transferred, in nanoseconds since the epoch.
+    pub transferred_at: Option<u64>,
+    /// The principal that last transferred the token.
+    pub transferred_by: Option<Principal>,
+    /// When the operator was last approved, in nanoseconds since the epoch.
+    pub approved_at: Option<u64>,
+    /// The principal that last approved an operator.
+    pub approved_by: Option<Principal>,
+    /// When the token was burned, in nanoseconds since the epoch.
+    pub burned_at: Option<u64>,
+    /// The principal that burned the token.
+    pub burned_by: Option<Principal>,
+}
+
+/// The metadata of the whole collection, returned by [`Dip721Canister::collection_metadata`].
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub struct Metadata {
+    /// The URL or data URI of the collection's logo.
+    pub logo: Option<String>,
+    /// The name of the collection.
+    pub name: Option<String>,
+    /// The symbol of the collection.
+    pub symbol: Option<String>,
+    /// The principals allowed to mint tokens and update the collection.
+    pub custodians: Vec<Principal>,
+    /// When the canister was created, in nanoseconds since the epoch.
+    pub created_at: u64,
+    /// When the canister was last upgraded, in nanoseconds since the epoch.
+    pub upgraded_at: u64,
+}
+
+impl<'agent> Dip721Canister<'agent> {
+    /// Create an instance of a `Dip721Canister` interface pointing to the specified Canister ID.
+    pub fn create(agent: &'agent Agent, canister_id: Principal) -> Self {
+        Self(
+            Canister::builder()
+                .with_agent(agent)
+                .with_canister_id(canister_id)
+                .build()
+                .unwrap(),
+        )
+    }
+
+    /// Create a `Dip721Canister` interface from an existing canister object.
+    pub fn from_canister(canister: Canister<'agent>) -> Self {
+        Self(canister)
+    }
+}
+
+impl<'agent> Dip721Canister<'agent> {
+    /// Get the metadata of the collection.
+    pub fn collection_metadata<'canister: 'agent>(
+        &'canister self,
+    ) -> impl 'agent + SyncCall<(Metadata,)> {
+        self.query_("metadata").build()
+    }
+
+    /// Get the number of tokens in the collection.
+    pub fn total_supply<'canister: 'agent>(&'canister self) -> impl 'agent + SyncCall<(Nat,)> {
+        self.query_("totalSupply").build()
+    }
+
+    /// Get the owner of a token. The owner is `None` if the token was burned.
+    pub fn owner_of<'canister: 'agent>(
+        &'canister self,
+        token_id: Nat,
+    ) -> impl 'agent + SyncCall<(Result<Option<Principal>, NftError>,)> {
+        self.query_("ownerOf").with_arg(token_id).build()
+    }
+
+    /// Get the number of tokens owned by `owner`.
+    pub fn balance_of<'canister: 'agent>(
+        &'canister self,
+        owner: Principal,
+    ) -> impl 'agent + SyncCall<(Result<Nat, NftError>,)> {
+        self.query_("balanceOf").with_arg(owner).build()
+    }
+
+    /// Transfer a token from `from` to `to`. The caller must be the owner or the operator of
+    /// the token. Returns the ID of the transaction.
+    pub fn transfer_from<'canister: 'agent>(
+        &'canister self,
+        from: Principal,
+        to: Principal,
+        token_id: Nat,
+    ) -> impl 'agent + AsyncCall<(Result<Nat, NftError>,)> {
+        self.update_("transferFrom")
+            .with_arg(from)
+            .with_arg(to)
+            .with_arg(token_id)
+            .build()
+    }
+
+    /// Get the metadata of a token.
+    pub fn token_metadata<'canister: 'agent>(
+        &'canister self,
+        token_id: Nat,
+    ) -> impl 'agent + SyncCall<(Result<TokenMetadata, NftError>,)> {
+        self.query_("tokenMetadata").with_arg(token_id).build()
+    }
+
+    /// Get the properties of a token, from its [`token_metadata`](Self::token_metadata).
+    pub async fn metadata<'canister: 'agent>(
+        &'canister self,
+        token_id: Nat,
+    ) -> Result<Result<Vec<(String, GenericValue)>, NftError>, AgentError> {
+        let (metadata,) = self.token_metadata(token_id).call().await?;
+        Ok(metadata.map(|metadata| metadata.properties))
+    }
+}
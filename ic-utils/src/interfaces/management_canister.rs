@@ -4,13 +4,25 @@
 
 use crate::{call::AsyncCall, Canister};
 use candid::{CandidType, Deserialize, Nat};
-use ic_agent::{export::Principal, Agent};
-use std::{convert::AsRef, fmt::Debug, ops::Deref};
+use ic_agent::{
+    agent::{RejectCode, RejectResponse},
+    export::Principal,
+    Agent, AgentError,
+};
+use sha2::{Digest, Sha256};
+use std::{collections::HashSet, convert::AsRef, fmt::Debug, ops::Deref};
 use strum_macros::{AsRefStr, EnumString};
+use thiserror::Error;
 
 pub mod attributes;
 pub mod builders;
-pub use builders::{CreateCanisterBuilder, InstallCodeBuilder, UpdateCanisterBuilder};
+pub use builders::{
+    CanisterSettings, CreateCanisterBuilder, InstallCodeBuilder, InstallMode, UpdateCanisterBuilder,
+};
+
+/// The [error code](https://internetcomputer.org/docs/current/references/ic-interface-spec/#error-codes)
+/// of the management canister rejecting a call that only the controllers of a canister may make.
+const NOT_A_CONTROLLER: &str = "IC0512";
 
 /// The IC management canister.
 #[derive(Debug, Clone)]
@@ -52,6 +64,20 @@ pub enum MgmtMethod {
     UninstallCode,
     /// See `Canister::<ManagementCanister>::update_settings`.
     UpdateSettings,
+    /// The `ecdsa_public_key` method, which can only be called by canisters.
+    EcdsaPublicKey,
+    /// The `sign_with_ecdsa` method, which can only be called by canisters.
+    SignWithEcdsa,
+    /// See `Canister::<ManagementCanister>::take_canister_snapshot`.
+    TakeCanisterSnapshot,
+    /// See `Canister::<ManagementCanister>::load_canister_snapshot`.
+    LoadCanisterSnapshot,
+    /// See `Canister::<ManagementCanister>::list_canister_snapshots`.
+    ListCanisterSnapshots,
+    /// See `Canister::<ManagementCanister>::upload_chunk`.
+    UploadChunk,
+    /// See `Canister::<ManagementCanister>::stored_chunks`.
+    StoredChunks,
 }
 
 impl<'agent> ManagementCanister<'agent> {
@@ -100,6 +126,9 @@ pub struct DefiniteCanisterSettings {
     pub memory_allocation: Nat,
     /// The IC will freeze a canister protectively if it will likely run out of cycles before this amount of time, in seconds (up to `u64::MAX`), has passed.
     pub freezing_threshold: Nat,
+    /// The maximum size, in bytes, of the canister's WebAssembly heap memory. Absent if the replica
+    /// does not report it.
+    pub wasm_memory_limit: Option<Nat>,
 }
 
 impl std::fmt::Display for StatusCallResult {
@@ -129,6 +158,177 @@ impl std::fmt::Display for CanisterStatus {
     }
 }
 
+/// The elliptic curve of a threshold ECDSA key.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, CandidType)]
+pub enum EcdsaCurve {
+    /// The secp256k1 curve.
+    #[serde(rename = "secp256k1")]
+    Secp256k1,
+}
+
+/// The identifier of a threshold ECDSA key.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, CandidType)]
+pub struct EcdsaKeyId {
+    /// The curve of the key.
+    pub curve: EcdsaCurve,
+    /// The name of the key, e.g. `dfx_test_key`.
+    pub name: String,
+}
+
+/// The argument of a `sign_with_ecdsa` call.
+#[derive(Clone, Debug, Deserialize, CandidType)]
+pub struct SignWithEcdsaArgument {
+    /// The 32-byte hash of the message to sign.
+    #[serde(with = "serde_bytes")]
+    pub message_hash: Vec<u8>,
+    /// The derivation path of the key, relative to the calling canister.
+    pub derivation_path: Vec<Vec<u8>>,
+    /// The key to sign with.
+    pub key_id: EcdsaKeyId,
+}
+
+/// The result of a `sign_with_ecdsa` call.
+#[derive(Clone, Debug, Deserialize, CandidType)]
+pub struct SignWithEcdsaResponse {
+    /// The signature, encoded as the concatenation of the 32-byte big-endian `r` and `s` values.
+    #[serde(with = "serde_bytes")]
+    pub signature: Vec<u8>,
+}
+
+/// The argument of an `ecdsa_public_key` call.
+#[derive(Clone, Debug, Deserialize, CandidType)]
+pub struct EcdsaPublicKeyArgument {
+    /// The canister whose key should be derived. Defaults to the caller if unspecified.
+    pub canister_id: Option<Principal>,
+    /// The derivation path of the key, relative to the canister.
+    pub derivation_path: Vec<Vec<u8>>,
+    /// The key to derive from.
+    pub key_id: EcdsaKeyId,
+}
+
+/// The result of an `ecdsa_public_key` call.
+#[derive(Clone, Debug, Deserialize, CandidType)]
+pub struct EcdsaPublicKeyResponse {
+    /// The SEC1-encoded compressed public key.
+    #[serde(with = "serde_bytes")]
+    pub public_key: Vec<u8>,
+    /// The chain code of the derived key.
+    #[serde(with = "serde_bytes")]
+    pub chain_code: Vec<u8>,
+}
+
+/// A threshold ECDSA signature together with the recovery ID needed to recover the public key from it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RecoverableSignature {
+    /// The signature, encoded as the concatenation of the 32-byte big-endian `r` and `s` values.
+    pub signature: Vec<u8>,
+    /// The recovery ID, either 0 or 1.
+    pub recovery_id: u8,
+}
+
+impl RecoverableSignature {
+    /// Find the recovery ID with which `signature` of `message_hash` recovers the SEC1-encoded
+    /// `public_key`. The signature is either in the raw 64-byte `r || s` format or DER-encoded.
+    ///
+    /// `sign_with_ecdsa` and `ecdsa_public_key` can only be called by canisters, so this is meant
+    /// for the signature and public key returned by a canister that called them on the caller's
+    /// behalf.
+    pub fn recover(
+        public_key: &[u8],
+        message_hash: &[u8],
+        signature: &[u8],
+    ) -> Result<Self, AgentError> {
+        use k256::ecdsa::{RecoveryId, VerifyingKey};
+
+        let public_key = VerifyingKey::from_sec1_bytes(public_key)
+            .map_err(|e| AgentError::MessageError(format!("Invalid ECDSA public key: {}", e)))?;
+        let signature = parse_ecdsa_signature(signature)?;
+
+        for recovery_id in 0..=1 {
+            let id = RecoveryId::from_byte(recovery_id).unwrap();
+            let recovered = VerifyingKey::recover_from_prehash(message_hash, &signature, id);
+            if recovered.map_or(false, |key| key == public_key) {
+                return Ok(Self {
+                    signature: signature.to_bytes().to_vec(),
+                    recovery_id,
+                });
+            }
+        }
+        Err(AgentError::MessageError(
+            "Could not determine the recovery ID of the ECDSA signature.".to_string(),
+        ))
+    }
+}
+
+/// A snapshot of the state of a canister, returned by `take_canister_snapshot` and `list_canister_snapshots`.
+#[derive(Clone, Debug, Deserialize, CandidType, Eq, PartialEq)]
+pub struct Snapshot {
+    /// The ID of the snapshot, used to load it back into the canister.
+    #[serde(with = "serde_bytes")]
+    pub id: Vec<u8>,
+    /// The time the snapshot was taken, in nanoseconds since the epoch.
+    pub taken_at_timestamp: u64,
+    /// The size of the snapshot in bytes.
+    pub total_size: u64,
+}
+
+/// The hash of a chunk of a WASM module in the chunk store of a canister, returned by
+/// `upload_chunk` and `stored_chunks`.
+#[derive(Clone, Debug, Deserialize, CandidType, Eq, PartialEq, Hash)]
+pub struct ChunkHash {
+    /// The SHA-256 hash of the chunk.
+    #[serde(with = "serde_bytes")]
+    pub hash: Vec<u8>,
+}
+
+/// The maximum size of a chunk in the chunk store of a canister.
+pub const MAX_CHUNK_SIZE: usize = 1 << 20;
+
+/// An error produced by `install_code_with_rollback`.
+#[derive(Debug, Error)]
+pub enum UpgradeError {
+    /// The snapshot of the canister could not be taken, so no upgrade was attempted.
+    #[error("Could not take a snapshot of canister {canister_id}: {reason}")]
+    SnapshotFailed {
+        /// The canister being upgraded.
+        canister_id: Principal,
+        /// The error returned by `take_canister_snapshot`.
+        reason: AgentError,
+    },
+    /// The canister trapped during the upgrade, e.g. in its upgrade hooks.
+    #[error("Upgrading canister {canister_id} trapped (snapshot restored: {snapshot_restored}): {reason}")]
+    Trapped {
+        /// The canister being upgraded.
+        canister_id: Principal,
+        /// The error returned by `install_code`.
+        reason: AgentError,
+        /// Whether the snapshot taken before the upgrade was loaded back into the canister.
+        /// This is `false` if no snapshot was taken, or if loading it failed.
+        snapshot_restored: bool,
+    },
+    /// The upgrade was rejected before the canister was changed, e.g. because the caller is not
+    /// a controller of the canister, so the snapshot was not loaded.
+    #[error("Upgrading canister {canister_id} was rejected: {reason}")]
+    Rejected {
+        /// The canister being upgraded.
+        canister_id: Principal,
+        /// The error returned by `install_code`.
+        reason: AgentError,
+    },
+    /// Whether the upgrade happened is unknown, e.g. because the replica could not be reached,
+    /// so the snapshot was not loaded, not to undo an upgrade that succeeded.
+    #[error("Upgrading canister {canister_id} failed: {reason}")]
+    Failed {
+        /// The canister being upgraded.
+        canister_id: Principal,
+        /// The error returned by `install_code`.
+        reason: AgentError,
+        /// The ID of the snapshot taken before the upgrade, if any, to load it back with
+        /// `load_canister_snapshot` once the upgrade is known to have failed.
+        snapshot_id: Option<Vec<u8>>,
+    },
+}
+
 impl<'agent> ManagementCanister<'agent> {
     /// Get the status of a canister.
     pub fn canister_status<'canister: 'agent>(
@@ -149,6 +349,63 @@ impl<'agent> ManagementCanister<'agent> {
             .map(|result: (StatusCallResult,)| (result.0,))
     }
 
+    /// Get the controllers of a canister, from the certified `/canister/<canister_id>/controllers`
+    /// path of the state tree. Unlike `canister_status`, anyone may read them.
+    pub async fn get_controllers<'canister: 'agent>(
+        &'canister self,
+        canister_id: &Principal,
+    ) -> Result<Vec<Principal>, AgentError> {
+        let controllers = self
+            .agent
+            .read_state_canister_info(*canister_id, "controllers")
+            .await?;
+        let controllers: Vec<serde_bytes::ByteBuf> = serde_cbor::from_slice(&controllers)?;
+        controllers
+            .iter()
+            .map(|controller| Principal::try_from_slice(controller).map_err(AgentError::from))
+            .collect()
+    }
+
+    /// Check whether `principal` is a controller of a canister, from the controllers certified
+    /// in the state tree, see [`get_controllers`](Self::get_controllers).
+    pub async fn is_controller<'canister: 'agent>(
+        &'canister self,
+        canister_id: &Principal,
+        principal: &Principal,
+    ) -> Result<bool, AgentError> {
+        let controllers = self.get_controllers(canister_id).await?;
+        Ok(controllers.contains(principal))
+    }
+
+    /// Get the cycle balance of a canister with `canister_status`.
+    ///
+    /// Only the controllers of the canister may call `canister_status`; if it is rejected because
+    /// the caller is not a controller, [`AgentError::NotPermitted`] is returned. Other rejections
+    /// are returned as they are.
+    pub async fn canister_cycles<'canister: 'agent>(
+        &'canister self,
+        canister_id: &Principal,
+    ) -> Result<u128, AgentError> {
+        match self.canister_status(canister_id).call_and_wait().await {
+            Ok((status,)) => u128::try_from(&status.cycles.0).map_err(|_| {
+                AgentError::MessageError(format!(
+                    "The cycle balance {} does not fit in a u128.",
+                    status.cycles
+                ))
+            }),
+            Err(AgentError::ReplicaError(reject))
+                if reject.reject_code == RejectCode::CanisterError
+                    && reject.error_code.as_deref() == Some(NOT_A_CONTROLLER) =>
+            {
+                Err(AgentError::NotPermitted(format!(
+                    "Only the controllers of canister {} may read its cycle balance: {}",
+                    canister_id, reject
+                )))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
     /// Create a canister.
     pub fn create_canister<'canister: 'agent>(
         &'canister self,
@@ -156,6 +413,39 @@ impl<'agent> ManagementCanister<'agent> {
         CreateCanisterBuilder::builder(self)
     }
 
+    /// Create a canister with the ID `canister_id`, e.g. to match the IDs hardcoded in test
+    /// fixtures. This is only available on local replicas, through
+    /// `provisional_create_canister_with_cycles`. The canister ID is returned.
+    ///
+    /// For more options, use [`create_canister`](Self::create_canister) with
+    /// [`as_provisional_create_with_specified_id`](CreateCanisterBuilder::as_provisional_create_with_specified_id).
+    pub fn provisional_create_canister_with_specified_id<'canister: 'agent>(
+        &'canister self,
+        canister_id: Principal,
+        settings: Option<CanisterSettings>,
+    ) -> impl 'agent + AsyncCall<(Principal,)> {
+        #[derive(CandidType)]
+        struct In {
+            amount: Option<Nat>,
+            settings: Option<CanisterSettings>,
+            specified_id: Option<Principal>,
+        }
+        #[derive(Deserialize, CandidType)]
+        struct Out {
+            canister_id: Principal,
+        }
+
+        self.update_(MgmtMethod::ProvisionalCreateCanisterWithCycles.as_ref())
+            .with_arg(In {
+                amount: None,
+                settings,
+                specified_id: Some(canister_id),
+            })
+            .with_effective_canister_id(canister_id)
+            .build()
+            .map(|result: (Out,)| (result.0.canister_id,))
+    }
+
     /// This method deposits the cycles included in this call into the specified canister.
     /// Only the controller of the canister can deposit cycles.
     pub fn deposit_cycles<'canister: 'agent>(
@@ -200,18 +490,18 @@ impl<'agent> ManagementCanister<'agent> {
     pub fn provisional_top_up_canister<'canister: 'agent>(
         &'canister self,
         canister_id: &Principal,
-        amount: u64,
+        amount: u128,
     ) -> impl 'agent + AsyncCall<()> {
         #[derive(CandidType)]
         struct Argument {
             canister_id: Principal,
-            amount: u64,
+            amount: Nat,
         }
 
         self.update_(MgmtMethod::ProvisionalTopUpCanister.as_ref())
             .with_arg(Argument {
                 canister_id: *canister_id,
-                amount,
+                amount: Nat::from(amount),
             })
             .with_effective_canister_id(canister_id.to_owned())
             .build()
@@ -295,6 +585,264 @@ impl<'agent> ManagementCanister<'agent> {
         InstallCodeBuilder::builder(self, canister_id, wasm)
     }
 
+    /// Upgrade the canister `canister_id` to `wasm`, passing it the encoded argument `arg`.
+    ///
+    /// If `snapshot_before` is true, a snapshot of the canister is taken first, and loaded back
+    /// into the canister if it traps during the upgrade, so that it is not left half-upgraded.
+    /// The snapshot is not deleted afterwards.
+    pub async fn install_code_with_rollback<'canister: 'agent>(
+        &'canister self,
+        canister_id: &Principal,
+        wasm: &'canister [u8],
+        arg: Vec<u8>,
+        snapshot_before: bool,
+    ) -> Result<(), UpgradeError> {
+        let snapshot = if snapshot_before {
+            let (snapshot,) = self
+                .take_canister_snapshot(canister_id, None)
+                .call_and_wait()
+                .await
+                .map_err(|reason| UpgradeError::SnapshotFailed {
+                    canister_id: *canister_id,
+                    reason,
+                })?;
+            Some(snapshot)
+        } else {
+            None
+        };
+
+        let result = self
+            .install_code(canister_id, wasm)
+            .with_mode(InstallMode::Upgrade)
+            .with_raw_arg(arg)
+            .call_and_wait()
+            .await;
+        let reason = match result {
+            Ok(()) => return Ok(()),
+            Err(reason) => reason,
+        };
+
+        let canister_id = *canister_id;
+        match reason {
+            AgentError::ReplicaError(RejectResponse {
+                reject_code: RejectCode::CanisterError,
+                ..
+            }) => {
+                let snapshot_restored = match snapshot {
+                    Some(snapshot) => self
+                        .load_canister_snapshot(&canister_id, snapshot.id)
+                        .call_and_wait()
+                        .await
+                        .is_ok(),
+                    None => false,
+                };
+                Err(UpgradeError::Trapped {
+                    canister_id,
+                    reason,
+                    snapshot_restored,
+                })
+            }
+            AgentError::ReplicaError(_) => Err(UpgradeError::Rejected {
+                canister_id,
+                reason,
+            }),
+            _ => Err(UpgradeError::Failed {
+                canister_id,
+                reason,
+                snapshot_id: snapshot.map(|snapshot| snapshot.id),
+            }),
+        }
+    }
+
+    /// Take a snapshot of the state of a canister, optionally replacing the existing snapshot
+    /// `replace_snapshot`.
+    pub fn take_canister_snapshot<'canister: 'agent>(
+        &'canister self,
+        canister_id: &Principal,
+        replace_snapshot: Option<Vec<u8>>,
+    ) -> impl 'agent + AsyncCall<(Snapshot,)> {
+        #[derive(CandidType)]
+        struct In {
+            canister_id: Principal,
+            replace_snapshot: Option<Vec<u8>>,
+        }
+
+        self.update_(MgmtMethod::TakeCanisterSnapshot.as_ref())
+            .with_arg(In {
+                canister_id: *canister_id,
+                replace_snapshot,
+            })
+            .with_effective_canister_id(canister_id.to_owned())
+            .build()
+    }
+
+    /// List the snapshots of a canister.
+    pub fn list_canister_snapshots<'canister: 'agent>(
+        &'canister self,
+        canister_id: &Principal,
+    ) -> impl 'agent + AsyncCall<(Vec<Snapshot>,)> {
+        #[derive(CandidType)]
+        struct In {
+            canister_id: Principal,
+        }
+
+        self.update_(MgmtMethod::ListCanisterSnapshots.as_ref())
+            .with_arg(In {
+                canister_id: *canister_id,
+            })
+            .with_effective_canister_id(canister_id.to_owned())
+            .build()
+    }
+
+    /// List the snapshots of a canister taken at or after `since_timestamp_ns`, in nanoseconds
+    /// since the epoch. The snapshots are filtered after listing all of them.
+    pub async fn list_canister_snapshots_since<'canister: 'agent>(
+        &'canister self,
+        canister_id: &Principal,
+        since_timestamp_ns: u64,
+    ) -> Result<Vec<Snapshot>, AgentError> {
+        let (snapshots,) = self
+            .list_canister_snapshots(canister_id)
+            .call_and_wait()
+            .await?;
+        Ok(snapshots_since(snapshots, since_timestamp_ns))
+    }
+
+    /// List the `n` most recent snapshots of a canister, most recent first.
+    pub async fn list_canister_snapshots_latest<'canister: 'agent>(
+        &'canister self,
+        canister_id: &Principal,
+        n: usize,
+    ) -> Result<Vec<Snapshot>, AgentError> {
+        let (snapshots,) = self
+            .list_canister_snapshots(canister_id)
+            .call_and_wait()
+            .await?;
+        Ok(latest_snapshots(snapshots, n))
+    }
+
+    /// Upload a chunk of a WASM module, of at most [`MAX_CHUNK_SIZE`] bytes, to the chunk store
+    /// of a canister.
+    pub fn upload_chunk<'canister: 'agent>(
+        &'canister self,
+        canister_id: &Principal,
+        chunk: Vec<u8>,
+    ) -> impl 'agent + AsyncCall<(ChunkHash,)> {
+        #[derive(CandidType)]
+        struct In {
+            canister_id: Principal,
+            chunk: Vec<u8>,
+        }
+
+        self.update_(MgmtMethod::UploadChunk.as_ref())
+            .with_arg(In {
+                canister_id: *canister_id,
+                chunk,
+            })
+            .with_effective_canister_id(canister_id.to_owned())
+            .build()
+    }
+
+    /// List the hashes of the chunks in the chunk store of a canister.
+    pub fn stored_chunks<'canister: 'agent>(
+        &'canister self,
+        canister_id: &Principal,
+    ) -> impl 'agent + AsyncCall<(Vec<ChunkHash>,)> {
+        #[derive(CandidType)]
+        struct In {
+            canister_id: Principal,
+        }
+
+        self.update_(MgmtMethod::StoredChunks.as_ref())
+            .with_arg(In {
+                canister_id: *canister_id,
+            })
+            .with_effective_canister_id(canister_id.to_owned())
+            .build()
+    }
+
+    /// Upload `wasm_module` to the chunk store of a canister, in chunks of [`MAX_CHUNK_SIZE`]
+    /// bytes, and return the hashes of its chunks in order. Chunks that are already in the store,
+    /// e.g. from an upload that was interrupted, are not uploaded again.
+    pub async fn upload_wasm_chunked<'canister: 'agent>(
+        &'canister self,
+        canister_id: &Principal,
+        wasm_module: &[u8],
+    ) -> Result<Vec<ChunkHash>, AgentError> {
+        let (stored,) = self.stored_chunks(canister_id).call_and_wait().await?;
+        let (hashes, missing) = missing_chunks(wasm_module, &stored.into_iter().collect());
+        for chunk in missing {
+            self.upload_chunk(canister_id, chunk.to_vec())
+                .call_and_wait()
+                .await?;
+        }
+        Ok(hashes)
+    }
+
+    /// Replace the state of a canister with a snapshot previously taken with
+    /// `take_canister_snapshot`.
+    pub fn load_canister_snapshot<'canister: 'agent>(
+        &'canister self,
+        canister_id: &Principal,
+        snapshot_id: Vec<u8>,
+    ) -> impl 'agent + AsyncCall<()> {
+        #[derive(CandidType)]
+        struct In {
+            canister_id: Principal,
+            snapshot_id: Vec<u8>,
+        }
+
+        self.update_(MgmtMethod::LoadCanisterSnapshot.as_ref())
+            .with_arg(In {
+                canister_id: *canister_id,
+                snapshot_id,
+            })
+            .with_effective_canister_id(canister_id.to_owned())
+            .build()
+    }
+
+    /// List the threshold ECDSA keys on `curve` of the subnet `subnet_id`.
+    ///
+    /// This is not supported: the interface specification does not certify the keys of a subnet
+    /// in its state tree, and only canisters can call the management canister methods that use
+    /// them. This always fails with [`AgentError::MessageError`], without making any request.
+    pub async fn ecdsa_key_ids(
+        &self,
+        subnet_id: Principal,
+        curve: EcdsaCurve,
+    ) -> Result<Vec<EcdsaKeyId>, AgentError> {
+        Err(AgentError::MessageError(format!(
+            "Listing the {:?} threshold ECDSA keys of subnet {} is not supported, as the state tree does not certify them.",
+            curve, subnet_id
+        )))
+    }
+
+    /// Get the WASM memory limit of a canister, in bytes, from its `canister_status`. Returns
+    /// [None] if the replica does not report it.
+    pub fn get_wasm_memory_limit<'canister: 'agent>(
+        &'canister self,
+        canister_id: &Principal,
+    ) -> impl 'agent + AsyncCall<(Option<u64>,)> {
+        self.canister_status(canister_id)
+            .map(|(status,): (StatusCallResult,)| {
+                (status
+                    .settings
+                    .wasm_memory_limit
+                    .and_then(|limit| u64::try_from(&limit.0).ok()),)
+            })
+    }
+
+    /// Set the WASM memory limit of a canister, in bytes. `update_settings` leaves the settings
+    /// it is not given unchanged, so the other settings of the canister are not affected.
+    pub fn set_wasm_memory_limit<'canister: 'agent>(
+        &'canister self,
+        canister_id: &Principal,
+        limit: u64,
+    ) -> UpdateCanisterBuilder<'agent, 'canister> {
+        self.update_settings(canister_id)
+            .with_wasm_memory_limit(limit)
+    }
+
     /// Update one or more of a canisters settings (i.e its controller, compute allocation, or memory allocation.)
     pub fn update_settings<'canister: 'agent>(
         &'canister self,
@@ -303,3 +851,335 @@ impl<'agent> ManagementCanister<'agent> {
         UpdateCanisterBuilder::builder(self, canister_id)
     }
 }
+
+/// Parse an ECDSA signature returned by `sign_with_ecdsa`, which is either in the raw 64-byte
+/// `r || s` format or DER-encoded.
+fn parse_ecdsa_signature(signature: &[u8]) -> Result<k256::ecdsa::Signature, AgentError> {
+    use k256::ecdsa::Signature;
+
+    if signature.len() == 64 {
+        Signature::from_slice(signature)
+    } else {
+        Signature::from_der(signature)
+    }
+    .map_err(|e| AgentError::MessageError(format!("Invalid ECDSA signature: {}", e)))
+}
+
+/// The hashes of the chunks of `wasm_module`, in order, and the chunks missing from `stored`.
+fn missing_chunks<'a>(
+    wasm_module: &'a [u8],
+    stored: &HashSet<ChunkHash>,
+) -> (Vec<ChunkHash>, Vec<&'a [u8]>) {
+    let mut hashes = Vec::new();
+    let mut missing = Vec::new();
+    for chunk in wasm_module.chunks(MAX_CHUNK_SIZE) {
+        let hash = ChunkHash {
+            hash: Sha256::digest(chunk).to_vec(),
+        };
+        if !stored.contains(&hash) {
+            missing.push(chunk);
+        }
+        hashes.push(hash);
+    }
+    (hashes, missing)
+}
+
+/// The snapshots taken at or after `since_timestamp_ns`.
+fn snapshots_since(mut snapshots: Vec<Snapshot>, since_timestamp_ns: u64) -> Vec<Snapshot> {
+    snapshots.retain(|snapshot| snapshot.taken_at_timestamp >= since_timestamp_ns);
+    snapshots
+}
+
+/// The `n` most recent snapshots, most recent first.
+fn latest_snapshots(mut snapshots: Vec<Snapshot>, n: usize) -> Vec<Snapshot> {
+    snapshots.sort_by(|a, b| b.taken_at_timestamp.cmp(&a.taken_at_timestamp));
+    snapshots.truncate(n);
+    snapshots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ManagementCanister, UpgradeError};
+    use ic_agent::agent::mock::{Matcher, MockAgent, MockTransport, Response};
+    use ic_agent::{export::Principal, AgentError};
+    use std::sync::Arc;
+
+    /// A transport failing the calls to `method` of the management canister about `canister_id`.
+    fn failing_call(canister_id: Principal, method: &'static str) -> MockAgent {
+        let path = format!("canister/{canister_id}/call");
+        MockAgent::new(MockTransport::new().expect(
+            Matcher::Custom(Arc::new(move |p, body| {
+                p == path && body.windows(method.len()).any(|w| w == method.as_bytes())
+            })),
+            Response::error(|| AgentError::TransportError("unreachable".into())),
+        ))
+    }
+
+    #[tokio::test]
+    async fn install_code_with_rollback() {
+        let canister_id = crate::test_util::canister_id();
+
+        // No upgrade is attempted without the snapshot.
+        let agent = failing_call(canister_id, "take_canister_snapshot");
+        let management_canister = ManagementCanister::create(agent.agent());
+        let result = management_canister
+            .install_code_with_rollback(&canister_id, b"\0asm", vec![], true)
+            .await;
+        assert!(matches!(
+            result,
+            Err(UpgradeError::SnapshotFailed { canister_id: id, reason: AgentError::TransportError(_) })
+                if id == canister_id
+        ));
+        agent.verify_all_called();
+
+        // The upgrade may have happened, so nothing is loaded back.
+        let agent = failing_call(canister_id, "install_code");
+        let management_canister = ManagementCanister::create(agent.agent());
+        let result = management_canister
+            .install_code_with_rollback(&canister_id, b"\0asm", vec![], false)
+            .await;
+        assert!(matches!(
+            result,
+            Err(UpgradeError::Failed {
+                reason: AgentError::TransportError(_),
+                snapshot_id: None,
+                ..
+            })
+        ));
+        agent.verify_all_called();
+    }
+
+    #[test]
+    fn recoverable_signature() {
+        use super::RecoverableSignature;
+        use k256::ecdsa::SigningKey;
+
+        let key = SigningKey::from_slice(&[1; 32]).unwrap();
+        let public_key = key.verifying_key().to_sec1_bytes();
+        let message_hash = [2; 32];
+        let (signature, recovery_id) = key.sign_prehash_recoverable(&message_hash).unwrap();
+
+        let recoverable =
+            RecoverableSignature::recover(&public_key, &message_hash, &signature.to_bytes())
+                .unwrap();
+        assert_eq!(recoverable.signature, signature.to_bytes().to_vec());
+        assert_eq!(recoverable.recovery_id, recovery_id.to_byte());
+        // A DER-encoded signature is accepted too.
+        let der = signature.to_der();
+        assert_eq!(
+            RecoverableSignature::recover(&public_key, &message_hash, der.as_bytes()).unwrap(),
+            recoverable
+        );
+
+        // Neither recovery ID gives back the key of another signer.
+        let other = SigningKey::from_slice(&[3; 32]).unwrap();
+        let other_key = other.verifying_key().to_sec1_bytes();
+        assert!(matches!(
+            RecoverableSignature::recover(&other_key, &message_hash, &signature.to_bytes()),
+            Err(AgentError::MessageError(_))
+        ));
+        assert!(RecoverableSignature::recover(&public_key, &message_hash, &[0; 10]).is_err());
+    }
+
+    #[test]
+    fn filter_snapshots() {
+        use super::{latest_snapshots, snapshots_since, Snapshot};
+
+        let snapshots: Vec<_> = [20, 10, 30]
+            .into_iter()
+            .map(|taken_at_timestamp| Snapshot {
+                id: vec![taken_at_timestamp as u8],
+                taken_at_timestamp,
+                total_size: 1,
+            })
+            .collect();
+        let ids = |snapshots: Vec<Snapshot>| -> Vec<_> {
+            snapshots
+                .into_iter()
+                .map(|snapshot| snapshot.id[0])
+                .collect()
+        };
+
+        // The bound is inclusive, and the order of the listing is kept.
+        assert_eq!(ids(snapshots_since(snapshots.clone(), 20)), vec![20, 30]);
+        assert_eq!(
+            ids(snapshots_since(snapshots.clone(), 31)),
+            Vec::<u8>::new()
+        );
+        assert_eq!(ids(latest_snapshots(snapshots.clone(), 2)), vec![30, 20]);
+        assert_eq!(ids(latest_snapshots(snapshots, 5)), vec![30, 20, 10]);
+    }
+
+    #[tokio::test]
+    async fn canister_cycles() {
+        use ic_agent::agent::{RejectCode, RejectResponse};
+
+        // A rejection of canister_status because the caller is not a controller is not permitted,
+        // and the canister_status call is the only request made.
+        let canister_id = crate::test_util::canister_id();
+        let path = format!("canister/{canister_id}/call");
+        let agent = MockAgent::new(MockTransport::new().expect(
+            Matcher::Custom(Arc::new(move |p, body| {
+                p == path
+                    && body
+                        .windows("canister_status".len())
+                        .any(|w| w == b"canister_status")
+            })),
+            Response::error(|| {
+                AgentError::ReplicaError(RejectResponse {
+                    reject_code: RejectCode::CanisterError,
+                    reject_message: "Only the controllers of the canister may call it.".into(),
+                    error_code: Some(super::NOT_A_CONTROLLER.into()),
+                })
+            }),
+        ));
+        let management_canister = ManagementCanister::create(agent.agent());
+        assert!(matches!(
+            management_canister.canister_cycles(&canister_id).await,
+            Err(AgentError::NotPermitted(_))
+        ));
+        agent.verify_all_called();
+    }
+
+    #[tokio::test]
+    async fn list_canister_snapshots_since() {
+        use crate::test_util::{canister_id, expect_method, is_sent, sent};
+
+        #[derive(candid::CandidType)]
+        struct In {
+            canister_id: Principal,
+        }
+
+        // The snapshots are listed from the subnet of the canister.
+        let agent = MockAgent::new(expect_method(
+            MockTransport::new(),
+            "call",
+            canister_id(),
+            "list_canister_snapshots",
+            candid::encode_one(In {
+                canister_id: canister_id(),
+            })
+            .unwrap(),
+            Response::error(sent),
+        ));
+        let management_canister = ManagementCanister::create(agent.agent());
+        let result = management_canister
+            .list_canister_snapshots_since(&canister_id(), 0)
+            .await;
+        assert!(is_sent(&result));
+        agent.verify_all_called();
+    }
+
+    #[test]
+    fn missing_chunks() {
+        use super::{missing_chunks, ChunkHash, MAX_CHUNK_SIZE};
+        use sha2::{Digest, Sha256};
+        use std::collections::HashSet;
+
+        let wasm_module: Vec<u8> = (0..MAX_CHUNK_SIZE * 5 / 2)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let chunks: Vec<_> = wasm_module.chunks(MAX_CHUNK_SIZE).collect();
+        assert_eq!(chunks.len(), 3);
+        let hash = |chunk: &[u8]| ChunkHash {
+            hash: Sha256::digest(chunk).to_vec(),
+        };
+
+        let (hashes, missing) = missing_chunks(&wasm_module, &HashSet::new());
+        assert_eq!(
+            hashes,
+            chunks.iter().map(|chunk| hash(chunk)).collect::<Vec<_>>()
+        );
+        assert_eq!(missing, chunks);
+
+        // A chunk already stored, e.g. by an interrupted upload, is not uploaded again, but its
+        // hash is still returned in order.
+        let stored = HashSet::from([hash(chunks[1])]);
+        let (resumed, missing) = missing_chunks(&wasm_module, &stored);
+        assert_eq!(resumed, hashes);
+        assert_eq!(missing, vec![chunks[0], chunks[2]]);
+    }
+
+    #[tokio::test]
+    async fn upload_wasm_chunked() {
+        use crate::test_util::{canister_id, expect_method, is_sent, sent};
+
+        #[derive(candid::CandidType)]
+        struct In {
+            canister_id: Principal,
+        }
+
+        // The stored chunks are listed first, and nothing is uploaded if that fails.
+        let agent = MockAgent::new(expect_method(
+            MockTransport::new(),
+            "call",
+            canister_id(),
+            "stored_chunks",
+            candid::encode_one(In {
+                canister_id: canister_id(),
+            })
+            .unwrap(),
+            Response::error(sent),
+        ));
+        let management_canister = ManagementCanister::create(agent.agent());
+        let result = management_canister
+            .upload_wasm_chunked(&canister_id(), b"\0asm")
+            .await;
+        assert!(is_sent(&result));
+        agent.verify_all_called();
+    }
+
+    #[tokio::test]
+    async fn provisional_create_canister_with_specified_id() {
+        use super::CanisterSettings;
+        use crate::call::AsyncCall;
+        use crate::test_util::{canister_id, expect_method, is_sent, sent};
+        use candid::Nat;
+
+        #[derive(candid::CandidType)]
+        struct In {
+            amount: Option<Nat>,
+            settings: Option<CanisterSettings>,
+            specified_id: Option<Principal>,
+        }
+
+        // The canister is created on the subnet of the specified ID.
+        let agent = MockAgent::new(expect_method(
+            MockTransport::new(),
+            "call",
+            canister_id(),
+            "provisional_create_canister_with_cycles",
+            candid::encode_one(In {
+                amount: None,
+                settings: None,
+                specified_id: Some(canister_id()),
+            })
+            .unwrap(),
+            Response::error(sent),
+        ));
+        let management_canister = ManagementCanister::create(agent.agent());
+        let result = management_canister
+            .provisional_create_canister_with_specified_id(canister_id(), None)
+            .call_and_wait()
+            .await;
+        assert!(is_sent(&result));
+        agent.verify_all_called();
+    }
+
+    #[tokio::test]
+    async fn ecdsa_key_ids() {
+        use super::EcdsaCurve;
+
+        // Nothing is sent, as no certified path lists the keys.
+        let agent = MockAgent::new(MockTransport::new());
+        let management_canister = ManagementCanister::create(agent.agent());
+        let subnet_id = Principal::from_slice(&[1; 29]);
+        assert!(matches!(
+            management_canister
+                .ecdsa_key_ids(subnet_id, EcdsaCurve::Secp256k1)
+                .await,
+            Err(AgentError::MessageError(_))
+        ));
+        agent.verify_all_called();
+    }
+}
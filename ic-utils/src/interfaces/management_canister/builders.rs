@@ -36,6 +36,12 @@ pub struct CanisterSettings {
     ///
     /// If unspecified and a canister is being created with these settings, defaults to 2592000, i.e. ~30 days.
     pub freezing_threshold: Option<Nat>,
+
+    /// The maximum size, in bytes, of the canister's WebAssembly heap memory. Messages that would
+    /// grow it beyond this limit fail.
+    ///
+    /// If unspecified and a canister is being created with these settings, there is no limit.
+    pub wasm_memory_limit: Option<Nat>,
 }
 
 /// A builder for a `create_canister` call.
@@ -267,6 +273,7 @@ impl<'agent, 'canister: 'agent> CreateCanisterBuilder<'agent, 'canister> {
                     compute_allocation,
                     memory_allocation,
                     freezing_threshold,
+                    wasm_memory_limit: None,
                 },
                 specified_id: self.specified_id,
             };
@@ -282,6 +289,7 @@ impl<'agent, 'canister: 'agent> CreateCanisterBuilder<'agent, 'canister> {
                     compute_allocation,
                     memory_allocation,
                     freezing_threshold,
+                    wasm_memory_limit: None,
                 })
                 .with_effective_canister_id(self.effective_canister_id)
         };
@@ -458,6 +466,7 @@ pub struct UpdateCanisterBuilder<'agent, 'canister: 'agent> {
     compute_allocation: Option<Result<ComputeAllocation, AgentError>>,
     memory_allocation: Option<Result<MemoryAllocation, AgentError>>,
     freezing_threshold: Option<Result<FreezingThreshold, AgentError>>,
+    wasm_memory_limit: Option<u64>,
 }
 
 impl<'agent, 'canister: 'agent> UpdateCanisterBuilder<'agent, 'canister> {
@@ -470,6 +479,7 @@ impl<'agent, 'canister: 'agent> UpdateCanisterBuilder<'agent, 'canister> {
             compute_allocation: None,
             memory_allocation: None,
             freezing_threshold: None,
+            wasm_memory_limit: None,
         }
     }
 
@@ -587,6 +597,14 @@ impl<'agent, 'canister: 'agent> UpdateCanisterBuilder<'agent, 'canister> {
         self.with_optional_freezing_threshold(Some(freezing_threshold))
     }
 
+    /// Pass in a WASM memory limit, in bytes, for the canister.
+    pub fn with_wasm_memory_limit(self, wasm_memory_limit: u64) -> Self {
+        Self {
+            wasm_memory_limit: Some(wasm_memory_limit),
+            ..self
+        }
+    }
+
     /// Create an [AsyncCall] implementation that, when called, will update a
     /// canisters settings.
     pub fn build(self) -> Result<impl 'agent + AsyncCall<()>, AgentError> {
@@ -627,6 +645,7 @@ impl<'agent, 'canister: 'agent> UpdateCanisterBuilder<'agent, 'canister> {
                     compute_allocation,
                     memory_allocation,
                     freezing_threshold,
+                    wasm_memory_limit: self.wasm_memory_limit.map(Nat::from),
                 },
             })
             .with_effective_canister_id(self.canister_id)
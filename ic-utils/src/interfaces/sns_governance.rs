@@ -0,0 +1,477 @@
+//! The canister interface for the governance canister of a Service Nervous System (SNS).
+//!
+//! SNS governance is similar to NNS governance, but its Candid types differ in field names and
+//! optionality. Only the subset of the interface needed to inspect proposals and manage neurons
+//! is defined here; fields that are not listed are ignored when decoding replies.
+
+use crate::{
+    call::{AsyncCall, SyncCall},
+    Canister,
+};
+use candid::{CandidType, Deserialize};
+use ic_agent::{export::Principal, Agent};
+use std::{convert::TryFrom, ops::Deref};
+
+/// The governance canister of an SNS.
+#[derive(Debug, Clone)]
+pub struct SnsGovernanceCanister<'agent>(Canister<'agent>);
+
+impl<'agent> Deref for SnsGovernanceCanister<'agent> {
+    type Target = Canister<'agent>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// The ID of an SNS neuron, which is the 32-byte subaccount of the governance canister holding
+/// the neuron's stake.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SnsNeuronId(pub [u8; 32]);
+
+impl From<SnsNeuronId> for NeuronId {
+    fn from(id: SnsNeuronId) -> Self {
+        Self { id: id.0.to_vec() }
+    }
+}
+
+impl TryFrom<NeuronId> for SnsNeuronId {
+    type Error = NeuronId;
+
+    /// Fails, returning the ID back, if it is not 32 bytes long.
+    fn try_from(id: NeuronId) -> Result<Self, Self::Error> {
+        match <[u8; 32]>::try_from(&id.id[..]) {
+            Ok(bytes) => Ok(Self(bytes)),
+            Err(_) => Err(id),
+        }
+    }
+}
+
+/// The ID of an SNS neuron, as it appears on the wire. See [SnsNeuronId].
+#[derive(Debug, Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct NeuronId {
+    /// The subaccount of the neuron.
+    #[serde(with = "serde_bytes")]
+    pub id: Vec<u8>,
+}
+
+/// The ID of an SNS proposal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct ProposalId {
+    /// The sequence number of the proposal.
+    pub id: u64,
+}
+
+/// An error returned by SNS governance.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct GovernanceError {
+    /// A human-readable description of the error.
+    pub error_message: String,
+    /// The kind of error, as a numeric code.
+    pub error_type: i32,
+}
+
+/// The user-supplied contents of a proposal.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct Proposal {
+    /// The title of the proposal.
+    pub title: String,
+    /// A Markdown description of the proposal.
+    pub summary: String,
+    /// A link to more information about the proposal.
+    pub url: String,
+}
+
+/// The current vote count of a proposal, in voting power.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct Tally {
+    /// The voting power that voted yes.
+    pub yes: u64,
+    /// The voting power that voted no.
+    pub no: u64,
+    /// The total voting power eligible to vote.
+    pub total: u64,
+    /// When the tally was last updated, in seconds since the epoch.
+    pub timestamp_seconds: u64,
+}
+
+/// A proposal and its state.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct ProposalData {
+    /// The ID of the proposal.
+    pub id: Option<ProposalId>,
+    /// The ID of the action the proposal performs.
+    pub action: u64,
+    /// The neuron that submitted the proposal.
+    pub proposer: Option<NeuronId>,
+    /// The contents of the proposal.
+    pub proposal: Option<Proposal>,
+    /// The current vote count.
+    pub latest_tally: Option<Tally>,
+    /// The fee charged to the proposer if the proposal is rejected, in e8s.
+    pub reject_cost_e8s: u64,
+    /// When the proposal was created, in seconds since the epoch.
+    pub proposal_creation_timestamp_seconds: u64,
+    /// When the proposal was decided, in seconds since the epoch, or 0 if it is still open.
+    pub decided_timestamp_seconds: u64,
+    /// When the proposal was executed, in seconds since the epoch, or 0 if it was not.
+    pub executed_timestamp_seconds: u64,
+    /// When the proposal failed to execute, in seconds since the epoch, or 0 if it did not.
+    pub failed_timestamp_seconds: u64,
+}
+
+/// The dissolve state of a neuron.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub enum DissolveState {
+    /// The neuron is not dissolving, and will take this many seconds to dissolve once started.
+    DissolveDelaySeconds(u64),
+    /// The neuron is dissolving, and will be dissolved at this time, in seconds since the epoch.
+    WhenDissolvedTimestampSeconds(u64),
+}
+
+/// An SNS neuron.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct Neuron {
+    /// The ID of the neuron.
+    pub id: Option<NeuronId>,
+    /// The stake of the neuron, in e8s.
+    pub cached_neuron_stake_e8s: u64,
+    /// The fees incurred by the neuron's rejected proposals, in e8s.
+    pub neuron_fees_e8s: u64,
+    /// The maturity of the neuron, in e8s.
+    pub maturity_e8s_equivalent: u64,
+    /// When the neuron was created, in seconds since the epoch.
+    pub created_timestamp_seconds: u64,
+    /// When the neuron started aging, in seconds since the epoch.
+    pub aging_since_timestamp_seconds: u64,
+    /// The dissolve state of the neuron.
+    pub dissolve_state: Option<DissolveState>,
+}
+
+/// The argument of a `list_proposals` call.
+#[derive(Debug, Clone, Default, CandidType, Deserialize)]
+pub struct ListProposalsRequest {
+    /// The maximum number of proposals to return.
+    pub limit: u32,
+    /// Only return proposals older than this one, for pagination.
+    pub before_proposal: Option<ProposalId>,
+    /// Exclude proposals with these action IDs.
+    pub exclude_type: Vec<u64>,
+    /// Only return proposals with these statuses. All statuses are included if empty.
+    pub include_status: Vec<i32>,
+    /// Only return proposals with these reward statuses. All are included if empty.
+    pub include_reward_status: Vec<i32>,
+}
+
+/// The result of a `list_proposals` call.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct ListProposalsResponse {
+    /// The proposals, newest first.
+    pub proposals: Vec<ProposalData>,
+}
+
+/// The result of a `get_proposal` call.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct GetProposalResponse {
+    /// The proposal, or an error if it could not be found.
+    pub result: Option<GetProposalResult>,
+}
+
+/// See [GetProposalResponse].
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub enum GetProposalResult {
+    /// The proposal could not be retrieved.
+    Error(GovernanceError),
+    /// The proposal.
+    Proposal(ProposalData),
+}
+
+/// The result of a `get_neuron` call.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct GetNeuronResponse {
+    /// The neuron, or an error if it could not be found.
+    pub result: Option<GetNeuronResult>,
+}
+
+/// See [GetNeuronResponse].
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub enum GetNeuronResult {
+    /// The neuron could not be retrieved.
+    Error(GovernanceError),
+    /// The neuron.
+    Neuron(Neuron),
+}
+
+/// An ICRC-1 account.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct Account {
+    /// The owner of the account. Defaults to the caller if unspecified.
+    pub owner: Option<Principal>,
+    /// The subaccount of the account. Defaults to the default subaccount if unspecified.
+    pub subaccount: Option<Subaccount>,
+}
+
+/// An ICRC-1 subaccount.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct Subaccount {
+    /// The 32-byte subaccount.
+    #[serde(with = "serde_bytes")]
+    pub subaccount: Vec<u8>,
+}
+
+/// An amount of tokens.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct Amount {
+    /// The amount, in e8s.
+    pub e8s: u64,
+}
+
+/// A command to perform on a neuron.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub enum ManageNeuronCommand {
+    /// Disburse the stake of a dissolved neuron.
+    Disburse {
+        /// The account to disburse to. Defaults to the caller's default account.
+        to_account: Option<Account>,
+        /// The amount to disburse. Defaults to the whole stake.
+        amount: Option<Amount>,
+    },
+    /// Split off part of the stake into a new neuron.
+    Split {
+        /// A memo identifying the new neuron.
+        memo: u64,
+        /// The amount to split off, in e8s.
+        amount_e8s: u64,
+    },
+    /// Follow other neurons' votes on proposals of a given action.
+    Follow {
+        /// The action ID to follow the neurons on.
+        function_id: u64,
+        /// The neurons to follow. Clears the followees if empty.
+        followees: Vec<NeuronId>,
+    },
+    /// Vote on a proposal.
+    RegisterVote {
+        /// The proposal to vote on.
+        proposal: Option<ProposalId>,
+        /// The vote: 1 for yes, 2 for no.
+        vote: i32,
+    },
+}
+
+/// The argument of a `manage_neuron` call.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct ManageNeuronRequest {
+    /// The subaccount of the neuron to manage, i.e. its ID.
+    #[serde(with = "serde_bytes")]
+    pub subaccount: Vec<u8>,
+    /// The command to perform.
+    pub command: Option<ManageNeuronCommand>,
+}
+
+impl ManageNeuronRequest {
+    /// Create a request performing `command` on the neuron `neuron_id`.
+    pub fn new(neuron_id: SnsNeuronId, command: ManageNeuronCommand) -> Self {
+        Self {
+            subaccount: neuron_id.0.to_vec(),
+            command: Some(command),
+        }
+    }
+}
+
+/// The outcome of a [ManageNeuronCommand].
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub enum ManageNeuronResponseCommand {
+    /// The command failed.
+    Error(GovernanceError),
+    /// The stake was disbursed.
+    Disburse {
+        /// The ledger block containing the transfer.
+        transfer_block_height: u64,
+    },
+    /// The neuron was split.
+    Split {
+        /// The ID of the new neuron.
+        created_neuron_id: Option<NeuronId>,
+    },
+    /// The followees were updated.
+    Follow(FollowResponse),
+    /// The vote was registered.
+    RegisterVote(RegisterVoteResponse),
+}
+
+/// The empty outcome of a [`ManageNeuronCommand::Follow`].
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct FollowResponse {}
+
+/// The empty outcome of a [`ManageNeuronCommand::RegisterVote`].
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct RegisterVoteResponse {}
+
+/// The result of a `manage_neuron` call.
+#[derive(Debug, Clone, CandidType, Deserialize)]
+pub struct ManageNeuronResponse {
+    /// The outcome of the command.
+    pub command: Option<ManageNeuronResponseCommand>,
+}
+
+impl<'agent> SnsGovernanceCanister<'agent> {
+    /// Create an instance of a `SnsGovernanceCanister` interface pointing to the specified Canister ID.
+    pub fn create(agent: &'agent Agent, canister_id: Principal) -> Self {
+        Self(
+            Canister::builder()
+                .with_agent(agent)
+                .with_canister_id(canister_id)
+                .build()
+                .unwrap(),
+        )
+    }
+
+    /// Create a `SnsGovernanceCanister` interface from an existing canister object.
+    pub fn from_canister(canister: Canister<'agent>) -> Self {
+        Self(canister)
+    }
+}
+
+impl<'agent> SnsGovernanceCanister<'agent> {
+    /// Get a proposal by its ID.
+    pub fn get_proposal<'canister: 'agent>(
+        &'canister self,
+        proposal_id: u64,
+    ) -> impl 'agent + SyncCall<(GetProposalResponse,)> {
+        #[derive(CandidType)]
+        struct In {
+            proposal_id: Option<ProposalId>,
+        }
+
+        self.query_("get_proposal")
+            .with_arg(In {
+                proposal_id: Some(ProposalId { id: proposal_id }),
+            })
+            .build()
+    }
+
+    /// List proposals, newest first.
+    pub fn list_proposals<'canister: 'agent>(
+        &'canister self,
+        args: ListProposalsRequest,
+    ) -> impl 'agent + SyncCall<(ListProposalsResponse,)> {
+        self.query_("list_proposals").with_arg(args).build()
+    }
+
+    /// Perform a command on a neuron controlled by the caller.
+    pub fn manage_neuron<'canister: 'agent>(
+        &'canister self,
+        args: ManageNeuronRequest,
+    ) -> impl 'agent + AsyncCall<(ManageNeuronResponse,)> {
+        self.update_("manage_neuron").with_arg(args).build()
+    }
+
+    /// Get a neuron by its ID.
+    pub fn get_neuron<'canister: 'agent>(
+        &'canister self,
+        neuron_id: SnsNeuronId,
+    ) -> impl 'agent + SyncCall<(GetNeuronResponse,)> {
+        #[derive(CandidType)]
+        struct In {
+            neuron_id: Option<NeuronId>,
+        }
+
+        self.query_("get_neuron")
+            .with_arg(In {
+                neuron_id: Some(neuron_id.into()),
+            })
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{canister_id, expect_method, is_sent, sent};
+    use ic_agent::agent::mock::{MockAgent, MockTransport, Response};
+
+    #[tokio::test]
+    async fn get_proposal() {
+        #[derive(CandidType)]
+        struct In {
+            proposal_id: Option<ProposalId>,
+        }
+
+        let proposal = ProposalData {
+            id: Some(ProposalId { id: 7 }),
+            action: 1,
+            proposer: Some(SnsNeuronId([1; 32]).into()),
+            proposal: Some(Proposal {
+                title: "Motion".to_string(),
+                summary: "Do it.".to_string(),
+                url: String::new(),
+            }),
+            latest_tally: None,
+            reject_cost_e8s: 100,
+            proposal_creation_timestamp_seconds: 1,
+            decided_timestamp_seconds: 0,
+            executed_timestamp_seconds: 0,
+            failed_timestamp_seconds: 0,
+        };
+        let reply = GetProposalResponse {
+            result: Some(GetProposalResult::Proposal(proposal)),
+        };
+        let agent = MockAgent::new(expect_method(
+            MockTransport::new(),
+            "query",
+            canister_id(),
+            "get_proposal",
+            candid::encode_one(In {
+                proposal_id: Some(ProposalId { id: 7 }),
+            })
+            .unwrap(),
+            Response::query_reply(candid::encode_one(reply).unwrap()),
+        ));
+        let governance = SnsGovernanceCanister::create(&agent, canister_id());
+
+        let (response,) = governance.get_proposal(7).call().await.unwrap();
+        match response.result {
+            Some(GetProposalResult::Proposal(proposal)) => {
+                assert_eq!(proposal.id, Some(ProposalId { id: 7 }));
+                assert_eq!(
+                    proposal.proposer.map(SnsNeuronId::try_from),
+                    Some(Ok(SnsNeuronId([1; 32])))
+                );
+                assert_eq!(proposal.proposal.unwrap().title, "Motion");
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+        agent.verify_all_called();
+    }
+
+    #[tokio::test]
+    async fn manage_neuron() {
+        let request = ManageNeuronRequest::new(
+            SnsNeuronId([1; 32]),
+            ManageNeuronCommand::RegisterVote {
+                proposal: Some(ProposalId { id: 7 }),
+                vote: 1,
+            },
+        );
+        let agent = MockAgent::new(expect_method(
+            MockTransport::new(),
+            "call",
+            canister_id(),
+            "manage_neuron",
+            candid::encode_one(&request).unwrap(),
+            Response::error(sent),
+        ));
+        let governance = SnsGovernanceCanister::create(&agent, canister_id());
+
+        assert!(is_sent(&governance.manage_neuron(request).call().await));
+        agent.verify_all_called();
+    }
+
+    #[test]
+    fn neuron_id() {
+        let id = NeuronId { id: vec![1; 32] };
+        assert_eq!(SnsNeuronId::try_from(id), Ok(SnsNeuronId([1; 32])));
+        let short = NeuronId { id: vec![1; 31] };
+        assert_eq!(SnsNeuronId::try_from(short.clone()), Err(short));
+    }
+}
@@ -681,6 +681,7 @@ impl<'agent> WalletCanister<'agent> {
             compute_allocation: compute_allocation.map(u8::from).map(Nat::from),
             memory_allocation: memory_allocation.map(u64::from).map(Nat::from),
             freezing_threshold: freezing_threshold.map(u64::from).map(Nat::from),
+            wasm_memory_limit: None,
         };
 
         self.update_("wallet_create_canister")
@@ -709,6 +710,7 @@ impl<'agent> WalletCanister<'agent> {
             compute_allocation: compute_allocation.map(u8::from).map(Nat::from),
             memory_allocation: memory_allocation.map(u64::from).map(Nat::from),
             freezing_threshold: freezing_threshold.map(u64::from).map(Nat::from),
+            wasm_memory_limit: None,
         };
 
         self.update_("wallet_create_canister128")
@@ -827,6 +829,7 @@ impl<'agent> WalletCanister<'agent> {
             compute_allocation: compute_allocation.map(u8::from).map(Nat::from),
             memory_allocation: memory_allocation.map(u64::from).map(Nat::from),
             freezing_threshold: freezing_threshold.map(u64::from).map(Nat::from),
+            wasm_memory_limit: None,
         };
 
         self.update_("wallet_create_wallet")
@@ -855,6 +858,7 @@ impl<'agent> WalletCanister<'agent> {
             compute_allocation: compute_allocation.map(u8::from).map(Nat::from),
             memory_allocation: memory_allocation.map(u64::from).map(Nat::from),
             freezing_threshold: freezing_threshold.map(u64::from).map(Nat::from),
+            wasm_memory_limit: None,
         };
 
         self.update_("wallet_create_wallet128")
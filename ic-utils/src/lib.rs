@@ -9,11 +9,32 @@
 )]
 #![cfg_attr(docsrs, feature(doc_cfg, doc_auto_cfg))]
 
+/// Cycle budgets for the update calls made to a canister.
+pub mod budget;
 /// Utilities to encapsulate calls to a canister.
 pub mod call;
 /// A higher-level canister type for managing various aspects of a canister.
 pub mod canister;
+/// Deadlines of the server requests IC calls are made on behalf of.
+pub mod context;
+/// Fault injection into the calls an agent sends through its transport, for tests.
+pub mod fault;
 /// A few known canister types for use with [`Canister`](canister::Canister).
 pub mod interfaces;
+/// Per-method call metrics for a [`Canister`](canister::Canister).
+pub mod metrics;
+/// Strategies for how often the status of an update call is polled while waiting for its result.
+pub mod polling;
+/// Routing of calls to the subnet hosting the canister they target.
+pub mod routing;
+/// Validation and metadata of WASM modules to install on a canister.
+pub mod wasm;
+/// Watching a query method for changes to its reply.
+#[cfg(not(target_family = "wasm"))]
+pub mod watch;
 
-pub use canister::{Argument, Canister};
+/// Setup shared by the tests of the crate.
+#[cfg(test)]
+mod test_util;
+
+pub use canister::{Argument, Canister, CanisterPool, OwnedCanister};
@@ -0,0 +1,190 @@
+use crate::canister::Canister;
+use ic_agent::AgentError;
+use std::{fmt::Debug, ops::Deref, sync::Arc, time::Duration};
+
+/// A sink for per-method call metrics, attached to a canister with
+/// [`Canister::with_metrics`](crate::Canister::with_metrics).
+pub trait MetricsRegistry: Debug + Send + Sync {
+    /// Record that a call to `method_name` finished with `result` after `duration`.
+    ///
+    /// For update calls, this is called once the reply is received when waiting for it, or once the
+    /// call is submitted otherwise. On WASM targets, which have no monotonic clock, `duration` is always zero.
+    fn record(&self, method_name: &str, result: Result<(), &AgentError>, duration: Duration);
+}
+
+/// A [Canister] that reports the outcome and latency of every query and update call made
+/// through it to a [MetricsRegistry]. Created with [`Canister::with_metrics`].
+#[derive(Debug, Clone)]
+pub struct InstrumentedCanister<'agent>(pub(crate) Canister<'agent>);
+
+impl<'agent> InstrumentedCanister<'agent> {
+    /// Unwrap the instrumented canister, e.g. to build an interface like
+    /// [`ManagementCanister`](crate::interfaces::ManagementCanister) around it. Calls made
+    /// through the result are still instrumented.
+    pub fn into_inner(self) -> Canister<'agent> {
+        self.0
+    }
+}
+
+impl<'agent> Deref for InstrumentedCanister<'agent> {
+    type Target = Canister<'agent>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Run `call`, recording its outcome to `metrics` if there is a registry.
+pub(crate) async fn record<T>(
+    metrics: Option<(Arc<dyn MetricsRegistry>, String)>,
+    call: impl std::future::Future<Output = Result<T, AgentError>>,
+) -> Result<T, AgentError> {
+    match metrics {
+        None => call.await,
+        Some((registry, method_name)) => {
+            let stopwatch = Stopwatch::start();
+            let result = call.await;
+            registry.record(
+                &method_name,
+                result.as_ref().map(|_| ()),
+                stopwatch.elapsed(),
+            );
+            result
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub(crate) struct Stopwatch(std::time::Instant);
+
+#[cfg(target_family = "wasm")]
+pub(crate) struct Stopwatch;
+
+impl Stopwatch {
+    pub(crate) fn start() -> Self {
+        #[cfg(not(target_family = "wasm"))]
+        {
+            Self(std::time::Instant::now())
+        }
+        #[cfg(target_family = "wasm")]
+        {
+            Self
+        }
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        #[cfg(not(target_family = "wasm"))]
+        {
+            self.0.elapsed()
+        }
+        #[cfg(target_family = "wasm")]
+        {
+            Duration::ZERO
+        }
+    }
+}
+
+#[cfg(feature = "prometheus")]
+pub use self::prometheus_impl::PrometheusMetricsRegistry;
+
+#[cfg(feature = "prometheus")]
+mod prometheus_impl {
+    use super::MetricsRegistry;
+    use ic_agent::AgentError;
+    use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry};
+    use std::{fmt, time::Duration};
+
+    /// A [MetricsRegistry] exporting the metrics `ic_canister_calls_total` and
+    /// `ic_canister_call_duration_seconds` to a [`prometheus::Registry`], labeled by method name
+    /// and by result (`ok` or `error`).
+    #[derive(Clone)]
+    pub struct PrometheusMetricsRegistry {
+        calls: IntCounterVec,
+        duration: HistogramVec,
+    }
+
+    impl PrometheusMetricsRegistry {
+        /// Create the metrics and register them with `registry`.
+        pub fn new(registry: &Registry) -> prometheus::Result<Self> {
+            let calls = IntCounterVec::new(
+                Opts::new("ic_canister_calls_total", "Number of canister calls."),
+                &["method", "result"],
+            )?;
+            let duration = HistogramVec::new(
+                HistogramOpts::new(
+                    "ic_canister_call_duration_seconds",
+                    "Duration of canister calls.",
+                ),
+                &["method", "result"],
+            )?;
+            registry.register(Box::new(calls.clone()))?;
+            registry.register(Box::new(duration.clone()))?;
+            Ok(Self { calls, duration })
+        }
+    }
+
+    impl fmt::Debug for PrometheusMetricsRegistry {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_struct("PrometheusMetricsRegistry")
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl MetricsRegistry for PrometheusMetricsRegistry {
+        fn record(&self, method_name: &str, result: Result<(), &AgentError>, duration: Duration) {
+            let labels = [method_name, if result.is_ok() { "ok" } else { "error" }];
+            self.calls.with_label_values(&labels).inc();
+            self.duration
+                .with_label_values(&labels)
+                .observe(duration.as_secs_f64());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MetricsRegistry;
+    use crate::call::SyncCall;
+    use crate::test_util::{self, canister_id, is_sent, sent};
+    use ic_agent::agent::mock::{MockAgent, MockTransport, Response};
+    use ic_agent::AgentError;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[derive(Debug, Default)]
+    struct Recorder(Mutex<Vec<(String, bool)>>);
+
+    impl MetricsRegistry for Recorder {
+        fn record(&self, method_name: &str, result: Result<(), &AgentError>, _: Duration) {
+            let call = (method_name.to_string(), result.is_ok());
+            self.0.lock().unwrap().push(call);
+        }
+    }
+
+    #[tokio::test]
+    async fn record_calls() {
+        let agent = MockAgent::new(
+            MockTransport::new()
+                .expect_query(
+                    canister_id(),
+                    Response::query_reply(candid::encode_one(1u32).unwrap()),
+                )
+                .expect_update(canister_id(), Response::error(sent)),
+        );
+        let canister = test_util::canister(&agent);
+        let recorder = Arc::new(Recorder::default());
+        let instrumented = canister.with_metrics(recorder.clone());
+
+        let (reply,): (u32,) = instrumented.query_("get").build().call().await.unwrap();
+        assert_eq!(reply, 1);
+        assert!(is_sent(
+            &instrumented.update_("set").build::<()>().call().await
+        ));
+        // Calls through the original canister are not recorded.
+        let _: (u32,) = canister.query_("get").build().call().await.unwrap();
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec![("get".to_string(), true), ("set".to_string(), false)]
+        );
+        agent.verify_all_called();
+    }
+}
@@ -0,0 +1,83 @@
+pub use ic_agent::agent::PollStrategy;
+use std::fmt;
+use std::time::Duration;
+
+/// Poll at a constant interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConstantPoll(pub Duration);
+
+impl PollStrategy for ConstantPoll {
+    fn next_delay(&mut self, _iteration: u32) -> Option<Duration> {
+        Some(self.0)
+    }
+}
+
+/// Wait `base` after the first poll, and double the delay after each poll, up to `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExponentialPoll {
+    /// The delay after the first poll.
+    pub base: Duration,
+    /// The maximum delay between two polls.
+    pub max: Duration,
+}
+
+impl PollStrategy for ExponentialPoll {
+    fn next_delay(&mut self, iteration: u32) -> Option<Duration> {
+        let factor = 1u32
+            .checked_shl(iteration.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        Some(self.base.saturating_mul(factor).min(self.max))
+    }
+}
+
+/// Wait a Fibonacci number of seconds between polls, i.e. 1, 1, 2, 3, 5, ... seconds, up to
+/// `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FibonacciPoll {
+    /// The maximum delay between two polls.
+    pub max: Duration,
+}
+
+impl PollStrategy for FibonacciPoll {
+    fn next_delay(&mut self, iteration: u32) -> Option<Duration> {
+        let (mut a, mut b) = (1u64, 1u64);
+        for _ in 1..iteration {
+            (a, b) = (b, a.saturating_add(b));
+        }
+        Some(Duration::from_secs(a).min(self.max))
+    }
+}
+
+/// A boxed [PollStrategy], which can be printed.
+pub(crate) struct BoxedPollStrategy(pub(crate) Box<dyn PollStrategy>);
+
+impl fmt::Debug for BoxedPollStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BoxedPollStrategy").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delays(mut strategy: impl PollStrategy, n: u32) -> Vec<u64> {
+        (1..=n)
+            .map(|i| strategy.next_delay(i).unwrap().as_secs())
+            .collect()
+    }
+
+    #[test]
+    fn strategies() {
+        let second = Duration::from_secs(1);
+        assert_eq!(delays(ConstantPoll(second), 3), [1, 1, 1]);
+        let mut exponential = ExponentialPoll {
+            base: second,
+            max: second * 10,
+        };
+        assert_eq!(delays(exponential, 6), [1, 2, 4, 8, 10, 10]);
+        assert_eq!(exponential.next_delay(100), Some(second * 10));
+        let fibonacci = FibonacciPoll { max: second * 10 };
+        assert_eq!(delays(fibonacci, 7), [1, 1, 2, 3, 5, 8, 10]);
+    }
+}
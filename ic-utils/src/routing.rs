@@ -0,0 +1,136 @@
+use crate::interfaces::management_canister::MgmtMethod;
+use candid::{CandidType, Decode, Deserialize};
+use ic_agent::export::Principal;
+use std::str::FromStr;
+
+/// The [effective canister ID](https://internetcomputer.org/docs/current/references/ic-interface-spec#http-effective-canister-id)
+/// of a call to `method` of `canister_id` with the Candid-encoded `arg`, which picks the subnet
+/// the call is routed to.
+///
+/// This is `canister_id` itself, except for calls to the management canister, whose effective
+/// canister ID is the `canister_id` field of their argument, i.e. the canister they manage, or
+/// the `specified_id` of `provisional_create_canister_with_cycles`. Only `create_canister`,
+/// `raw_rand` and `provisional_create_canister_with_cycles` without a `specified_id` are routed
+/// to the management canister ID itself.
+///
+/// Fails if the argument of a call to the management canister that manages a canister cannot be
+/// decoded, if the method can only be called by canisters, or if it is not a known method of the
+/// management canister.
+pub fn effective_canister_id_for_call(
+    canister_id: &Principal,
+    method: &str,
+    arg: &[u8],
+) -> Result<Principal, candid::Error> {
+    if *canister_id != Principal::management_canister() {
+        return Ok(*canister_id);
+    }
+
+    #[derive(CandidType, Deserialize)]
+    struct CanisterIdArg {
+        canister_id: Principal,
+    }
+    #[derive(CandidType, Deserialize)]
+    struct SpecifiedIdArg {
+        specified_id: Option<Principal>,
+    }
+
+    let effective_canister_id = match MgmtMethod::from_str(method) {
+        Ok(MgmtMethod::ProvisionalCreateCanisterWithCycles) => {
+            Decode!(arg, SpecifiedIdArg)?.specified_id
+        }
+        Ok(MgmtMethod::CreateCanister | MgmtMethod::RawRand) => None,
+        Ok(MgmtMethod::EcdsaPublicKey | MgmtMethod::SignWithEcdsa) => {
+            return Err(candid::Error::msg(format!(
+                "{} can only be called by canisters",
+                method
+            )))
+        }
+        Ok(_) => Some(Decode!(arg, CanisterIdArg)?.canister_id),
+        Err(_) => {
+            return Err(candid::Error::msg(format!(
+                "{} is not a method of the management canister",
+                method
+            )))
+        }
+    };
+    Ok(effective_canister_id.unwrap_or_else(Principal::management_canister))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Encode;
+
+    #[test]
+    fn effective_canister_id() {
+        let management_canister = Principal::management_canister();
+        let canister_id = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+
+        assert_eq!(
+            effective_canister_id_for_call(&canister_id, "greet", b"not candid").unwrap(),
+            canister_id
+        );
+
+        let arg = Encode!(&CanisterIdArg { canister_id }).unwrap();
+        assert_eq!(
+            effective_canister_id_for_call(&management_canister, "start_canister", &arg).unwrap(),
+            canister_id
+        );
+        assert_eq!(
+            effective_canister_id_for_call(&management_canister, "raw_rand", &arg).unwrap(),
+            management_canister
+        );
+        assert_eq!(
+            effective_canister_id_for_call(&management_canister, "raw_rand", b"not candid")
+                .unwrap(),
+            management_canister
+        );
+
+        // A call that manages a canister is not routed to the management canister ID when the
+        // canister cannot be told from its argument.
+        for method in ["stop_canister", "canister_status", "install_code"] {
+            assert!(
+                effective_canister_id_for_call(&management_canister, method, b"not candid")
+                    .is_err()
+            );
+        }
+        assert!(effective_canister_id_for_call(&management_canister, "greet", &arg).is_err());
+        assert!(
+            effective_canister_id_for_call(&management_canister, "sign_with_ecdsa", &arg).is_err()
+        );
+
+        let arg = Encode!(&SpecifiedIdArg {
+            specified_id: Some(canister_id)
+        })
+        .unwrap();
+        assert_eq!(
+            effective_canister_id_for_call(
+                &management_canister,
+                "provisional_create_canister_with_cycles",
+                &arg
+            )
+            .unwrap(),
+            canister_id
+        );
+        let arg = Encode!(&SpecifiedIdArg { specified_id: None }).unwrap();
+        assert_eq!(
+            effective_canister_id_for_call(
+                &management_canister,
+                "provisional_create_canister_with_cycles",
+                &arg
+            )
+            .unwrap(),
+            management_canister
+        );
+    }
+
+    #[derive(CandidType)]
+    struct CanisterIdArg {
+        canister_id: Principal,
+    }
+
+    #[derive(CandidType)]
+    struct SpecifiedIdArg {
+        specified_id: Option<Principal>,
+    }
+}
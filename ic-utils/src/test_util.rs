@@ -0,0 +1,95 @@
+use crate::Canister;
+use ic_agent::agent::mock::{Matcher, MockTransport, Response};
+use ic_agent::{export::Principal, Agent, AgentError};
+use serde::Deserialize;
+use std::sync::Arc;
+
+/// The canister the tests call.
+pub(crate) fn canister_id() -> Principal {
+    Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap()
+}
+
+/// A handle to [`canister_id`] using `agent`, e.g. a
+/// [`MockAgent`](ic_agent::agent::mock::MockAgent).
+pub(crate) fn canister(agent: &Agent) -> Canister<'_> {
+    Canister::builder()
+        .with_agent(agent)
+        .with_canister_id(canister_id())
+        .build()
+        .unwrap()
+}
+
+/// The error the requests expected by [`expect_arg`] fail with, showing they were sent.
+pub(crate) fn sent() -> AgentError {
+    AgentError::MessageError("sent".to_string())
+}
+
+/// Whether `result` is the error of a request expected by [`expect_arg`].
+pub(crate) fn is_sent<T>(result: &Result<T, AgentError>) -> bool {
+    matches!(result, Err(AgentError::MessageError(message)) if message == "sent")
+}
+
+/// Expect a request to the `endpoint` of `effective_canister_id`, e.g. `call` or `query`,
+/// whose envelope carries `arg`, and fail it with [`sent`].
+pub(crate) fn expect_arg(
+    transport: MockTransport,
+    endpoint: &str,
+    effective_canister_id: Principal,
+    arg: Vec<u8>,
+) -> MockTransport {
+    expect_content(
+        transport,
+        endpoint,
+        effective_canister_id,
+        Response::error(sent),
+        move |content| content.arg.as_slice() == arg,
+    )
+}
+
+/// Expect a request to the `endpoint` of `effective_canister_id` calling `method_name` with
+/// `arg`, and answer it with `response`.
+pub(crate) fn expect_method(
+    transport: MockTransport,
+    endpoint: &str,
+    effective_canister_id: Principal,
+    method_name: &'static str,
+    arg: Vec<u8>,
+    response: Response,
+) -> MockTransport {
+    expect_content(
+        transport,
+        endpoint,
+        effective_canister_id,
+        response,
+        move |content| content.method_name == method_name && content.arg.as_slice() == arg,
+    )
+}
+
+#[derive(Deserialize)]
+struct Envelope {
+    content: Content,
+}
+
+#[derive(Deserialize)]
+struct Content {
+    method_name: String,
+    arg: serde_bytes::ByteBuf,
+}
+
+fn expect_content(
+    transport: MockTransport,
+    endpoint: &str,
+    effective_canister_id: Principal,
+    response: Response,
+    matches: impl Fn(&Content) -> bool + Send + Sync + 'static,
+) -> MockTransport {
+    let expected_path = format!("canister/{effective_canister_id}/{endpoint}");
+    transport.expect(
+        Matcher::Custom(Arc::new(move |path, envelope| {
+            path == expected_path
+                && serde_cbor::from_slice::<Envelope>(envelope)
+                    .map_or(false, |envelope| matches(&envelope.content))
+        })),
+        response,
+    )
+}
@@ -0,0 +1,225 @@
+use std::convert::TryInto;
+use thiserror::Error;
+
+const WASM_MAGIC: &[u8; 4] = b"\0asm";
+const WASM_VERSION: u32 = 1;
+const CUSTOM_SECTION_ID: u8 = 0;
+
+/// An error found while validating a WASM module in [`CanisterWasm::from_bytes`].
+#[derive(Debug, Error)]
+pub enum WasmValidationError {
+    /// The module is too short to contain the WASM header.
+    #[error("The module is {0} bytes long, which is too short to be a WASM module.")]
+    TooShort(usize),
+
+    /// The module does not start with the WASM magic number. This is also the case for gzipped
+    /// modules, which must be installed as-is instead.
+    #[error("The module does not start with the WASM magic number: {0:02x?}")]
+    InvalidMagic([u8; 4]),
+
+    /// The module has a WASM version other than 1.
+    #[error("Unsupported WASM version {0}.")]
+    UnsupportedVersion(u32),
+
+    /// A section of the module could not be read.
+    #[error("Malformed section at offset {0}.")]
+    MalformedSection(usize),
+
+    /// The module failed validation by `wasmparser`.
+    #[cfg(feature = "wasmparser")]
+    #[error("Invalid WASM module: {0}")]
+    Invalid(#[from] wasmparser::BinaryReaderError),
+}
+
+/// Whether a canister metadata section can be read by anyone, or only by the controllers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WasmSectionVisibility {
+    /// An `icp:public` section.
+    Public,
+    /// An `icp:private` section.
+    Private,
+}
+
+/// A canister metadata custom section of a WASM module, such as `icp:public candid:service`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WasmSection {
+    /// The name of the metadata, without the `icp:public` or `icp:private` prefix, e.g. `candid:service`.
+    pub name: String,
+    /// Who can read the metadata once the module is installed.
+    pub visibility: WasmSectionVisibility,
+    /// The content of the section.
+    pub content: Vec<u8>,
+}
+
+/// A WASM module that was checked to have a valid header and section layout, to be installed
+/// on a canister.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanisterWasm(Vec<u8>);
+
+impl CanisterWasm {
+    /// Validate `bytes` as a WASM module. This checks the magic number and version, and that
+    /// every section is within bounds. With the `wasmparser` feature, the whole module is also
+    /// validated.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, WasmValidationError> {
+        if bytes.len() < 8 {
+            return Err(WasmValidationError::TooShort(bytes.len()));
+        }
+        let magic: [u8; 4] = bytes[..4].try_into().unwrap();
+        if &magic != WASM_MAGIC {
+            return Err(WasmValidationError::InvalidMagic(magic));
+        }
+        let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        if version != WASM_VERSION {
+            return Err(WasmValidationError::UnsupportedVersion(version));
+        }
+        for section in Sections(&bytes, 8) {
+            section?;
+        }
+        #[cfg(feature = "wasmparser")]
+        wasmparser::validate(&bytes)?;
+        Ok(Self(bytes))
+    }
+
+    /// The bytes of the module.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Unwrap the bytes of the module, e.g. to pass them to
+    /// [`ManagementCanister::install_code`](crate::interfaces::ManagementCanister::install_code).
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    /// The canister metadata sections of the module, in the order they appear. Custom sections
+    /// whose name does not start with `icp:public ` or `icp:private ` are skipped.
+    pub fn metadata_sections(&self) -> Vec<WasmSection> {
+        Sections(&self.0, 8)
+            .filter_map(|section| {
+                let (id, content) = section.ok()?;
+                if id != CUSTOM_SECTION_ID {
+                    return None;
+                }
+                let mut reader = content;
+                let name_len = leb128::read::unsigned(&mut reader).ok()? as usize;
+                let name = std::str::from_utf8(reader.get(..name_len)?).ok()?;
+                let content = reader[name_len..].to_vec();
+                let (visibility, name) = if let Some(name) = name.strip_prefix("icp:public ") {
+                    (WasmSectionVisibility::Public, name)
+                } else if let Some(name) = name.strip_prefix("icp:private ") {
+                    (WasmSectionVisibility::Private, name)
+                } else {
+                    return None;
+                };
+                Some(WasmSection {
+                    name: name.to_string(),
+                    visibility,
+                    content,
+                })
+            })
+            .collect()
+    }
+}
+
+impl AsRef<[u8]> for CanisterWasm {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl TryFrom<Vec<u8>> for CanisterWasm {
+    type Error = WasmValidationError;
+    fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> {
+        Self::from_bytes(bytes)
+    }
+}
+
+/// An iterator over the sections of a module, as pairs of section ID and content, starting at
+/// the given offset. Stops after the first malformed section.
+struct Sections<'a>(&'a [u8], usize);
+
+impl<'a> Iterator for Sections<'a> {
+    type Item = Result<(u8, &'a [u8]), WasmValidationError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Sections(bytes, offset) = self;
+        if *offset >= bytes.len() {
+            return None;
+        }
+        let start = *offset;
+        let mut reader = &bytes[start + 1..];
+        let section = leb128::read::unsigned(&mut reader)
+            .ok()
+            .and_then(|size| reader.get(..size as usize))
+            .map(|content| (bytes[start], content));
+        match section {
+            Some((id, content)) => {
+                *offset = bytes.len() - reader.len() + content.len();
+                Some(Ok((id, content)))
+            }
+            None => {
+                *offset = bytes.len();
+                Some(Err(WasmValidationError::MalformedSection(start)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn custom_section(name: &str, content: &[u8]) -> Vec<u8> {
+        let mut payload = vec![name.len() as u8];
+        payload.extend_from_slice(name.as_bytes());
+        payload.extend_from_slice(content);
+        let mut section = vec![CUSTOM_SECTION_ID, payload.len() as u8];
+        section.extend(payload);
+        section
+    }
+
+    #[test]
+    fn validates_header() {
+        assert!(matches!(
+            CanisterWasm::from_bytes(vec![0, 1]),
+            Err(WasmValidationError::TooShort(2))
+        ));
+        assert!(matches!(
+            CanisterWasm::from_bytes(vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0]),
+            Err(WasmValidationError::InvalidMagic(_))
+        ));
+        assert!(matches!(
+            CanisterWasm::from_bytes(b"\0asm\x02\0\0\0".to_vec()),
+            Err(WasmValidationError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            CanisterWasm::from_bytes(b"\0asm\x01\0\0\0\0\x05ab".to_vec()),
+            Err(WasmValidationError::MalformedSection(8))
+        ));
+        assert!(CanisterWasm::from_bytes(b"\0asm\x01\0\0\0".to_vec()).is_ok());
+    }
+
+    #[test]
+    fn metadata_sections() {
+        let mut bytes = b"\0asm\x01\0\0\0".to_vec();
+        bytes.extend(custom_section("icp:public candid:service", b"service : {}"));
+        bytes.extend(custom_section("name", b"names"));
+        bytes.extend(custom_section("icp:private git_commit", b"abc"));
+        let wasm = CanisterWasm::from_bytes(bytes).unwrap();
+        assert_eq!(
+            wasm.metadata_sections(),
+            vec![
+                WasmSection {
+                    name: "candid:service".to_string(),
+                    visibility: WasmSectionVisibility::Public,
+                    content: b"service : {}".to_vec(),
+                },
+                WasmSection {
+                    name: "git_commit".to_string(),
+                    visibility: WasmSectionVisibility::Private,
+                    content: b"abc".to_vec(),
+                },
+            ]
+        );
+    }
+}
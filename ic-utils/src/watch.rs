@@ -0,0 +1,152 @@
+use crate::{canister::CanisterBuilderError, Canister};
+use candid::{CandidType, Decode};
+use ic_agent::AgentError;
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// The receiving end of the channel published by [`Canister::watch`], holding the last reply of
+/// the method, or the error of the last failed poll.
+pub type WatchReceiver<T> = watch::Receiver<Option<Result<T, AgentError>>>;
+
+/// A handle to the background task started by [`Canister::watch`]. The task runs until
+/// [`stop`](WatchHandle::stop) is called, the handle is dropped, or every receiver has been
+/// dropped.
+#[derive(Debug)]
+pub struct WatchHandle {
+    task: JoinHandle<()>,
+}
+
+impl WatchHandle {
+    /// Stop polling the method. Receivers see the channel close after the last value.
+    pub fn stop(self) {
+        self.task.abort();
+    }
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Canister<'_> {
+    /// Poll the query method `method_name` every `interval`, and publish its reply on a
+    /// [`watch`] channel. The channel starts out as `None`, and is only updated with a reply
+    /// when it is different from the previous one, byte for byte. Every failed call, and every
+    /// reply that cannot be decoded as `T`, is published as an error, after which the next reply
+    /// is published even if it is the same as before.
+    ///
+    /// The polling happens in a task spawned on the current Tokio runtime, with a copy of the
+    /// agent of this canister, so the task does not borrow the canister. Keep the
+    /// [`WatchHandle`] for as long as the method should be polled.
+    pub fn watch<T>(
+        &self,
+        method_name: &str,
+        interval: Duration,
+    ) -> Result<(WatchReceiver<T>, WatchHandle), CanisterBuilderError>
+    where
+        T: CandidType + DeserializeOwned + Send + Sync + 'static,
+    {
+        let canister = self.clone().into_owned(Arc::new(self.agent.clone()))?;
+        let (sender, receiver) = watch::channel(None);
+        let method_name = method_name.to_string();
+        let task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            let mut previous: Option<Vec<u8>> = None;
+            loop {
+                ticker.tick().await;
+                if sender.is_closed() {
+                    break;
+                }
+                let reply = match canister.query_(&method_name).build_boxed().await {
+                    Ok(reply) => reply,
+                    Err(error) => {
+                        previous = None;
+                        sender.send_replace(Some(Err(error)));
+                        continue;
+                    }
+                };
+                if previous.as_ref() == Some(&reply) {
+                    continue;
+                }
+                match Decode!(&reply, T) {
+                    Ok(value) => {
+                        previous = Some(reply);
+                        sender.send_replace(Some(Ok(value)));
+                    }
+                    Err(error) => {
+                        previous = None;
+                        sender.send_replace(Some(Err(AgentError::CandidError(Box::new(error)))));
+                    }
+                }
+            }
+        });
+        Ok((receiver, WatchHandle { task }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util;
+    use ic_agent::agent::mock::{MockAgent, MockTransport, Response};
+    use ic_agent::AgentError;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn stop_closes_the_channel() {
+        let agent = MockAgent::new(MockTransport::new());
+        let canister = test_util::canister(&agent);
+
+        let (mut receiver, handle) = canister
+            .watch::<u64>("read", Duration::from_secs(60))
+            .unwrap();
+        assert!(receiver.borrow().is_none());
+        handle.stop();
+        assert!(receiver.changed().await.is_err());
+
+        // Dropping the handle stops the task too, even though the canister outlives it.
+        let (mut receiver, handle) = canister
+            .watch::<u64>("read", Duration::from_secs(60))
+            .unwrap();
+        drop(handle);
+        assert!(receiver.changed().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn publishes_replies() {
+        let agent = MockAgent::new(MockTransport::new().expect_query(
+            test_util::canister_id(),
+            Response::query_reply(candid::encode_one(7u64).unwrap()),
+        ));
+        let canister = test_util::canister(&agent);
+
+        let (mut receiver, _handle) = canister
+            .watch::<u64>("read", Duration::from_secs(60))
+            .unwrap();
+        receiver.changed().await.unwrap();
+        assert!(matches!(*receiver.borrow(), Some(Ok(7))));
+        agent.verify_all_called();
+    }
+
+    #[tokio::test]
+    async fn publishes_errors() {
+        let agent = MockAgent::new(MockTransport::new().expect_query(
+            test_util::canister_id(),
+            Response::query_reply(candid::encode_one("not a number").unwrap()),
+        ));
+        let canister = test_util::canister(&agent);
+
+        let (mut receiver, _handle) = canister
+            .watch::<u64>("read", Duration::from_secs(60))
+            .unwrap();
+        receiver.changed().await.unwrap();
+        assert!(matches!(
+            *receiver.borrow(),
+            Some(Err(AgentError::CandidError(_)))
+        ));
+        agent.verify_all_called();
+    }
+}
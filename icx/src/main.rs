@@ -3,7 +3,7 @@ use candid::{
     check_prog,
     parser::value::IDLValue,
     types::{Function, Type},
-    CandidType, Decode, Deserialize, IDLArgs, IDLProg, TypeEnv,
+    IDLArgs, IDLProg, TypeEnv,
 };
 use clap::{crate_authors, crate_version, Parser};
 use ic_agent::{
@@ -16,9 +16,8 @@ use ic_agent::{
     identity::BasicIdentity,
     Agent, AgentError, Identity,
 };
-use ic_utils::interfaces::management_canister::{
-    builders::{CanisterInstall, CanisterSettings},
-    MgmtMethod,
+use ic_utils::{
+    interfaces::management_canister::MgmtMethod, routing::effective_canister_id_for_call,
 };
 use ring::signature::Ed25519KeyPair;
 use std::{
@@ -278,40 +277,24 @@ pub fn get_effective_canister_id(
             )
         })?;
         match method_name {
-            MgmtMethod::CreateCanister | MgmtMethod::RawRand => bail!(
+            MgmtMethod::CreateCanister
+            | MgmtMethod::RawRand
+            | MgmtMethod::EcdsaPublicKey
+            | MgmtMethod::SignWithEcdsa => bail!(
                 "{} can only be called via an inter-canister call.",
                 method_name.as_ref()
             ),
-            MgmtMethod::InstallCode => {
-                let install_args = Decode!(arg_value, CanisterInstall)
-                    .context("Argument is not valid for CanisterInstall")?;
-                Ok(install_args.canister_id)
-            }
-            MgmtMethod::StartCanister
-            | MgmtMethod::StopCanister
-            | MgmtMethod::CanisterStatus
-            | MgmtMethod::DeleteCanister
-            | MgmtMethod::DepositCycles
-            | MgmtMethod::UninstallCode
-            | MgmtMethod::ProvisionalTopUpCanister => {
-                #[derive(CandidType, Deserialize)]
-                struct In {
-                    canister_id: Principal,
-                }
-                let in_args =
-                    Decode!(arg_value, In).context("Argument is not a valid Principal")?;
-                Ok(in_args.canister_id)
-            }
-            MgmtMethod::ProvisionalCreateCanisterWithCycles => Ok(Principal::management_canister()),
-            MgmtMethod::UpdateSettings => {
-                #[derive(CandidType, Deserialize)]
-                struct In {
-                    canister_id: Principal,
-                    settings: CanisterSettings,
-                }
-                let in_args =
-                    Decode!(arg_value, In).context("Argument is not valid for UpdateSettings")?;
-                Ok(in_args.canister_id)
+            _ => {
+                let context = match method_name {
+                    MgmtMethod::InstallCode => "Argument is not valid for CanisterInstall",
+                    MgmtMethod::UpdateSettings => "Argument is not valid for UpdateSettings",
+                    MgmtMethod::ProvisionalCreateCanisterWithCycles => {
+                        "Argument is not valid for ProvisionalCreateCanisterWithCycles"
+                    }
+                    _ => "Argument is not a valid Principal",
+                };
+                effective_canister_id_for_call(&canister_id, method_name.as_ref(), arg_value)
+                    .context(context)
             }
         }
     } else {
@@ -31,6 +31,8 @@ enum Ops {
     Trap = 20,
     SetGlobal = 21,
     GetGlobal = 22,
+    CertifiedDataSet = 36,
+    DataCertificate = 38,
 }
 
 /// A succinct shortcut for creating a `PayloadBuilder`, which is used to encode
@@ -187,6 +189,16 @@ impl PayloadBuilder {
         self.op(Ops::GetGlobal)
     }
 
+    /// Set the certified data of the canister. Only allowed in update calls.
+    pub fn certified_data_set(self, data: &[u8]) -> Self {
+        self.push_bytes(data).op(Ops::CertifiedDataSet)
+    }
+
+    /// Push the data certificate of the canister. Only available in non-replicated query calls.
+    pub fn data_certificate(self) -> Self {
+        self.op(Ops::DataCertificate)
+    }
+
     pub fn debug_print(self, msg: &[u8]) -> Self {
         self.push_bytes(msg).op(Ops::DebugPrint)
     }
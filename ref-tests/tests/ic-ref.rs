@@ -591,6 +591,7 @@ mod management_canister {
                     compute_allocation: None,
                     memory_allocation: None,
                     freezing_threshold: None,
+                    wasm_memory_limit: None,
                 },
             };
 
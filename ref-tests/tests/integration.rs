@@ -88,6 +88,46 @@ fn canister_query() {
     })
 }
 
+#[ignore]
+#[test]
+fn canister_certified_data() {
+    with_universal_canister(|agent, canister_id| async move {
+        let universal = Canister::builder()
+            .with_canister_id(canister_id)
+            .with_agent(&agent)
+            .build()?;
+        assert_eq!(
+            universal.get_certified_data().await,
+            Err(AgentError::NoCertifiedData(canister_id))
+        );
+
+        let data = [7; 32];
+        let arg = payload().certified_data_set(&data).reply().build();
+        agent
+            .update(&canister_id, "update")
+            .with_arg(&arg)
+            .call_and_wait()
+            .await?;
+        assert_eq!(universal.get_certified_data().await?, data);
+
+        Ok(())
+    })
+}
+
+#[ignore]
+#[test]
+fn canister_metadata() {
+    with_universal_canister(|agent, canister_id| async move {
+        // The universal canister has no custom sections, so the path is certified to be absent.
+        let result = agent
+            .read_state_canister_metadata(canister_id, "candid:service")
+            .await;
+        assert!(matches!(result, Err(AgentError::LookupPathAbsent(_))));
+
+        Ok(())
+    })
+}
+
 #[ignore]
 #[test]
 fn canister_reject_call() {
@@ -98,7 +138,7 @@ fn canister_reject_call() {
         let bob =
             WalletCanister::create(&agent, create_wallet_canister(&agent, None).await?).await?;
 
-        let result = alice.wallet_send(*bob.canister_id_(), 1_000_000).await;
+        let result = alice.wallet_send(bob.canister_id(), 1_000_000).await;
 
         assert_eq!(
             result,
@@ -113,6 +153,67 @@ fn canister_reject_call() {
     });
 }
 
+#[ignore]
+#[test]
+fn test_query_certificate_verification() {
+    with_universal_canister(|agent, canister_id| async move {
+        let arg = payload().certified_data_set(b"hello").reply().build();
+        agent
+            .update(&canister_id, "update")
+            .with_arg(&arg)
+            .call_and_wait()
+            .await?;
+
+        let arg = payload().data_certificate().append_and_reply().build();
+        let certificate = agent
+            .query(&canister_id, "query")
+            .with_arg(arg)
+            .call()
+            .await?;
+
+        let verification = agent.verify_response_certificate(&certificate, canister_id)?;
+        assert!(verification.certified_at_ns > 0);
+
+        Ok(())
+    })
+}
+
+#[ignore]
+#[test]
+fn test_query_certificate_verification_tampered() {
+    with_universal_canister(|agent, canister_id| async move {
+        let arg = payload().certified_data_set(b"hello").reply().build();
+        agent
+            .update(&canister_id, "update")
+            .with_arg(&arg)
+            .call_and_wait()
+            .await?;
+
+        let arg = payload().data_certificate().append_and_reply().build();
+        let mut certificate = agent
+            .query(&canister_id, "query")
+            .with_arg(arg)
+            .call()
+            .await?;
+
+        // flip a bit of the BLS signature, which follows the "signature" key
+        let signature_key = b"isignature\x58\x30";
+        let signature_start = certificate
+            .windows(signature_key.len())
+            .position(|window| window == signature_key)
+            .expect("certificate has no signature")
+            + signature_key.len();
+        certificate[signature_start] ^= 1;
+
+        assert_eq!(
+            agent.verify_response_certificate(&certificate, canister_id),
+            Err(AgentError::CertificateVerificationFailed())
+        );
+
+        Ok(())
+    })
+}
+
 #[ignore]
 #[test]
 fn wallet_canister_forward() {
@@ -331,6 +432,7 @@ fn wallet_create_wallet() {
                 compute_allocation: None,
                 memory_allocation: None,
                 freezing_threshold: None,
+                wasm_memory_limit: None,
             },
         };
         let args = Argument::from_candid((create_args,));
@@ -377,7 +479,7 @@ fn wallet_canister_funds() {
         let alice_previous_balance = alice.wallet_balance().await?;
         let bob_previous_balance = bob.wallet_balance().await?;
 
-        alice.wallet_send(*bob.canister_id_(), 1_000_000).await?;
+        alice.wallet_send(bob.canister_id(), 1_000_000).await?;
 
         let bob_balance = bob.wallet_balance().await?;
 